@@ -0,0 +1,301 @@
+pub mod activity;
+pub mod clocks;
+pub mod coverage;
+pub mod edges;
+pub mod fifo;
+pub mod fingerprint;
+pub mod histogram;
+pub mod overlay;
+pub mod query;
+pub mod reset;
+pub mod states;
+
+use std::collections::BTreeMap;
+
+use makai_waveform_db::bitvector::{BitVector, Logic};
+use makai_waveform_db::errors::WaveformError;
+use makai_waveform_db::vector::WaveformSignalVector;
+use makai_waveform_db::{Waveform, WaveformSearchMode, WaveformSignalResult, WaveformValueResult};
+
+use crate::parser::{VcdEntry, VcdHeader, VcdVariableWidth};
+
+#[derive(Debug)]
+pub enum AnalysisError {
+    Waveform(WaveformError),
+    // makai_waveform_db stores real values but cannot read them back, so
+    // the values of real signals (by idcode) are not available
+    UnreadableReal(usize),
+}
+
+impl From<WaveformError> for AnalysisError {
+    fn from(err: WaveformError) -> Self {
+        Self::Waveform(err)
+    }
+}
+
+// Lets writers of VCD text report the signals they could not read
+impl From<AnalysisError> for std::io::Error {
+    fn from(err: AnalysisError) -> Self {
+        Self::new(std::io::ErrorKind::InvalidData, format!("{:?}", err))
+    }
+}
+
+pub type AnalysisResult<T> = Result<T, AnalysisError>;
+
+// Converts a two-state bit-vector of up to 64 bits into an integer, returns
+// None if the vector is too wide or has any unknown or high-impedance bits
+pub fn bitvector_to_u64(bv: &BitVector) -> Option<u64> {
+    if bv.get_bit_width() > u64::BITS as usize {
+        return None;
+    }
+    // Two-state vectors that fit are stored as an integer already
+    if !bv.is_four_state() && !bv.is_pointer() {
+        return Some(bv.to_bits_two_state::<u64>());
+    }
+    let mut value = 0u64;
+    for (i, bit) in bv.iter().enumerate() {
+        match bit {
+            Logic::Zero => {}
+            Logic::One => value |= 1 << i,
+            Logic::Unknown | Logic::HighImpedance => return None,
+        }
+    }
+    Some(value)
+}
+
+/// Iterates over every change of a vector signal as (timestamp, value) pairs
+pub fn vector_changes<'a>(
+    waveform: &'a Waveform,
+    signal: &'a WaveformSignalVector,
+) -> impl Iterator<Item = (u64, BitVector)> + 'a {
+    // An empty history has no blocks to iterate over
+    let history = if signal.is_empty() {
+        None
+    } else {
+        Some(signal.get_history().into_iter())
+    };
+    history.into_iter().flatten().map(move |index| {
+        (
+            waveform.get_timestamps()[index.get_timestamp_index()],
+            signal.get_bitvector(index.get_value_index()),
+        )
+    })
+}
+
+/// Iterates over the timestamps at which a vector or real signal changed
+pub fn change_timestamps(waveform: &Waveform, idcode: usize) -> impl Iterator<Item = u64> + '_ {
+    let history = match waveform.get_signal(idcode) {
+        Some(WaveformSignalResult::Vector(signal)) if !signal.is_empty() => {
+            Some(signal.get_history())
+        }
+        Some(WaveformSignalResult::Real(signal)) if !signal.is_empty() => {
+            Some(signal.get_history())
+        }
+        _ => None,
+    };
+    history
+        .into_iter()
+        .flatten()
+        .map(|index| waveform.get_timestamps()[index.get_timestamp_index()])
+}
+
+/// Returns every change of a vector signal of up to 64 bits as (timestamp,
+/// value) pairs, values with unknown or high-impedance bits are None, returns
+/// None if the signal is missing, real or too wide
+pub fn get_u64_history(waveform: &Waveform, idcode: usize) -> Option<Vec<(u64, Option<u64>)>> {
+    match waveform.get_signal(idcode) {
+        Some(WaveformSignalResult::Vector(signal)) if signal.get_width() <= u64::BITS as usize => {
+            Some(
+                vector_changes(waveform, signal)
+                    .map(|(timestamp, bv)| (timestamp, bitvector_to_u64(&bv)))
+                    .collect(),
+            )
+        }
+        _ => None,
+    }
+}
+
+/// Searches for the value of a vector signal like Waveform::search_value,
+/// returning the value as an integer (None for unknown or high-impedance
+/// bits) along with its timestamp index
+pub fn search_value_u64(
+    waveform: &Waveform,
+    idcode: usize,
+    timestamp_index: usize,
+    search_mode: WaveformSearchMode,
+) -> Option<(Option<u64>, usize)> {
+    match waveform.get_signal(idcode)? {
+        WaveformSignalResult::Vector(signal) if !signal.is_empty() => {}
+        _ => return None,
+    }
+    match waveform.search_value(idcode, timestamp_index, search_mode)? {
+        WaveformValueResult::Vector(bv, index) if bv.get_bit_width() <= u64::BITS as usize => {
+            Some((bitvector_to_u64(&bv), index))
+        }
+        _ => None,
+    }
+}
+
+/// Returns every change of a vector signal as (timestamp, entry) pairs, real
+/// signals with any changes are an UnreadableReal error
+pub fn signal_changes(waveform: &Waveform, idcode: usize) -> AnalysisResult<Vec<(u64, VcdEntry)>> {
+    let timestamps = waveform.get_timestamps();
+    match waveform.get_signal(idcode) {
+        Some(WaveformSignalResult::Vector(signal)) if !signal.is_empty() => Ok(signal
+            .get_history()
+            .into_iter()
+            .map(|index| {
+                let bv = signal.get_bitvector(index.get_value_index());
+                (
+                    timestamps[index.get_timestamp_index()],
+                    VcdEntry::Vector(bv, idcode),
+                )
+            })
+            .collect()),
+        Some(WaveformSignalResult::Real(signal)) if !signal.is_empty() => {
+            Err(AnalysisError::UnreadableReal(idcode))
+        }
+        _ => Ok(Vec::new()),
+    }
+}
+
+/// Returns every change of a variable like signal_changes, except events
+/// whose stored pulses are returned as one VcdEntry::Event per trigger
+pub fn variable_changes(
+    waveform: &Waveform,
+    idcode: usize,
+    width: &VcdVariableWidth,
+) -> AnalysisResult<Vec<(u64, VcdEntry)>> {
+    let changes = signal_changes(waveform, idcode)?;
+    if *width != VcdVariableWidth::Event {
+        return Ok(changes);
+    }
+    Ok(changes
+        .into_iter()
+        .filter(|(_, entry)| is_trigger(entry))
+        .map(|(timestamp, _)| (timestamp, VcdEntry::Event(idcode)))
+        .collect())
+}
+
+/// Iterates over the changes of a variable like variable_changes, reading
+/// them from the waveform as they are needed instead of collecting them
+pub fn iter_variable_changes<'a>(
+    waveform: &'a Waveform,
+    idcode: usize,
+    width: &VcdVariableWidth,
+) -> AnalysisResult<Box<dyn Iterator<Item = (u64, VcdEntry)> + 'a>> {
+    let signal = match waveform.get_signal(idcode) {
+        Some(WaveformSignalResult::Vector(signal)) => signal,
+        Some(WaveformSignalResult::Real(signal)) if !signal.is_empty() => {
+            return Err(AnalysisError::UnreadableReal(idcode));
+        }
+        _ => return Ok(Box::new(std::iter::empty())),
+    };
+    let changes = vector_changes(waveform, signal)
+        .map(move |(timestamp, bv)| (timestamp, VcdEntry::Vector(bv, idcode)));
+    if *width != VcdVariableWidth::Event {
+        return Ok(Box::new(changes));
+    }
+    Ok(Box::new(
+        changes
+            .filter(|(_, entry)| is_trigger(entry))
+            .map(move |(timestamp, _)| (timestamp, VcdEntry::Event(idcode))),
+    ))
+}
+
+/// Returns the timestamps at which a variable changed like change_timestamps,
+/// except events which only count their triggers, see variable_changes
+pub fn variable_change_timestamps(
+    waveform: &Waveform,
+    idcode: usize,
+    width: &VcdVariableWidth,
+) -> Vec<u64> {
+    if *width != VcdVariableWidth::Event {
+        return change_timestamps(waveform, idcode).collect();
+    }
+    // Events are stored as vectors, so their changes can always be read
+    variable_changes(waveform, idcode, width)
+        .map(|changes| {
+            changes
+                .into_iter()
+                .map(|(timestamp, _)| timestamp)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// Events are stored as a one at the timestamp they are triggered, which is
+// cleared back to zero at the next timestamp
+fn is_trigger(entry: &VcdEntry) -> bool {
+    matches!(entry, VcdEntry::Vector(bv, _) if bv.get_bit(0) == Logic::One)
+}
+
+/// Builds a new waveform holding only the timestamps from start to end (both
+/// inclusive), every signal keeps the value it held going into the start
+pub fn trim_waveform(
+    header: &VcdHeader,
+    waveform: &Waveform,
+    start: u64,
+    end: u64,
+) -> AnalysisResult<Waveform> {
+    let mut trimmed = Waveform::new();
+    header.initialize_waveform(&mut trimmed);
+    if waveform.get_timestamps().is_empty() || start > end {
+        return Ok(trimmed);
+    }
+    let start_index = waveform.search_timestamp(start, WaveformSearchMode::Before);
+    let mut entries: BTreeMap<u64, Vec<VcdEntry>> = BTreeMap::new();
+    for (idcode, width) in header.get_idcodes_map() {
+        // Searching the value of a real signal would read it back
+        let changes = signal_changes(waveform, *idcode)?;
+        // Carry over the value each signal held going into the trimmed range,
+        // events only exist at the instant they were triggered
+        let initial = start_index
+            .filter(|_| *width != VcdVariableWidth::Event)
+            .and_then(|index| waveform.search_value(*idcode, index, WaveformSearchMode::Before));
+        match initial {
+            Some(WaveformValueResult::Vector(bv, _)) => {
+                entries
+                    .entry(start)
+                    .or_default()
+                    .push(VcdEntry::Vector(bv, *idcode));
+            }
+            Some(WaveformValueResult::Real(value, _)) => {
+                entries
+                    .entry(start)
+                    .or_default()
+                    .push(VcdEntry::Real(value, *idcode));
+            }
+            None => {}
+        }
+        let is_event = *width == VcdVariableWidth::Event;
+        for (timestamp, entry) in changes {
+            // The pulse of an event triggered at the start is kept, but not
+            // the end of one triggered before it
+            let after_start =
+                timestamp > start || (is_event && timestamp == start && is_trigger(&entry));
+            if after_start && timestamp <= end {
+                entries.entry(timestamp).or_default().push(entry);
+            }
+        }
+    }
+    for (timestamp, entries) in entries {
+        trimmed.insert_timestamp(timestamp)?;
+        for entry in entries {
+            match entry {
+                VcdEntry::Timestamp(_) => {}
+                VcdEntry::Scalar(bit, idcode) => trimmed.update_vector(idcode, bit.into())?,
+                VcdEntry::Vector(bv, idcode) => trimmed.update_vector(idcode, bv)?,
+                VcdEntry::Real(value, idcode) => trimmed.update_real(idcode, value)?,
+                VcdEntry::Event(idcode) => {
+                    trimmed.update_vector(idcode, BitVector::new_one_bit())?
+                }
+                VcdEntry::Port(value, idcode) => {
+                    trimmed.update_vector(idcode, value.to_bitvector())?
+                }
+                VcdEntry::String(_, _) => {}
+            }
+        }
+    }
+    Ok(trimmed)
+}
@@ -0,0 +1,186 @@
+use std::collections::{BTreeMap, HashSet};
+use std::ops::Range;
+
+use makai_waveform_db::Waveform;
+
+use crate::analysis::variable_change_timestamps;
+use crate::parser::{VcdHeader, VcdScope};
+
+/// Counts the changes of one signal in buckets of the given width (in
+/// timescale units) covering the whole waveform, read from the history index
+/// without materializing any values. Every signal gets the same number of
+/// buckets, so the series of different signals line up. Events only count
+/// their triggers. Returns an empty series if the signal is missing.
+pub fn activity_series(
+    header: &VcdHeader,
+    waveform: &Waveform,
+    idcode: usize,
+    bucket_width: u64,
+) -> Vec<u32> {
+    let Some(width) = header.get_idcodes_map().get(&idcode) else {
+        return Vec::new();
+    };
+    if waveform.get_signal(idcode).is_none() {
+        return Vec::new();
+    }
+    let bucket_width = bucket_width.max(1);
+    let range = waveform.get_timestamp_range();
+    let mut buckets = vec![0u32; ((range.end - range.start) / bucket_width + 1) as usize];
+    for timestamp in variable_change_timestamps(waveform, idcode, width) {
+        let bucket = &mut buckets[((timestamp - range.start) / bucket_width) as usize];
+        *bucket = bucket.saturating_add(1);
+    }
+    buckets
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VcdScopeActivity {
+    path: String,
+    // Number of changes in each window for this scope and all scopes below it
+    windows: Vec<u64>,
+}
+
+impl VcdScopeActivity {
+    pub fn get_path(&self) -> &String {
+        &self.path
+    }
+
+    pub fn get_windows(&self) -> &Vec<u64> {
+        &self.windows
+    }
+
+    pub fn get_total(&self) -> u64 {
+        self.windows.iter().sum()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VcdActivityReport {
+    start: u64,
+    end: u64,
+    window: u64,
+    global: Vec<u64>,
+    scopes: Vec<VcdScopeActivity>,
+    longest_idle: Option<Range<u64>>,
+}
+
+fn collect_idcodes(scope: &VcdScope, idcodes: &mut HashSet<usize>) {
+    for variable in scope.get_variables() {
+        idcodes.insert(variable.get_idcode());
+    }
+    for child in scope.get_scopes() {
+        collect_idcodes(child, idcodes);
+    }
+}
+
+impl VcdActivityReport {
+    /// Slices the simulation into windows of the given duration (in
+    /// timescale units) and counts value changes per window, both globally
+    /// and for every scope
+    pub fn new(header: &VcdHeader, waveform: &Waveform, window: u64) -> Self {
+        let window = window.max(1);
+        let range = waveform.get_timestamp_range();
+        let (start, end) = (range.start, range.end);
+        let window_count = ((end - start) / window + 1) as usize;
+        // Count the changes at each timestamp for every unique idcode once,
+        // events only count their triggers
+        let mut changes: BTreeMap<usize, BTreeMap<u64, u64>> = BTreeMap::new();
+        for (idcode, width) in header.get_idcodes_map() {
+            let counts = changes.entry(*idcode).or_default();
+            for timestamp in variable_change_timestamps(waveform, *idcode, width) {
+                *counts.entry(timestamp).or_insert(0) += 1;
+            }
+        }
+        let bucket = |idcodes: &mut dyn Iterator<Item = &usize>| {
+            let mut windows = vec![0; window_count];
+            for idcode in idcodes {
+                for (timestamp, count) in &changes[idcode] {
+                    windows[((timestamp - start) / window) as usize] += count;
+                }
+            }
+            windows
+        };
+        let global = bucket(&mut changes.keys());
+        let mut scopes = Vec::new();
+        let mut stack: Vec<(String, &VcdScope)> = header
+            .get_scopes()
+            .iter()
+            .rev()
+            .map(|scope| (scope.get_name().clone(), scope))
+            .collect();
+        while let Some((path, scope)) = stack.pop() {
+            let mut idcodes = HashSet::new();
+            collect_idcodes(scope, &mut idcodes);
+            let windows = bucket(&mut idcodes.iter());
+            for child in scope.get_scopes().iter().rev() {
+                stack.push((format!("{}.{}", path, child.get_name()), child));
+            }
+            scopes.push(VcdScopeActivity { path, windows });
+        }
+        // Find the largest gap between any two consecutive changes
+        let mut active: Vec<u64> = changes
+            .values()
+            .flat_map(|counts| counts.keys().copied())
+            .collect();
+        active.sort_unstable();
+        active.dedup();
+        active.push(end);
+        let longest_idle = active
+            .windows(2)
+            .map(|pair| pair[0]..pair[1])
+            .filter(|range| !range.is_empty())
+            .max_by_key(|range| range.end - range.start);
+        Self {
+            start,
+            end,
+            window,
+            global,
+            scopes,
+            longest_idle,
+        }
+    }
+
+    pub fn get_window(&self) -> u64 {
+        self.window
+    }
+
+    /// Returns the time range covered by the window at the given index
+    pub fn get_window_range(&self, index: usize) -> Range<u64> {
+        let start = self.start + index as u64 * self.window;
+        start..(start + self.window).min(self.end + 1)
+    }
+
+    pub fn get_global(&self) -> &Vec<u64> {
+        &self.global
+    }
+
+    pub fn get_scopes(&self) -> &Vec<VcdScopeActivity> {
+        &self.scopes
+    }
+
+    pub fn get_scope(&self, path: &str) -> Option<&VcdScopeActivity> {
+        self.scopes.iter().find(|scope| scope.path == path)
+    }
+
+    /// Average number of changes per timescale unit over the whole simulation
+    pub fn get_change_rate(&self) -> f64 {
+        let duration = (self.end - self.start).max(1);
+        self.global.iter().sum::<u64>() as f64 / duration as f64
+    }
+
+    /// Returns the longest time range where no signal changed
+    pub fn get_longest_idle(&self) -> Option<Range<u64>> {
+        self.longest_idle.clone()
+    }
+
+    /// Returns the time range of the window with the most changes
+    pub fn get_busiest(&self) -> Option<Range<u64>> {
+        let (index, _) = self
+            .global
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .max_by_key(|(index, count)| (**count, std::cmp::Reverse(*index)))?;
+        Some(self.get_window_range(index))
+    }
+}
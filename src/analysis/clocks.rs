@@ -0,0 +1,137 @@
+use std::collections::{HashMap, HashSet};
+
+use makai_waveform_db::bitvector::Logic;
+use makai_waveform_db::Waveform;
+
+use crate::analysis::{variable_change_timestamps, vector_changes};
+use crate::parser::{VcdClockDomains, VcdHeader, VcdVariableWidth};
+
+// Minimum number of rising edges before a signal is considered a clock
+const CLOCK_MIN_EDGES: usize = 4;
+// Fraction of periods that must match the most common period
+const CLOCK_PERIOD_RATIO: f64 = 0.9;
+// Fraction of changes that must land on a clock edge to join its domain
+const DOMAIN_ALIGNMENT_RATIO: f64 = 0.9;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VcdClock {
+    idcode: usize,
+    period: u64,
+    rising_edges: Vec<u64>,
+}
+
+impl VcdClock {
+    /// Checks if a 1-bit signal alternates between zero and one with a mostly
+    /// regular period, returns None if it does not look like a clock
+    pub fn detect(waveform: &Waveform, idcode: usize) -> Option<Self> {
+        let signal = waveform.get_vector_signal(idcode)?;
+        if signal.get_width() != 1 {
+            return None;
+        }
+        let mut rising_edges = Vec::new();
+        let mut last = None;
+        for (timestamp, bv) in vector_changes(waveform, signal) {
+            let bit = bv.get_bit(0);
+            match (last, bit) {
+                // Ignore the signal until it is first driven
+                (None, Logic::Unknown | Logic::HighImpedance) => continue,
+                (None, _) => {}
+                (Some(Logic::Zero), Logic::One) => rising_edges.push(timestamp),
+                (Some(Logic::One), Logic::Zero) => {}
+                _ => return None,
+            }
+            last = Some(bit);
+        }
+        if rising_edges.len() < CLOCK_MIN_EDGES {
+            return None;
+        }
+        let mut periods: HashMap<u64, usize> = HashMap::new();
+        for pair in rising_edges.windows(2) {
+            *periods.entry(pair[1] - pair[0]).or_insert(0) += 1;
+        }
+        let (period, count) = periods.into_iter().max_by_key(|(_, count)| *count)?;
+        if (count as f64) < (rising_edges.len() - 1) as f64 * CLOCK_PERIOD_RATIO {
+            return None;
+        }
+        Some(Self {
+            idcode,
+            period,
+            rising_edges,
+        })
+    }
+
+    pub fn get_idcode(&self) -> usize {
+        self.idcode
+    }
+
+    pub fn get_period(&self) -> u64 {
+        self.period
+    }
+
+    pub fn get_rising_edges(&self) -> &Vec<u64> {
+        &self.rising_edges
+    }
+}
+
+/// Returns every signal in the header that looks like a free-running clock
+pub fn detect_clocks(header: &VcdHeader, waveform: &Waveform) -> Vec<VcdClock> {
+    let mut clocks: Vec<VcdClock> = header
+        .get_idcodes_map()
+        .iter()
+        .filter(|(_, width)| **width == VcdVariableWidth::Vector { width: 1 })
+        .filter_map(|(idcode, _)| VcdClock::detect(waveform, *idcode))
+        .collect();
+    clocks.sort_by_key(|clock| clock.idcode);
+    clocks
+}
+
+/// Groups every non-clock signal with the clock whose rising edges line up
+/// with most of its changes, ignoring each signal's initial value
+pub fn group_clock_domains(
+    header: &VcdHeader,
+    waveform: &Waveform,
+    clocks: &[VcdClock],
+) -> VcdClockDomains {
+    let edges: Vec<HashSet<u64>> = clocks
+        .iter()
+        .map(|clock| clock.rising_edges.iter().copied().collect())
+        .collect();
+    let clock_idcodes: HashSet<usize> = clocks.iter().map(|clock| clock.idcode).collect();
+    let mut domains: HashMap<usize, Vec<usize>> = HashMap::new();
+    for (idcode, width) in header.get_idcodes_map() {
+        if clock_idcodes.contains(idcode) {
+            continue;
+        }
+        // Events have no initial value, only triggers
+        let initial = usize::from(*width != VcdVariableWidth::Event);
+        let changes: Vec<u64> = variable_change_timestamps(waveform, *idcode, width)
+            .into_iter()
+            .skip(initial)
+            .collect();
+        if changes.is_empty() {
+            continue;
+        }
+        // Prefer the slowest clock when several line up equally well
+        let best = clocks
+            .iter()
+            .zip(&edges)
+            .map(|(clock, edges)| {
+                let aligned = changes.iter().filter(|t| edges.contains(t)).count();
+                (aligned as f64 / changes.len() as f64, clock)
+            })
+            .filter(|(ratio, _)| *ratio >= DOMAIN_ALIGNMENT_RATIO)
+            .max_by(|(a, clock_a), (b, clock_b)| {
+                a.total_cmp(b).then(clock_a.period.cmp(&clock_b.period))
+            });
+        if let Some((_, clock)) = best {
+            domains.entry(clock.idcode).or_default().push(*idcode);
+        }
+    }
+    VcdClockDomains::new(domains)
+}
+
+/// Detects clocks in a loaded waveform and groups every other signal by the
+/// clock it appears synchronous to, for VcdHeader::set_clock_domains
+pub fn infer_clock_domains(header: &VcdHeader, waveform: &Waveform) -> VcdClockDomains {
+    group_clock_domains(header, waveform, &detect_clocks(header, waveform))
+}
@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+
+use makai_waveform_db::{Waveform, WaveformSignalResult};
+
+use crate::parser::{VcdHeader, VcdScope};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VcdCoverageClass {
+    // No value was ever dumped for the variable
+    NeverDumped,
+    // A value was dumped but it never changed afterwards
+    NeverToggled,
+    Toggled,
+}
+
+impl VcdCoverageClass {
+    pub fn from_waveform(waveform: &Waveform, idcode: usize) -> Self {
+        let toggled = match waveform.get_signal(idcode) {
+            Some(WaveformSignalResult::Vector(signal)) => {
+                if signal.is_empty() {
+                    return Self::NeverDumped;
+                }
+                let first = signal.get_bitvector(0);
+                (1..signal.len()).any(|i| signal.get_bitvector(i) != first)
+            }
+            Some(WaveformSignalResult::Real(signal)) => {
+                if signal.is_empty() {
+                    return Self::NeverDumped;
+                }
+                // Real values cannot be read back, any change after the
+                // first counts as a toggle
+                signal.len() > 1
+            }
+            None => return Self::NeverDumped,
+        };
+        if toggled {
+            Self::Toggled
+        } else {
+            Self::NeverToggled
+        }
+    }
+}
+
+impl std::fmt::Display for VcdCoverageClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NeverDumped => write!(f, "never dumped"),
+            Self::NeverToggled => write!(f, "never toggled"),
+            Self::Toggled => write!(f, "toggled"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct VcdCoverageCounts {
+    never_dumped: usize,
+    never_toggled: usize,
+    toggled: usize,
+}
+
+impl VcdCoverageCounts {
+    fn add_class(&mut self, class: VcdCoverageClass) {
+        match class {
+            VcdCoverageClass::NeverDumped => self.never_dumped += 1,
+            VcdCoverageClass::NeverToggled => self.never_toggled += 1,
+            VcdCoverageClass::Toggled => self.toggled += 1,
+        }
+    }
+
+    fn add_counts(&mut self, other: &Self) {
+        self.never_dumped += other.never_dumped;
+        self.never_toggled += other.never_toggled;
+        self.toggled += other.toggled;
+    }
+
+    pub fn get_never_dumped(&self) -> usize {
+        self.never_dumped
+    }
+
+    pub fn get_never_toggled(&self) -> usize {
+        self.never_toggled
+    }
+
+    pub fn get_toggled(&self) -> usize {
+        self.toggled
+    }
+
+    pub fn get_total(&self) -> usize {
+        self.never_dumped + self.never_toggled + self.toggled
+    }
+
+    /// Fraction of variables that toggled at least once, from 0.0 to 1.0
+    pub fn get_toggle_ratio(&self) -> f64 {
+        if self.get_total() == 0 {
+            0.0
+        } else {
+            self.toggled as f64 / self.get_total() as f64
+        }
+    }
+}
+
+impl std::fmt::Display for VcdCoverageCounts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}/{} toggled ({:.1}%), {} never toggled, {} never dumped",
+            self.toggled,
+            self.get_total(),
+            self.get_toggle_ratio() * 100.0,
+            self.never_toggled,
+            self.never_dumped
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct VcdScopeCoverage {
+    name: String,
+    // Counts for this scope and all scopes below it
+    counts: VcdCoverageCounts,
+    variables: Vec<(String, VcdCoverageClass)>,
+    scopes: Vec<VcdScopeCoverage>,
+}
+
+impl VcdScopeCoverage {
+    fn new(
+        scope: &VcdScope,
+        waveform: &Waveform,
+        classes: &mut HashMap<usize, VcdCoverageClass>,
+    ) -> Self {
+        let mut counts = VcdCoverageCounts::default();
+        let mut variables = Vec::new();
+        for variable in scope.get_variables() {
+            let class = *classes.entry(variable.get_idcode()).or_insert_with(|| {
+                VcdCoverageClass::from_waveform(waveform, variable.get_idcode())
+            });
+            counts.add_class(class);
+            variables.push((variable.get_name().clone(), class));
+        }
+        let mut scopes = Vec::new();
+        for child in scope.get_scopes() {
+            let child = Self::new(child, waveform, classes);
+            counts.add_counts(&child.counts);
+            scopes.push(child);
+        }
+        Self {
+            name: scope.get_name().clone(),
+            counts,
+            variables,
+            scopes,
+        }
+    }
+
+    pub fn get_name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn get_counts(&self) -> &VcdCoverageCounts {
+        &self.counts
+    }
+
+    pub fn get_variables(&self) -> &Vec<(String, VcdCoverageClass)> {
+        &self.variables
+    }
+
+    pub fn get_scopes(&self) -> &Vec<VcdScopeCoverage> {
+        &self.scopes
+    }
+
+    fn fmt_indented(&self, f: &mut std::fmt::Formatter<'_>, depth: usize) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{:indent$}{}: {}",
+            "",
+            self.name,
+            self.counts,
+            indent = depth * 2
+        )?;
+        for scope in &self.scopes {
+            scope.fmt_indented(f, depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct VcdCoverageReport {
+    counts: VcdCoverageCounts,
+    scopes: Vec<VcdScopeCoverage>,
+}
+
+impl VcdCoverageReport {
+    /// Classifies every declared variable as never dumped, never toggled, or
+    /// toggled, and aggregates the results by scope
+    pub fn new(header: &VcdHeader, waveform: &Waveform) -> Self {
+        let mut classes = HashMap::new();
+        let mut counts = VcdCoverageCounts::default();
+        let mut scopes = Vec::new();
+        for scope in header.get_scopes() {
+            let scope = VcdScopeCoverage::new(scope, waveform, &mut classes);
+            counts.add_counts(&scope.counts);
+            scopes.push(scope);
+        }
+        Self { counts, scopes }
+    }
+
+    pub fn get_counts(&self) -> &VcdCoverageCounts {
+        &self.counts
+    }
+
+    pub fn get_scopes(&self) -> &Vec<VcdScopeCoverage> {
+        &self.scopes
+    }
+
+    /// Returns the full paths of every variable with the given class
+    pub fn get_paths(&self, class: VcdCoverageClass) -> Vec<String> {
+        fn collect(
+            scope: &VcdScopeCoverage,
+            prefix: &str,
+            class: VcdCoverageClass,
+            paths: &mut Vec<String>,
+        ) {
+            let prefix = format!("{}{}", prefix, scope.name);
+            for (name, c) in &scope.variables {
+                if *c == class {
+                    paths.push(format!("{}.{}", prefix, name));
+                }
+            }
+            for child in &scope.scopes {
+                collect(child, &format!("{}.", prefix), class, paths);
+            }
+        }
+        let mut paths = Vec::new();
+        for scope in &self.scopes {
+            collect(scope, "", class, &mut paths);
+        }
+        paths
+    }
+}
+
+impl std::fmt::Display for VcdCoverageReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Total: {}", self.counts)?;
+        for scope in &self.scopes {
+            scope.fmt_indented(f, 0)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,247 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use makai_waveform_db::bitvector::Logic;
+use makai_waveform_db::history::WaveformHistoryIter;
+use makai_waveform_db::vector::WaveformSignalVector;
+use makai_waveform_db::Waveform;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VcdEdge {
+    Rising,
+    Falling,
+    Any,
+}
+
+impl VcdEdge {
+    /// Returns true if going from one bit to the next is this edge, only
+    /// changes between zero and one are edges
+    pub fn matches(&self, last: Logic, bit: Logic) -> bool {
+        matches!(
+            (self, last, bit),
+            (Self::Rising | Self::Any, Logic::Zero, Logic::One)
+                | (Self::Falling | Self::Any, Logic::One, Logic::Zero)
+        )
+    }
+}
+
+/// Iterates over the timestamps of the edges of a 1-bit signal, walking the
+/// history of changes. The waveform only hands out values as bit-vectors, so
+/// reading the bit of each change still builds one.
+pub struct VcdEdges<'a> {
+    waveform: &'a Waveform,
+    signal: &'a WaveformSignalVector,
+    history: Option<WaveformHistoryIter<'a>>,
+    edge: VcdEdge,
+    end: u64,
+    last: Logic,
+}
+
+impl<'a> Iterator for VcdEdges<'a> {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for index in self.history.as_mut()? {
+            let timestamp = self.waveform.get_timestamps()[index.get_timestamp_index()];
+            if timestamp >= self.end {
+                self.history = None;
+                return None;
+            }
+            let bit = self
+                .signal
+                .get_bitvector(index.get_value_index())
+                .get_bit(0);
+            let last = std::mem::replace(&mut self.last, bit);
+            if self.edge.matches(last, bit) {
+                return Some(timestamp);
+            }
+        }
+        self.history = None;
+        None
+    }
+}
+
+/// Returns the timestamps of the edges of a 1-bit signal within a range of
+/// timestamps, an edge at the start of the range counts if the value before
+/// it was the other level. Returns None if the signal is missing or wider.
+pub fn iter_edges(
+    waveform: &Waveform,
+    idcode: usize,
+    edge: VcdEdge,
+    range: Range<u64>,
+) -> Option<VcdEdges<'_>> {
+    let signal = waveform.get_vector_signal(idcode)?;
+    if signal.get_width() != 1 {
+        return None;
+    }
+    let mut last = Logic::Unknown;
+    // An empty history has no blocks to iterate over
+    let history = if signal.is_empty() {
+        None
+    } else {
+        let mut history = signal.get_history().into_iter();
+        // Skip to the last change before the range to know the level going in
+        let first = waveform
+            .get_timestamps()
+            .partition_point(|timestamp| *timestamp < range.start);
+        if first > 0 {
+            if let Some(index) = history.seek(first - 1) {
+                last = signal.get_bitvector(index.get_value_index()).get_bit(0);
+            }
+        }
+        Some(history)
+    };
+    Some(VcdEdges {
+        waveform,
+        signal,
+        history,
+        edge,
+        end: range.end,
+        last,
+    })
+}
+
+/// Counts the edges of a 1-bit signal within a range of timestamps
+pub fn count_edges(
+    waveform: &Waveform,
+    idcode: usize,
+    edge: VcdEdge,
+    range: Range<u64>,
+) -> Option<usize> {
+    Some(iter_edges(waveform, idcode, edge, range)?.count())
+}
+
+/// Period statistics of a 1-bit signal, measured between rising edges
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VcdFrequency {
+    cycles: usize,
+    min_period: u64,
+    max_period: u64,
+    average_period: f64,
+    jitter: f64,
+}
+
+impl VcdFrequency {
+    /// Returns the number of complete cycles, one less than the rising edges
+    pub fn get_cycles(&self) -> usize {
+        self.cycles
+    }
+
+    pub fn get_min_period(&self) -> u64 {
+        self.min_period
+    }
+
+    pub fn get_max_period(&self) -> u64 {
+        self.max_period
+    }
+
+    pub fn get_average_period(&self) -> f64 {
+        self.average_period
+    }
+
+    /// Returns the standard deviation of the periods
+    pub fn get_jitter(&self) -> f64 {
+        self.jitter
+    }
+
+    /// Returns the average frequency in hertz for a timescale like the one
+    /// from VcdHeader::get_timescale
+    pub fn get_frequency(&self, timescale: i32) -> f64 {
+        10f64.powi(timescale) / self.average_period
+    }
+}
+
+/// Measures the periods between rising edges of a 1-bit signal within a range
+/// of timestamps, returns None if there are fewer than two rising edges
+pub fn measure_frequency(
+    waveform: &Waveform,
+    idcode: usize,
+    range: Range<u64>,
+) -> Option<VcdFrequency> {
+    let edges: Vec<u64> = iter_edges(waveform, idcode, VcdEdge::Rising, range)?.collect();
+    let periods: Vec<u64> = edges.windows(2).map(|pair| pair[1] - pair[0]).collect();
+    if periods.is_empty() {
+        return None;
+    }
+    let average_period = periods.iter().sum::<u64>() as f64 / periods.len() as f64;
+    let variance = periods
+        .iter()
+        .map(|period| (*period as f64 - average_period).powi(2))
+        .sum::<f64>()
+        / periods.len() as f64;
+    Some(VcdFrequency {
+        cycles: periods.len(),
+        min_period: *periods.iter().min()?,
+        max_period: *periods.iter().max()?,
+        average_period,
+        jitter: variance.sqrt(),
+    })
+}
+
+/// Skews from the edges of one 1-bit signal to the nearest edge of another
+#[derive(Clone, Debug, PartialEq)]
+pub struct VcdSkew {
+    // (edge of a, edge of b minus edge of a)
+    skews: Vec<(u64, i64)>,
+}
+
+impl VcdSkew {
+    /// Returns the skew of every edge of the first signal, positive when the
+    /// nearest edge of the second signal comes after it
+    pub fn get_skews(&self) -> &Vec<(u64, i64)> {
+        &self.skews
+    }
+
+    pub fn get_min(&self) -> i64 {
+        self.skews.iter().map(|(_, skew)| *skew).min().unwrap_or(0)
+    }
+
+    pub fn get_max(&self) -> i64 {
+        self.skews.iter().map(|(_, skew)| *skew).max().unwrap_or(0)
+    }
+
+    pub fn get_average(&self) -> f64 {
+        let total: i64 = self.skews.iter().map(|(_, skew)| *skew).sum();
+        total as f64 / self.skews.len().max(1) as f64
+    }
+
+    /// Returns how many edges had each skew, by skew
+    pub fn get_distribution(&self) -> BTreeMap<i64, usize> {
+        let mut distribution = BTreeMap::new();
+        for (_, skew) in &self.skews {
+            *distribution.entry(*skew).or_insert(0) += 1;
+        }
+        distribution
+    }
+}
+
+/// Measures the skew from each edge of a to the nearest edge of b of the same
+/// polarity, ties go to the earlier edge of b. Returns None if either signal
+/// is missing or wider than one bit.
+pub fn measure_skew(
+    waveform: &Waveform,
+    a: usize,
+    b: usize,
+    edge: VcdEdge,
+    range: Range<u64>,
+) -> Option<VcdSkew> {
+    let edges_a = iter_edges(waveform, a, edge, range.clone())?;
+    let edges_b: Vec<u64> = iter_edges(waveform, b, edge, range)?.collect();
+    let mut skews = Vec::new();
+    let mut next = 0;
+    for timestamp in edges_a {
+        // Both lists are sorted so the nearest edge of b only moves forward
+        while next < edges_b.len() && edges_b[next] < timestamp {
+            next += 1;
+        }
+        let before = next.checked_sub(1).map(|i| edges_b[i]);
+        let nearest = match (before, edges_b.get(next)) {
+            (Some(before), Some(after)) if after - timestamp < timestamp - before => *after,
+            (Some(before), _) => before,
+            (None, Some(after)) => *after,
+            (None, None) => break,
+        };
+        skews.push((timestamp, nearest as i64 - timestamp as i64));
+    }
+    Some(VcdSkew { skews })
+}
@@ -0,0 +1,180 @@
+use makai_waveform_db::Waveform;
+
+use crate::analysis::edges::{iter_edges, VcdEdge};
+use crate::analysis::get_u64_history;
+use crate::parser::VcdHeader;
+
+/// Where the occupancy of a FIFO comes from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VcdFifoSource<'a> {
+    // 1-bit push and pop strobes, sampled on every rising edge of a clock
+    Strobes {
+        clock: &'a str,
+        push: &'a str,
+        pop: &'a str,
+    },
+    // A vector signal holding the number of entries
+    Counter {
+        occupancy: &'a str,
+    },
+}
+
+// Steps through the changes of a 1-bit signal to find its level just before
+// each of an increasing series of timestamps
+struct VcdSampler {
+    history: Vec<(u64, Option<u64>)>,
+    next: usize,
+    level: bool,
+}
+
+impl VcdSampler {
+    fn new(header: &VcdHeader, waveform: &Waveform, path: &str) -> Option<Self> {
+        let variable = header.get_variable(path)?;
+        if waveform
+            .get_vector_signal(variable.get_idcode())?
+            .get_width()
+            != 1
+        {
+            return None;
+        }
+        Some(Self {
+            history: get_u64_history(waveform, variable.get_idcode())?,
+            next: 0,
+            level: false,
+        })
+    }
+
+    // Unknown and high-impedance levels count as low
+    fn sample_before(&mut self, timestamp: u64) -> bool {
+        while let Some((change, value)) = self.history.get(self.next) {
+            if *change >= timestamp {
+                break;
+            }
+            self.level = *value == Some(1);
+            self.next += 1;
+        }
+        self.level
+    }
+}
+
+/// Number of entries in a FIFO over time, along with the deepest it got
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VcdFifoOccupancy {
+    // Occupancy every time it changed, starting from the first known value
+    occupancy: Vec<(u64, u64)>,
+    max_depth: u64,
+    max_timestamp: Option<u64>,
+    // Clock edges that popped an empty FIFO, only found from strobes
+    underflows: Vec<u64>,
+    end: u64,
+}
+
+impl VcdFifoOccupancy {
+    /// Tracks a FIFO, returns None if a path is not declared or a strobe or
+    /// clock is not a 1-bit signal
+    pub fn new(header: &VcdHeader, waveform: &Waveform, source: VcdFifoSource) -> Option<Self> {
+        let mut fifo = Self {
+            end: waveform.get_timestamps().last().copied().unwrap_or(0),
+            ..Default::default()
+        };
+        match source {
+            VcdFifoSource::Strobes { clock, push, pop } => {
+                let clock = header.get_variable(clock)?.get_idcode();
+                let mut push = VcdSampler::new(header, waveform, push)?;
+                let mut pop = VcdSampler::new(header, waveform, pop)?;
+                let start = waveform.get_timestamps().first().copied().unwrap_or(0);
+                fifo.record(start, 0);
+                let mut depth = 0u64;
+                for edge in iter_edges(waveform, clock, VcdEdge::Rising, 0..u64::MAX)? {
+                    // Strobes are sampled as the flops would see them, before
+                    // anything the edge itself changed
+                    match (push.sample_before(edge), pop.sample_before(edge)) {
+                        (true, false) => depth += 1,
+                        (false, true) if depth == 0 => fifo.underflows.push(edge),
+                        (false, true) => depth -= 1,
+                        _ => continue,
+                    }
+                    fifo.record(edge, depth);
+                }
+            }
+            VcdFifoSource::Counter { occupancy } => {
+                let idcode = header.get_variable(occupancy)?.get_idcode();
+                for (timestamp, value) in get_u64_history(waveform, idcode)? {
+                    if let Some(depth) = value {
+                        fifo.record(timestamp, depth);
+                    }
+                }
+            }
+        }
+        Some(fifo)
+    }
+
+    fn record(&mut self, timestamp: u64, depth: u64) {
+        if matches!(self.occupancy.last(), Some((_, last)) if *last == depth) {
+            return;
+        }
+        if depth > self.max_depth || self.max_timestamp.is_none() {
+            self.max_depth = depth;
+            self.max_timestamp = Some(timestamp);
+        }
+        self.occupancy.push((timestamp, depth));
+    }
+
+    /// Returns the occupancy every time it changed as (timestamp, entries)
+    pub fn get_occupancy(&self) -> &[(u64, u64)] {
+        &self.occupancy
+    }
+
+    /// Returns the occupancy at a timestamp, None before the first known value
+    pub fn get_occupancy_at(&self, timestamp: u64) -> Option<u64> {
+        let index = self
+            .occupancy
+            .partition_point(|(change, _)| *change <= timestamp);
+        Some(self.occupancy.get(index.checked_sub(1)?)?.1)
+    }
+
+    pub fn get_max_depth(&self) -> u64 {
+        self.max_depth
+    }
+
+    /// Returns when the maximum depth was first reached
+    pub fn get_max_timestamp(&self) -> Option<u64> {
+        self.max_timestamp
+    }
+
+    pub fn get_underflows(&self) -> &[u64] {
+        &self.underflows
+    }
+
+    /// Average occupancy weighted by time, up to the last timestamp of the
+    /// waveform
+    pub fn get_average(&self) -> f64 {
+        let start = match self.occupancy.first() {
+            Some((start, _)) if *start < self.end => *start,
+            _ => return 0.0,
+        };
+        let mut total = 0u128;
+        for (i, (timestamp, depth)) in self.occupancy.iter().enumerate() {
+            let next = self
+                .occupancy
+                .get(i + 1)
+                .map_or(self.end, |(next, _)| *next);
+            total += (next - timestamp) as u128 * *depth as u128;
+        }
+        total as f64 / (self.end - start) as f64
+    }
+}
+
+impl std::fmt::Display for VcdFifoOccupancy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.max_timestamp {
+            Some(timestamp) => writeln!(f, "Max depth: {} at {}", self.max_depth, timestamp)?,
+            None => writeln!(f, "Max depth: 0")?,
+        }
+        writeln!(f, "Average occupancy: {:.3}", self.get_average())?;
+        if !self.underflows.is_empty() {
+            writeln!(f, "Underflows: {}", self.underflows.len())?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+
+use makai_waveform_db::bitvector::Logic;
+use makai_waveform_db::{Waveform, WaveformSignalResult};
+
+use crate::analysis::{change_timestamps, signal_changes};
+use crate::parser::{VcdEntry, VcdHeader, VcdScope, VcdVariableWidth};
+
+// FNV-1a, which unlike the standard library hashers gives the same hash across
+// platforms and compiler versions
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct VcdHasher {
+    hash: u64,
+}
+
+impl Default for VcdHasher {
+    fn default() -> Self {
+        Self {
+            hash: 0xcbf29ce484222325,
+        }
+    }
+}
+
+impl VcdHasher {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    #[inline]
+    pub(crate) fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.hash = (self.hash ^ *byte as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+
+    pub(crate) fn write_u64(&mut self, value: u64) {
+        self.write(&value.to_le_bytes());
+    }
+
+    // Strings are prefixed with their length so concatenations cannot collide
+    pub(crate) fn write_str(&mut self, value: &str) {
+        self.write_u64(value.len() as u64);
+        self.write(value.as_bytes());
+    }
+
+    pub(crate) fn write_bit(&mut self, bit: Logic) {
+        self.write(bit.to_str().as_bytes());
+    }
+
+    pub(crate) fn finish(&self) -> u64 {
+        self.hash
+    }
+}
+
+fn hash_changes(waveform: &Waveform, idcode: usize) -> u64 {
+    let mut hasher = VcdHasher::new();
+    // Real values cannot be read back, so only when they changed is hashed
+    let Ok(changes) = signal_changes(waveform, idcode) else {
+        for timestamp in change_timestamps(waveform, idcode) {
+            hasher.write_u64(timestamp);
+        }
+        return hasher.finish();
+    };
+    for (timestamp, entry) in changes {
+        hasher.write_u64(timestamp);
+        if let VcdEntry::Vector(bv, _) = entry {
+            hasher.write_u64(bv.get_bit_width() as u64);
+            for bit in bv.iter() {
+                hasher.write_bit(bit);
+            }
+        }
+    }
+    hasher.finish()
+}
+
+fn hash_scope(
+    scope: &VcdScope,
+    waveform: &Waveform,
+    changes: &mut HashMap<usize, u64>,
+    hasher: &mut VcdHasher,
+) {
+    hasher.write(scope.get_type().to_byte_str());
+    hasher.write_str(scope.get_name());
+    hasher.write_u64(scope.get_variables().len() as u64);
+    for variable in scope.get_variables() {
+        hasher.write(variable.get_net_type().to_byte_str());
+        hasher.write_str(variable.get_name());
+        match variable.get_width() {
+            VcdVariableWidth::Vector { width } => hasher.write_u64(*width as u64),
+            VcdVariableWidth::Real => hasher.write(b"r"),
+            VcdVariableWidth::Event => hasher.write(b"e"),
+            VcdVariableWidth::String => hasher.write(b"s"),
+        }
+        // Aliased variables share a history, which is only hashed once
+        let idcode = variable.get_idcode();
+        let hash = *changes
+            .entry(idcode)
+            .or_insert_with(|| hash_changes(waveform, idcode));
+        hasher.write_u64(hash);
+    }
+    hasher.write_u64(scope.get_scopes().len() as u64);
+    for child in scope.get_scopes() {
+        hash_scope(child, waveform, changes, hasher);
+    }
+}
+
+/// Returns a hash of the hierarchy and every value change of a dump, which
+/// does not depend on the idcodes the simulator chose or how it was loaded.
+/// Equal dumps always have equal fingerprints, so different fingerprints are
+/// enough to know two dumps differ without comparing them. Like
+/// waveform_digest, only the timestamps of real changes are hashed.
+pub fn fingerprint(header: &VcdHeader, waveform: &Waveform) -> u64 {
+    let mut hasher = VcdHasher::new();
+    let mut changes = HashMap::new();
+    match header.get_timescale() {
+        Some(timescale) => hasher.write(&timescale.to_le_bytes()),
+        None => hasher.write(b"none"),
+    }
+    hasher.write_u64(header.get_scopes().len() as u64);
+    for scope in header.get_scopes() {
+        hash_scope(scope, waveform, &mut changes, &mut hasher);
+    }
+    hasher.write_u64(waveform.get_timestamps().len() as u64);
+    for timestamp in waveform.get_timestamps() {
+        hasher.write_u64(*timestamp);
+    }
+    hasher.finish()
+}
+
+/// Returns a hash of everything stored in a waveform, its timestamps and the
+/// history of every declared signal in idcode order, unlike fingerprint it
+/// depends on the idcodes. Waveforms with equal contents have equal digests,
+/// only the timestamps of real changes are hashed since their values cannot
+/// be read back.
+pub fn waveform_digest(header: &VcdHeader, waveform: &Waveform) -> u64 {
+    let mut hasher = VcdHasher::new();
+    hasher.write_u64(waveform.get_timestamps().len() as u64);
+    for timestamp in waveform.get_timestamps() {
+        hasher.write_u64(*timestamp);
+    }
+    let mut idcodes: Vec<usize> = header.get_idcodes_map().keys().copied().collect();
+    idcodes.sort_unstable();
+    for idcode in idcodes {
+        hasher.write_u64(idcode as u64);
+        match waveform.get_signal(idcode) {
+            Some(WaveformSignalResult::Vector(signal)) => {
+                hasher.write_u64(signal.get_width() as u64);
+                hasher.write_u64(signal.len() as u64);
+                if !signal.is_empty() {
+                    for index in signal.get_history() {
+                        hasher.write_u64(index.get_timestamp_index() as u64);
+                        for bit in signal.get_bitvector(index.get_value_index()).iter() {
+                            hasher.write_bit(bit);
+                        }
+                    }
+                }
+            }
+            Some(WaveformSignalResult::Real(signal)) => {
+                hasher.write(b"r");
+                hasher.write_u64(signal.len() as u64);
+                if !signal.is_empty() {
+                    for index in signal.get_history() {
+                        hasher.write_u64(index.get_timestamp_index() as u64);
+                    }
+                }
+            }
+            None => hasher.write(b"none"),
+        }
+    }
+    hasher.finish()
+}
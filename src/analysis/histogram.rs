@@ -0,0 +1,111 @@
+use std::collections::BTreeMap;
+
+use makai_waveform_db::Waveform;
+
+use crate::analysis::{bitvector_to_u64, vector_changes};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VcdHistogramWeight {
+    // Every change of the signal counts once
+    Occurrences,
+    // Every value is weighted by the time the signal spent holding it
+    Duration,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VcdHistogram {
+    weight: VcdHistogramWeight,
+    bins: BTreeMap<u64, u64>,
+    // Weight of values containing unknown or high-impedance bits
+    unknown: u64,
+}
+
+impl VcdHistogram {
+    /// Builds the distribution of values taken by a vector signal of up to 64
+    /// bits, returns None if the idcode is not a narrow enough vector signal
+    pub fn new(waveform: &Waveform, idcode: usize, weight: VcdHistogramWeight) -> Option<Self> {
+        let signal = waveform.get_vector_signal(idcode)?;
+        if signal.get_width() > u64::BITS as usize {
+            return None;
+        }
+        let end = waveform.get_timestamps().last().copied().unwrap_or(0);
+        let mut bins = BTreeMap::new();
+        let mut unknown = 0;
+        let mut changes = vector_changes(waveform, signal).peekable();
+        while let Some((timestamp, bv)) = changes.next() {
+            let amount = match weight {
+                VcdHistogramWeight::Occurrences => 1,
+                VcdHistogramWeight::Duration => {
+                    changes.peek().map(|(next, _)| *next).unwrap_or(end) - timestamp
+                }
+            };
+            match bitvector_to_u64(&bv) {
+                Some(value) => *bins.entry(value).or_insert(0) += amount,
+                None => unknown += amount,
+            }
+        }
+        Some(Self {
+            weight,
+            bins,
+            unknown,
+        })
+    }
+
+    pub fn get_weight(&self) -> VcdHistogramWeight {
+        self.weight
+    }
+
+    pub fn get_bins(&self) -> &BTreeMap<u64, u64> {
+        &self.bins
+    }
+
+    pub fn get_unknown(&self) -> u64 {
+        self.unknown
+    }
+
+    pub fn get_total(&self) -> u64 {
+        self.bins.values().sum::<u64>() + self.unknown
+    }
+
+    /// Fraction of the total weight held by the given value, from 0.0 to 1.0
+    pub fn get_fraction(&self, value: u64) -> f64 {
+        let total = self.get_total();
+        if total == 0 {
+            0.0
+        } else {
+            *self.bins.get(&value).unwrap_or(&0) as f64 / total as f64
+        }
+    }
+
+    /// Returns the value with the largest weight and that weight
+    pub fn get_most_common(&self) -> Option<(u64, u64)> {
+        self.bins
+            .iter()
+            .max_by_key(|(_, weight)| **weight)
+            .map(|(value, weight)| (*value, *weight))
+    }
+}
+
+impl std::fmt::Display for VcdHistogram {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let total = self.get_total().max(1) as f64;
+        for (value, weight) in &self.bins {
+            writeln!(
+                f,
+                "{:#x}: {} ({:.1}%)",
+                value,
+                weight,
+                *weight as f64 * 100.0 / total
+            )?;
+        }
+        if self.unknown > 0 {
+            writeln!(
+                f,
+                "x/z: {} ({:.1}%)",
+                self.unknown,
+                self.unknown as f64 * 100.0 / total
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,174 @@
+use makai_waveform_db::Waveform;
+
+use crate::analysis::{signal_changes, AnalysisError, AnalysisResult};
+use crate::parser::{VcdEntry, VcdHeader};
+
+// Compares the values of two changes, ignoring their idcodes
+pub(crate) fn is_same_value(a: &VcdEntry, b: &VcdEntry) -> bool {
+    match (a, b) {
+        (VcdEntry::Scalar(a, _), VcdEntry::Scalar(b, _)) => a == b,
+        (VcdEntry::Vector(a, _), VcdEntry::Vector(b, _)) => a == b,
+        (VcdEntry::Real(a, _), VcdEntry::Real(b, _)) => a == b,
+        (VcdEntry::Event(_), VcdEntry::Event(_)) => true,
+        _ => false,
+    }
+}
+
+// Compares two changes by timestamp and value, ignoring their idcodes
+fn is_same_change(a: &(u64, VcdEntry), b: &(u64, VcdEntry)) -> bool {
+    a.0 == b.0 && is_same_value(&a.1, &b.1)
+}
+
+/// Returns the first timestamp at which two change histories differ, either
+/// by value or because one of them has a change the other does not
+pub fn first_divergence(a: &[(u64, VcdEntry)], b: &[(u64, VcdEntry)]) -> Option<u64> {
+    if let Some((a, b)) = a.iter().zip(b).find(|(a, b)| !is_same_change(a, b)) {
+        return Some(a.0.min(b.0));
+    }
+    let shorter = a.len().min(b.len());
+    a.get(shorter)
+        .or_else(|| b.get(shorter))
+        .map(|change| change.0)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VcdOverlaySignal {
+    path: String,
+    idcode_a: usize,
+    idcode_b: usize,
+    comparable: bool,
+    first_divergence: Option<u64>,
+}
+
+impl VcdOverlaySignal {
+    pub fn get_path(&self) -> &String {
+        &self.path
+    }
+
+    pub fn get_idcode_a(&self) -> usize {
+        self.idcode_a
+    }
+
+    pub fn get_idcode_b(&self) -> usize {
+        self.idcode_b
+    }
+
+    /// Returns false for real signals with changes, whose values cannot be
+    /// read back to compare
+    pub fn is_comparable(&self) -> bool {
+        self.comparable
+    }
+
+    /// Returns the first timestamp at which the two runs disagree, or None
+    /// if the signal is identical in both or is not comparable
+    pub fn get_first_divergence(&self) -> Option<u64> {
+        self.first_divergence
+    }
+}
+
+/// Pairs the variables of two loaded dumps by path so two runs of the same
+/// design can be viewed and compared side by side
+pub struct VcdOverlay<'a> {
+    waveform_a: &'a Waveform,
+    waveform_b: &'a Waveform,
+    signals: Vec<VcdOverlaySignal>,
+    only_a: Vec<String>,
+    only_b: Vec<String>,
+}
+
+impl<'a> VcdOverlay<'a> {
+    pub fn new(
+        header_a: &VcdHeader,
+        waveform_a: &'a Waveform,
+        header_b: &VcdHeader,
+        waveform_b: &'a Waveform,
+    ) -> AnalysisResult<Self> {
+        let mut signals = Vec::new();
+        let mut only_a = Vec::new();
+        for (path, variable_a) in header_a.get_variables_with_paths() {
+            let Some(variable_b) = header_b.get_variable(&path) else {
+                only_a.push(path);
+                continue;
+            };
+            let (comparable, first_divergence) = match (
+                signal_changes(waveform_a, variable_a.get_idcode()),
+                signal_changes(waveform_b, variable_b.get_idcode()),
+            ) {
+                (Ok(changes_a), Ok(changes_b)) => (true, first_divergence(&changes_a, &changes_b)),
+                // A real signal is reported rather than failing the overlay
+                (Err(AnalysisError::UnreadableReal(_)), _)
+                | (_, Err(AnalysisError::UnreadableReal(_))) => (false, None),
+                (Err(err), _) | (_, Err(err)) => return Err(err),
+            };
+            signals.push(VcdOverlaySignal {
+                path,
+                idcode_a: variable_a.get_idcode(),
+                idcode_b: variable_b.get_idcode(),
+                comparable,
+                first_divergence,
+            });
+        }
+        let only_b = header_b
+            .get_variables_with_paths()
+            .into_iter()
+            .filter(|(path, _)| header_a.get_variable(path).is_none())
+            .map(|(path, _)| path)
+            .collect();
+        Ok(Self {
+            waveform_a,
+            waveform_b,
+            signals,
+            only_a,
+            only_b,
+        })
+    }
+
+    /// Returns the variables found in both dumps, in the declaration order
+    /// of the first dump
+    pub fn get_signals(&self) -> &Vec<VcdOverlaySignal> {
+        &self.signals
+    }
+
+    pub fn get_signal(&self, path: &str) -> Option<&VcdOverlaySignal> {
+        self.signals.iter().find(|signal| signal.path == path)
+    }
+
+    pub fn get_only_in_a(&self) -> &Vec<String> {
+        &self.only_a
+    }
+
+    pub fn get_only_in_b(&self) -> &Vec<String> {
+        &self.only_b
+    }
+
+    /// Returns the paired signals whose values cannot be compared, see
+    /// VcdOverlaySignal::is_comparable
+    pub fn get_incomparable(&self) -> Vec<&VcdOverlaySignal> {
+        self.signals
+            .iter()
+            .filter(|signal| !signal.comparable)
+            .collect()
+    }
+
+    /// Returns the paired signals that differ, ordered by the time they
+    /// first diverge
+    pub fn get_divergent(&self) -> Vec<&VcdOverlaySignal> {
+        let mut divergent: Vec<&VcdOverlaySignal> = self
+            .signals
+            .iter()
+            .filter(|signal| signal.first_divergence.is_some())
+            .collect();
+        divergent.sort_by_key(|signal| signal.first_divergence);
+        divergent
+    }
+
+    /// Returns the change history of a signal in the first dump
+    pub fn get_history_a(&self, signal: &VcdOverlaySignal) -> AnalysisResult<Vec<(u64, VcdEntry)>> {
+        signal_changes(self.waveform_a, signal.idcode_a)
+    }
+
+    /// Returns the change history of a signal in the second dump
+    pub fn get_history_b(&self, signal: &VcdOverlaySignal) -> AnalysisResult<Vec<(u64, VcdEntry)>> {
+        signal_changes(self.waveform_b, signal.idcode_b)
+    }
+}
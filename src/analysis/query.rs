@@ -0,0 +1,227 @@
+use std::time::Duration;
+
+use makai_waveform_db::{Waveform, WaveformSearchMode, WaveformSignalResult, WaveformValueResult};
+
+use crate::parser::{VcdHeader, VcdVariable};
+
+// Time units and their length in femtoseconds, "s" is last so "ms" is not
+// read as seconds
+const UNITS: [(&str, u128); 6] = [
+    ("fs", 1),
+    ("ps", 1_000),
+    ("ns", 1_000_000),
+    ("us", 1_000_000_000),
+    ("ms", 1_000_000_000_000),
+    ("s", 1_000_000_000_000_000),
+];
+
+/// A point in simulation time, either a duration or text like "12.5us"
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VcdTime {
+    Duration(Duration),
+    Text(String),
+}
+
+impl From<Duration> for VcdTime {
+    fn from(duration: Duration) -> Self {
+        Self::Duration(duration)
+    }
+}
+
+impl From<&str> for VcdTime {
+    fn from(text: &str) -> Self {
+        Self::Text(text.to_string())
+    }
+}
+
+impl VcdTime {
+    /// Returns the time in femtoseconds, None if the text is not a number
+    /// followed by a unit or is finer than a femtosecond
+    pub fn to_femtoseconds(&self) -> Option<u128> {
+        match self {
+            Self::Duration(duration) => Some(duration.as_nanos() * 1_000_000),
+            Self::Text(text) => parse_femtoseconds(text.trim()),
+        }
+    }
+}
+
+fn parse_femtoseconds(text: &str) -> Option<u128> {
+    let (number, scale) = UNITS.iter().find_map(|(unit, scale)| {
+        text.strip_suffix(unit)
+            .map(|number| (number.trim_end(), *scale))
+    })?;
+    let (whole, fraction) = match number.split_once('.') {
+        Some((whole, fraction)) => (whole, fraction),
+        None => (number, ""),
+    };
+    if (whole.is_empty() && fraction.is_empty())
+        || !whole
+            .bytes()
+            .chain(fraction.bytes())
+            .all(|b| b.is_ascii_digit())
+    {
+        return None;
+    }
+    let mut femtoseconds = if whole.is_empty() {
+        0
+    } else {
+        whole.parse::<u128>().ok()?.checked_mul(scale)?
+    };
+    // Each fractional digit is worth a tenth of the one before it
+    let mut digit_scale = scale;
+    for digit in fraction.bytes().map(|b| (b - b'0') as u128) {
+        if digit_scale % 10 != 0 {
+            if digit != 0 {
+                return None;
+            }
+            continue;
+        }
+        digit_scale /= 10;
+        femtoseconds = femtoseconds.checked_add(digit * digit_scale)?;
+    }
+    Some(femtoseconds)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VcdQueryError {
+    UnknownPath(String),
+    InvalidTime(VcdTime),
+    // The header has no $timescale to convert times with
+    MissingTimescale,
+    // The signal has no value at or before the time
+    NoValue,
+    // The signal (by idcode) is real, whose values cannot be read back
+    UnreadableReal(usize),
+}
+
+impl std::fmt::Display for VcdQueryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownPath(path) => write!(f, "no variable declared at {}", path),
+            Self::InvalidTime(VcdTime::Text(text)) => write!(f, "invalid time {:?}", text),
+            Self::InvalidTime(VcdTime::Duration(duration)) => {
+                write!(f, "invalid time {:?}", duration)
+            }
+            Self::MissingTimescale => write!(f, "no timescale to convert times with"),
+            Self::NoValue => write!(f, "no value at or before the time"),
+            Self::UnreadableReal(idcode) => {
+                write!(
+                    f,
+                    "the values of real signal {} cannot be read back",
+                    idcode
+                )
+            }
+        }
+    }
+}
+
+/// Converts a time to a timestamp using the header timescale, rounding down
+/// to the tick the time falls in
+pub fn time_to_timestamp(
+    header: &VcdHeader,
+    time: impl Into<VcdTime>,
+) -> Result<u64, VcdQueryError> {
+    let time = time.into();
+    let timescale = header
+        .get_timescale()
+        .ok_or(VcdQueryError::MissingTimescale)?;
+    let femtoseconds = time
+        .to_femtoseconds()
+        .ok_or_else(|| VcdQueryError::InvalidTime(time.clone()))?;
+    // Timescales are 10^-timescale seconds, no finer than a femtosecond
+    let tick = 10u128.pow((15 - timescale).max(0) as u32);
+    Ok(u64::try_from(femtoseconds / tick).unwrap_or(u64::MAX))
+}
+
+// Returns the timestamp index at or before a timestamp, None if the waveform
+// starts after it
+fn search_timestamp_index(waveform: &Waveform, timestamp: u64) -> Option<usize> {
+    // Searching an empty waveform underflows
+    if waveform.get_timestamps().is_empty() {
+        return None;
+    }
+    waveform.search_timestamp(timestamp, WaveformSearchMode::Before)
+}
+
+// Returns whether a signal is real and has changes, whose values would have
+// to be read back to be formatted
+fn is_unreadable_real(waveform: &Waveform, idcode: usize) -> bool {
+    matches!(
+        waveform.get_signal(idcode),
+        Some(WaveformSignalResult::Real(signal)) if !signal.is_empty()
+    )
+}
+
+// Formats the value of a vector variable at a timestamp index, real
+// variables have to be checked with is_unreadable_real first
+fn format_value(waveform: &Waveform, variable: &VcdVariable, index: usize) -> Option<String> {
+    match waveform.search_value(variable.get_idcode(), index, WaveformSearchMode::Before) {
+        Some(WaveformValueResult::Vector(bv, _)) => Some(variable.get_interpretation().format(&bv)),
+        // A real signal without changes has no value to find
+        _ => None,
+    }
+}
+
+/// Returns the formatted value of the variable at a path like "top.cpu.pc"
+/// at a time like "12.5us", vectors are formatted by their interpretation
+pub fn search_value_at(
+    header: &VcdHeader,
+    waveform: &Waveform,
+    path: &str,
+    time: impl Into<VcdTime>,
+) -> Result<String, VcdQueryError> {
+    let variable = header
+        .get_variable(path)
+        .ok_or_else(|| VcdQueryError::UnknownPath(path.to_string()))?;
+    if is_unreadable_real(waveform, variable.get_idcode()) {
+        return Err(VcdQueryError::UnreadableReal(variable.get_idcode()));
+    }
+    let timestamp = time_to_timestamp(header, time)?;
+    search_timestamp_index(waveform, timestamp)
+        .and_then(|index| format_value(waveform, variable, index))
+        .ok_or(VcdQueryError::NoValue)
+}
+
+/// Returns the formatted values of many variables at one time, paths are
+/// resolved and the timestamp is searched for once. Values are None for
+/// paths that are not declared or have no value yet, and for real signals
+/// whose values cannot be read back, so only an invalid time is an error.
+pub fn values_at(
+    header: &VcdHeader,
+    waveform: &Waveform,
+    paths: &[&str],
+    time: impl Into<VcdTime>,
+) -> Result<Vec<Option<String>>, VcdQueryError> {
+    let mut rows = values_over(header, waveform, paths, [time])?;
+    Ok(rows.pop().unwrap_or_default())
+}
+
+/// Returns the formatted values of many variables at many times, one row of
+/// values in path order for each time
+pub fn values_over<T: Into<VcdTime>>(
+    header: &VcdHeader,
+    waveform: &Waveform,
+    paths: &[&str],
+    times: impl IntoIterator<Item = T>,
+) -> Result<Vec<Vec<Option<String>>>, VcdQueryError> {
+    let variables: Vec<Option<&VcdVariable>> = paths
+        .iter()
+        .map(|path| header.get_variable(path))
+        .map(|variable| {
+            variable.filter(|variable| !is_unreadable_real(waveform, variable.get_idcode()))
+        })
+        .collect();
+    let mut rows = Vec::new();
+    for time in times {
+        let index = search_timestamp_index(waveform, time_to_timestamp(header, time)?);
+        let mut row = Vec::new();
+        for variable in &variables {
+            row.push(match (variable, index) {
+                (Some(variable), Some(index)) => format_value(waveform, variable, index),
+                _ => None,
+            });
+        }
+        rows.push(row);
+    }
+    Ok(rows)
+}
@@ -0,0 +1,128 @@
+use std::collections::VecDeque;
+use std::ops::RangeInclusive;
+
+use makai_waveform_db::bitvector::Logic;
+use makai_waveform_db::Waveform;
+
+use crate::analysis::{trim_waveform, vector_changes, AnalysisResult};
+use crate::parser::{VcdHeader, VcdScope};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VcdResetPolarity {
+    ActiveHigh,
+    ActiveLow,
+}
+
+impl VcdResetPolarity {
+    // Guesses polarity from common naming conventions like rst_n or resetb
+    pub fn from_name(name: &str) -> Self {
+        let name = name.to_lowercase();
+        let suffixes = ["_n", "_b", "_l", "rstn", "resetn", "rstb", "resetb"];
+        if suffixes.iter().any(|suffix| name.ends_with(suffix)) {
+            Self::ActiveLow
+        } else {
+            Self::ActiveHigh
+        }
+    }
+
+    pub fn get_active(&self) -> Logic {
+        match self {
+            Self::ActiveHigh => Logic::One,
+            Self::ActiveLow => Logic::Zero,
+        }
+    }
+
+    pub fn get_inactive(&self) -> Logic {
+        match self {
+            Self::ActiveHigh => Logic::Zero,
+            Self::ActiveLow => Logic::One,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VcdReset {
+    idcode: usize,
+    polarity: VcdResetPolarity,
+    deassertion: u64,
+}
+
+fn is_reset_name(name: &str) -> bool {
+    let name = name.to_lowercase();
+    name.contains("rst") || name.contains("reset")
+}
+
+impl VcdReset {
+    /// Finds the first time a 1-bit reset signal is released after having
+    /// been asserted, returns None if the reset never deasserts
+    pub fn new(waveform: &Waveform, idcode: usize, polarity: VcdResetPolarity) -> Option<Self> {
+        let signal = waveform.get_vector_signal(idcode)?;
+        if signal.get_width() != 1 {
+            return None;
+        }
+        let mut asserted = false;
+        for (timestamp, bv) in vector_changes(waveform, signal) {
+            let bit = bv.get_bit(0);
+            if bit == polarity.get_active() {
+                asserted = true;
+            } else if asserted && bit == polarity.get_inactive() {
+                return Some(Self {
+                    idcode,
+                    polarity,
+                    deassertion: timestamp,
+                });
+            }
+        }
+        None
+    }
+
+    /// Looks up a reset signal by its path, guessing polarity from its name
+    pub fn from_path(header: &VcdHeader, waveform: &Waveform, path: &str) -> Option<Self> {
+        let variable = header.get_variable(path)?;
+        let polarity = VcdResetPolarity::from_name(variable.get_name());
+        Self::new(waveform, variable.get_idcode(), polarity)
+    }
+
+    /// Searches the hierarchy breadth-first for a reset-like 1-bit signal that
+    /// deasserts, so the shallowest matching reset wins
+    pub fn detect(header: &VcdHeader, waveform: &Waveform) -> Option<Self> {
+        let mut queue: VecDeque<&VcdScope> = header.get_scopes().iter().collect();
+        while let Some(scope) = queue.pop_front() {
+            for variable in scope.get_variables() {
+                if variable.get_bit_width() != 1 || !is_reset_name(variable.get_name()) {
+                    continue;
+                }
+                let polarity = VcdResetPolarity::from_name(variable.get_name());
+                if let Some(reset) = Self::new(waveform, variable.get_idcode(), polarity) {
+                    return Some(reset);
+                }
+            }
+            queue.extend(scope.get_scopes());
+        }
+        None
+    }
+
+    pub fn get_idcode(&self) -> usize {
+        self.idcode
+    }
+
+    pub fn get_polarity(&self) -> VcdResetPolarity {
+        self.polarity
+    }
+
+    pub fn get_deassertion(&self) -> u64 {
+        self.deassertion
+    }
+
+    /// Returns the first and last timestamps (both inclusive) after reset
+    pub fn get_post_reset_range(&self, waveform: &Waveform) -> RangeInclusive<u64> {
+        let end = waveform.get_timestamp_range().end;
+        self.deassertion..=end.max(self.deassertion)
+    }
+
+    /// Builds a copy of the waveform starting at the reset deassertion
+    pub fn trim(&self, header: &VcdHeader, waveform: &Waveform) -> AnalysisResult<Waveform> {
+        let range = self.get_post_reset_range(waveform);
+        trim_waveform(header, waveform, *range.start(), *range.end())
+    }
+}
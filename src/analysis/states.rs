@@ -0,0 +1,142 @@
+use std::collections::BTreeMap;
+
+use makai_waveform_db::Waveform;
+
+use crate::analysis::vector_changes;
+use crate::export::bitvector_to_string;
+use crate::parser::VcdHeader;
+
+/// Total time a vector signal spent holding each of its values, counted from
+/// its first change to the last timestamp of the waveform
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VcdTimeInState {
+    width: usize,
+    // Time in timescale units, keyed by the value as a binary string with any
+    // unknown or high-impedance bits kept
+    states: BTreeMap<String, u64>,
+    timescale: Option<i32>,
+}
+
+impl VcdTimeInState {
+    /// Returns None if the idcode is not a vector signal
+    pub fn new(header: &VcdHeader, waveform: &Waveform, idcode: usize) -> Option<Self> {
+        let signal = waveform.get_vector_signal(idcode)?;
+        let end = waveform.get_timestamps().last().copied().unwrap_or(0);
+        let mut states = BTreeMap::new();
+        let mut changes = vector_changes(waveform, signal).peekable();
+        while let Some((timestamp, bv)) = changes.next() {
+            let next = changes.peek().map(|(next, _)| *next).unwrap_or(end);
+            *states.entry(bitvector_to_string(&bv)).or_insert(0) += next - timestamp;
+        }
+        Some(Self {
+            width: signal.get_width(),
+            states,
+            timescale: *header.get_timescale(),
+        })
+    }
+
+    pub fn get_width(&self) -> usize {
+        self.width
+    }
+
+    pub fn get_states(&self) -> &BTreeMap<String, u64> {
+        &self.states
+    }
+
+    /// Returns the time spent at a value like "01x1", zero if never held
+    pub fn get_time(&self, state: &str) -> u64 {
+        *self.states.get(state).unwrap_or(&0)
+    }
+
+    /// Returns the time spent at a value in seconds, None without a timescale
+    pub fn get_seconds(&self, state: &str) -> Option<f64> {
+        Some(self.get_time(state) as f64 / 10f64.powi(self.timescale?))
+    }
+
+    pub fn get_total(&self) -> u64 {
+        self.states.values().sum()
+    }
+
+    /// Fraction of the total time spent at a value, from 0.0 to 1.0
+    pub fn get_fraction(&self, state: &str) -> f64 {
+        match self.get_total() {
+            0 => 0.0,
+            total => self.get_time(state) as f64 / total as f64,
+        }
+    }
+
+    /// Fraction of the total time a 1-bit signal spent high, None for wider
+    /// signals
+    pub fn get_duty_cycle(&self) -> Option<f64> {
+        (self.width == 1).then(|| self.get_fraction("1"))
+    }
+}
+
+impl std::fmt::Display for VcdTimeInState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (state, time) in &self.states {
+            write!(
+                f,
+                "{}: {} ({:.1}%)",
+                state,
+                time,
+                self.get_fraction(state) * 100.0
+            )?;
+            if let Some(seconds) = self.get_seconds(state) {
+                write!(f, " {:e}s", seconds)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Time in state of every vector variable up to a width, aliased variables
+/// are listed under each of their paths
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VcdTimeInStateReport {
+    signals: Vec<(String, VcdTimeInState)>,
+}
+
+impl VcdTimeInStateReport {
+    pub fn new(header: &VcdHeader, waveform: &Waveform, max_width: usize) -> Self {
+        let signals = header
+            .get_variables_with_paths()
+            .into_iter()
+            .filter_map(|(path, variable)| {
+                let states = VcdTimeInState::new(header, waveform, variable.get_idcode())?;
+                (states.get_width() > 0 && states.get_width() <= max_width)
+                    .then_some((path, states))
+            })
+            .collect();
+        Self { signals }
+    }
+
+    pub fn get_signals(&self) -> &[(String, VcdTimeInState)] {
+        &self.signals
+    }
+
+    pub fn get_signal(&self, path: &str) -> Option<&VcdTimeInState> {
+        self.signals
+            .iter()
+            .find(|(signal_path, _)| signal_path == path)
+            .map(|(_, states)| states)
+    }
+}
+
+impl std::fmt::Display for VcdTimeInStateReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (path, states) in &self.signals {
+            match states.get_duty_cycle() {
+                Some(duty_cycle) => {
+                    writeln!(f, "{} (duty cycle {:.1}%)", path, duty_cycle * 100.0)?
+                }
+                None => writeln!(f, "{}", path)?,
+            }
+            for line in states.to_string().lines() {
+                writeln!(f, "  {}", line)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,544 @@
+use std::collections::{BTreeMap, HashSet};
+use std::env;
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::process;
+use std::sync::{Arc, Mutex};
+
+use makai::utils::bytes::ByteStorage;
+use makai_waveform_db::Waveform;
+
+use makai_vcd_reader::analysis::activity::VcdActivityReport;
+use makai_vcd_reader::analysis::coverage::VcdCoverageReport;
+use makai_vcd_reader::analysis::fingerprint::fingerprint;
+use makai_vcd_reader::analysis::overlay::VcdOverlay;
+use makai_vcd_reader::analysis::states::VcdTimeInStateReport;
+use makai_vcd_reader::analysis::AnalysisError;
+use makai_vcd_reader::errors::TokenizerError;
+use makai_vcd_reader::export::dot::{to_dot, VcdDotOptions};
+use makai_vcd_reader::export::downsample::write_downsampled;
+use makai_vcd_reader::export::ndjson::VcdJsonExporter;
+use makai_vcd_reader::export::sqlite::write_sql;
+use makai_vcd_reader::format::open;
+use makai_vcd_reader::lexer::line_index::LineIndex;
+use makai_vcd_reader::lexer::position::LexerPosition;
+use makai_vcd_reader::lexer::scan::scan_stats;
+use makai_vcd_reader::lexer::Lexer;
+use makai_vcd_reader::parser::{VcdHeader, VcdReader};
+use makai_vcd_reader::tokenizer::token::Token;
+use makai_vcd_reader::tokenizer::Tokenizer;
+use makai_vcd_reader::utils::summary::VcdSummary;
+use makai_vcd_reader::utils::*;
+
+const USAGE: &str = "\
+usage: vcd-tool <command> [args...]
+
+commands:
+  info <file>                      print header summary
+  validate <file>                  load the file and report the first error
+  summary <file> [--json]          print change counts and a hash of the value changes
+  fingerprint <file>               print a hash of the hierarchy and value changes
+  scan <file>                      count tokens and timestamps without loading the file
+  idcodes <file>                   list idcodes with their encoding and variables
+  widths <file>                    list variables with values dumped at another width
+  stats <file> [window]            print toggle coverage and activity
+  states <file> [max-width]        print the time every narrow signal spent at each value
+  slice <file> <start> <end>       print the dump between two timestamps
+  downsample <file> <tick>         print the dump sampled at multiples of a tick
+  filter <file> <path-prefix>...   print the dump with only matching signals
+  convert <file> <format>          re-emit the dump in another format (vcd, sql, ndjson, dot)
+  diff <file-a> <file-b>           compare the value changes of two dumps";
+
+#[derive(Debug)]
+enum ToolError {
+    Usage(String),
+    Io(io::Error),
+    Vcd(VcdError),
+}
+
+impl From<io::Error> for ToolError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<VcdError> for ToolError {
+    fn from(err: VcdError) -> Self {
+        Self::Vcd(err)
+    }
+}
+
+impl From<AnalysisError> for ToolError {
+    fn from(err: AnalysisError) -> Self {
+        Self::Io(err.into())
+    }
+}
+
+impl From<TokenizerError> for ToolError {
+    fn from(err: TokenizerError) -> Self {
+        Self::Vcd(VcdError::Tokenizer(err))
+    }
+}
+
+impl From<LexerPosition> for ToolError {
+    fn from(pos: LexerPosition) -> Self {
+        Self::Vcd(VcdError::Lexer(pos))
+    }
+}
+
+impl std::fmt::Display for ToolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Usage(message) => write!(f, "{}\n\n{}", message, USAGE),
+            Self::Io(err) => write!(f, "{}", err),
+            Self::Vcd(err) => write!(f, "{:?}", err),
+        }
+    }
+}
+
+type ToolResult<T> = Result<T, ToolError>;
+
+fn get_arg<'a>(args: &'a [String], index: usize, name: &str) -> ToolResult<&'a String> {
+    args.get(index)
+        .ok_or_else(|| ToolError::Usage(format!("missing argument <{}>", name)))
+}
+
+fn parse_timestamp(arg: &str) -> ToolResult<u64> {
+    arg.parse::<u64>()
+        .map_err(|_| ToolError::Usage(format!("invalid timestamp '{}'", arg)))
+}
+
+fn load(path: &str) -> ToolResult<(VcdHeader, Waveform)> {
+    Ok(open(path)?)
+}
+
+fn format_timescale(timescale: i32) -> String {
+    let units = ["s", "ms", "us", "ns", "ps", "fs"];
+    let unit = ((timescale + 2) / 3) as usize;
+    match units.get(unit) {
+        Some(name) => format!(
+            "{}{}",
+            10u64.pow((unit as i32 * 3 - timescale) as u32),
+            name
+        ),
+        None => format!("1e-{}s", timescale),
+    }
+}
+
+// Streams every token of a file along with its raw source text
+fn for_each_token<F>(bytes: &str, f: &mut F) -> ToolResult<()>
+where
+    F: FnMut(&Token, &[u8], &ByteStorage) -> ToolResult<bool>,
+{
+    let mut lexer = Lexer::new(bytes);
+    let mut tokenizer = Tokenizer::new(bytes);
+    let mut bs = ByteStorage::new();
+    while let Some(token) = tokenizer.next(lexer.next_token()?, &mut bs)? {
+        let raw = &bytes.as_bytes()[token.get_position().get_range()];
+        if !f(&token, raw, &bs)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn info(args: &[String]) -> ToolResult<i32> {
+    let (header, waveform) = load(get_arg(args, 0, "file")?)?;
+    let variables = header.get_variables_with_paths();
+    let range = waveform.get_timestamp_range();
+    println!(
+        "Version: {}",
+        header.get_version().as_deref().unwrap_or("").trim()
+    );
+    println!(
+        "Date: {}",
+        header.get_date().as_deref().unwrap_or("").trim()
+    );
+    match header.get_timescale() {
+        Some(timescale) => println!("Timescale: {}", format_timescale(*timescale)),
+        None => println!("Timescale: unspecified"),
+    }
+    println!("Top-level scopes: {}", header.get_scopes().len());
+    println!("Variables: {}", variables.len());
+    println!("Idcodes: {}", header.get_idcodes_map().len());
+    println!(
+        "Timestamps: {} ({}..{})",
+        waveform.get_timestamps().len(),
+        range.start,
+        range.end
+    );
+    Ok(0)
+}
+
+fn validate(args: &[String]) -> ToolResult<i32> {
+    let bytes = fs::read_to_string(get_arg(args, 0, "file")?)?;
+    let line_index = LineIndex::new(&bytes);
+    match load_single_threaded(bytes.clone(), &mut |_| {}) {
+        Ok((header, waveform)) => {
+            println!(
+                "OK: {} variables, {} timestamps",
+                header.get_variables_with_paths().len(),
+                waveform.get_timestamps().len()
+            );
+            Ok(0)
+        }
+        Err(err) => {
+            match err.get_position() {
+                Some(pos) => {
+                    let pos = line_index.resolve_display(&bytes, &pos);
+                    println!(
+                        "Error at line {}, column {}: {:?}",
+                        pos.get_line(),
+                        pos.get_column(),
+                        err
+                    );
+                }
+                None => println!("Error: {:?}", err),
+            }
+            if let Some(repair) = err.get_repair() {
+                println!("Suggestion: {}", repair);
+            }
+            Ok(1)
+        }
+    }
+}
+
+fn summary(args: &[String]) -> ToolResult<i32> {
+    let bytes = fs::read_to_string(get_arg(args, 0, "file")?)?;
+    let json = match args.get(1).map(|arg| arg.as_str()) {
+        None => false,
+        Some("--json") => true,
+        Some(arg) => return Err(ToolError::Usage(format!("unknown option '{}'", arg))),
+    };
+    let summary = Arc::new(Mutex::new(VcdSummary::new()));
+    let mut options = VcdLoadOptions::new();
+    options.set_progress_steps(None);
+    options.set_summary(summary.clone());
+    load_single_threaded_with_options(bytes, options, &mut |_| {})?;
+    let summary = summary.lock().unwrap();
+    if json {
+        println!("{}", summary.to_json());
+    } else {
+        println!("{}", summary);
+    }
+    Ok(0)
+}
+
+fn fingerprint_file(args: &[String]) -> ToolResult<i32> {
+    let (header, waveform) = load(get_arg(args, 0, "file")?)?;
+    println!("{:016x}", fingerprint(&header, &waveform));
+    Ok(0)
+}
+
+fn scan(args: &[String]) -> ToolResult<i32> {
+    let bytes = fs::read_to_string(get_arg(args, 0, "file")?)?;
+    println!("{}", scan_stats(&bytes)?);
+    Ok(0)
+}
+
+fn idcodes(args: &[String]) -> ToolResult<i32> {
+    let bytes = fs::read_to_string(get_arg(args, 0, "file")?)?;
+    let mut lexer = Lexer::new(&bytes);
+    let mut tokenizer = Tokenizer::new(&bytes);
+    let mut parser = VcdReader::new();
+    parser
+        .parse_header(&mut |bs| tokenizer.next(lexer.next_token()?, bs))
+        .map_err(VcdError::from)?;
+    let mut paths: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+    for (path, variable) in parser.get_header().get_variables_with_paths() {
+        paths.entry(variable.get_idcode()).or_default().push(path);
+    }
+    let mut out = BufWriter::new(io::stdout().lock());
+    for (idcode, encoding, printable) in parser.get_idcodes() {
+        writeln!(
+            out,
+            "{}\t{}\t{:#x}\t{}",
+            printable,
+            encoding,
+            idcode,
+            paths
+                .get(&idcode)
+                .map(|paths| paths.join(","))
+                .unwrap_or_default()
+        )?;
+    }
+    out.flush()?;
+    Ok(0)
+}
+
+fn widths(args: &[String]) -> ToolResult<i32> {
+    let bytes = fs::read_to_string(get_arg(args, 0, "file")?)?;
+    let mut lexer = Lexer::new(&bytes);
+    let mut tokenizer = Tokenizer::new(&bytes);
+    let mut parser = VcdReader::new();
+    parser.set_width_reconciliation(true);
+    parser
+        .parse_header(&mut |bs| tokenizer.next(lexer.next_token()?, bs))
+        .map_err(VcdError::from)?;
+    while parser
+        .parse_waveform(&mut |bs| tokenizer.next(lexer.next_token()?, bs))
+        .map_err(VcdError::from)?
+        .is_some()
+    {}
+    let mut paths: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+    for (path, variable) in parser.get_header().get_variables_with_paths() {
+        paths.entry(variable.get_idcode()).or_default().push(path);
+    }
+    let mismatches = parser.get_width_mismatches();
+    for mismatch in &mismatches {
+        println!(
+            "{}: {}",
+            paths
+                .get(&mismatch.get_idcode())
+                .map(|paths| paths.join(","))
+                .unwrap_or_default(),
+            mismatch
+        );
+    }
+    Ok(if mismatches.is_empty() { 0 } else { 1 })
+}
+
+fn stats(args: &[String]) -> ToolResult<i32> {
+    let (header, waveform) = load(get_arg(args, 0, "file")?)?;
+    let range = waveform.get_timestamp_range();
+    let window = match args.get(1) {
+        Some(arg) => parse_timestamp(arg)?,
+        None => ((range.end - range.start) / 100).max(1),
+    };
+    print!("{}", VcdCoverageReport::new(&header, &waveform));
+    let activity = VcdActivityReport::new(&header, &waveform, window);
+    println!("Changes per time unit: {:.3}", activity.get_change_rate());
+    if let Some(idle) = activity.get_longest_idle() {
+        println!("Longest idle: {}..{}", idle.start, idle.end);
+    }
+    if let Some(busiest) = activity.get_busiest() {
+        println!("Busiest window: {}..{}", busiest.start, busiest.end);
+    }
+    Ok(0)
+}
+
+fn states(args: &[String]) -> ToolResult<i32> {
+    let (header, waveform) = load(get_arg(args, 0, "file")?)?;
+    let max_width = match args.get(1) {
+        Some(arg) => arg
+            .parse()
+            .map_err(|_| ToolError::Usage(format!("invalid width '{}'", arg)))?,
+        None => 8,
+    };
+    print!(
+        "{}",
+        VcdTimeInStateReport::new(&header, &waveform, max_width)
+    );
+    Ok(0)
+}
+
+fn write_initial_values(
+    out: &mut dyn Write,
+    start: u64,
+    latest: &BTreeMap<Vec<u8>, Vec<u8>>,
+) -> io::Result<()> {
+    writeln!(out, "#{}\n$dumpvars", start)?;
+    for value in latest.values() {
+        out.write_all(value)?;
+        out.write_all(b"\n")?;
+    }
+    writeln!(out, "$end")
+}
+
+fn slice(args: &[String]) -> ToolResult<i32> {
+    let bytes = fs::read_to_string(get_arg(args, 0, "file")?)?;
+    let start = parse_timestamp(get_arg(args, 1, "start")?)?;
+    let end = parse_timestamp(get_arg(args, 2, "end")?)?;
+    let mut out = BufWriter::new(io::stdout().lock());
+    let mut in_header = true;
+    let mut started = false;
+    // Latest raw value change of every idcode up to and including the start
+    let mut latest: BTreeMap<Vec<u8>, Vec<u8>> = BTreeMap::new();
+    for_each_token(&bytes, &mut |token, raw, bs| {
+        if in_header {
+            in_header = !matches!(token, Token::EndDefinitions(_));
+            out.write_all(raw)?;
+            out.write_all(b"\n")?;
+            return Ok(true);
+        }
+        match token {
+            Token::Timestamp(timestamp, _) if !started && *timestamp > start => {
+                started = true;
+                write_initial_values(&mut out, start, &latest)?;
+                if *timestamp > end {
+                    return Ok(false);
+                }
+                writeln!(out, "#{}", timestamp)?;
+            }
+            Token::Timestamp(timestamp, _) if started && *timestamp > end => return Ok(false),
+            Token::ScalarValue(_, idcode, _)
+            | Token::VectorValue(_, idcode, _)
+            | Token::RealValue(_, idcode, _)
+            | Token::StringValue(_, idcode, _)
+            | Token::PortValue(_, idcode, _)
+                if !started =>
+            {
+                latest.insert(idcode.get_bytes(bs), raw.to_vec());
+            }
+            _ if !started => {}
+            _ => {
+                out.write_all(raw)?;
+                out.write_all(b"\n")?;
+            }
+        }
+        Ok(true)
+    })?;
+    // The dump ended before passing the start of the slice
+    if !started && !latest.is_empty() {
+        write_initial_values(&mut out, start, &latest)?;
+    }
+    out.flush()?;
+    Ok(0)
+}
+
+fn downsample(args: &[String]) -> ToolResult<i32> {
+    let (header, waveform) = load(get_arg(args, 0, "file")?)?;
+    let tick = parse_timestamp(get_arg(args, 1, "tick")?)?;
+    let mut out = BufWriter::new(io::stdout().lock());
+    write_downsampled(&header, &waveform, tick, &mut out)?;
+    out.flush()?;
+    Ok(0)
+}
+
+fn filter(args: &[String]) -> ToolResult<i32> {
+    let bytes = fs::read_to_string(get_arg(args, 0, "file")?)?;
+    let prefixes = &args[1..];
+    if prefixes.is_empty() {
+        return Err(ToolError::Usage(
+            "missing argument <path-prefix>".to_string(),
+        ));
+    }
+    let mut out = BufWriter::new(io::stdout().lock());
+    let mut scopes: Vec<String> = Vec::new();
+    let mut idcodes: HashSet<Vec<u8>> = HashSet::new();
+    for_each_token(&bytes, &mut |token, raw, bs| {
+        let keep = match token {
+            Token::Scope { scope_id, .. } => {
+                scopes.push(String::from_utf8_lossy(&bs.get_bytes(*scope_id)).to_string());
+                true
+            }
+            Token::UpScope(_) => {
+                scopes.pop();
+                true
+            }
+            Token::Var {
+                token_idcode,
+                variable_description,
+                ..
+            } => {
+                let name = bs.get_bytes(variable_description.get_id());
+                let path = format!("{}.{}", scopes.join("."), String::from_utf8_lossy(&name));
+                let matched = prefixes
+                    .iter()
+                    .any(|prefix| path == *prefix || path.starts_with(&format!("{}.", prefix)));
+                if matched {
+                    idcodes.insert(token_idcode.get_bytes(bs));
+                }
+                matched
+            }
+            Token::ScalarValue(_, idcode, _)
+            | Token::VectorValue(_, idcode, _)
+            | Token::RealValue(_, idcode, _)
+            | Token::StringValue(_, idcode, _)
+            | Token::PortValue(_, idcode, _) => idcodes.contains(&idcode.get_bytes(bs)),
+            _ => true,
+        };
+        if keep {
+            out.write_all(raw)?;
+            out.write_all(b"\n")?;
+        }
+        Ok(true)
+    })?;
+    out.flush()?;
+    Ok(0)
+}
+
+fn convert(args: &[String]) -> ToolResult<i32> {
+    let path = get_arg(args, 0, "file")?;
+    let format = get_arg(args, 1, "format")?;
+    let mut out = BufWriter::new(io::stdout().lock());
+    match format.as_str() {
+        "vcd" => for_each_token(&fs::read_to_string(path)?, &mut |token, _, bs| {
+            token.write_to(bs, &mut out)?;
+            Ok(true)
+        })?,
+        "sql" => {
+            let (header, waveform) = load(path)?;
+            write_sql(&header, &waveform, &mut out)?;
+        }
+        "ndjson" => {
+            let (header, waveform) = load(path)?;
+            VcdJsonExporter::new(&header, &mut out).write_waveform(&waveform)?;
+        }
+        "dot" => {
+            let (header, waveform) = load(path)?;
+            let range = waveform.get_timestamp_range();
+            let activity = VcdActivityReport::new(&header, &waveform, range.end - range.start + 1);
+            let mut options = VcdDotOptions::new();
+            options.set_variable_counts(true);
+            options.set_activity(&activity);
+            write!(out, "{}", to_dot(&header, &options))?;
+        }
+        _ => return Err(ToolError::Usage(format!("unknown format '{}'", format))),
+    }
+    out.flush()?;
+    Ok(0)
+}
+
+fn diff(args: &[String]) -> ToolResult<i32> {
+    let (header_a, waveform_a) = load(get_arg(args, 0, "file-a")?)?;
+    let (header_b, waveform_b) = load(get_arg(args, 1, "file-b")?)?;
+    let overlay = VcdOverlay::new(&header_a, &waveform_a, &header_b, &waveform_b)?;
+    for path in overlay.get_only_in_a() {
+        println!("{}: only in {}", path, args[0]);
+    }
+    for signal in overlay.get_signals() {
+        if let Some(timestamp) = signal.get_first_divergence() {
+            println!("{}: first difference at {}", signal.get_path(), timestamp);
+        } else if !signal.is_comparable() {
+            println!("{}: real values cannot be compared", signal.get_path());
+        }
+    }
+    for path in overlay.get_only_in_b() {
+        println!("{}: only in {}", path, args[1]);
+    }
+    let differences = overlay.get_only_in_a().len()
+        + overlay.get_divergent().len()
+        + overlay.get_only_in_b().len();
+    println!("{} differences", differences);
+    Ok(if differences == 0 { 0 } else { 1 })
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.first().map(|command| command.as_str()) {
+        Some("info") => info(&args[1..]),
+        Some("validate") => validate(&args[1..]),
+        Some("summary") => summary(&args[1..]),
+        Some("fingerprint") => fingerprint_file(&args[1..]),
+        Some("scan") => scan(&args[1..]),
+        Some("idcodes") => idcodes(&args[1..]),
+        Some("widths") => widths(&args[1..]),
+        Some("stats") => stats(&args[1..]),
+        Some("states") => states(&args[1..]),
+        Some("slice") => slice(&args[1..]),
+        Some("downsample") => downsample(&args[1..]),
+        Some("filter") => filter(&args[1..]),
+        Some("convert") => convert(&args[1..]),
+        Some("diff") => diff(&args[1..]),
+        Some(command) => Err(ToolError::Usage(format!("unknown command '{}'", command))),
+        None => Err(ToolError::Usage("missing command".to_string())),
+    };
+    match result {
+        Ok(code) => process::exit(code),
+        Err(err) => {
+            eprintln!("error: {}", err);
+            process::exit(2);
+        }
+    }
+}
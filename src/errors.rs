@@ -1,6 +1,28 @@
+use crate::export::json_string;
 use crate::lexer::position::*;
 use crate::tokenizer::token::*;
 
+// Formats an error as a JSON object, positions are reported as a byte range
+// along with the line and column the error starts on
+pub(crate) fn error_to_json(code: &str, message: &str, pos: Option<LexerPosition>) -> String {
+    let location = match pos {
+        Some(pos) => format!(
+            "{{\"start\":{},\"end\":{}}},\"line\":{},\"column\":{}",
+            pos.get_range().start,
+            pos.get_range().end,
+            pos.get_line(),
+            pos.get_column()
+        ),
+        None => "null,\"line\":null,\"column\":null".to_string(),
+    };
+    format!(
+        "{{\"code\":{},\"message\":{},\"range\":{}}}",
+        json_string(code),
+        json_string(message),
+        location
+    )
+}
+
 #[derive(Debug)]
 pub enum TokenizerError {
     UnexpectedTermination(LexerPosition),
@@ -11,6 +33,67 @@ pub enum TokenizerError {
     IncorrectVariableWidth(usize, usize, LexerPosition),
     IncorrectRealWidth(LexerPosition),
     LexerError(LexerPosition),
+    // Reading a stream failed, the position is where the unread part starts
+    ReadError(std::io::Error, LexerPosition),
+}
+
+impl TokenizerError {
+    pub fn get_position(&self) -> LexerPosition {
+        match self {
+            Self::UnexpectedTermination(pos)
+            | Self::IntegerParseError(_, pos)
+            | Self::ScalarParseError(pos)
+            | Self::VectorParseError(pos)
+            | Self::RealParseError(_, pos)
+            | Self::IncorrectVariableWidth(_, _, pos)
+            | Self::IncorrectRealWidth(pos)
+            | Self::LexerError(pos)
+            | Self::ReadError(_, pos) => *pos,
+        }
+    }
+
+    /// Returns a stable code identifying the kind of error
+    pub fn get_code(&self) -> &'static str {
+        match self {
+            Self::UnexpectedTermination(_) => "T001",
+            Self::IntegerParseError(_, _) => "T002",
+            Self::ScalarParseError(_) => "T003",
+            Self::VectorParseError(_) => "T004",
+            Self::RealParseError(_, _) => "T005",
+            Self::IncorrectVariableWidth(_, _, _) => "T006",
+            Self::IncorrectRealWidth(_) => "T007",
+            Self::LexerError(_) => "T008",
+            Self::ReadError(_, _) => "T009",
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        error_to_json(
+            self.get_code(),
+            &self.to_string(),
+            Some(self.get_position()),
+        )
+    }
+}
+
+impl std::fmt::Display for TokenizerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnexpectedTermination(_) => write!(f, "unexpected end of input"),
+            Self::IntegerParseError(err, _) => write!(f, "invalid integer ({})", err),
+            Self::ScalarParseError(_) => write!(f, "invalid scalar value"),
+            Self::VectorParseError(_) => write!(f, "invalid vector value"),
+            Self::RealParseError(err, _) => write!(f, "invalid real value ({})", err),
+            Self::IncorrectVariableWidth(width, reference_width, _) => write!(
+                f,
+                "variable width {} does not match reference width {}",
+                width, reference_width
+            ),
+            Self::IncorrectRealWidth(_) => write!(f, "real variable has an invalid width"),
+            Self::LexerError(_) => write!(f, "unrecognized token"),
+            Self::ReadError(err, _) => write!(f, "failed to read input ({})", err),
+        }
+    }
 }
 
 impl From<LexerPosition> for TokenizerError {
@@ -37,9 +120,93 @@ pub enum ParserError {
     UnexpectedVariable(LexerPosition),
     UnmatchedIdcode(LexerPosition),
     MismatchedWidth(LexerPosition),
+    MissingTimestamp(LexerPosition),
+    DuplicateChange(LexerPosition),
+    DuplicateDirective(LexerPosition),
+    HeaderLimit(LexerPosition),
+    ScopeDepth(usize, LexerPosition),
     Custom(String, Option<Token>),
 }
 
+impl ParserError {
+    pub fn get_position(&self) -> Option<LexerPosition> {
+        match self {
+            Self::UnexpectedTermination | Self::Custom(_, None) => None,
+            Self::Tokenizer(err) => Some(err.get_position()),
+            Self::UnexpectedToken(token) | Self::Custom(_, Some(token)) => {
+                Some(token.get_position())
+            }
+            Self::UnexpectedUpscope(pos)
+            | Self::UnexpectedEndDefinitions(pos)
+            | Self::UnexpectedVariable(pos)
+            | Self::UnmatchedIdcode(pos)
+            | Self::MismatchedWidth(pos)
+            | Self::MissingTimestamp(pos)
+            | Self::DuplicateChange(pos)
+            | Self::DuplicateDirective(pos)
+            | Self::HeaderLimit(pos)
+            | Self::ScopeDepth(_, pos) => Some(*pos),
+        }
+    }
+
+    /// Returns a stable code identifying the kind of error, tokenizer errors
+    /// keep their own codes
+    pub fn get_code(&self) -> &'static str {
+        match self {
+            Self::UnexpectedTermination => "P001",
+            Self::Tokenizer(err) => err.get_code(),
+            Self::UnexpectedToken(_) => "P002",
+            Self::UnexpectedUpscope(_) => "P003",
+            Self::UnexpectedEndDefinitions(_) => "P004",
+            Self::UnexpectedVariable(_) => "P005",
+            Self::UnmatchedIdcode(_) => "P006",
+            Self::MismatchedWidth(_) => "P007",
+            Self::Custom(_, _) => "P008",
+            Self::MissingTimestamp(_) => "P009",
+            Self::DuplicateChange(_) => "P010",
+            Self::DuplicateDirective(_) => "P011",
+            Self::HeaderLimit(_) => "P012",
+            Self::ScopeDepth(_, _) => "P013",
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        error_to_json(self.get_code(), &self.to_string(), self.get_position())
+    }
+}
+
+impl std::fmt::Display for ParserError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnexpectedTermination => write!(f, "unexpected end of input"),
+            Self::Tokenizer(err) => write!(f, "{}", err),
+            Self::UnexpectedToken(_) => write!(f, "unexpected token"),
+            Self::UnexpectedUpscope(_) => write!(f, "$upscope without a matching $scope"),
+            Self::UnexpectedEndDefinitions(_) => {
+                write!(f, "$enddefinitions inside an unclosed $scope")
+            }
+            Self::UnexpectedVariable(_) => write!(f, "$var outside of any $scope"),
+            Self::UnmatchedIdcode(_) => {
+                write!(f, "idcode redeclared with a different width")
+            }
+            Self::MismatchedWidth(_) => write!(f, "variable width does not match its type"),
+            Self::MissingTimestamp(_) => write!(f, "value change before the first timestamp"),
+            Self::DuplicateChange(_) => {
+                write!(f, "signal changed more than once in the same timestamp")
+            }
+            Self::DuplicateDirective(_) => write!(f, "header directive appears more than once"),
+            Self::HeaderLimit(_) => write!(
+                f,
+                "not a VCD header, no $enddefinitions within the header limit"
+            ),
+            Self::ScopeDepth(depth, _) => {
+                write!(f, "$scope nested deeper than the limit of {}", depth)
+            }
+            Self::Custom(message, _) => write!(f, "{}", message),
+        }
+    }
+}
+
 impl From<TokenizerError> for ParserError {
     fn from(err: TokenizerError) -> Self {
         ParserError::Tokenizer(err)
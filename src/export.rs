@@ -0,0 +1,168 @@
+#[cfg(feature = "arrow")]
+pub mod arrow;
+pub mod csv;
+pub mod dot;
+pub mod downsample;
+pub mod ndjson;
+pub mod npy;
+pub mod patch;
+pub mod sqlite;
+pub mod vcd;
+pub mod wavedrom;
+
+pub use dot::{to_dot, VcdDotOptions};
+
+use std::io;
+
+use makai_waveform_db::bitvector::{BitVector, Logic};
+
+use crate::analysis::{bitvector_to_u64, AnalysisError};
+use crate::parser::interpretation::VcdInterpretation;
+
+#[derive(Debug)]
+pub enum ExportError {
+    Io(io::Error),
+    Analysis(AnalysisError),
+    // The signal does not exist or cannot be represented in the format
+    UnsupportedSignal(usize),
+    #[cfg(feature = "sqlite")]
+    Sqlite(rusqlite::Error),
+    #[cfg(feature = "arrow")]
+    Arrow(arrow_schema::ArrowError),
+    #[cfg(feature = "parquet")]
+    Parquet(parquet::errors::ParquetError),
+}
+
+impl From<io::Error> for ExportError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<AnalysisError> for ExportError {
+    fn from(err: AnalysisError) -> Self {
+        Self::Analysis(err)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl From<rusqlite::Error> for ExportError {
+    fn from(err: rusqlite::Error) -> Self {
+        Self::Sqlite(err)
+    }
+}
+
+#[cfg(feature = "arrow")]
+impl From<arrow_schema::ArrowError> for ExportError {
+    fn from(err: arrow_schema::ArrowError) -> Self {
+        Self::Arrow(err)
+    }
+}
+
+#[cfg(feature = "parquet")]
+impl From<parquet::errors::ParquetError> for ExportError {
+    fn from(err: parquet::errors::ParquetError) -> Self {
+        Self::Parquet(err)
+    }
+}
+
+pub type ExportResult<T> = Result<T, ExportError>;
+
+// Formats a bit-vector as a VCD-style binary string, most significant bit first
+pub fn bitvector_to_string(bv: &BitVector) -> String {
+    (0..bv.get_bit_width())
+        .rev()
+        .map(|i| match bv.get_bit(i) {
+            Logic::Zero => '0',
+            Logic::One => '1',
+            Logic::Unknown => 'x',
+            Logic::HighImpedance => 'z',
+        })
+        .collect()
+}
+
+// Sign-extends the low width bits of a value
+fn sign_extend(value: u64, width: usize) -> i64 {
+    if width == 0 {
+        return 0;
+    }
+    let shift = u64::BITS as usize - width;
+    ((value << shift) as i64) >> shift
+}
+
+impl VcdInterpretation {
+    /// Converts a vector to an integer, returns None for unknown or
+    /// high-impedance bits, vectors wider than 64 bits and interpretations
+    /// that are not integers
+    pub fn to_i64(&self, bv: &BitVector) -> Option<i64> {
+        let value = bitvector_to_u64(bv)?;
+        match self {
+            Self::Unsigned => i64::try_from(value).ok(),
+            Self::Signed => Some(sign_extend(value, bv.get_bit_width())),
+            Self::FixedPoint { .. } | Self::Float => None,
+        }
+    }
+
+    /// Converts a vector to a number, returns None for unknown or
+    /// high-impedance bits, vectors wider than 64 bits and floats that are
+    /// not 32 or 64 bits wide
+    pub fn to_f64(&self, bv: &BitVector) -> Option<f64> {
+        let value = bitvector_to_u64(bv)?;
+        let width = bv.get_bit_width();
+        match self {
+            Self::Unsigned => Some(value as f64),
+            Self::Signed => Some(sign_extend(value, width) as f64),
+            Self::FixedPoint {
+                signed,
+                fraction_bits,
+            } => {
+                let integer = if *signed {
+                    sign_extend(value, width) as f64
+                } else {
+                    value as f64
+                };
+                Some(integer / 2f64.powi(*fraction_bits as i32))
+            }
+            Self::Float => match width {
+                32 => Some(f32::from_bits(value as u32) as f64),
+                64 => Some(f64::from_bits(value)),
+                _ => None,
+            },
+        }
+    }
+
+    /// Formats a vector as a number, unsigned vectors and values that cannot
+    /// be converted are formatted as binary strings
+    pub fn format(&self, bv: &BitVector) -> String {
+        match self {
+            Self::Unsigned => bitvector_to_string(bv),
+            Self::Signed => match self.to_i64(bv) {
+                Some(value) => format!("{}", value),
+                None => bitvector_to_string(bv),
+            },
+            Self::FixedPoint { .. } | Self::Float => match self.to_f64(bv) {
+                Some(value) => format!("{:?}", value),
+                None => bitvector_to_string(bv),
+            },
+        }
+    }
+}
+
+// Quotes and escapes a string for use in JSON output
+pub(crate) fn json_string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    escaped.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
@@ -0,0 +1,142 @@
+use std::sync::Arc;
+
+use arrow_array::builder::{Float64Builder, Int64Builder, StringBuilder, UInt64Builder};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use makai_waveform_db::Waveform;
+
+use crate::export::sqlite::{change_rows, ChangeRow};
+use crate::export::ExportResult;
+use crate::parser::VcdHeader;
+
+/// Changes per record batch, and per row group of a Parquet file
+pub const RECORD_BATCH_SIZE: usize = 64 * 1024;
+
+/// The schema of the change records, the same columns as the changes table
+/// of the SQLite export with idcodes as they appear in the VCD
+pub fn change_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("timestamp", DataType::UInt64, false),
+        Field::new("idcode", DataType::Utf8, false),
+        Field::new("value", DataType::Utf8, false),
+        Field::new("value_int", DataType::Int64, true),
+        Field::new("value_real", DataType::Float64, true),
+    ]))
+}
+
+// Collects change rows into columns until there are enough for a batch
+struct VcdBatchBuilder {
+    schema: SchemaRef,
+    timestamp: UInt64Builder,
+    idcode: StringBuilder,
+    value: StringBuilder,
+    value_int: Int64Builder,
+    value_real: Float64Builder,
+    len: usize,
+}
+
+impl VcdBatchBuilder {
+    fn new(batch_size: usize) -> Self {
+        Self {
+            schema: change_schema(),
+            timestamp: UInt64Builder::with_capacity(batch_size),
+            idcode: StringBuilder::with_capacity(batch_size, batch_size),
+            value: StringBuilder::with_capacity(batch_size, batch_size),
+            value_int: Int64Builder::with_capacity(batch_size),
+            value_real: Float64Builder::with_capacity(batch_size),
+            len: 0,
+        }
+    }
+
+    fn append(&mut self, row: ChangeRow) {
+        self.timestamp.append_value(row.timestamp as u64);
+        self.idcode.append_value(&row.idcode);
+        self.value.append_value(&row.value);
+        self.value_int.append_option(row.value_int);
+        self.value_real.append_option(row.value_real);
+        self.len += 1;
+    }
+
+    fn finish(&mut self) -> ExportResult<RecordBatch> {
+        self.len = 0;
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(self.timestamp.finish()),
+            Arc::new(self.idcode.finish()),
+            Arc::new(self.value.finish()),
+            Arc::new(self.value_int.finish()),
+            Arc::new(self.value_real.finish()),
+        ];
+        Ok(RecordBatch::try_new(self.schema.clone(), columns)?)
+    }
+}
+
+// Hands the changes to f a batch at a time, so a file can be written without
+// holding every batch
+fn for_each_batch(
+    header: &VcdHeader,
+    waveform: &Waveform,
+    batch_size: usize,
+    f: &mut dyn FnMut(RecordBatch) -> ExportResult<()>,
+) -> ExportResult<()> {
+    let batch_size = batch_size.max(1);
+    let mut builder = VcdBatchBuilder::new(batch_size);
+    for row in change_rows(header, waveform) {
+        builder.append(row?);
+        if builder.len >= batch_size {
+            f(builder.finish()?)?;
+        }
+    }
+    if builder.len > 0 {
+        f(builder.finish()?)?;
+    }
+    Ok(())
+}
+
+/// Converts every value change to Arrow record batches of up to batch_size
+/// rows following change_schema, signals come in idcode order and the
+/// changes of each in timestamp order
+pub fn to_record_batches(
+    header: &VcdHeader,
+    waveform: &Waveform,
+    batch_size: usize,
+) -> ExportResult<Vec<RecordBatch>> {
+    let mut batches = Vec::new();
+    for_each_batch(header, waveform, batch_size, &mut |batch| {
+        batches.push(batch);
+        Ok(())
+    })?;
+    Ok(batches)
+}
+
+/// Writes every value change as a Parquet file with the columns of
+/// change_schema, one row group per record batch
+#[cfg(feature = "parquet")]
+pub fn write_parquet<W: std::io::Write + Send>(
+    header: &VcdHeader,
+    waveform: &Waveform,
+    writer: W,
+) -> ExportResult<()> {
+    // The default properties would merge batches into row groups of about a
+    // million rows
+    let properties = parquet::file::properties::WriterProperties::builder()
+        .set_max_row_group_size(RECORD_BATCH_SIZE)
+        .build();
+    let mut writer =
+        parquet::arrow::ArrowWriter::try_new(writer, change_schema(), Some(properties))?;
+    for_each_batch(header, waveform, RECORD_BATCH_SIZE, &mut |batch| {
+        Ok(writer.write(&batch)?)
+    })?;
+    writer.close()?;
+    Ok(())
+}
+
+/// Creates a Parquet file at the given path, see write_parquet
+#[cfg(feature = "parquet")]
+pub fn export_parquet<P: AsRef<std::path::Path>>(
+    header: &VcdHeader,
+    waveform: &Waveform,
+    path: P,
+) -> ExportResult<()> {
+    let file = std::fs::File::create(path)?;
+    write_parquet(header, waveform, std::io::BufWriter::new(file))
+}
@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use crate::export::bitvector_to_string;
+use crate::parser::{VcdEntry, VcdHeader};
+
+// Quotes a CSV field if it holds a separator, quote or line break
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+/// Streams value changes as CSV rows of timestamp, path and value, vectors
+/// are written as binary strings, reals as numbers and event triggers as 1
+pub struct VcdCsvExporter<W: Write> {
+    writer: W,
+    // Variables sharing an idcode each get their own row
+    paths: HashMap<usize, Vec<String>>,
+    timestamp: u64,
+}
+
+impl<W: Write> VcdCsvExporter<W> {
+    /// Creates an exporter and writes the header row
+    pub fn new(header: &VcdHeader, writer: W) -> io::Result<Self> {
+        Self::with_filter(header, writer, |_| true)
+    }
+
+    /// Only exports variables whose full path passes the filter
+    pub fn with_filter<F>(header: &VcdHeader, mut writer: W, filter: F) -> io::Result<Self>
+    where
+        F: Fn(&str) -> bool,
+    {
+        let mut paths: HashMap<usize, Vec<String>> = HashMap::new();
+        for (path, variable) in header.get_variables_with_paths() {
+            if filter(&path) {
+                paths
+                    .entry(variable.get_idcode())
+                    .or_default()
+                    .push(csv_field(&path));
+            }
+        }
+        writeln!(writer, "timestamp,path,value")?;
+        Ok(Self {
+            writer,
+            paths,
+            timestamp: 0,
+        })
+    }
+
+    /// Writes an entry as it comes out of the parser, timestamps are tracked
+    /// and applied to the value changes that follow them
+    pub fn write_entry(&mut self, entry: &VcdEntry) -> io::Result<()> {
+        let idcode = match entry {
+            VcdEntry::Timestamp(timestamp) => {
+                self.timestamp = *timestamp;
+                return Ok(());
+            }
+            VcdEntry::Scalar(_, idcode)
+            | VcdEntry::Vector(_, idcode)
+            | VcdEntry::Real(_, idcode)
+            | VcdEntry::Event(idcode)
+            | VcdEntry::Port(_, idcode)
+            | VcdEntry::String(_, idcode) => *idcode,
+        };
+        if let Some(paths) = self.paths.get(&idcode) {
+            let value = match entry {
+                VcdEntry::Scalar(bit, _) => bit.to_str().to_string(),
+                VcdEntry::Vector(bv, _) => bitvector_to_string(bv),
+                VcdEntry::Real(value, _) => format!("{:?}", value),
+                VcdEntry::Port(value, _) => bitvector_to_string(&value.to_bitvector()),
+                VcdEntry::String(value, _) => String::from_utf8_lossy(value).to_string(),
+                _ => "1".to_string(),
+            };
+            for path in paths {
+                writeln!(self.writer, "{},{},{}", self.timestamp, path, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn get_writer(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn into_writer(self) -> W {
+        self.writer
+    }
+}
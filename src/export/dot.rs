@@ -0,0 +1,86 @@
+use crate::analysis::activity::VcdActivityReport;
+use crate::export::json_string;
+use crate::parser::{VcdHeader, VcdScope};
+
+/// Controls what is drawn by to_dot
+#[derive(Clone, Debug, Default)]
+pub struct VcdDotOptions<'a> {
+    variable_counts: bool,
+    activity: Option<&'a VcdActivityReport>,
+    max_depth: Option<usize>,
+}
+
+impl<'a> VcdDotOptions<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the number of variables declared directly in each scope
+    pub fn set_variable_counts(&mut self, variable_counts: bool) {
+        self.variable_counts = variable_counts;
+    }
+
+    /// Adds the total number of changes of each scope from an activity report
+    pub fn set_activity(&mut self, activity: &'a VcdActivityReport) {
+        self.activity = Some(activity);
+    }
+
+    /// Only draws scopes up to the given depth, top-level scopes are depth 1
+    pub fn set_max_depth(&mut self, max_depth: Option<usize>) {
+        self.max_depth = max_depth;
+    }
+}
+
+// Writes the node of a scope and the edges to its children, nodes are named
+// by their index in a depth-first walk so names with symbols are fine
+fn write_scope(
+    scope: &VcdScope,
+    path: &str,
+    depth: usize,
+    options: &VcdDotOptions,
+    next_node: &mut usize,
+    dot: &mut String,
+) -> usize {
+    let node = *next_node;
+    *next_node += 1;
+    let mut label = scope.get_name().clone();
+    if options.variable_counts {
+        label.push_str(&format!("\n{} variables", scope.get_variables().len()));
+    }
+    if let Some(activity) = options
+        .activity
+        .and_then(|activity| activity.get_scope(path))
+    {
+        label.push_str(&format!("\n{} changes", activity.get_total()));
+    }
+    // DOT strings use the same quoting and escapes as JSON
+    dot.push_str(&format!("  n{} [label={}];\n", node, json_string(&label)));
+    if !matches!(options.max_depth, Some(max_depth) if depth >= max_depth) {
+        for child in scope.get_scopes() {
+            let child_path = format!("{}.{}", path, child.get_name());
+            let child_node = write_scope(child, &child_path, depth + 1, options, next_node, dot);
+            dot.push_str(&format!("  n{} -> n{};\n", node, child_node));
+        }
+    }
+    node
+}
+
+/// Draws the scope hierarchy as a Graphviz DOT digraph, one node per scope
+pub fn to_dot(header: &VcdHeader, options: &VcdDotOptions) -> String {
+    let mut dot = String::from("digraph hierarchy {\n  rankdir=LR;\n  node [shape=box];\n");
+    let mut next_node = 0;
+    if options.max_depth != Some(0) {
+        for scope in header.get_scopes() {
+            write_scope(
+                scope,
+                scope.get_name(),
+                1,
+                options,
+                &mut next_node,
+                &mut dot,
+            );
+        }
+    }
+    dot.push_str("}\n");
+    dot
+}
@@ -0,0 +1,81 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::io::{self, Write};
+
+use makai_waveform_db::Waveform;
+
+use crate::analysis::iter_variable_changes;
+use crate::export::vcd::{write_entry, write_header};
+use crate::parser::{VcdEntry, VcdHeader, VcdVariableWidth};
+
+// Turns the changes of a signal into its samples at multiples of tick
+fn sample_changes<'a>(
+    changes: impl Iterator<Item = (u64, VcdEntry)> + 'a,
+    tick: u64,
+    is_event: bool,
+) -> impl Iterator<Item = (u64, VcdEntry)> + 'a {
+    let mut changes = changes.peekable();
+    let mut last: Option<VcdEntry> = None;
+    std::iter::from_fn(move || {
+        while let Some((timestamp, entry)) = changes.next() {
+            let sample = timestamp.div_ceil(tick).saturating_mul(tick);
+            // Only the final change before the next tick is sampled
+            if let Some((next, _)) = changes.peek() {
+                if next.div_ceil(tick).saturating_mul(tick) == sample {
+                    continue;
+                }
+            }
+            if !is_event && last.as_ref() == Some(&entry) {
+                continue;
+            }
+            last = Some(entry.clone());
+            return Some((sample, entry));
+        }
+        None
+    })
+}
+
+/// Writes a VCD holding the value of every signal sampled at multiples of
+/// tick (in timescale units), a signal takes the last value it changed to at
+/// or before each tick and is only written when that value differs from the
+/// previous sample, events are written at the first tick after they trigger.
+/// Signals are read side by side and each tick is written once every signal
+/// has passed it, so the samples are never all held at once.
+pub fn write_downsampled(
+    header: &VcdHeader,
+    waveform: &Waveform,
+    tick: u64,
+    writer: &mut dyn Write,
+) -> io::Result<()> {
+    let tick = tick.max(1);
+    let widths = header.get_idcodes_map();
+    let mut ids: Vec<usize> = widths.keys().copied().collect();
+    ids.sort_unstable();
+    // The next sample of every signal is kept in a heap ordered by tick and
+    // then by id
+    let mut signals = Vec::with_capacity(ids.len());
+    let mut next = BinaryHeap::new();
+    for &id in &ids {
+        let changes = iter_variable_changes(waveform, id, &widths[&id])?;
+        let is_event = widths[&id] == VcdVariableWidth::Event;
+        let mut samples = sample_changes(changes, tick, is_event).peekable();
+        if let Some((sample, _)) = samples.peek() {
+            next.push(Reverse((*sample, id, signals.len())));
+            signals.push(samples);
+        }
+    }
+    let idcodes = write_header(header, writer)?;
+    let mut written = None;
+    while let Some(Reverse((sample, id, signal))) = next.pop() {
+        if written != Some(sample) {
+            writeln!(writer, "#{}", sample)?;
+            written = Some(sample);
+        }
+        let (_, entry) = signals[signal].next().unwrap();
+        write_entry(&entry, &widths[&id], &idcodes[&id], writer)?;
+        if let Some((sample, _)) = signals[signal].peek() {
+            next.push(Reverse((*sample, id, signal)));
+        }
+    }
+    Ok(())
+}
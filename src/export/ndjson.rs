@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use makai_waveform_db::bitvector::BitVector;
+use makai_waveform_db::Waveform;
+
+use crate::analysis::variable_changes;
+use crate::export::{bitvector_to_string, json_string};
+use crate::parser::interpretation::VcdInterpretation;
+use crate::parser::{VcdEntry, VcdHeader, VcdVariableWidth};
+
+// JSON has no literal for infinities or NaN, those are written as null
+fn json_real(value: f64) -> String {
+    if value.is_finite() {
+        format!("{:?}", value)
+    } else {
+        "null".to_string()
+    }
+}
+
+// Vectors are written as binary strings unless they have an interpretation
+// and a value that converts to a finite number
+fn json_vector(bv: &BitVector, interpretation: VcdInterpretation) -> String {
+    match interpretation.to_f64(bv) {
+        Some(value) if interpretation != VcdInterpretation::Unsigned && value.is_finite() => {
+            interpretation.format(bv)
+        }
+        _ => json_string(&bitvector_to_string(bv)),
+    }
+}
+
+/// Streams value changes as newline-delimited JSON objects of the form
+/// `{"t":<timestamp>,"path":<path>,"v":<value>}`, vectors are written as
+/// binary strings (or numbers if the variable has an interpretation), reals
+/// as numbers and event triggers as true
+pub struct VcdJsonExporter<W: Write> {
+    writer: W,
+    // Variables sharing an idcode each get their own line
+    paths: HashMap<usize, Vec<(String, VcdInterpretation)>>,
+    widths: HashMap<usize, VcdVariableWidth>,
+    timestamp: u64,
+}
+
+impl<W: Write> VcdJsonExporter<W> {
+    pub fn new(header: &VcdHeader, writer: W) -> Self {
+        Self::with_filter(header, writer, |_| true)
+    }
+
+    /// Only exports variables whose full path passes the filter
+    pub fn with_filter<F>(header: &VcdHeader, writer: W, filter: F) -> Self
+    where
+        F: Fn(&str) -> bool,
+    {
+        let mut paths: HashMap<usize, Vec<(String, VcdInterpretation)>> = HashMap::new();
+        for (path, variable) in header.get_variables_with_paths() {
+            if filter(&path) {
+                paths
+                    .entry(variable.get_idcode())
+                    .or_default()
+                    .push((json_string(&path), variable.get_interpretation()));
+            }
+        }
+        Self {
+            writer,
+            paths,
+            widths: header.get_idcodes_map().clone(),
+            timestamp: 0,
+        }
+    }
+
+    /// Writes an entry as it comes out of the parser, timestamps are tracked
+    /// and applied to the value changes that follow them
+    pub fn write_entry(&mut self, entry: &VcdEntry) -> io::Result<()> {
+        let idcode = match entry {
+            VcdEntry::Timestamp(timestamp) => {
+                self.timestamp = *timestamp;
+                return Ok(());
+            }
+            VcdEntry::Scalar(_, idcode)
+            | VcdEntry::Vector(_, idcode)
+            | VcdEntry::Real(_, idcode)
+            | VcdEntry::Event(idcode)
+            | VcdEntry::Port(_, idcode)
+            | VcdEntry::String(_, idcode) => *idcode,
+        };
+        if let Some(paths) = self.paths.get(&idcode) {
+            for (path, interpretation) in paths {
+                let value = match entry {
+                    VcdEntry::Scalar(bit, _) => json_vector(&(*bit).into(), *interpretation),
+                    VcdEntry::Vector(bv, _) => json_vector(bv, *interpretation),
+                    VcdEntry::Real(value, _) => json_real(*value),
+                    VcdEntry::Port(value, _) => json_vector(&value.to_bitvector(), *interpretation),
+                    VcdEntry::String(value, _) => json_string(&String::from_utf8_lossy(value)),
+                    _ => "true".to_string(),
+                };
+                writeln!(
+                    self.writer,
+                    "{{\"t\":{},\"path\":{},\"v\":{}}}",
+                    self.timestamp, path, value
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes every change of an already loaded waveform in timestamp order
+    pub fn write_waveform(&mut self, waveform: &Waveform) -> io::Result<()> {
+        let mut idcodes: Vec<usize> = self.paths.keys().copied().collect();
+        idcodes.sort_unstable();
+        let mut changes: Vec<(u64, VcdEntry)> = Vec::new();
+        for idcode in idcodes {
+            changes.extend(variable_changes(waveform, idcode, &self.widths[&idcode])?);
+        }
+        changes.sort_by_key(|(timestamp, _)| *timestamp);
+        for (timestamp, entry) in changes {
+            self.timestamp = timestamp;
+            self.write_entry(&entry)?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn get_writer(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn into_writer(self) -> W {
+        self.writer
+    }
+}
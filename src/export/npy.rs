@@ -0,0 +1,145 @@
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use makai_waveform_db::{Waveform, WaveformSignalResult};
+
+use crate::analysis::{bitvector_to_u64, vector_changes, AnalysisError};
+use crate::export::{ExportError, ExportResult};
+use crate::parser::interpretation::VcdInterpretation;
+use crate::parser::VcdHeader;
+
+const NPY_MAGIC: &[u8] = b"\x93NUMPY\x01\x00";
+// The magic, header length and header are padded to a multiple of this
+const NPY_ALIGNMENT: usize = 64;
+
+// Vectors get a validity flag since unknown and high-impedance bits have no
+// integer representation, reals are stored as-is
+const VECTOR_DESCR: &str = "[('t', '<u8'), ('v', '<u8'), ('valid', '|b1')]";
+const SIGNED_DESCR: &str = "[('t', '<u8'), ('v', '<i8'), ('valid', '|b1')]";
+const INTERPRETED_DESCR: &str = "[('t', '<u8'), ('v', '<f8'), ('valid', '|b1')]";
+const REAL_DESCR: &str = "[('t', '<u8'), ('v', '<f8')]";
+
+fn write_npy_header(writer: &mut dyn Write, descr: &str, len: usize) -> io::Result<()> {
+    let mut header = format!(
+        "{{'descr': {}, 'fortran_order': False, 'shape': ({},), }}",
+        descr, len
+    );
+    // Account for the magic, the header length and the trailing newline
+    let unpadded = NPY_MAGIC.len() + 2 + header.len() + 1;
+    let padding = (NPY_ALIGNMENT - unpadded % NPY_ALIGNMENT) % NPY_ALIGNMENT;
+    header.push_str(&" ".repeat(padding));
+    header.push('\n');
+    writer.write_all(NPY_MAGIC)?;
+    writer.write_all(&(header.len() as u16).to_le_bytes())?;
+    writer.write_all(header.as_bytes())?;
+    Ok(())
+}
+
+/// Writes the changes of a signal as a NumPy structured array with a `t`
+/// timestamp field and a `v` value field, vectors wider than 64 bits and
+/// reals with changes are not supported
+pub fn write_npy(waveform: &Waveform, idcode: usize, writer: &mut dyn Write) -> ExportResult<()> {
+    write_npy_interpreted(waveform, idcode, VcdInterpretation::Unsigned, writer)
+}
+
+/// Like write_npy, but vectors are converted with an interpretation, signed
+/// vectors are stored as `i8` and fixed-point or float vectors as `f8`
+pub fn write_npy_interpreted(
+    waveform: &Waveform,
+    idcode: usize,
+    interpretation: VcdInterpretation,
+    writer: &mut dyn Write,
+) -> ExportResult<()> {
+    match waveform.get_signal(idcode) {
+        Some(WaveformSignalResult::Vector(signal)) => {
+            if signal.get_width() > u64::BITS as usize {
+                return Err(ExportError::UnsupportedSignal(idcode));
+            }
+            // Values are converted to their little-endian bytes up front
+            let changes: Vec<(u64, Option<[u8; 8]>)> = vector_changes(waveform, signal)
+                .map(|(timestamp, bv)| {
+                    let value = match interpretation {
+                        VcdInterpretation::Unsigned => bitvector_to_u64(&bv).map(u64::to_le_bytes),
+                        VcdInterpretation::Signed => {
+                            interpretation.to_i64(&bv).map(i64::to_le_bytes)
+                        }
+                        _ => interpretation.to_f64(&bv).map(f64::to_le_bytes),
+                    };
+                    (timestamp, value)
+                })
+                .collect();
+            let descr = match interpretation {
+                VcdInterpretation::Unsigned => VECTOR_DESCR,
+                VcdInterpretation::Signed => SIGNED_DESCR,
+                _ => INTERPRETED_DESCR,
+            };
+            write_npy_header(writer, descr, changes.len())?;
+            for (timestamp, value) in changes {
+                writer.write_all(&timestamp.to_le_bytes())?;
+                writer.write_all(&value.unwrap_or_default())?;
+                writer.write_all(&[value.is_some() as u8])?;
+            }
+        }
+        Some(WaveformSignalResult::Real(signal)) => {
+            if !signal.is_empty() {
+                return Err(AnalysisError::UnreadableReal(idcode).into());
+            }
+            write_npy_header(writer, REAL_DESCR, 0)?;
+        }
+        None => return Err(ExportError::UnsupportedSignal(idcode)),
+    }
+    Ok(())
+}
+
+// Escaped identifiers can hold path separators, which are percent-encoded
+// (along with the percent sign itself) so every file stays inside the
+// directory and distinct paths keep distinct names
+fn npy_file_name(path: &str) -> String {
+    let mut name = String::with_capacity(path.len() + 4);
+    for c in path.chars() {
+        match c {
+            '%' => name.push_str("%25"),
+            '/' => name.push_str("%2F"),
+            '\\' => name.push_str("%5C"),
+            _ => name.push(c),
+        }
+    }
+    name.push_str(".npy");
+    name
+}
+
+/// Writes one .npy file per variable into a directory, named after the full
+/// path of the variable (with path separators percent-encoded) and converted
+/// with its interpretation, and returns
+/// the paths of the variables that were skipped for being too wide or real
+pub fn export_npy<P: AsRef<Path>>(
+    header: &VcdHeader,
+    waveform: &Waveform,
+    dir: P,
+) -> ExportResult<Vec<String>> {
+    let dir = dir.as_ref();
+    fs::create_dir_all(dir)?;
+    let mut skipped = Vec::new();
+    for (path, variable) in header.get_variables_with_paths() {
+        let mut bytes = Vec::new();
+        match write_npy_interpreted(
+            waveform,
+            variable.get_idcode(),
+            variable.get_interpretation(),
+            &mut bytes,
+        ) {
+            Ok(()) => {}
+            Err(ExportError::UnsupportedSignal(_))
+            | Err(ExportError::Analysis(AnalysisError::UnreadableReal(_))) => {
+                skipped.push(path);
+                continue;
+            }
+            Err(err) => return Err(err),
+        }
+        let mut file = BufWriter::new(fs::File::create(dir.join(npy_file_name(&path)))?);
+        file.write_all(&bytes)?;
+        file.flush()?;
+    }
+    Ok(skipped)
+}
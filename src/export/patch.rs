@@ -0,0 +1,145 @@
+use std::collections::{BTreeMap, HashSet};
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+
+use makai_waveform_db::Waveform;
+
+use crate::analysis::overlay::is_same_value;
+use crate::analysis::variable_changes;
+use crate::export::vcd::{write_entry, write_header};
+use crate::parser::{VcdEntry, VcdHeader, VcdVariableWidth};
+
+type VcdChanges = Vec<(u64, VcdEntry)>;
+
+// Returns the first and last timestamp at which the candidate has to be
+// written for its values to match, or None if the values always agree.
+// Events hold no value, so they only differ at the timestamps they trigger.
+fn divergent_range(
+    golden: &[(u64, VcdEntry)],
+    candidate: &[(u64, VcdEntry)],
+    is_event: bool,
+) -> Option<(u64, u64)> {
+    let mut golden = golden.iter().peekable();
+    let mut candidate = candidate.iter().peekable();
+    let (mut value_golden, mut value_candidate) = (None, None);
+    let mut range: Option<(u64, u64)> = None;
+    let mut differed = false;
+    loop {
+        let timestamp = match (golden.peek(), candidate.peek()) {
+            (Some(a), Some(b)) => a.0.min(b.0),
+            (Some(a), None) => a.0,
+            (None, Some(b)) => b.0,
+            (None, None) => break,
+        };
+        if is_event {
+            (value_golden, value_candidate) = (None, None);
+        }
+        while let Some((_, entry)) = golden.next_if(|change| change.0 == timestamp) {
+            value_golden = Some(entry);
+        }
+        while let Some((_, entry)) = candidate.next_if(|change| change.0 == timestamp) {
+            value_candidate = Some(entry);
+        }
+        let differs = match (value_golden, value_candidate) {
+            (Some(a), Some(b)) => !is_same_value(a, b),
+            (None, None) => false,
+            _ => true,
+        };
+        // The change that makes the values agree again belongs to the patch
+        if differs || differed {
+            range = Some((range.map_or(timestamp, |range| range.0), timestamp));
+        }
+        differed = differs && !is_event;
+    }
+    range
+}
+
+/// Writes a minimal VCD of how a candidate dump differs from a golden one,
+/// for attaching just the divergent slice of a large dump to a bug report.
+/// Variables are paired by path and only the ones passing the filter are
+/// compared, the patch declares just the variables whose values differ and
+/// covers the window from the first difference to the last. It starts with
+/// the golden state at the start of the window in a $dumpvars section, so
+/// replaying the patch over the golden dump gives the candidate. Returns the
+/// window, or None without writing anything if the dumps agree.
+pub fn write_patch<F: Fn(&str) -> bool>(
+    golden_header: &VcdHeader,
+    golden: &Waveform,
+    candidate_header: &VcdHeader,
+    candidate: &Waveform,
+    filter: F,
+    writer: &mut dyn Write,
+) -> io::Result<Option<RangeInclusive<u64>>> {
+    let widths = candidate_header.get_idcodes_map();
+    // Keyed by the idcode of the candidate so they come out in order
+    let mut signals: BTreeMap<usize, (VcdChanges, VcdChanges)> = BTreeMap::new();
+    let mut paths = HashSet::new();
+    let mut window: Option<(u64, u64)> = None;
+    for (path, variable) in candidate_header.get_variables_with_paths() {
+        if !filter(&path) {
+            continue;
+        }
+        let Some(golden_variable) = golden_header.get_variable(&path) else {
+            continue;
+        };
+        let idcode = variable.get_idcode();
+        let width = &widths[&idcode];
+        let golden_changes = variable_changes(
+            golden,
+            golden_variable.get_idcode(),
+            golden_variable.get_width(),
+        )?;
+        let candidate_changes = variable_changes(candidate, idcode, width)?;
+        let is_event = *width == VcdVariableWidth::Event;
+        let Some((start, end)) = divergent_range(&golden_changes, &candidate_changes, is_event)
+        else {
+            continue;
+        };
+        window = Some(window.map_or((start, end), |(s, e)| (s.min(start), e.max(end))));
+        paths.insert(path);
+        signals.insert(idcode, (golden_changes, candidate_changes));
+    }
+    let Some((start, end)) = window else {
+        return Ok(None);
+    };
+
+    let mut header = candidate_header.clone();
+    header.retain_variables(|path| paths.contains(path));
+    let idcodes = write_header(&header, writer)?;
+    let mut changes: BTreeMap<u64, BTreeMap<usize, &VcdEntry>> = BTreeMap::new();
+    writeln!(writer, "#{}", start)?;
+    writeln!(writer, "$dumpvars")?;
+    for (idcode, (golden_changes, candidate_changes)) in &signals {
+        let width = &widths[idcode];
+        if *width != VcdVariableWidth::Event {
+            // The golden value going into the window, unless the candidate
+            // already changes at its start
+            let seed = candidate_changes
+                .iter()
+                .rfind(|change| change.0 == start)
+                .or_else(|| golden_changes.iter().rfind(|change| change.0 < start));
+            if let Some((_, entry)) = seed {
+                write_entry(entry, width, &idcodes[idcode], writer)?;
+            }
+        }
+        for (timestamp, entry) in candidate_changes {
+            let in_window = *timestamp > start && *timestamp <= end;
+            if in_window || (*timestamp == start && *width == VcdVariableWidth::Event) {
+                changes
+                    .entry(*timestamp)
+                    .or_default()
+                    .insert(*idcode, entry);
+            }
+        }
+    }
+    writeln!(writer, "$end")?;
+    for (timestamp, entries) in changes {
+        if timestamp != start {
+            writeln!(writer, "#{}", timestamp)?;
+        }
+        for (idcode, entry) in entries {
+            write_entry(entry, &widths[&idcode], &idcodes[&idcode], writer)?;
+        }
+    }
+    Ok(Some(start..=end))
+}
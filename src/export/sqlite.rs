@@ -0,0 +1,280 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+
+use makai_waveform_db::{Waveform, WaveformSignalResult};
+
+use crate::analysis::{AnalysisError, AnalysisResult};
+use crate::export::bitvector_to_string;
+use crate::parser::interpretation::VcdInterpretation;
+use crate::parser::{VcdHeader, VcdScope};
+
+#[cfg(feature = "sqlite")]
+use crate::export::ExportResult;
+
+// Idcodes are stored as they appear in the VCD, the numbers the parser packs
+// them into depend on the load for idcodes too long to pack. Signals are
+// keyed by idcode and path since aliases share an idcode and a path can be
+// declared more than once.
+const SCHEMA: &str = "\
+CREATE TABLE metadata (key TEXT PRIMARY KEY, value TEXT);
+CREATE TABLE signals (path TEXT, idcode TEXT, width INTEGER, net_type TEXT, PRIMARY KEY (idcode, path));
+CREATE TABLE changes (timestamp INTEGER, idcode TEXT, value TEXT, value_int INTEGER, value_real REAL);
+CREATE TABLE attributes (path TEXT, key TEXT, value TEXT);
+";
+
+// Created after the bulk insert so rows do not have to update the index
+const INDEX: &str = "CREATE INDEX changes_by_signal ON changes (idcode, timestamp);\n";
+
+struct SignalRow {
+    path: String,
+    idcode: String,
+    width: i64,
+    net_type: String,
+}
+
+pub(crate) struct ChangeRow {
+    pub(crate) timestamp: i64,
+    pub(crate) idcode: String,
+    pub(crate) value: String,
+    // Set for two-state vectors that fit into a signed 64-bit integer
+    pub(crate) value_int: Option<i64>,
+    // Set for vectors with a fixed-point or float interpretation
+    pub(crate) value_real: Option<f64>,
+}
+
+fn metadata_rows(header: &VcdHeader) -> Vec<(&'static str, String)> {
+    let mut rows = Vec::new();
+    if let Some(version) = header.get_version() {
+        rows.push(("version", version.clone()));
+    }
+    if let Some(date) = header.get_date() {
+        rows.push(("date", date.clone()));
+    }
+    if let Some(timescale) = header.get_timescale() {
+        rows.push(("timescale", format!("{}", timescale)));
+    }
+    rows
+}
+
+// Idcodes are declared with the variables, so every one has its source text
+fn idcode_string(header: &VcdHeader, idcode: usize) -> String {
+    header
+        .get_idcode_string(idcode)
+        .cloned()
+        .unwrap_or_default()
+}
+
+// A variable declared twice at the same path with the same idcode is one row
+fn signal_rows(header: &VcdHeader) -> Vec<SignalRow> {
+    let mut seen = HashSet::new();
+    header
+        .get_variables_with_paths()
+        .into_iter()
+        .filter(|(path, variable)| seen.insert((path.clone(), variable.get_idcode())))
+        .map(|(path, variable)| SignalRow {
+            path,
+            idcode: idcode_string(header, variable.get_idcode()),
+            width: variable.get_bit_width() as i64,
+            net_type: String::from_utf8_lossy(variable.get_net_type().to_byte_str()).to_string(),
+        })
+        .collect()
+}
+
+// Collects the user attributes of every scope and variable as (path, key,
+// value) rows, sorted by key within each path
+fn attribute_rows(header: &VcdHeader) -> Vec<(String, String, String)> {
+    fn sorted(path: &str, attributes: &HashMap<String, String>) -> Vec<(String, String, String)> {
+        let mut rows: Vec<(String, String, String)> = attributes
+            .iter()
+            .map(|(key, value)| (path.to_string(), key.clone(), value.clone()))
+            .collect();
+        rows.sort();
+        rows
+    }
+    fn collect(scope: &VcdScope, prefix: &str, rows: &mut Vec<(String, String, String)>) {
+        let path = format!("{}{}", prefix, scope.get_name());
+        rows.extend(sorted(&path, scope.get_attributes()));
+        for child in scope.get_scopes() {
+            collect(child, &format!("{}.", path), rows);
+        }
+    }
+    let mut rows = Vec::new();
+    for scope in header.get_scopes() {
+        collect(scope, "", &mut rows);
+    }
+    for (path, variable) in header.get_variables_with_paths() {
+        rows.extend(sorted(&path, variable.get_attributes()));
+    }
+    rows
+}
+
+// The changes of every signal in idcode order, each in timestamp order
+pub(crate) fn change_rows<'a>(
+    header: &VcdHeader,
+    waveform: &'a Waveform,
+) -> impl Iterator<Item = AnalysisResult<ChangeRow>> + 'a {
+    let mut idcodes: Vec<(usize, String)> = header
+        .get_idcodes_map()
+        .keys()
+        .map(|idcode| (*idcode, idcode_string(header, *idcode)))
+        .collect();
+    idcodes.sort_unstable();
+    // Changes are stored once per idcode, so the first variable declared
+    // with an idcode decides its interpretation
+    let mut interpretations: HashMap<usize, VcdInterpretation> = HashMap::new();
+    for (_, variable) in header.get_variables_with_paths() {
+        interpretations
+            .entry(variable.get_idcode())
+            .or_insert(variable.get_interpretation());
+    }
+    let timestamps = waveform.get_timestamps();
+    idcodes.into_iter().flat_map(move |(idcode, name)| {
+        let rows: Vec<AnalysisResult<ChangeRow>> = match waveform.get_signal(idcode) {
+            Some(WaveformSignalResult::Vector(signal)) if !signal.is_empty() => {
+                let interpretation = interpretations.get(&idcode).copied().unwrap_or_default();
+                signal
+                    .get_history()
+                    .into_iter()
+                    .map(|index| {
+                        let bv = signal.get_bitvector(index.get_value_index());
+                        let value_real = match interpretation {
+                            VcdInterpretation::Unsigned | VcdInterpretation::Signed => None,
+                            _ => interpretation.to_f64(&bv),
+                        };
+                        Ok(ChangeRow {
+                            timestamp: timestamps[index.get_timestamp_index()] as i64,
+                            idcode: name.clone(),
+                            value: bitvector_to_string(&bv),
+                            value_int: interpretation.to_i64(&bv),
+                            value_real,
+                        })
+                    })
+                    .collect()
+            }
+            Some(WaveformSignalResult::Real(signal)) if !signal.is_empty() => {
+                vec![Err(AnalysisError::UnreadableReal(idcode))]
+            }
+            _ => Vec::new(),
+        };
+        rows
+    })
+}
+
+fn sql_text(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+fn sql_integer(value: Option<i64>) -> String {
+    match value {
+        Some(value) => format!("{}", value),
+        None => "NULL".to_string(),
+    }
+}
+
+// SQLite has no literal for infinities or NaN, those are stored as NULL
+fn sql_real(value: Option<f64>) -> String {
+    match value {
+        Some(value) if value.is_finite() => format!("{:e}", value),
+        _ => "NULL".to_string(),
+    }
+}
+
+/// Writes the waveform as a SQL script that creates and fills the metadata,
+/// signals, attributes and changes tables, meant to be piped into the sqlite3 shell
+pub fn write_sql(
+    header: &VcdHeader,
+    waveform: &Waveform,
+    writer: &mut dyn io::Write,
+) -> io::Result<()> {
+    writeln!(writer, "BEGIN TRANSACTION;")?;
+    write!(writer, "{}", SCHEMA)?;
+    for (key, value) in metadata_rows(header) {
+        writeln!(
+            writer,
+            "INSERT INTO metadata VALUES ({}, {});",
+            sql_text(key),
+            sql_text(&value)
+        )?;
+    }
+    for row in signal_rows(header) {
+        writeln!(
+            writer,
+            "INSERT INTO signals VALUES ({}, {}, {}, {});",
+            sql_text(&row.path),
+            sql_text(&row.idcode),
+            row.width,
+            sql_text(&row.net_type)
+        )?;
+    }
+    for (path, key, value) in attribute_rows(header) {
+        writeln!(
+            writer,
+            "INSERT INTO attributes VALUES ({}, {}, {});",
+            sql_text(&path),
+            sql_text(&key),
+            sql_text(&value)
+        )?;
+    }
+    for row in change_rows(header, waveform) {
+        let row = row?;
+        writeln!(
+            writer,
+            "INSERT INTO changes VALUES ({}, {}, {}, {}, {});",
+            row.timestamp,
+            sql_text(&row.idcode),
+            sql_text(&row.value),
+            sql_integer(row.value_int),
+            sql_real(row.value_real)
+        )?;
+    }
+    write!(writer, "{}", INDEX)?;
+    writeln!(writer, "COMMIT;")?;
+    Ok(())
+}
+
+/// Creates a SQLite database at the given path holding the waveform, the
+/// tables must not exist yet
+#[cfg(feature = "sqlite")]
+pub fn export_sqlite<P: AsRef<std::path::Path>>(
+    header: &VcdHeader,
+    waveform: &Waveform,
+    path: P,
+) -> ExportResult<()> {
+    let mut conn = rusqlite::Connection::open(path)?;
+    let tx = conn.transaction()?;
+    tx.execute_batch(SCHEMA)?;
+    {
+        let mut stmt = tx.prepare("INSERT INTO metadata VALUES (?1, ?2)")?;
+        for (key, value) in metadata_rows(header) {
+            stmt.execute(rusqlite::params![key, value])?;
+        }
+        let mut stmt = tx.prepare("INSERT INTO signals VALUES (?1, ?2, ?3, ?4)")?;
+        for row in signal_rows(header) {
+            stmt.execute(rusqlite::params![
+                row.path,
+                row.idcode,
+                row.width,
+                row.net_type
+            ])?;
+        }
+        let mut stmt = tx.prepare("INSERT INTO attributes VALUES (?1, ?2, ?3)")?;
+        for (path, key, value) in attribute_rows(header) {
+            stmt.execute(rusqlite::params![path, key, value])?;
+        }
+        let mut stmt = tx.prepare("INSERT INTO changes VALUES (?1, ?2, ?3, ?4, ?5)")?;
+        for row in change_rows(header, waveform) {
+            let row = row?;
+            let value_real = row.value_real.filter(|value| value.is_finite());
+            stmt.execute(rusqlite::params![
+                row.timestamp,
+                row.idcode,
+                row.value,
+                row.value_int,
+                value_real
+            ])?;
+        }
+    }
+    tx.execute_batch(INDEX)?;
+    tx.commit()?;
+    Ok(())
+}
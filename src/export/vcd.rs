@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use crate::export::bitvector_to_string;
+use crate::parser::attrs::VcdAttr;
+use crate::parser::namespace::format_marker;
+use crate::parser::{
+    split_timescale, VcdEntry, VcdHeader, VcdScope, VcdVariableDescription, VcdVariableWidth,
+};
+
+// Returns the idcode for an index using the printable characters from '!' to
+// '~', shortest idcodes first
+pub(crate) fn idcode_string(index: usize) -> String {
+    let mut idcode = String::new();
+    let mut index = index;
+    loop {
+        idcode.push((b'!' + (index % 94) as u8) as char);
+        index /= 94;
+        if index == 0 {
+            break;
+        }
+        index -= 1;
+    }
+    idcode
+}
+
+fn write_attrs(attrs: &[VcdAttr], writer: &mut dyn Write) -> io::Result<()> {
+    for attr in attrs {
+        writeln!(writer, "$attrbegin {} $end", attr)?;
+    }
+    Ok(())
+}
+
+fn write_scope(
+    scope: &VcdScope,
+    idcodes: &HashMap<usize, String>,
+    writer: &mut dyn Write,
+) -> io::Result<()> {
+    write_attrs(scope.get_attrs(), writer)?;
+    writeln!(
+        writer,
+        "$scope {} {} $end",
+        String::from_utf8_lossy(scope.get_type().to_byte_str()),
+        scope.get_name()
+    )?;
+    for variable in scope.get_variables() {
+        let range = match variable.get_description() {
+            VcdVariableDescription::Unspecified => String::new(),
+            VcdVariableDescription::Vector { width } => format!(" [{}]", width),
+            VcdVariableDescription::VectorSelect { msb, lsb } => format!(" [{}:{}]", msb, lsb),
+            VcdVariableDescription::Dimensions(dimensions) => {
+                let dimensions: Vec<String> = dimensions.iter().map(|d| d.to_string()).collect();
+                format!(" {}", dimensions.concat())
+            }
+        };
+        write_attrs(variable.get_attrs(), writer)?;
+        writeln!(
+            writer,
+            "$var {} {} {} {}{} $end",
+            String::from_utf8_lossy(variable.get_net_type().to_byte_str()),
+            variable.get_bit_width(),
+            idcodes[&variable.get_idcode()],
+            variable.get_name(),
+            range
+        )?;
+    }
+    for child in scope.get_scopes() {
+        write_scope(child, idcodes, writer)?;
+    }
+    writeln!(writer, "$upscope $end")
+}
+
+/// Writes the declarations of a header, ending with $enddefinitions, and
+/// returns the new idcode assigned to every original idcode, new idcodes are
+/// assigned in order of the original ids so the output is deterministic
+pub(crate) fn write_header(
+    header: &VcdHeader,
+    writer: &mut dyn Write,
+) -> io::Result<HashMap<usize, String>> {
+    let mut ids: Vec<usize> = header.get_idcodes_map().keys().copied().collect();
+    ids.sort_unstable();
+    let idcodes: HashMap<usize, String> = ids
+        .into_iter()
+        .enumerate()
+        .map(|(index, id)| (id, idcode_string(index)))
+        .collect();
+    if let Some(date) = header.get_date() {
+        writeln!(writer, "$date{}$end", date)?;
+    }
+    if let Some(version) = header.get_version() {
+        writeln!(writer, "$version{}$end", version)?;
+    }
+    for comment in header.get_comments() {
+        writeln!(writer, "$comment{}$end", comment)?;
+    }
+    if let Some((unit, offset)) = header.get_timescale().and_then(split_timescale) {
+        writeln!(
+            writer,
+            "$timescale {}{} $end",
+            String::from_utf8_lossy(offset.to_byte_str()),
+            String::from_utf8_lossy(unit.to_byte_str())
+        )?;
+    }
+    for scope in header.get_scopes() {
+        write_scope(scope, &idcodes, writer)?;
+    }
+    for namespace in header.get_namespaces() {
+        writeln!(
+            writer,
+            "$comment{}$end",
+            format_marker(namespace.get_name())
+        )?;
+        for scope in namespace.get_scopes() {
+            write_scope(scope, &idcodes, writer)?;
+        }
+    }
+    write_attrs(header.get_attrs(), writer)?;
+    writeln!(writer, "$enddefinitions $end")?;
+    Ok(idcodes)
+}
+
+/// Writes a value change of a variable with the given width, timestamps are
+/// written as is and ignore the idcode
+pub(crate) fn write_entry(
+    entry: &VcdEntry,
+    width: &VcdVariableWidth,
+    idcode: &str,
+    writer: &mut dyn Write,
+) -> io::Result<()> {
+    match (entry, width) {
+        (VcdEntry::Timestamp(timestamp), _) => writeln!(writer, "#{}", timestamp),
+        // Events are read back as their triggers, see variable_changes
+        (VcdEntry::Event(_), _) | (_, VcdVariableWidth::Event) => writeln!(writer, "1{}", idcode),
+        (VcdEntry::Scalar(bit, _), VcdVariableWidth::Vector { width: 1 }) => {
+            writeln!(writer, "{}{}", bit.to_str(), idcode)
+        }
+        (VcdEntry::Scalar(bit, _), _) => writeln!(writer, "b{} {}", bit.to_str(), idcode),
+        (VcdEntry::Vector(bv, _), VcdVariableWidth::Vector { width: 1 }) => {
+            writeln!(writer, "{}{}", bitvector_to_string(bv), idcode)
+        }
+        (VcdEntry::Vector(bv, _), _) => writeln!(writer, "b{} {}", bitvector_to_string(bv), idcode),
+        (VcdEntry::Real(value, _), _) => writeln!(writer, "r{:?} {}", value, idcode),
+        (VcdEntry::Port(value, _), _) => writeln!(writer, "{} {}", value, idcode),
+        (VcdEntry::String(value, _), _) => {
+            writeln!(writer, "s{} {}", String::from_utf8_lossy(value), idcode)
+        }
+    }
+}
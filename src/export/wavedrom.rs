@@ -0,0 +1,162 @@
+use std::ops::RangeInclusive;
+
+use makai_waveform_db::bitvector::{BitVector, Logic};
+use makai_waveform_db::Waveform;
+
+use crate::analysis::{bitvector_to_u64, variable_changes, AnalysisResult};
+use crate::export::json_string;
+use crate::parser::interpretation::VcdInterpretation;
+use crate::parser::{VcdEntry, VcdHeader, VcdVariableWidth};
+
+/// Controls the window and ticks drawn by to_wavedrom
+#[derive(Clone, Debug)]
+pub struct VcdWaveDromOptions {
+    range: RangeInclusive<u64>,
+    step: Option<u64>,
+}
+
+impl VcdWaveDromOptions {
+    pub fn new(range: RangeInclusive<u64>) -> Self {
+        Self { range, step: None }
+    }
+
+    /// Draws a tick every step timestamps from the start of the window, a
+    /// signal shows the value it held at each tick. None by default, which
+    /// draws a tick at the start and at every change in the window.
+    pub fn set_step(&mut self, step: Option<u64>) {
+        self.step = step.filter(|step| *step > 0);
+    }
+}
+
+// Bus labels are hex unless the variable has an interpretation
+fn bus_label(bv: &BitVector, interpretation: VcdInterpretation) -> String {
+    match (interpretation, bitvector_to_u64(bv)) {
+        (VcdInterpretation::Unsigned, Some(value)) => format!("0x{:x}", value),
+        _ => interpretation.format(bv),
+    }
+}
+
+// The wave character of a value and its label, only buses and reals have one
+fn sample(entry: Option<&VcdEntry>, interpretation: VcdInterpretation) -> (char, Option<String>) {
+    let bv = match entry {
+        Some(VcdEntry::Vector(bv, _)) => bv,
+        Some(VcdEntry::Real(value, _)) => return ('=', Some(format!("{}", value))),
+        _ => return ('x', None),
+    };
+    let bits: Vec<Logic> = (0..bv.get_bit_width()).map(|i| bv.get_bit(i)).collect();
+    match bits.as_slice() {
+        [Logic::Zero] => ('0', None),
+        [Logic::One] => ('1', None),
+        _ if bits.iter().all(|bit| *bit == Logic::Unknown) => ('x', None),
+        _ if bits.iter().all(|bit| *bit == Logic::HighImpedance) => ('z', None),
+        _ => ('=', Some(bus_label(bv, interpretation))),
+    }
+}
+
+// Formats the wave of one signal, a value held from the last tick is a '.'
+fn format_signal(
+    path: &str,
+    changes: &[(u64, VcdEntry)],
+    width: &VcdVariableWidth,
+    interpretation: VcdInterpretation,
+    ticks: &[u64],
+) -> String {
+    let mut wave = String::new();
+    let mut data = Vec::new();
+    let mut last: Option<(char, Option<String>)> = None;
+    let mut next = 0;
+    let mut previous_tick = None;
+    for tick in ticks {
+        let first = next;
+        while next < changes.len() && changes[next].0 <= *tick {
+            next += 1;
+        }
+        if *width == VcdVariableWidth::Event {
+            // An event is high at the ticks it triggered at or since the
+            // tick before, there is no value to hold
+            let triggered = changes[first..next].iter().any(|(timestamp, _)| {
+                previous_tick.map_or(timestamp == tick, |previous| *timestamp > previous)
+            });
+            wave.push(if triggered { '1' } else { '0' });
+        } else {
+            let current = sample(
+                next.checked_sub(1).map(|index| &changes[index].1),
+                interpretation,
+            );
+            if last.as_ref() == Some(&current) {
+                wave.push('.');
+            } else {
+                wave.push(current.0);
+                data.extend(current.1.clone());
+                last = Some(current);
+            }
+        }
+        previous_tick = Some(*tick);
+    }
+    let mut json = format!(
+        "  {{\"name\": {}, \"wave\": {}",
+        json_string(path),
+        json_string(&wave)
+    );
+    if !data.is_empty() {
+        let data: Vec<String> = data.iter().map(|label| json_string(label)).collect();
+        json.push_str(&format!(", \"data\": [{}]", data.join(", ")));
+    }
+    json.push('}');
+    json
+}
+
+/// Draws the variables passing the filter over a window as WaveDrom `signal`
+/// JSON, for timing diagrams in documentation. Single bits are drawn as
+/// levels, buses and reals with their values as labels, and events high at
+/// the ticks they triggered at. Every tick is a column of the diagram, so
+/// this is meant for small windows.
+pub fn to_wavedrom<F: Fn(&str) -> bool>(
+    header: &VcdHeader,
+    waveform: &Waveform,
+    filter: F,
+    options: &VcdWaveDromOptions,
+) -> AnalysisResult<String> {
+    let (start, end) = (*options.range.start(), *options.range.end());
+    let widths = header.get_idcodes_map();
+    let signals: Vec<_> = header
+        .get_variables_with_paths()
+        .into_iter()
+        .filter(|(path, _)| filter(path))
+        .map(|(path, variable)| {
+            let idcode = variable.get_idcode();
+            Ok((
+                path,
+                idcode,
+                variable.get_interpretation(),
+                variable_changes(waveform, idcode, variable.get_width())?,
+            ))
+        })
+        .collect::<AnalysisResult<_>>()?;
+
+    let ticks: Vec<u64> = match options.step {
+        _ if start > end => Vec::new(),
+        Some(step) => (0..=(end - start) / step)
+            .map(|i| start + i * step)
+            .collect(),
+        None => {
+            let mut ticks: Vec<u64> = signals
+                .iter()
+                .flat_map(|(_, _, _, changes)| changes.iter().map(|(timestamp, _)| *timestamp))
+                .filter(|timestamp| (start..=end).contains(timestamp))
+                .chain([start])
+                .collect();
+            ticks.sort_unstable();
+            ticks.dedup();
+            ticks
+        }
+    };
+
+    let lines: Vec<String> = signals
+        .iter()
+        .map(|(path, idcode, interpretation, changes)| {
+            format_signal(path, changes, &widths[idcode], *interpretation, &ticks)
+        })
+        .collect();
+    Ok(format!("{{\"signal\": [\n{}\n]}}\n", lines.join(",\n")))
+}
@@ -0,0 +1,199 @@
+use std::fs::File;
+#[cfg(feature = "gzip")]
+use std::io::BufReader;
+use std::io::Read;
+#[cfg(feature = "gzip")]
+use std::io::{Seek, SeekFrom};
+use std::path::Path;
+#[cfg(feature = "parallel")]
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "parallel")]
+use std::thread;
+
+#[cfg(feature = "gzip")]
+use flate2::read::MultiGzDecoder;
+
+use makai_waveform_db::Waveform;
+
+use crate::parser::VcdHeader;
+#[cfg(feature = "gzip")]
+use crate::utils::load_from_reader_with_options;
+#[cfg(feature = "parallel")]
+use crate::utils::load_multi_threaded_with_options;
+#[cfg(not(feature = "parallel"))]
+use crate::utils::load_single_threaded_with_options;
+use crate::utils::{VcdError, VcdLoadOptions, VcdResult};
+
+// How far into a text dump to look for extended VCD declarations
+const SNIFF_LIMIT: usize = 64 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VcdFileFormat {
+    Vcd,
+    // Extended VCD with port values, from $dumpports
+    Evcd,
+    Fst,
+    Ghw,
+    // Compressed with gzip, the format inside is not known until inflated
+    Gzip,
+    Unknown,
+}
+
+impl std::fmt::Display for VcdFileFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Vcd => write!(f, "VCD"),
+            Self::Evcd => write!(f, "extended VCD"),
+            Self::Fst => write!(f, "FST"),
+            Self::Ghw => write!(f, "GHW"),
+            Self::Gzip => write!(f, "gzip"),
+            Self::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle)
+}
+
+/// Guesses the format of a waveform file from its first bytes, text dumps
+/// are told apart by looking for port declarations near the start
+pub fn detect_format(bytes: &[u8]) -> VcdFileFormat {
+    if bytes.starts_with(&[0x1f, 0x8b]) {
+        return VcdFileFormat::Gzip;
+    }
+    if bytes.starts_with(b"GHDLwave\n") {
+        return VcdFileFormat::Ghw;
+    }
+    // FST files start with a header block, type zero and a length of 329
+    if bytes.starts_with(&[0, 0, 0, 0, 0, 0, 0, 0x01, 0x49]) {
+        return VcdFileFormat::Fst;
+    }
+    let head = &bytes[..bytes.len().min(SNIFF_LIMIT)];
+    match head.iter().find(|b| !b.is_ascii_whitespace()) {
+        Some(b'$') | Some(b'#') => {}
+        _ => return VcdFileFormat::Unknown,
+    }
+    if contains(head, b"$dumpports") || contains(head, b"$var port") {
+        VcdFileFormat::Evcd
+    } else {
+        VcdFileFormat::Vcd
+    }
+}
+
+/// Loads a gzipped VCD, decompressing it as it is parsed so neither the
+/// compressed nor the uncompressed file is ever held in memory whole.
+/// Files made of several concatenated gzip members are read through to the
+/// last member. Progress is reported as the number of uncompressed bytes
+/// parsed so far.
+#[cfg(feature = "gzip")]
+pub fn load_gzip<R: Read>(
+    reader: R,
+    options: VcdLoadOptions,
+    status: &mut dyn FnMut(usize),
+) -> VcdResult<(VcdHeader, Waveform)> {
+    let reader = BufReader::new(MultiGzDecoder::new(reader));
+    load_from_reader_with_options(reader, options, status)
+}
+
+/// Decompresses a gzipped VCD into memory, for the multi-threaded loader
+/// which needs the whole file
+#[cfg(feature = "gzip")]
+pub fn read_gzip<R: Read>(reader: R) -> VcdResult<String> {
+    let mut bytes = String::new();
+    MultiGzDecoder::new(reader).read_to_string(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// A waveform file loaded in one call, hiding the lexer, tokenizer, reader and
+/// waveform pipeline behind the file format detection of open
+pub struct VcdFile {
+    header: VcdHeader,
+    waveform: Waveform,
+    format: VcdFileFormat,
+}
+
+impl VcdFile {
+    /// Loads a file without reporting progress, on every available core when
+    /// the parallel feature is enabled
+    pub fn open<P: AsRef<Path>>(path: P) -> VcdResult<Self> {
+        let mut options = VcdLoadOptions::new();
+        options.set_progress_steps(None);
+        Self::open_with_options(path, options)
+    }
+
+    /// Loads a file with the given options, gzipped files are always
+    /// decompressed and parsed on the calling thread
+    pub fn open_with_options<P: AsRef<Path>>(path: P, options: VcdLoadOptions) -> VcdResult<Self> {
+        let mut file = File::open(path)?;
+        // Only the start of the file is needed to tell the format apart
+        let mut bytes = Vec::new();
+        (&mut file)
+            .take(SNIFF_LIMIT as u64)
+            .read_to_end(&mut bytes)?;
+        let format = detect_format(&bytes);
+        let (header, waveform) = match format {
+            // Extended VCDs are read by the same lexer
+            VcdFileFormat::Vcd | VcdFileFormat::Evcd => {
+                file.read_to_end(&mut bytes)?;
+                let bytes = String::from_utf8(bytes).map_err(|err| {
+                    VcdError::Io(std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+                })?;
+                load_vcd(bytes, options)?
+            }
+            #[cfg(feature = "gzip")]
+            VcdFileFormat::Gzip => {
+                // Inflated straight from the file, the compressed bytes are
+                // never held in memory whole
+                file.seek(SeekFrom::Start(0))?;
+                load_gzip(file, options, &mut |_| {})?
+            }
+            format => return Err(VcdError::UnsupportedFormat(format)),
+        };
+        Ok(Self {
+            header,
+            waveform,
+            format,
+        })
+    }
+
+    pub fn get_header(&self) -> &VcdHeader {
+        &self.header
+    }
+
+    pub fn get_waveform(&self) -> &Waveform {
+        &self.waveform
+    }
+
+    /// Returns the format the file was detected as, before decompression
+    pub fn get_format(&self) -> VcdFileFormat {
+        self.format
+    }
+
+    pub fn into_parts(self) -> (VcdHeader, Waveform) {
+        (self.header, self.waveform)
+    }
+}
+
+#[cfg(feature = "parallel")]
+fn load_vcd(bytes: String, options: VcdLoadOptions) -> VcdResult<(VcdHeader, Waveform)> {
+    let threads = thread::available_parallelism().map_or(1, |threads| threads.get());
+    let status = Arc::new(Mutex::new((0, 0)));
+    load_multi_threaded_with_options(bytes, threads, options, status)
+        .join()
+        .unwrap()
+}
+
+#[cfg(not(feature = "parallel"))]
+fn load_vcd(bytes: String, options: VcdLoadOptions) -> VcdResult<(VcdHeader, Waveform)> {
+    load_single_threaded_with_options(bytes, options, &mut |_| {})
+}
+
+/// Loads any supported waveform file, currently only VCD, extended VCD and
+/// gzipped VCD with the gzip feature, other formats fail with an error naming
+/// the detected format
+pub fn open<P: AsRef<Path>>(path: P) -> VcdResult<(VcdHeader, Waveform)> {
+    VcdFile::open(path).map(VcdFile::into_parts)
+}
@@ -0,0 +1,225 @@
+#[cfg(feature = "fuzz")]
+use arbitrary::{Arbitrary, Unstructured};
+use makai::utils::bytes::ByteStorage;
+
+use crate::lexer::Lexer;
+use crate::tokenizer::Tokenizer;
+use crate::utils::{load_single_threaded, VcdResult};
+
+// Tokenizes a source and writes every token back out in canonical form
+fn rewrite(s: &str) -> VcdResult<Vec<u8>> {
+    let mut lexer = Lexer::new(s);
+    let mut tokenizer = Tokenizer::new(s);
+    let mut bs = ByteStorage::new();
+    let mut written = Vec::new();
+    while let Some(token) = tokenizer.next(lexer.next_token()?, &mut bs)? {
+        token.write_to(&bs, &mut written)?;
+    }
+    Ok(written)
+}
+
+/// Runs arbitrary bytes through the tokenizer, parser and loader, returning
+/// any error in the input instead of panicking. Inputs that tokenize are also
+/// written back out and tokenized again, which must give the same output, a
+/// mismatch is a bug in the crate and panics so fuzzers pick it up.
+pub fn check(bytes: &[u8]) -> VcdResult<()> {
+    let s = String::from_utf8_lossy(bytes);
+    let written = rewrite(&s)?;
+    let rewritten = rewrite(&String::from_utf8_lossy(&written))
+        .expect("written tokens could not be tokenized again");
+    assert!(
+        written == rewritten,
+        "written tokens changed after a round trip"
+    );
+    load_single_threaded(s.into_owned(), &mut |_| {})?;
+    Ok(())
+}
+
+/// A dump built from well-formed declarations and value changes, meant to be
+/// generated by fuzzers, every generated dump should pass check
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VcdFuzzInput {
+    text: String,
+}
+
+impl VcdFuzzInput {
+    pub fn get_text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn into_text(self) -> String {
+        self.text
+    }
+}
+
+#[cfg(feature = "fuzz")]
+#[derive(Clone, Copy)]
+enum VcdFuzzKind {
+    Vector(usize),
+    Real,
+    Event,
+}
+
+#[cfg(feature = "fuzz")]
+const FUZZ_NET_TYPES: &[&str] = &[
+    "wire",
+    "reg",
+    "integer",
+    "parameter",
+    "tri",
+    "wand",
+    "wor",
+    "supply0",
+    "supply1",
+];
+
+#[cfg(feature = "fuzz")]
+fn fuzz_idcode(index: usize) -> String {
+    let mut idcode = String::new();
+    let mut index = index;
+    loop {
+        idcode.push((b'!' + (index % 94) as u8) as char);
+        index /= 94;
+        if index == 0 {
+            break;
+        }
+        index -= 1;
+    }
+    idcode
+}
+
+#[cfg(feature = "fuzz")]
+fn fuzz_name(u: &mut Unstructured) -> arbitrary::Result<String> {
+    let len = u.int_in_range(1..=8)?;
+    let mut name = String::new();
+    for _ in 0..len {
+        name.push(*u.choose(&['a', 'b', 'c', 'x', 'y', 'z', '_', '0', '1', '9'])?);
+    }
+    Ok(name)
+}
+
+#[cfg(feature = "fuzz")]
+fn fuzz_declarations(
+    u: &mut Unstructured,
+    text: &mut String,
+    signals: &mut Vec<(String, VcdFuzzKind)>,
+    depth: usize,
+) -> arbitrary::Result<()> {
+    let scope_type = u.choose(&["module", "task", "function", "begin", "fork"])?;
+    text.push_str(&format!("$scope {} {} $end\n", scope_type, fuzz_name(u)?));
+    for _ in 0..u.int_in_range(0..=8)? {
+        // Occasionally alias an existing signal of the same kind
+        if !signals.is_empty() && u.ratio(1, 8)? {
+            let (idcode, kind) = signals[u.int_in_range(0..=signals.len() - 1)?].clone();
+            let declaration = match kind {
+                VcdFuzzKind::Vector(width) => format!("wire {}", width),
+                VcdFuzzKind::Real => "real 64".to_string(),
+                VcdFuzzKind::Event => "event 1".to_string(),
+            };
+            text.push_str(&format!(
+                "$var {} {} {} $end\n",
+                declaration,
+                idcode,
+                fuzz_name(u)?
+            ));
+            continue;
+        }
+        let idcode = fuzz_idcode(signals.len());
+        let kind = match u.int_in_range(0..=9)? {
+            0 => VcdFuzzKind::Real,
+            1 => VcdFuzzKind::Event,
+            _ => VcdFuzzKind::Vector(u.int_in_range(1..=96)?),
+        };
+        let declaration = match kind {
+            VcdFuzzKind::Vector(width) => {
+                let net_type = u.choose(FUZZ_NET_TYPES)?;
+                let range = match u.int_in_range(0..=2)? {
+                    0 => String::new(),
+                    1 => format!(" [{}]", width),
+                    _ => format!(" [{}:0]", width - 1),
+                };
+                format!(
+                    "{} {} {} {}{}",
+                    net_type,
+                    width,
+                    idcode,
+                    fuzz_name(u)?,
+                    range
+                )
+            }
+            VcdFuzzKind::Real => format!("real 64 {} {}", idcode, fuzz_name(u)?),
+            VcdFuzzKind::Event => format!("event 1 {} {}", idcode, fuzz_name(u)?),
+        };
+        text.push_str(&format!("$var {} $end\n", declaration));
+        signals.push((idcode, kind));
+    }
+    if depth < 3 {
+        for _ in 0..u.int_in_range(0..=2)? {
+            fuzz_declarations(u, text, signals, depth + 1)?;
+        }
+    }
+    text.push_str("$upscope $end\n");
+    Ok(())
+}
+
+#[cfg(feature = "fuzz")]
+fn fuzz_value(u: &mut Unstructured, idcode: &str, kind: VcdFuzzKind) -> arbitrary::Result<String> {
+    Ok(match kind {
+        VcdFuzzKind::Vector(1) => format!("{}{}", u.choose(&['0', '1', 'x', 'z'])?, idcode),
+        VcdFuzzKind::Vector(width) => {
+            let mut bits = String::new();
+            for _ in 0..width {
+                bits.push(*u.choose(&['0', '1', 'x', 'z'])?);
+            }
+            format!("b{} {}", bits, idcode)
+        }
+        VcdFuzzKind::Real => {
+            let value = u.int_in_range(0..=1_000_000u32)? as f64 / 1000.0;
+            format!("r{:?} {}", value, idcode)
+        }
+        VcdFuzzKind::Event => format!("1{}", idcode),
+    })
+}
+
+#[cfg(feature = "fuzz")]
+impl<'a> Arbitrary<'a> for VcdFuzzInput {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        let mut text = String::new();
+        if u.arbitrary()? {
+            text.push_str("$date fuzz $end\n");
+        }
+        if u.arbitrary()? {
+            text.push_str("$version fuzz $end\n");
+        }
+        if u.arbitrary()? {
+            text.push_str(&format!(
+                "$timescale {}{} $end\n",
+                u.choose(&["1", "10", "100"])?,
+                u.choose(&["fs", "ps", "ns", "us", "ms", "s"])?
+            ));
+        }
+        let mut signals = Vec::new();
+        for _ in 0..u.int_in_range(0..=2)? {
+            fuzz_declarations(u, &mut text, &mut signals, 0)?;
+        }
+        text.push_str("$enddefinitions $end\n");
+        let mut timestamp = 0u64;
+        for step in 0..u.int_in_range(0..=32)? {
+            if step > 0 {
+                timestamp += u.int_in_range(1..=1000)?;
+            }
+            text.push_str(&format!("#{}\n", timestamp));
+            if u.ratio(1, 8)? {
+                text.push_str("$comment fuzz $end\n");
+            }
+            // Each signal changes at most once per timestamp
+            for (idcode, kind) in &signals {
+                if u.ratio(1, 3)? {
+                    text.push_str(&fuzz_value(u, idcode, *kind)?);
+                    text.push('\n');
+                }
+            }
+        }
+        Ok(Self { text })
+    }
+}
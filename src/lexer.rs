@@ -1,4 +1,8 @@
+pub mod incremental;
+pub mod line_index;
 pub mod position;
+pub mod scan;
+pub mod stream;
 
 use core::ops::Range;
 
@@ -24,15 +28,56 @@ fn count_newlines(lex: &mut logos::Lexer<LogosToken>) -> (usize, usize) {
     (newlines, columns)
 }
 
+// State shared with the callbacks of the generated lexer
+#[derive(Clone, Copy, Debug, Default)]
+struct LogosExtras {
+    // Index past which an unformatted block is not searched for its $end
+    block_limit: Option<usize>,
+    // Whether the last block failed because it reached the limit
+    limited: bool,
+}
+
+// Extends an unformatted block to the first $end keyword, a $end followed by
+// whitespace or the end of the input. The text before it can hold anything
+// else, like dollar signs or words starting with $end.
+fn scan_block(lex: &mut logos::Lexer<LogosToken>) -> Option<(usize, usize)> {
+    let remainder = lex.remainder().as_bytes();
+    // The $end has to end by the limit, the bytes after it are never read
+    let searched = match lex.extras.block_limit {
+        Some(limit) => limit.saturating_sub(lex.span().end).min(remainder.len()),
+        None => remainder.len(),
+    };
+    let mut start = 0;
+    loop {
+        let Some(position) = remainder[start..searched]
+            .windows(b"$end".len())
+            .position(|window| window == b"$end")
+        else {
+            lex.extras.limited = searched < remainder.len();
+            return None;
+        };
+        let found = start + position;
+        let end = found + b"$end".len();
+        if remainder.get(end).map_or(true, |b| b.is_ascii_whitespace()) {
+            lex.bump(end);
+            return Some(count_newlines(lex));
+        }
+        start = found + 1;
+    }
+}
+
 #[derive(Logos, Debug, PartialEq)]
+#[logos(extras = LogosExtras)]
 enum LogosToken {
     // Unformatted blocks
-    #[regex(r"\$comment[^$]*\$+([^\$e][^\$]*\$+)*end", count_newlines)]
+    #[token("$comment", scan_block)]
     SectionComment((usize, usize)),
-    #[regex(r"\$date[^$]*\$+([^\$e][^\$]*\$+)*end", count_newlines)]
+    #[token("$date", scan_block)]
     SectionDate((usize, usize)),
-    #[regex(r"\$version[^$]*\$+([^\$e][^\$]*\$+)*end", count_newlines)]
+    #[token("$version", scan_block)]
     SectionVersion((usize, usize)),
+    #[token("$attrbegin", scan_block)]
+    SectionAttrBegin((usize, usize)),
     // Formatted blocks
     #[regex(r"\$scope[\s]+[\S]+[\s]+[\S]+[\s]+\$end", count_newlines)]
     SectionScope((usize, usize)),
@@ -42,7 +87,7 @@ enum LogosToken {
     )]
     SectionTimescale((usize, usize)),
     #[regex(
-        r"\$var[\s]+[\S]+[\s]+[1-9][0-9_]*[\s]+[\x21-\x7E]+[\s]+[\S]+[\s]+(\[(0|([1-9][0-9_]*))([:](0|([1-9][0-9_]*)))?\][\s]+)?\$end",
+        r"\$var[\s]+[\S]+[\s]+([0-9][0-9_]*|\[[0-9][0-9_]*:[0-9][0-9_]*\])[\s]+[\S]+[\s]+[\S]+[\s]+(\[[0-9][0-9_]*([:][0-9][0-9_]*)?\][\s]*)*\$end",
         count_newlines
     )]
     SectionVar((usize, usize)),
@@ -51,6 +96,10 @@ enum LogosToken {
     SectionUpScope((usize, usize)),
     #[regex(r"\$enddefinitions[\s]*\$end", count_newlines)]
     SectionEndDefinitions((usize, usize)),
+    #[regex(r"\$attrend[\s]*\$end", count_newlines)]
+    SectionAttrEnd((usize, usize)),
+    #[regex(r"\$vcdclose[\s]+#[0-9]+[\s]+\$end", count_newlines)]
+    SectionVcdClose((usize, usize)),
     // Simulation commands
     #[regex(r"\$dumpall")]
     CommandDumpAll,
@@ -60,25 +109,48 @@ enum LogosToken {
     CommandDumpOn,
     #[regex(r"\$dumpvars")]
     CommandDumpVars,
+    // Extended VCD spellings of the commands above
+    #[regex(r"\$dumpportsall")]
+    CommandDumpPortsAll,
+    #[regex(r"\$dumpportsoff")]
+    CommandDumpPortsOff,
+    #[regex(r"\$dumpportson")]
+    CommandDumpPortsOn,
+    #[regex(r"\$dumpports")]
+    CommandDumpPorts,
     #[regex(r"\$end")]
     CommandEnd,
-    // Simulation values
+    // Any other keyword, a block of an unknown directive if it is not a
+    // malformed known one
+    #[regex(r"\$[a-zA-Z_][a-zA-Z0-9_]*")]
+    Directive,
+    // Simulation values, idcodes outside of the printable range are accepted
+    // here and reported by the parser if validation is enabled
     #[regex(r"#[ ]*([0]|([1-9][0-9]*))")]
     Timestamp,
-    #[regex(r"[0][\x21-\x7E]+")]
+    #[regex(r"[0][\S]+")]
     ScalarZero,
-    #[regex(r"[1][\x21-\x7E]+")]
+    #[regex(r"[1][\S]+")]
     ScalarOne,
-    #[regex(r"[xX][\x21-\x7E]+")]
+    #[regex(r"[xX][\S]+")]
     ScalarUnknown,
-    #[regex(r"[zZ][\x21-\x7E]+")]
+    #[regex(r"[zZ][\S]+")]
     ScalarHighImpedance,
-    #[regex(r"[bB][01]+[ ]+[\x21-\x7E]+", priority = 1)]
+    #[regex(r"[bB][01]+[ ]+[\S]+", priority = 1)]
     VectorValue,
-    #[regex(r"[bB][01xXzZ]+[ ]+[\x21-\x7E]+", priority = 0)]
+    #[regex(r"[bB][01xXzZ]+[ ]+[\S]+", priority = 0)]
     VectorValueFourState,
-    #[regex(r"[rR](([1-9][0-9]*|[0])[.][0-9]+)[ ]+[\x21-\x7E]+")]
+    // Signs, integer mantissas and exponents are accepted, as written by most
+    // simulators and by the exporters (r-1.5, r1e-9, r3.2E5, r0)
+    #[regex(r"[rR][+-]?([0-9]+([.][0-9]*)?|[.][0-9]+)([eE][+-]?[0-9]+)?[ ]+[\S]+")]
     RealValue,
+    // SystemVerilog strings, which cannot hold whitespace
+    #[regex(r"s[\S]*[ ]+[\S]+")]
+    StringValue,
+    // Extended VCD port values, the states are followed by the strengths of
+    // their zero and one components
+    #[regex(r"p[01?DUNZduLHXTlhFfAaBbCc]+[ ]+[0-7]+[ ]+[0-7]+[ ]+[\S]+")]
+    PortValue,
     // Whitespace
     #[token("\n")]
     NewLine,
@@ -97,8 +169,14 @@ pub enum LexerToken {
     SectionScope(ByteRange, LexerPosition),
     SectionTimescale(ByteRange, LexerPosition),
     SectionVar(ByteRange, LexerPosition),
+    SectionAttrBegin(ByteRange, LexerPosition),
     SectionUpScope(LexerPosition),
     SectionEndDefinitions(LexerPosition),
+    SectionAttrEnd(LexerPosition),
+    SectionVcdClose(ByteRange, LexerPosition),
+    // A directive the lexer does not know, like $timezero, only lexed when
+    // unknown directives are accepted
+    SectionUnknown(ByteRange, LexerPosition),
     CommandDumpAll(LexerPosition),
     CommandDumpOff(LexerPosition),
     CommandDumpOn(LexerPosition),
@@ -112,6 +190,131 @@ pub enum LexerToken {
     VectorValue(ByteRange, LexerPosition),
     VectorValueFourState(ByteRange, LexerPosition),
     RealValue(ByteRange, LexerPosition),
+    StringValue(ByteRange, LexerPosition),
+    PortValue(ByteRange, LexerPosition),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LexerHighlight {
+    // Free-form text blocks like $comment, $date and $version
+    Comment,
+    // Header declarations like $scope, $var and $timescale
+    Declaration,
+    // Simulation commands like $dumpvars and $end
+    Command,
+    Timestamp,
+    Scalar,
+    Vector,
+    Real,
+}
+
+impl LexerToken {
+    pub fn get_position(&self) -> LexerPosition {
+        match self {
+            Self::SectionComment(_, pos)
+            | Self::SectionDate(_, pos)
+            | Self::SectionVersion(_, pos)
+            | Self::SectionScope(_, pos)
+            | Self::SectionTimescale(_, pos)
+            | Self::SectionVar(_, pos)
+            | Self::SectionAttrBegin(_, pos)
+            | Self::SectionUpScope(pos)
+            | Self::SectionEndDefinitions(pos)
+            | Self::SectionAttrEnd(pos)
+            | Self::SectionVcdClose(_, pos)
+            | Self::SectionUnknown(_, pos)
+            | Self::CommandDumpAll(pos)
+            | Self::CommandDumpOff(pos)
+            | Self::CommandDumpOn(pos)
+            | Self::CommandDumpVars(pos)
+            | Self::CommandEnd(pos)
+            | Self::Timestamp(_, pos)
+            | Self::ScalarZero(_, pos)
+            | Self::ScalarOne(_, pos)
+            | Self::ScalarUnknown(_, pos)
+            | Self::ScalarHighImpedance(_, pos)
+            | Self::VectorValue(_, pos)
+            | Self::VectorValueFourState(_, pos)
+            | Self::RealValue(_, pos)
+            | Self::StringValue(_, pos)
+            | Self::PortValue(_, pos) => *pos,
+        }
+    }
+
+    /// Returns the byte range of the whole token in the source
+    pub fn get_range(&self) -> ByteRange {
+        self.get_position().get_range()
+    }
+
+    pub fn get_highlight(&self) -> LexerHighlight {
+        match self {
+            Self::SectionComment(_, _)
+            | Self::SectionDate(_, _)
+            | Self::SectionVersion(_, _)
+            | Self::SectionUnknown(_, _) => LexerHighlight::Comment,
+            Self::SectionScope(_, _)
+            | Self::SectionTimescale(_, _)
+            | Self::SectionVar(_, _)
+            | Self::SectionAttrBegin(_, _)
+            | Self::SectionUpScope(_)
+            | Self::SectionEndDefinitions(_)
+            | Self::SectionAttrEnd(_) => LexerHighlight::Declaration,
+            Self::CommandDumpAll(_)
+            | Self::CommandDumpOff(_)
+            | Self::CommandDumpOn(_)
+            | Self::CommandDumpVars(_)
+            | Self::CommandEnd(_)
+            | Self::SectionVcdClose(_, _) => LexerHighlight::Command,
+            Self::Timestamp(_, _) => LexerHighlight::Timestamp,
+            Self::ScalarZero(_, _)
+            | Self::ScalarOne(_, _)
+            | Self::ScalarUnknown(_, _)
+            | Self::ScalarHighImpedance(_, _) => LexerHighlight::Scalar,
+            Self::VectorValue(_, _) | Self::VectorValueFourState(_, _) | Self::PortValue(_, _) => {
+                LexerHighlight::Vector
+            }
+            Self::RealValue(_, _) | Self::StringValue(_, _) => LexerHighlight::Real,
+        }
+    }
+
+    /// Moves the token to a new position, shifting any inner byte range by
+    /// the same amount as the position index
+    pub fn with_position(&self, new_pos: LexerPosition) -> Self {
+        let old_index = self.get_position().get_index();
+        let shift = |span: &ByteRange| {
+            (span.start - old_index + new_pos.get_index())
+                ..(span.end - old_index + new_pos.get_index())
+        };
+        match self {
+            Self::SectionComment(span, _) => Self::SectionComment(shift(span), new_pos),
+            Self::SectionDate(span, _) => Self::SectionDate(shift(span), new_pos),
+            Self::SectionVersion(span, _) => Self::SectionVersion(shift(span), new_pos),
+            Self::SectionScope(span, _) => Self::SectionScope(shift(span), new_pos),
+            Self::SectionTimescale(span, _) => Self::SectionTimescale(shift(span), new_pos),
+            Self::SectionVar(span, _) => Self::SectionVar(shift(span), new_pos),
+            Self::SectionAttrBegin(span, _) => Self::SectionAttrBegin(shift(span), new_pos),
+            Self::SectionUpScope(_) => Self::SectionUpScope(new_pos),
+            Self::SectionEndDefinitions(_) => Self::SectionEndDefinitions(new_pos),
+            Self::SectionAttrEnd(_) => Self::SectionAttrEnd(new_pos),
+            Self::SectionVcdClose(span, _) => Self::SectionVcdClose(shift(span), new_pos),
+            Self::SectionUnknown(span, _) => Self::SectionUnknown(shift(span), new_pos),
+            Self::CommandDumpAll(_) => Self::CommandDumpAll(new_pos),
+            Self::CommandDumpOff(_) => Self::CommandDumpOff(new_pos),
+            Self::CommandDumpOn(_) => Self::CommandDumpOn(new_pos),
+            Self::CommandDumpVars(_) => Self::CommandDumpVars(new_pos),
+            Self::CommandEnd(_) => Self::CommandEnd(new_pos),
+            Self::Timestamp(span, _) => Self::Timestamp(shift(span), new_pos),
+            Self::ScalarZero(span, _) => Self::ScalarZero(shift(span), new_pos),
+            Self::ScalarOne(span, _) => Self::ScalarOne(shift(span), new_pos),
+            Self::ScalarUnknown(span, _) => Self::ScalarUnknown(shift(span), new_pos),
+            Self::ScalarHighImpedance(span, _) => Self::ScalarHighImpedance(shift(span), new_pos),
+            Self::VectorValue(span, _) => Self::VectorValue(shift(span), new_pos),
+            Self::VectorValueFourState(span, _) => Self::VectorValueFourState(shift(span), new_pos),
+            Self::RealValue(span, _) => Self::RealValue(shift(span), new_pos),
+            Self::StringValue(span, _) => Self::StringValue(shift(span), new_pos),
+            Self::PortValue(span, _) => Self::PortValue(shift(span), new_pos),
+        }
+    }
 }
 
 impl Default for LexerToken {
@@ -120,10 +323,66 @@ impl Default for LexerToken {
     }
 }
 
+// Saved lexer state that can be restored to re-lex from the same point
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LexerCheckpoint {
+    index: usize,
+    line: usize,
+    column: usize,
+}
+
+impl LexerCheckpoint {
+    pub fn get_index(&self) -> usize {
+        self.index
+    }
+
+    pub fn get_line(&self) -> usize {
+        self.line
+    }
+
+    pub fn get_column(&self) -> usize {
+        self.column
+    }
+}
+
+// Returns true if nothing but whitespace follows the line of an index, as
+// left by a writer that stopped partway through its last line
+pub(crate) fn is_last_line(s: &str, index: usize) -> bool {
+    !s[index..].trim_end().contains('\n')
+}
+
+// Keywords that only fail to lex when their block is malformed
+const KNOWN_DIRECTIVES: [&str; 20] = [
+    "comment",
+    "date",
+    "version",
+    "attrbegin",
+    "attrend",
+    "scope",
+    "timescale",
+    "var",
+    "upscope",
+    "enddefinitions",
+    "vcdclose",
+    "dumpall",
+    "dumpoff",
+    "dumpon",
+    "dumpvars",
+    "dumpports",
+    "dumpportsall",
+    "dumpportsoff",
+    "dumpportson",
+    "end",
+];
+
 pub struct Lexer<'a> {
     lexer: logos::Lexer<'a, LogosToken>,
     line: usize,
     column: usize,
+    lenient: bool,
+    unknown_directives: bool,
+    warnings: bool,
+    truncation: Option<LexerPosition>,
 }
 
 impl<'a> Lexer<'a> {
@@ -132,9 +391,79 @@ impl<'a> Lexer<'a> {
             lexer: LogosToken::lexer(s),
             line: 1,
             column: 1,
+            lenient: false,
+            unknown_directives: false,
+            warnings: true,
+            truncation: None,
         }
     }
 
+    /// Creates a lexer that starts partway through the source, the index must
+    /// be at a token boundary for the results to be meaningful
+    pub fn new_at(s: &'a str, index: usize) -> Self {
+        let mut lexer = LogosToken::lexer(s);
+        lexer.bump(index);
+        let prefix = &s.as_bytes()[..index];
+        let line = 1 + prefix.iter().filter(|b| **b == b'\n').count();
+        let column = match prefix.iter().rposition(|b| *b == b'\n') {
+            Some(newline) => index - newline,
+            None => index + 1,
+        };
+        Self {
+            lexer,
+            line,
+            column,
+            lenient: false,
+            unknown_directives: false,
+            warnings: true,
+            truncation: None,
+        }
+    }
+
+    /// Creates a lexer with the same settings that starts partway through the
+    /// same source, see new_at
+    pub fn fork_at(&self, index: usize) -> Self {
+        let mut lexer = Self::new_at(self.lexer.source(), index);
+        lexer.lexer.extras.block_limit = self.lexer.extras.block_limit;
+        lexer.lenient = self.lenient;
+        lexer.unknown_directives = self.unknown_directives;
+        lexer.warnings = self.warnings;
+        lexer
+    }
+
+    /// Ends the input at an incomplete last line with a warning instead of
+    /// an error, such as a bare `#` or a directive missing its `$end`
+    pub fn set_lenient(&mut self, lenient: bool) {
+        self.lenient = lenient;
+    }
+
+    /// Lexes a `$word ... $end` block of a directive it does not know, like
+    /// $timezero or a vendor extension, instead of failing on it. Malformed
+    /// blocks of known directives still fail.
+    pub fn set_unknown_directives(&mut self, enabled: bool) {
+        self.unknown_directives = enabled;
+    }
+
+    /// Stops searching an unformatted block like $comment for its $end at a
+    /// byte index of the source, so a header limit holds even inside one
+    /// huge block. A block cut off by it is an error whose range ends past
+    /// the limit. The limit is dropped once $enddefinitions is lexed.
+    pub fn set_block_limit(&mut self, limit: Option<usize>) {
+        self.lexer.extras.block_limit = limit;
+    }
+
+    /// Logs a warning when a lenient lexer ignores an incomplete last line,
+    /// true by default, the position is kept either way
+    pub fn set_warnings(&mut self, enabled: bool) {
+        self.warnings = enabled;
+    }
+
+    /// Returns where the input was cut short if a lenient lexer ignored an
+    /// incomplete last line
+    pub fn get_truncation(&self) -> Option<LexerPosition> {
+        self.truncation
+    }
+
     pub fn get_position(&self) -> LexerPosition {
         LexerPosition::new(
             self.lexer.span().start,
@@ -144,6 +473,33 @@ impl<'a> Lexer<'a> {
         )
     }
 
+    /// Saves the current state so lexing can later resume from this point
+    pub fn checkpoint(&self) -> LexerCheckpoint {
+        LexerCheckpoint {
+            index: self.lexer.span().end,
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    /// Rewinds (or fast-forwards) to a checkpoint taken from a lexer over the
+    /// same source
+    pub fn restore(&mut self, checkpoint: LexerCheckpoint) {
+        let mut lexer = LogosToken::lexer_with_extras(self.lexer.source(), self.lexer.extras);
+        lexer.bump(checkpoint.index);
+        self.lexer = lexer;
+        self.line = checkpoint.line;
+        self.column = checkpoint.column;
+    }
+
+    /// Returns the next token without consuming it
+    pub fn peek_token(&mut self) -> Result<Option<LexerToken>, LexerPosition> {
+        let checkpoint = self.checkpoint();
+        let result = self.next_token();
+        self.restore(checkpoint);
+        result
+    }
+
     fn process_newlines(&mut self, newlines: usize, columns: usize) {
         if newlines != 0 {
             self.column = columns;
@@ -151,13 +507,28 @@ impl<'a> Lexer<'a> {
         }
     }
 
+    // The position of a block cut off by the block limit, its range ends past
+    // the limit so callers can tell it from malformed input
+    fn block_limit_error(&self, pos: LexerPosition) -> LexerPosition {
+        let limit = self.lexer.extras.block_limit.unwrap_or(0);
+        let length = (limit + 1).saturating_sub(pos.get_index()).max(pos.len());
+        LexerPosition::new(pos.get_index(), pos.get_line(), pos.get_column(), length)
+    }
+
     pub fn next_token(&mut self) -> Result<Option<LexerToken>, LexerPosition> {
         loop {
+            self.lexer.extras.limited = false;
             let next = self.lexer.next();
             let span = self.lexer.span();
             let pos = self.get_position();
             self.column += span.len();
             let logos_token = match next {
+                Some(LogosToken::Directive)
+                    if !self.unknown_directives
+                        || KNOWN_DIRECTIVES.contains(&&self.lexer.slice()[1..]) =>
+                {
+                    LogosToken::Error
+                }
                 Some(logos_token) => logos_token,
                 None => return Ok(None),
             };
@@ -178,6 +549,11 @@ impl<'a> Lexer<'a> {
                     let span = (span.start + b"$version".len())..(span.end - b"$end".len());
                     LexerToken::SectionVersion(span, pos)
                 }
+                LogosToken::SectionAttrBegin((newlines, columns)) => {
+                    self.process_newlines(newlines, columns);
+                    let span = (span.start + b"$attrbegin".len())..(span.end - b"$end".len());
+                    LexerToken::SectionAttrBegin(span, pos)
+                }
                 // Formatted blocks
                 LogosToken::SectionScope((newlines, columns)) => {
                     self.process_newlines(newlines, columns);
@@ -201,12 +577,45 @@ impl<'a> Lexer<'a> {
                 }
                 LogosToken::SectionEndDefinitions((newlines, columns)) => {
                     self.process_newlines(newlines, columns);
+                    self.lexer.extras.block_limit = None;
                     LexerToken::SectionEndDefinitions(pos)
                 }
+                LogosToken::SectionAttrEnd((newlines, columns)) => {
+                    self.process_newlines(newlines, columns);
+                    LexerToken::SectionAttrEnd(pos)
+                }
+                LogosToken::SectionVcdClose((newlines, columns)) => {
+                    self.process_newlines(newlines, columns);
+                    let span = (span.start + b"$vcdclose".len())..(span.end - b"$end".len());
+                    LexerToken::SectionVcdClose(span, pos)
+                }
+                LogosToken::Directive => {
+                    let (newlines, columns) = match scan_block(&mut self.lexer) {
+                        Some(counts) => counts,
+                        None if self.lexer.extras.limited => {
+                            return Err(self.block_limit_error(pos))
+                        }
+                        None => return Err(pos),
+                    };
+                    let block = self.lexer.span();
+                    self.column += block.len() - span.len();
+                    self.process_newlines(newlines, columns);
+                    let pos = LexerPosition::new(
+                        pos.get_index(),
+                        pos.get_line(),
+                        pos.get_column(),
+                        block.len(),
+                    );
+                    LexerToken::SectionUnknown((span.start + 1)..(block.end - b"$end".len()), pos)
+                }
                 LogosToken::CommandDumpAll => LexerToken::CommandDumpAll(pos),
                 LogosToken::CommandDumpOff => LexerToken::CommandDumpOff(pos),
                 LogosToken::CommandDumpOn => LexerToken::CommandDumpOn(pos),
                 LogosToken::CommandDumpVars => LexerToken::CommandDumpVars(pos),
+                LogosToken::CommandDumpPortsAll => LexerToken::CommandDumpAll(pos),
+                LogosToken::CommandDumpPortsOff => LexerToken::CommandDumpOff(pos),
+                LogosToken::CommandDumpPortsOn => LexerToken::CommandDumpOn(pos),
+                LogosToken::CommandDumpPorts => LexerToken::CommandDumpVars(pos),
                 LogosToken::CommandEnd => LexerToken::CommandEnd(pos),
                 LogosToken::Timestamp => LexerToken::Timestamp(span, pos),
                 LogosToken::ScalarZero => LexerToken::ScalarZero(span, pos),
@@ -216,12 +625,32 @@ impl<'a> Lexer<'a> {
                 LogosToken::VectorValue => LexerToken::VectorValue(span, pos),
                 LogosToken::VectorValueFourState => LexerToken::VectorValueFourState(span, pos),
                 LogosToken::RealValue => LexerToken::RealValue(span, pos),
+                LogosToken::StringValue => LexerToken::StringValue(span, pos),
+                LogosToken::PortValue => LexerToken::PortValue(span, pos),
                 LogosToken::Whitespace => continue,
                 LogosToken::NewLine => {
                     self.process_newlines(1, 1);
                     continue;
                 }
-                LogosToken::Error => return Err(pos),
+                LogosToken::Error if self.lexer.extras.limited => {
+                    return Err(self.block_limit_error(pos));
+                }
+                LogosToken::Error => {
+                    if self.lenient && is_last_line(self.lexer.source(), pos.get_index()) {
+                        if self.warnings {
+                            log::warn!(
+                                "Input is truncated at line {}, column {}, ignoring the rest",
+                                pos.get_line(),
+                                pos.get_column()
+                            );
+                        }
+                        self.truncation = Some(pos);
+                        // Later calls keep returning the end of the input
+                        self.lexer.bump(self.lexer.remainder().len());
+                        return Ok(None);
+                    }
+                    return Err(pos);
+                }
             };
             return Ok(Some(lexer_token));
         }
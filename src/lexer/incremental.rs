@@ -0,0 +1,85 @@
+use std::mem::discriminant;
+
+use crate::lexer::position::*;
+use crate::lexer::*;
+
+/// Lexes every token in the source, meant as the starting point for relex
+pub fn lex_all(s: &str) -> Result<Vec<LexerToken>, LexerPosition> {
+    let mut lexer = Lexer::new(s);
+    let mut tokens = Vec::new();
+    while let Some(token) = lexer.next_token()? {
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+fn is_same_token(old: &LexerToken, new: &LexerToken, delta: isize) -> bool {
+    let (old_range, new_range) = (old.get_range(), new.get_range());
+    old_range.start as isize + delta == new_range.start as isize
+        && old_range.len() == new_range.len()
+        && discriminant(old) == discriminant(new)
+}
+
+/// Updates the tokens of a source after an edit without lexing the whole
+/// source again. The edit replaced the byte range `edit` of the old source
+/// with `inserted` bytes, and `s` is the source after the edit. Tokens before
+/// the edit are kept as-is, tokens after the edit are re-used with shifted
+/// positions as soon as lexing the new source lines back up with them.
+pub fn relex(
+    tokens: &[LexerToken],
+    s: &str,
+    edit: ByteRange,
+    inserted: usize,
+) -> Result<Vec<LexerToken>, LexerPosition> {
+    // A token directly touching the edit could grow, so it gets lexed again
+    let keep = tokens
+        .iter()
+        .take_while(|token| token.get_range().end < edit.start)
+        .count();
+    let restart = match keep {
+        0 => 0,
+        _ => tokens[keep - 1].get_range().end,
+    };
+    let delta = inserted as isize - edit.len() as isize;
+    let edit_end = edit.start + inserted;
+    let mut result = tokens[..keep].to_vec();
+    let mut old = keep;
+    let mut lexer = Lexer::new_at(s, restart);
+    while let Some(token) = lexer.next_token()? {
+        let range = token.get_range();
+        if range.start < edit_end {
+            result.push(token);
+            continue;
+        }
+        // Skip over old tokens that the new token has already passed
+        while old < tokens.len()
+            && (tokens[old].get_range().start as isize + delta) < range.start as isize
+        {
+            old += 1;
+        }
+        if old < tokens.len() && is_same_token(&tokens[old], &token, delta) {
+            let (old_pos, new_pos) = (tokens[old].get_position(), token.get_position());
+            let lines = new_pos.get_line() as isize - old_pos.get_line() as isize;
+            let columns = new_pos.get_column() as isize - old_pos.get_column() as isize;
+            result.push(token);
+            for token in &tokens[old + 1..] {
+                let pos = token.get_position();
+                // Only tokens sharing a line with the resync point move columns
+                let column = if pos.get_line() == old_pos.get_line() {
+                    (pos.get_column() as isize + columns) as usize
+                } else {
+                    pos.get_column()
+                };
+                result.push(token.with_position(LexerPosition::new(
+                    (pos.get_index() as isize + delta) as usize,
+                    (pos.get_line() as isize + lines) as usize,
+                    column,
+                    pos.len(),
+                )));
+            }
+            return Ok(result);
+        }
+        result.push(token);
+    }
+    Ok(result)
+}
@@ -0,0 +1,110 @@
+use core::ops::Range;
+
+use crate::lexer::position::*;
+
+// Maps byte offsets in the source to line and column numbers on demand, built
+// once over the whole source so any thread can resolve positions consistently
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LineIndex {
+    // Byte offset where every line starts, the first line starts at zero
+    line_starts: Vec<usize>,
+    length: usize,
+    // Number of columns a tab advances to reach the next tab stop
+    tab_width: usize,
+}
+
+impl LineIndex {
+    pub fn new(s: &str) -> Self {
+        let mut line_starts = vec![0];
+        for (i, b) in s.bytes().enumerate() {
+            if b == b'\n' {
+                line_starts.push(i + 1);
+            }
+        }
+        Self {
+            line_starts,
+            length: s.len(),
+            tab_width: 1,
+        }
+    }
+
+    /// Sets how display columns treat tabs, a width of one counts a tab as a
+    /// single character like any other
+    pub fn set_tab_width(&mut self, tab_width: usize) {
+        self.tab_width = tab_width.max(1);
+    }
+
+    pub fn get_tab_width(&self) -> usize {
+        self.tab_width
+    }
+
+    pub fn get_line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    /// Returns the byte range of a line (starting from one), excluding the
+    /// trailing newline
+    pub fn get_line_range(&self, line: usize) -> Option<Range<usize>> {
+        let start = *self.line_starts.get(line.checked_sub(1)?)?;
+        let end = match self.line_starts.get(line) {
+            Some(next) => next - 1,
+            None => self.length,
+        };
+        Some(start..end)
+    }
+
+    /// Returns the line and column (both starting from one) of a byte offset,
+    /// offsets past the end of the source are clamped to the end
+    pub fn get_line_column(&self, index: usize) -> (usize, usize) {
+        let index = index.min(self.length);
+        let line = match self.line_starts.binary_search(&index) {
+            Ok(line) => line,
+            Err(line) => line - 1,
+        };
+        (line + 1, index - self.line_starts[line] + 1)
+    }
+
+    /// Returns the byte offset of a line and column (both starting from one)
+    pub fn get_index(&self, line: usize, column: usize) -> Option<usize> {
+        let range = self.get_line_range(line)?;
+        let index = range.start + column.checked_sub(1)?;
+        if index <= range.end {
+            Some(index)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the line and column (both starting from one) of a byte offset
+    /// counting characters rather than bytes and expanding tabs, the source
+    /// must be the one the index was built from
+    pub fn get_display_line_column(&self, s: &str, index: usize) -> (usize, usize) {
+        let (line, _) = self.get_line_column(index);
+        let start = self.line_starts[line - 1];
+        let index = index.min(self.length);
+        let mut column = 1;
+        for (offset, c) in s[start..].char_indices() {
+            if start + offset >= index {
+                break;
+            }
+            column = match c {
+                '\t' => ((column - 1) / self.tab_width + 1) * self.tab_width + 1,
+                _ => column + 1,
+            };
+        }
+        (line, column)
+    }
+
+    /// Recomputes the line and column of a position from its byte offset
+    pub fn resolve(&self, pos: &LexerPosition) -> LexerPosition {
+        let (line, column) = self.get_line_column(pos.get_index());
+        LexerPosition::new(pos.get_index(), line, column, pos.len())
+    }
+
+    /// Recomputes the line and display column of a position from its byte
+    /// offset, see get_display_line_column
+    pub fn resolve_display(&self, s: &str, pos: &LexerPosition) -> LexerPosition {
+        let (line, column) = self.get_display_line_column(s, pos.get_index());
+        LexerPosition::new(pos.get_index(), line, column, pos.len())
+    }
+}
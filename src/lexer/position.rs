@@ -1,3 +1,5 @@
+use crate::lexer::ByteRange;
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
 pub struct LexerPosition {
     index: usize,
@@ -35,4 +37,10 @@ impl LexerPosition {
     pub fn is_empty(&self) -> bool {
         self.length == 0
     }
+
+    /// Returns the byte range covered in the source, for tokens this is the
+    /// whole token including any keywords and the closing $end
+    pub fn get_range(&self) -> ByteRange {
+        self.index..self.index + self.length
+    }
 }
@@ -0,0 +1,113 @@
+use crate::errors::TokenizerResult;
+use crate::lexer::{Lexer, LexerHighlight, LexerToken};
+use crate::tokenizer::tokenize_timestamp;
+
+/// Token counts of a dump found by the lexer alone, without tokenizing values
+/// or building a header
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VcdScanStats {
+    comments: usize,
+    declarations: usize,
+    commands: usize,
+    timestamps: usize,
+    scalars: usize,
+    vectors: usize,
+    reals: usize,
+    last_timestamp: Option<u64>,
+    size: usize,
+}
+
+impl VcdScanStats {
+    /// Returns how many tokens of a kind were found
+    pub fn get_count(&self, kind: LexerHighlight) -> usize {
+        match kind {
+            LexerHighlight::Comment => self.comments,
+            LexerHighlight::Declaration => self.declarations,
+            LexerHighlight::Command => self.commands,
+            LexerHighlight::Timestamp => self.timestamps,
+            LexerHighlight::Scalar => self.scalars,
+            LexerHighlight::Vector => self.vectors,
+            LexerHighlight::Real => self.reals,
+        }
+    }
+
+    pub fn get_token_count(&self) -> usize {
+        self.comments
+            + self.declarations
+            + self.commands
+            + self.timestamps
+            + self.scalars
+            + self.vectors
+            + self.reals
+    }
+
+    /// Returns every value change, not counting timestamps
+    pub fn get_change_count(&self) -> usize {
+        self.scalars + self.vectors + self.reals
+    }
+
+    pub fn get_timestamp_count(&self) -> usize {
+        self.timestamps
+    }
+
+    pub fn get_last_timestamp(&self) -> Option<u64> {
+        self.last_timestamp
+    }
+
+    /// Returns the size of the source in bytes
+    pub fn get_size(&self) -> usize {
+        self.size
+    }
+}
+
+impl std::fmt::Display for VcdScanStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "bytes: {}", self.size)?;
+        writeln!(f, "tokens: {}", self.get_token_count())?;
+        writeln!(
+            f,
+            "  comments: {}, declarations: {}, commands: {}",
+            self.comments, self.declarations, self.commands
+        )?;
+        writeln!(
+            f,
+            "changes: {} (scalars: {}, vectors: {}, reals: {})",
+            self.get_change_count(),
+            self.scalars,
+            self.vectors,
+            self.reals
+        )?;
+        match self.last_timestamp {
+            Some(last) => write!(f, "timestamps: {} (last {})", self.timestamps, last),
+            None => write!(f, "timestamps: 0"),
+        }
+    }
+}
+
+/// Counts the tokens of a dump by running only the lexer, which is much
+/// faster than a load for finding out how big a dump really is. Only
+/// timestamps are decoded, the header and values are not checked.
+pub fn scan_stats(source: &str) -> TokenizerResult<VcdScanStats> {
+    let mut stats = VcdScanStats {
+        size: source.len(),
+        ..Default::default()
+    };
+    let mut lexer = Lexer::new(source);
+    while let Some(token) = lexer.next_token()? {
+        let count = match token.get_highlight() {
+            LexerHighlight::Comment => &mut stats.comments,
+            LexerHighlight::Declaration => &mut stats.declarations,
+            LexerHighlight::Command => &mut stats.commands,
+            LexerHighlight::Timestamp => &mut stats.timestamps,
+            LexerHighlight::Scalar => &mut stats.scalars,
+            LexerHighlight::Vector => &mut stats.vectors,
+            LexerHighlight::Real => &mut stats.reals,
+        };
+        *count += 1;
+        if let LexerToken::Timestamp(span, pos) = token {
+            let timestamp = tokenize_timestamp(&source.as_bytes()[span], pos)?;
+            stats.last_timestamp = Some(timestamp);
+        }
+    }
+    Ok(stats)
+}
@@ -0,0 +1,255 @@
+use std::collections::VecDeque;
+use std::io::{self, BufRead};
+
+use bytes::Bytes;
+use makai::utils::bytes::ByteStorage;
+
+use crate::errors::{TokenizerError, TokenizerResult};
+use crate::lexer::position::LexerPosition;
+use crate::lexer::{Lexer, LexerCheckpoint, LexerToken};
+use crate::tokenizer::token::Token;
+use crate::tokenizer::Tokenizer;
+
+// Bytes read from the stream before lexing again
+pub(crate) const CHUNK_SIZE: usize = 1 << 16;
+// Input that does not lex is only an error once this much of it has been read,
+// it could otherwise be a long token cut short by the end of a chunk
+const MAX_TOKEN_SIZE: usize = 1 << 24;
+
+/// Lexes and tokenizes a VCD read incrementally from a buffered reader, so
+/// only the part of the file being parsed is held in memory. Tokens keep the
+/// positions and ranges they have in the whole stream.
+pub struct StreamTokenizer<R: BufRead> {
+    reader: R,
+    // Bytes read but not lexed yet, starting at the checkpoint
+    window: Vec<u8>,
+    checkpoint: LexerCheckpoint,
+    tokens: VecDeque<LexerToken>,
+    // Holds the source of the tokens waiting to be tokenized
+    tokenizer: Tokenizer,
+    eof: bool,
+    chunk_size: usize,
+    lenient: bool,
+    unknown_directives: bool,
+    warnings: bool,
+    // Dropped once $enddefinitions is lexed, like the lexer does
+    block_limit: Option<usize>,
+    truncation: Option<LexerPosition>,
+}
+
+impl<R: BufRead> StreamTokenizer<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            window: Vec::new(),
+            checkpoint: LexerCheckpoint {
+                index: 0,
+                line: 1,
+                column: 1,
+            },
+            tokens: VecDeque::new(),
+            tokenizer: Tokenizer::new_window(Bytes::new(), 0),
+            eof: false,
+            chunk_size: CHUNK_SIZE,
+            lenient: false,
+            unknown_directives: false,
+            warnings: true,
+            block_limit: None,
+            truncation: None,
+        }
+    }
+
+    /// Sets how many bytes are read from the stream at a time, which bounds
+    /// the input held in memory to a few chunks plus the longest token
+    pub fn set_chunk_size(&mut self, chunk_size: usize) {
+        self.chunk_size = chunk_size.max(1);
+    }
+
+    /// Ends the stream at an incomplete last line instead of failing, see
+    /// Lexer::set_lenient
+    pub fn set_lenient(&mut self, lenient: bool) {
+        self.lenient = lenient;
+    }
+
+    /// Lexes blocks of directives it does not know, see
+    /// Lexer::set_unknown_directives
+    pub fn set_unknown_directives(&mut self, enabled: bool) {
+        self.unknown_directives = enabled;
+    }
+
+    /// Logs a warning when an incomplete last line is ignored, see
+    /// Lexer::set_warnings
+    pub fn set_warnings(&mut self, enabled: bool) {
+        self.warnings = enabled;
+    }
+
+    /// Stops searching an unformatted block for its $end at a byte index of
+    /// the stream, see Lexer::set_block_limit
+    pub fn set_block_limit(&mut self, limit: Option<usize>) {
+        self.block_limit = limit;
+    }
+
+    /// Returns where the stream was cut short if an incomplete last line was
+    /// ignored
+    pub fn get_truncation(&self) -> Option<LexerPosition> {
+        self.truncation
+    }
+
+    /// Returns how many bytes of the stream have been lexed
+    pub fn get_index(&self) -> usize {
+        self.checkpoint.index
+    }
+
+    fn get_position(&self, index: usize) -> LexerPosition {
+        LexerPosition::new(
+            self.checkpoint.index + index,
+            self.checkpoint.line,
+            self.checkpoint.column,
+            0,
+        )
+    }
+
+    fn read_chunk(&mut self) -> TokenizerResult<()> {
+        let target = self.window.len() + self.chunk_size;
+        while !self.eof && self.window.len() < target {
+            let buffer = match self.reader.fill_buf() {
+                Ok(buffer) => buffer,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    let pos = self.get_position(self.window.len());
+                    return Err(TokenizerError::ReadError(err, pos));
+                }
+            };
+            let length = buffer.len();
+            self.eof = length == 0;
+            self.window.extend_from_slice(buffer);
+            self.reader.consume(length);
+        }
+        Ok(())
+    }
+
+    // Lexes every token of the window that cannot continue past it, returns
+    // false if there were none
+    fn lex_window(&mut self) -> TokenizerResult<bool> {
+        let text = match std::str::from_utf8(&self.window) {
+            Ok(text) => text,
+            // A character split between chunks is completed by the next read
+            Err(err) if err.error_len().is_none() && !self.eof => {
+                std::str::from_utf8(&self.window[..err.valid_up_to()]).unwrap()
+            }
+            Err(err) => {
+                let pos = self.get_position(err.valid_up_to());
+                let err = io::Error::new(io::ErrorKind::InvalidData, err);
+                return Err(TokenizerError::ReadError(err, pos));
+            }
+        };
+        let complete = self.eof && text.len() == self.window.len();
+        let offset = self.checkpoint.index;
+        let mut lexer = Lexer::new(text);
+        lexer.restore(LexerCheckpoint {
+            index: 0,
+            ..self.checkpoint
+        });
+        // Only the end of the stream can be a truncated line
+        lexer.set_lenient(self.lenient && complete);
+        lexer.set_unknown_directives(self.unknown_directives);
+        lexer.set_warnings(self.warnings);
+        lexer.set_block_limit(self.block_limit.map(|limit| limit.saturating_sub(offset)));
+        let mut end = None;
+        loop {
+            match lexer.next_token() {
+                Ok(Some(token)) => {
+                    // A token could be the start of a longer one, like $end of
+                    // $enddefinitions, until whitespace follows it
+                    let rest = &text.as_bytes()[token.get_range().end..];
+                    if !complete && !rest.iter().any(|b| b.is_ascii_whitespace()) {
+                        break;
+                    }
+                    if matches!(token, LexerToken::SectionEndDefinitions(_)) {
+                        self.block_limit = None;
+                    }
+                    let pos = token.get_position();
+                    self.tokens
+                        .push_back(token.with_position(LexerPosition::new(
+                            pos.get_index() + offset,
+                            pos.get_line(),
+                            pos.get_column(),
+                            pos.len(),
+                        )));
+                    end = Some(lexer.checkpoint());
+                }
+                Ok(None) => break,
+                // Tokens before an error are returned first, the error is
+                // reported once it is at the start of the window
+                Err(_) if end.is_some() => break,
+                // A block cut off by the limit fails without reading further
+                Err(pos)
+                    if complete
+                        || text.len() > MAX_TOKEN_SIZE
+                        || matches!(self.block_limit, Some(limit) if pos.get_range().end + offset > limit) =>
+                {
+                    return Err(TokenizerError::LexerError(LexerPosition::new(
+                        pos.get_index() + offset,
+                        pos.get_line(),
+                        pos.get_column(),
+                        pos.len(),
+                    )));
+                }
+                Err(_) => return Ok(false),
+            }
+        }
+        let end = match (end, lexer.get_truncation()) {
+            (Some(end), _) => end,
+            (None, Some(pos)) => {
+                self.truncation = Some(LexerPosition::new(
+                    pos.get_index() + offset,
+                    pos.get_line(),
+                    pos.get_column(),
+                    pos.len(),
+                ));
+                self.window.clear();
+                return Ok(false);
+            }
+            (None, None) => return Ok(false),
+        };
+        let bytes = Bytes::copy_from_slice(&self.window[..end.index]);
+        self.tokenizer = Tokenizer::new_window(bytes, offset);
+        self.window.drain(..end.index);
+        self.checkpoint = LexerCheckpoint {
+            index: offset + end.index,
+            ..end
+        };
+        Ok(true)
+    }
+
+    // Adds input handed over by the caller instead of read from the reader
+    pub(crate) fn push(&mut self, bytes: &[u8]) {
+        self.window.extend_from_slice(bytes);
+    }
+
+    // Marks the input handed over so far as the whole rest of the stream
+    pub(crate) fn close(&mut self) {
+        self.eof = true;
+    }
+
+    // Returns the next token of the input so far without reading more, None
+    // if the rest of it could still continue into input not seen yet
+    pub(crate) fn next_buffered(&mut self, bs: &mut ByteStorage) -> TokenizerResult<Option<Token>> {
+        if self.tokens.is_empty() && !self.lex_window()? {
+            return Ok(None);
+        }
+        self.tokenizer.next(self.tokens.pop_front(), bs)
+    }
+
+    pub fn next(&mut self, bs: &mut ByteStorage) -> TokenizerResult<Option<Token>> {
+        while self.tokens.is_empty() {
+            if self.lex_window()? {
+                break;
+            } else if self.eof {
+                return Ok(None);
+            }
+            self.read_chunk()?;
+        }
+        self.tokenizer.next(self.tokens.pop_front(), bs)
+    }
+}
@@ -1,5 +1,11 @@
+pub mod analysis;
 pub mod errors;
+pub mod export;
+pub mod format;
+pub mod fuzz;
 pub mod lexer;
 pub mod parser;
+pub mod testgen;
 pub mod tokenizer;
 pub mod utils;
+pub mod writer;
@@ -1,10 +1,35 @@
-use std::collections::HashMap;
+pub mod attrs;
+pub mod demangle;
+pub mod dumpall;
+pub mod interpretation;
+pub mod middleware;
+pub mod namespace;
+pub mod paths;
+pub mod profile;
+pub mod repair;
+pub mod transform;
+pub mod widths;
 
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
 use makai::utils::bytes::ByteStorage;
-use makai_waveform_db::{bitvector::BitVector, Waveform};
+use makai_waveform_db::bitvector::{BitVector, Logic};
+use makai_waveform_db::Waveform;
 
 use crate::errors::*;
 use crate::lexer::position::LexerPosition;
+use crate::parser::attrs::VcdAttr;
+use crate::parser::demangle::{VcdDemanglers, VCD_ORIGINAL_NAME};
+use crate::parser::dumpall::{VcdDumpAllIssue, VcdDumpAllValidator};
+use crate::parser::interpretation::VcdInterpretation;
+use crate::parser::middleware::VcdTokenMiddlewares;
+use crate::parser::namespace::*;
+use crate::parser::profile::VcdSimulatorProfile;
+use crate::parser::repair::{starts_body, VcdRepair, VcdRepairKind};
+use crate::parser::transform::{truncate_bits, VcdTransforms};
+use crate::parser::widths::{VcdWidthMismatch, VcdWidthReconciler};
 use crate::tokenizer::token::*;
 
 // Returns the timescale resolution x, where x is 10^(-x)
@@ -25,13 +50,56 @@ pub fn convert_timescale(timescale: TokenTimescale, offset: TokenTimescaleOffset
     base + offset
 }
 
+// Splits a timescale resolution back into a unit and an offset, returns None
+// if it cannot be written as 1, 10 or 100 of a unit
+pub fn split_timescale(timescale: i32) -> Option<(TokenTimescale, TokenTimescaleOffset)> {
+    let units = [
+        TokenTimescale::Seconds,
+        TokenTimescale::Milliseconds,
+        TokenTimescale::Microseconds,
+        TokenTimescale::Nanoseconds,
+        TokenTimescale::Picoseconds,
+        TokenTimescale::Femtoseconds,
+    ];
+    let unit = (timescale + 2).div_euclid(3);
+    let offset = match unit * 3 - timescale {
+        0 => TokenTimescaleOffset::One,
+        1 => TokenTimescaleOffset::Ten,
+        _ => TokenTimescaleOffset::Hundred,
+    };
+    let unit = units.get(usize::try_from(unit).ok()?)?.clone();
+    Some((unit, offset))
+}
+
+// Counts a problem tolerated in the input by a parser and logs it, unless
+// its warnings are off, keeping the message if they are being collected
+macro_rules! warn_input {
+    ($parser:expr, $($arg:tt)+) => {{
+        $parser.warning_count += 1;
+        if $parser.warnings {
+            log::warn!($($arg)+);
+        }
+        if $parser.warning_messages.is_some() {
+            let message = format!($($arg)+);
+            if let Some(messages) = &mut $parser.warning_messages {
+                messages.push(message);
+            }
+        }
+    }};
+}
+
 pub type VcdVariableNetType = TokenVariableNetType;
 pub type VcdScopeType = TokenScopeType;
+pub type VcdVariableDimension = TokenVariableDimension;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum VcdVariableWidth {
     Vector { width: usize },
     Real,
+    // Events carry no value, they only mark the times they were triggered
+    Event,
+    // Strings have no signal in a waveform, see VcdStringSignals
+    String,
 }
 
 impl VcdVariableWidth {
@@ -39,8 +107,17 @@ impl VcdVariableWidth {
         match self {
             Self::Vector { width } => *width,
             Self::Real => 64,
+            Self::Event => 1,
+            Self::String => 0,
         }
     }
+
+    /// Returns true for zero-width vectors, like the genvars some netlist
+    /// tools declare, they have no signal in a waveform and their changes
+    /// are ignored
+    pub fn is_empty(&self) -> bool {
+        *self == Self::Vector { width: 0 }
+    }
 }
 
 impl std::fmt::Display for VcdVariableWidth {
@@ -52,6 +129,8 @@ impl std::fmt::Display for VcdVariableWidth {
                 _ => write!(f, "[{}]", width),
             },
             Self::Real => write!(f, "[real]"),
+            Self::Event => write!(f, "[event]"),
+            Self::String => write!(f, "[string]"),
         }
     }
 }
@@ -61,6 +140,8 @@ pub enum VcdVariableDescription {
     Unspecified,
     Vector { width: usize },
     VectorSelect { msb: usize, lsb: usize },
+    // Packed arrays like bus[1:0][15:0], outermost dimension first
+    Dimensions(Vec<VcdVariableDimension>),
 }
 
 impl VcdVariableDescription {
@@ -71,6 +152,9 @@ impl VcdVariableDescription {
             TokenVariableDescription::VectorSelect { id: _, msb, lsb } => {
                 Self::VectorSelect { msb, lsb }
             }
+            TokenVariableDescription::Dimensions { id: _, dimensions } => {
+                Self::Dimensions(dimensions)
+            }
         }
     }
 }
@@ -82,6 +166,9 @@ pub struct VcdVariable {
     width: VcdVariableWidth,
     net_type: VcdVariableNetType,
     idcode: usize,
+    interpretation: VcdInterpretation,
+    attributes: HashMap<String, String>,
+    attrs: Vec<VcdAttr>,
 }
 
 impl VcdVariable {
@@ -93,12 +180,24 @@ impl VcdVariable {
         pos: &LexerPosition,
         bs: &ByteStorage,
     ) -> ParserResult<Self> {
-        let (name_id, width) = match net_type {
-            VcdVariableNetType::Real | VcdVariableNetType::Realtime => match description {
+        // Shortreal values are widened and stored like any other real
+        let (name_id, width) = match (net_type.get_real_width(), &net_type) {
+            // Simulators declare events with all sorts of widths, which do not
+            // matter since an event only marks when it was triggered
+            (None, VcdVariableNetType::Event) => match description {
+                TokenVariableDescription::Unspecified { id } => (id, VcdVariableWidth::Event),
+                _ => return Err(ParserError::MismatchedWidth(*pos)),
+            },
+            // The declared width of a string is not meaningful
+            (None, VcdVariableNetType::String) => match description {
+                TokenVariableDescription::Unspecified { id } => (id, VcdVariableWidth::String),
+                _ => return Err(ParserError::MismatchedWidth(*pos)),
+            },
+            (Some(_), _) => match description {
                 TokenVariableDescription::Unspecified { id } => (id, VcdVariableWidth::Real),
                 _ => return Err(ParserError::MismatchedWidth(*pos)),
             },
-            _ => match description {
+            (None, _) => match description {
                 TokenVariableDescription::Unspecified { id } => {
                     (id, VcdVariableWidth::Vector { width: token_width })
                 }
@@ -109,7 +208,14 @@ impl VcdVariable {
                     (id, VcdVariableWidth::Vector { width: token_width })
                 }
                 TokenVariableDescription::VectorSelect { id, msb, lsb } => {
-                    let width = msb - lsb + 1;
+                    let width = msb.abs_diff(lsb).saturating_add(1);
+                    if width != token_width {
+                        return Err(ParserError::MismatchedWidth(*pos));
+                    }
+                    (id, VcdVariableWidth::Vector { width })
+                }
+                TokenVariableDescription::Dimensions { id, .. } => {
+                    let width = description.get_width();
                     if width != token_width {
                         return Err(ParserError::MismatchedWidth(*pos));
                     }
@@ -123,6 +229,9 @@ impl VcdVariable {
             width,
             net_type,
             idcode: token_idcode.get_id(),
+            interpretation: VcdInterpretation::default(),
+            attributes: HashMap::new(),
+            attrs: Vec::new(),
         })
     }
 
@@ -141,6 +250,47 @@ impl VcdVariable {
     pub fn get_idcode(&self) -> usize {
         self.idcode
     }
+
+    pub fn get_net_type(&self) -> &VcdVariableNetType {
+        &self.net_type
+    }
+
+    pub fn get_description(&self) -> &VcdVariableDescription {
+        &self.description
+    }
+
+    pub fn get_interpretation(&self) -> VcdInterpretation {
+        self.interpretation
+    }
+
+    /// Sets how the bits of the variable are read as a number, ignored for
+    /// real and event variables
+    pub fn set_interpretation(&mut self, interpretation: VcdInterpretation) {
+        self.interpretation = interpretation;
+    }
+
+    pub fn get_attributes(&self) -> &HashMap<String, String> {
+        &self.attributes
+    }
+
+    pub fn get_attribute(&self, key: &str) -> Option<&String> {
+        self.attributes.get(key)
+    }
+
+    /// Sets a user attribute, returning the previous value if there was one
+    pub fn set_attribute(&mut self, key: &str, value: &str) -> Option<String> {
+        self.attributes.insert(key.to_string(), value.to_string())
+    }
+
+    pub fn remove_attribute(&mut self, key: &str) -> Option<String> {
+        self.attributes.remove(key)
+    }
+
+    /// Returns the $attrbegin attributes declared for it, in order, the named
+    /// ones are also in get_attributes
+    pub fn get_attrs(&self) -> &Vec<VcdAttr> {
+        &self.attrs
+    }
 }
 
 impl std::fmt::Display for VcdVariable {
@@ -155,6 +305,8 @@ pub struct VcdScope {
     scope_type: VcdScopeType,
     scopes: Vec<VcdScope>,
     variables: Vec<VcdVariable>,
+    attributes: HashMap<String, String>,
+    attrs: Vec<VcdAttr>,
 }
 
 impl VcdScope {
@@ -164,6 +316,8 @@ impl VcdScope {
             scope_type,
             scopes: Vec::new(),
             variables: Vec::new(),
+            attributes: HashMap::new(),
+            attrs: Vec::new(),
         }
     }
 
@@ -171,6 +325,10 @@ impl VcdScope {
         &self.name
     }
 
+    pub fn set_name(&mut self, name: &str) {
+        self.name = name.to_string();
+    }
+
     pub fn get_type(&self) -> &VcdScopeType {
         &self.scope_type
     }
@@ -182,13 +340,43 @@ impl VcdScope {
     pub fn get_variables(&self) -> &Vec<VcdVariable> {
         &self.variables
     }
+
+    pub fn get_attributes(&self) -> &HashMap<String, String> {
+        &self.attributes
+    }
+
+    pub fn get_attribute(&self, key: &str) -> Option<&String> {
+        self.attributes.get(key)
+    }
+
+    /// Sets a user attribute, returning the previous value if there was one
+    pub fn set_attribute(&mut self, key: &str, value: &str) -> Option<String> {
+        self.attributes.insert(key.to_string(), value.to_string())
+    }
+
+    pub fn remove_attribute(&mut self, key: &str) -> Option<String> {
+        self.attributes.remove(key)
+    }
+
+    /// Returns the $attrbegin attributes declared for it, in order, the named
+    /// ones are also in get_attributes
+    pub fn get_attrs(&self) -> &Vec<VcdAttr> {
+        &self.attrs
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum VcdEntry {
     Timestamp(u64),
+    // A change of a 1-bit signal, stored without building a vector
+    Scalar(Logic, usize),
     Vector(BitVector, usize),
     Real(f64, usize),
+    Event(usize),
+    // A change of an extended VCD port, with the direction and strength of
+    // every bit
+    Port(TokenPortValue, usize),
+    String(Bytes, usize),
 }
 
 impl Default for VcdEntry {
@@ -197,13 +385,183 @@ impl Default for VcdEntry {
     }
 }
 
+impl VcdEntry {
+    /// Returns the entry with a scalar change widened to a 1-bit vector, for
+    /// code that only handles vectors
+    pub fn into_vector(self) -> Self {
+        match self {
+            Self::Scalar(bit, idcode) => Self::Vector(BitVector::from(bit), idcode),
+            Self::Port(value, idcode) => Self::Vector(value.to_bitvector(), idcode),
+            entry => entry,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VcdDirective {
+    Date,
+    Version,
+    Timescale,
+}
+
+impl std::fmt::Display for VcdDirective {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Date => write!(f, "$date"),
+            Self::Version => write!(f, "$version"),
+            Self::Timescale => write!(f, "$timescale"),
+        }
+    }
+}
+
+/// A directive the lexer does not know, only read when unknown directives are
+/// accepted by the lexer
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VcdUnknownDirective {
+    keyword: String,
+    text: String,
+    pos: LexerPosition,
+}
+
+impl VcdUnknownDirective {
+    fn new(block: &str, pos: LexerPosition) -> Self {
+        let (keyword, text) = block.split_once(char::is_whitespace).unwrap_or((block, ""));
+        Self {
+            keyword: keyword.to_string(),
+            text: text.trim().to_string(),
+            pos,
+        }
+    }
+
+    /// Returns the keyword without its dollar sign, like timezero
+    pub fn get_keyword(&self) -> &String {
+        &self.keyword
+    }
+
+    /// Returns the trimmed text between the keyword and $end
+    pub fn get_text(&self) -> &String {
+        &self.text
+    }
+
+    pub fn get_position(&self) -> LexerPosition {
+        self.pos
+    }
+}
+
+/// What to do when a header directive appears more than once, which usually
+/// means dumps were concatenated or corrupted
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum VcdDuplicatePolicy {
+    Error,
+    KeepFirst,
+    #[default]
+    KeepLast,
+}
+
+/// An idcode with characters outside of `!` to `~`, which the spec does not
+/// allow and which usually means the simulator wrote it incorrectly
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VcdIdcodeIssue {
+    idcode: String,
+    pos: LexerPosition,
+}
+
+impl VcdIdcodeIssue {
+    pub fn get_idcode(&self) -> &str {
+        &self.idcode
+    }
+
+    pub fn get_position(&self) -> LexerPosition {
+        self.pos
+    }
+}
+
+pub fn is_printable_idcode(bytes: &[u8]) -> bool {
+    !bytes.is_empty() && bytes.iter().all(|b| (b'!'..=b'~').contains(b))
+}
+
+/// Signals grouped by the clock they appear synchronous to, see
+/// analysis::clocks::infer_clock_domains
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VcdClockDomains {
+    // Clock idcode to the sorted idcodes of signals synchronous to it
+    domains: HashMap<usize, Vec<usize>>,
+}
+
+impl VcdClockDomains {
+    pub fn new(mut domains: HashMap<usize, Vec<usize>>) -> Self {
+        for signals in domains.values_mut() {
+            signals.sort_unstable();
+        }
+        Self { domains }
+    }
+
+    pub fn get_domains(&self) -> &HashMap<usize, Vec<usize>> {
+        &self.domains
+    }
+
+    /// Returns the idcode of the clock the given signal is synchronous to
+    pub fn get_clock(&self, idcode: usize) -> Option<usize> {
+        self.domains
+            .iter()
+            .find(|(_, signals)| signals.binary_search(&idcode).is_ok())
+            .map(|(clock, _)| *clock)
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct VcdHeader {
     version: Option<String>,
     date: Option<String>,
+    comments: Vec<String>,
     timescale: Option<i32>,
+    directives: Vec<(VcdDirective, LexerPosition)>,
     idcodes: HashMap<usize, VcdVariableWidth>, // id, width
+    // Idcodes as they appeared in the source, the byte storage holding the
+    // ones too long to pack is not kept with the header
+    idcode_strings: HashMap<usize, String>,
     scopes: Vec<VcdScope>,
+    namespaces: Vec<VcdNamespace>,
+    attrs: Vec<VcdAttr>,
+    unknown_directives: Vec<VcdUnknownDirective>,
+    clock_domains: VcdClockDomains,
+}
+
+// Attaches $attrbegin attributes in the order they were declared, each named
+// one is also set in the attribute map with its argument as the value, so
+// get_attribute reads them like any other attribute
+fn attach_attrs(
+    pending: &mut Vec<VcdAttr>,
+    attrs: &mut Vec<VcdAttr>,
+    attributes: &mut HashMap<String, String>,
+) {
+    for attr in pending.iter() {
+        if !attr.get_name().is_empty() {
+            attributes.insert(attr.get_name().clone(), attr.get_arg().clone());
+        }
+    }
+    attrs.append(pending);
+}
+
+// Adds a scope to its siblings, merging it into one with the same name and
+// type along with any of its own scopes that repeat
+fn merge_scope(siblings: &mut Vec<VcdScope>, scope: VcdScope) {
+    let existing = siblings
+        .iter_mut()
+        .find(|other| other.name == scope.name && other.scope_type == scope.scope_type);
+    match existing {
+        Some(existing) => {
+            existing.variables.extend(scope.variables);
+            existing.attrs.extend(scope.attrs);
+            for (key, value) in scope.attributes {
+                existing.attributes.entry(key).or_insert(value);
+            }
+            for child in scope.scopes {
+                merge_scope(&mut existing.scopes, child);
+            }
+        }
+        None => siblings.push(scope),
+    }
 }
 
 fn get_scope_recursive<'a>(scope: &'a VcdScope, path: &str) -> Option<&'a VcdScope> {
@@ -244,37 +602,161 @@ fn get_variable_recursive<'a>(scope: &'a VcdScope, path: &str) -> Option<&'a Vcd
     None
 }
 
+fn get_variable_recursive_mut<'a>(
+    scope: &'a mut VcdScope,
+    path: &str,
+) -> Option<&'a mut VcdVariable> {
+    match path.split_once('.') {
+        None => scope
+            .variables
+            .iter_mut()
+            .find(|variable| variable.get_name() == path),
+        Some((name, path)) => scope
+            .scopes
+            .iter_mut()
+            .find(|scope| scope.get_name() == name)
+            .and_then(|scope| get_variable_recursive_mut(scope, path)),
+    }
+}
+
+fn get_scope_recursive_mut<'a>(scope: &'a mut VcdScope, path: &str) -> Option<&'a mut VcdScope> {
+    let (name, rest) = match path.split_once('.') {
+        Some((name, rest)) => (name, Some(rest)),
+        None => (path, None),
+    };
+    let scope = scope
+        .scopes
+        .iter_mut()
+        .find(|scope| scope.get_name() == name)?;
+    match rest {
+        Some(rest) => get_scope_recursive_mut(scope, rest),
+        None => Some(scope),
+    }
+}
+
 impl VcdHeader {
     pub fn new() -> Self {
         Self {
             version: None,
             date: None,
+            comments: Vec::new(),
             timescale: None,
+            directives: Vec::new(),
             idcodes: HashMap::new(),
+            idcode_strings: HashMap::new(),
             scopes: Vec::new(),
+            namespaces: Vec::new(),
+            attrs: Vec::new(),
+            unknown_directives: Vec::new(),
+            clock_domains: VcdClockDomains::default(),
         }
     }
 
     pub fn initialize_waveform(&self, waveform: &mut Waveform) {
         for (idcode, width) in self.get_idcodes_map().iter() {
             match width {
+                VcdVariableWidth::Vector { width: 0 } => {}
                 VcdVariableWidth::Vector { width } => {
                     waveform.initialize_vector(*idcode, *width);
                 }
                 VcdVariableWidth::Real => {
                     waveform.initialize_real(*idcode);
                 }
+                // Events are stored as one-bit pulses, set when the event is
+                // triggered and cleared at the next timestamp
+                VcdVariableWidth::Event => {
+                    waveform.initialize_vector(*idcode, 1);
+                }
+                VcdVariableWidth::String => {}
             }
         }
     }
 
+    /// Returns the root scopes that are not in a namespace
     pub fn get_scopes(&self) -> &Vec<VcdScope> {
         &self.scopes
     }
 
+    pub fn get_namespaces(&self) -> &Vec<VcdNamespace> {
+        &self.namespaces
+    }
+
+    pub fn get_namespace(&self, name: &str) -> Option<&VcdNamespace> {
+        self.namespaces
+            .iter()
+            .find(|namespace| namespace.get_name() == name)
+    }
+
+    /// Adds the hierarchy of another header as a namespace, returning the
+    /// offset added to its idcodes so they do not collide with the ones
+    /// already declared, changes from its waveform must be shifted by the
+    /// same offset. Only the hierarchy and idcodes of the other header are
+    /// kept, None is returned if the name is invalid or taken or the other
+    /// header has namespaces of its own.
+    pub fn add_namespace(&mut self, name: &str, header: VcdHeader) -> Option<usize> {
+        if !is_valid_namespace(name)
+            || self.get_namespace(name).is_some()
+            || !header.namespaces.is_empty()
+        {
+            return None;
+        }
+        let offset = self.idcodes.keys().max().map_or(0, |idcode| idcode + 1);
+        if header
+            .idcodes
+            .keys()
+            .any(|idcode| idcode.checked_add(offset).is_none())
+        {
+            return None;
+        }
+        let mut namespace = VcdNamespace::new(name);
+        for mut scope in header.scopes {
+            offset_idcodes(&mut scope, offset);
+            namespace.scopes.push(scope);
+        }
+        self.idcodes.extend(
+            header
+                .idcodes
+                .into_iter()
+                .map(|(idcode, width)| (idcode + offset, width)),
+        );
+        self.idcode_strings.extend(
+            header
+                .idcode_strings
+                .into_iter()
+                .map(|(idcode, string)| (idcode + offset, string)),
+        );
+        self.namespaces.push(namespace);
+        Some(offset)
+    }
+
+    // Returns the roots a path is looked up in and the rest of the path, a
+    // prefix only names a namespace if one exists with that name
+    fn get_roots<'a>(&self, path: &'a str) -> (&Vec<VcdScope>, &'a str) {
+        if let Some((name, rest)) = path.split_once(VCD_NAMESPACE_SEPARATOR) {
+            if let Some(namespace) = self.get_namespace(name) {
+                return (&namespace.scopes, rest);
+            }
+        }
+        (&self.scopes, path)
+    }
+
+    fn get_roots_mut<'a>(&mut self, path: &'a str) -> (&mut Vec<VcdScope>, &'a str) {
+        if let Some((name, rest)) = path.split_once(VCD_NAMESPACE_SEPARATOR) {
+            if let Some(namespace) = self
+                .namespaces
+                .iter_mut()
+                .find(|namespace| namespace.get_name() == name)
+            {
+                return (&mut namespace.scopes, rest);
+            }
+        }
+        (&mut self.scopes, path)
+    }
+
     pub fn get_scope(&self, path: &str) -> Option<&VcdScope> {
+        let (scopes, path) = self.get_roots(path);
         let sections: Vec<&str> = path.split('.').collect();
-        for scope in &self.scopes {
+        for scope in scopes {
             if sections.is_empty() {
                 return None;
             } else if scope.get_name() == sections[0] {
@@ -289,8 +771,9 @@ impl VcdHeader {
     }
 
     pub fn get_variable(&self, path: &str) -> Option<&VcdVariable> {
+        let (scopes, path) = self.get_roots(path);
         let sections: Vec<&str> = path.split('.').collect();
-        for scope in &self.scopes {
+        for scope in scopes {
             if sections.len() < 2 {
                 return None;
             } else if scope.get_name() == sections[0] {
@@ -300,10 +783,166 @@ impl VcdHeader {
         None
     }
 
+    pub fn get_scope_mut(&mut self, path: &str) -> Option<&mut VcdScope> {
+        let (scopes, path) = self.get_roots_mut(path);
+        let (name, rest) = match path.split_once('.') {
+            Some((name, rest)) => (name, Some(rest)),
+            None => (path, None),
+        };
+        let scope = scopes.iter_mut().find(|scope| scope.get_name() == name)?;
+        match rest {
+            Some(rest) => get_scope_recursive_mut(scope, rest),
+            None => Some(scope),
+        }
+    }
+
+    /// Applies attributes from lines of the form `<path> <key>=<value>`, where
+    /// the path names a variable or a scope, blank lines and lines starting
+    /// with `#` are skipped, returns the lines that could not be applied
+    pub fn apply_attributes(&mut self, s: &str) -> Vec<String> {
+        let mut unapplied = Vec::new();
+        for line in s.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let applied = match line.split_once(char::is_whitespace) {
+                Some((path, attribute)) => match attribute.trim().split_once('=') {
+                    Some((key, value)) => {
+                        let (key, value) = (key.trim(), value.trim());
+                        if let Some(variable) = self.get_variable_mut(path) {
+                            variable.set_attribute(key, value);
+                            true
+                        } else if let Some(scope) = self.get_scope_mut(path) {
+                            scope.set_attribute(key, value);
+                            true
+                        } else {
+                            false
+                        }
+                    }
+                    None => false,
+                },
+                None => false,
+            };
+            if !applied {
+                unapplied.push(line.to_string());
+            }
+        }
+        unapplied
+    }
+
+    pub fn get_variable_mut(&mut self, path: &str) -> Option<&mut VcdVariable> {
+        let (scopes, path) = self.get_roots_mut(path);
+        let (name, path) = path.split_once('.')?;
+        scopes
+            .iter_mut()
+            .find(|scope| scope.get_name() == name)
+            .and_then(|scope| get_variable_recursive_mut(scope, path))
+    }
+
+    /// Returns every variable in the hierarchy along with its full dotted path,
+    /// in declaration order, followed by the variables of every namespace
+    /// with the namespace before their path
+    pub fn get_variables_with_paths(&self) -> Vec<(String, &VcdVariable)> {
+        fn collect<'a>(
+            scope: &'a VcdScope,
+            prefix: &str,
+            variables: &mut Vec<(String, &'a VcdVariable)>,
+        ) {
+            let prefix = format!("{}{}", prefix, scope.get_name());
+            for variable in &scope.variables {
+                variables.push((format!("{}.{}", prefix, variable.get_name()), variable));
+            }
+            for child in &scope.scopes {
+                collect(child, &format!("{}.", prefix), variables);
+            }
+        }
+        let mut variables = Vec::new();
+        for scope in &self.scopes {
+            collect(scope, "", &mut variables);
+        }
+        for namespace in &self.namespaces {
+            let prefix = format!("{}{}", namespace.get_name(), VCD_NAMESPACE_SEPARATOR);
+            for scope in &namespace.scopes {
+                collect(scope, &prefix, &mut variables);
+            }
+        }
+        variables
+    }
+
+    /// Keeps only the variables whose full path passes a filter, scopes left
+    /// without any variables are removed and so are the idcodes no variable
+    /// uses anymore
+    pub fn retain_variables<F: Fn(&str) -> bool>(&mut self, filter: F) {
+        // Returns whether the scope still has any variables below it
+        fn retain(scope: &mut VcdScope, prefix: &str, filter: &dyn Fn(&str) -> bool) -> bool {
+            let prefix = format!("{}{}", prefix, scope.name);
+            scope
+                .variables
+                .retain(|variable| filter(&format!("{}.{}", prefix, variable.get_name())));
+            scope
+                .scopes
+                .retain_mut(|child| retain(child, &format!("{}.", prefix), filter));
+            !scope.variables.is_empty() || !scope.scopes.is_empty()
+        }
+        self.scopes.retain_mut(|scope| retain(scope, "", &filter));
+        for namespace in &mut self.namespaces {
+            let prefix = format!("{}{}", namespace.get_name(), VCD_NAMESPACE_SEPARATOR);
+            namespace
+                .scopes
+                .retain_mut(|scope| retain(scope, &prefix, &filter));
+        }
+        let used: HashSet<usize> = self
+            .get_variables_with_paths()
+            .into_iter()
+            .map(|(_, variable)| variable.get_idcode())
+            .collect();
+        self.idcodes.retain(|idcode, _| used.contains(idcode));
+        self.idcode_strings
+            .retain(|idcode, _| used.contains(idcode));
+    }
+
+    /// Changes the idcode of every variable, variables mapped to the same
+    /// idcode end up sharing it with the width of the lowest original idcode
+    pub fn remap_idcodes<F: Fn(usize) -> usize>(&mut self, remap: F) {
+        fn remap_scope(scope: &mut VcdScope, remap: &dyn Fn(usize) -> usize) {
+            for variable in &mut scope.variables {
+                variable.idcode = remap(variable.idcode);
+            }
+            for child in &mut scope.scopes {
+                remap_scope(child, remap);
+            }
+        }
+        for scope in &mut self.scopes {
+            remap_scope(scope, &remap);
+        }
+        for namespace in &mut self.namespaces {
+            for scope in &mut namespace.scopes {
+                remap_scope(scope, &remap);
+            }
+        }
+        let mut idcodes: Vec<(usize, VcdVariableWidth)> = self.idcodes.drain().collect();
+        idcodes.sort_unstable_by_key(|(idcode, _)| *idcode);
+        for (idcode, width) in idcodes {
+            self.idcodes.entry(remap(idcode)).or_insert(width);
+        }
+        let mut idcode_strings: Vec<(usize, String)> = self.idcode_strings.drain().collect();
+        idcode_strings.sort_unstable_by_key(|(idcode, _)| *idcode);
+        for (idcode, string) in idcode_strings {
+            self.idcode_strings.entry(remap(idcode)).or_insert(string);
+        }
+    }
+
     pub fn get_idcodes_map(&self) -> &HashMap<usize, VcdVariableWidth> {
         &self.idcodes
     }
 
+    /// Returns a declared idcode as it appeared in the source, after a remap
+    /// the one of the lowest original idcode
+    pub fn get_idcode_string(&self, idcode: usize) -> Option<&String> {
+        self.idcode_strings.get(&idcode)
+    }
+
     pub fn get_version(&self) -> &Option<String> {
         &self.version
     }
@@ -312,9 +951,41 @@ impl VcdHeader {
         &self.date
     }
 
+    /// Returns the text of every $comment in the header, in order
+    pub fn get_comments(&self) -> &Vec<String> {
+        &self.comments
+    }
+
     pub fn get_timescale(&self) -> &Option<i32> {
         &self.timescale
     }
+
+    /// Returns the $attrbegin attributes that were not followed by any
+    /// declaration, in order
+    pub fn get_attrs(&self) -> &Vec<VcdAttr> {
+        &self.attrs
+    }
+
+    /// Returns every $date, $version and $timescale directive in the header
+    /// with its position, including the ones that were ignored
+    pub fn get_directives(&self) -> &Vec<(VcdDirective, LexerPosition)> {
+        &self.directives
+    }
+
+    /// Returns the directives of the header the lexer did not know, in order
+    pub fn get_unknown_directives(&self) -> &Vec<VcdUnknownDirective> {
+        &self.unknown_directives
+    }
+
+    /// Sets the grouping of signals by clock, see
+    /// analysis::clocks::infer_clock_domains
+    pub fn set_clock_domains(&mut self, clock_domains: VcdClockDomains) {
+        self.clock_domains = clock_domains;
+    }
+
+    pub fn get_clock_domains(&self) -> &VcdClockDomains {
+        &self.clock_domains
+    }
 }
 
 impl Default for VcdHeader {
@@ -326,7 +997,52 @@ impl Default for VcdHeader {
 pub struct VcdReader {
     bs: ByteStorage,
     header: VcdHeader,
-    scope_depth: usize,
+    // Scopes that are still open, innermost last, each is added to its parent
+    // when it closes
+    open_scopes: Vec<VcdScope>,
+    // Attributes waiting for the scope or variable declared after them
+    pending_attrs: Vec<VcdAttr>,
+    max_scope_depth: Option<usize>,
+    dumpall_validator: Option<VcdDumpAllValidator>,
+    width_reconciler: Option<VcdWidthReconciler>,
+    idcode_issues: Option<Vec<VcdIdcodeIssue>>,
+    repairs: Option<Vec<VcdRepair>>,
+    duplicate_policy: VcdDuplicatePolicy,
+    simulator_profile: VcdSimulatorProfile,
+    // Idcodes already warned about for values wider than their variable
+    truncated: HashSet<usize>,
+    // The waveform cannot store a change before any timestamp or two changes
+    // of a signal at the same timestamp, so both are caught here
+    timestamp: Option<u64>,
+    changed: HashSet<usize>,
+    // A change read before the first timestamp, returned after the timestamp
+    // assumed for it
+    pending: Option<Token>,
+    // Whether time went back and changes are dropped until it passes the
+    // latest timestamp again
+    rewound: bool,
+    // Whether a $dumpall block is open, its values restate the current ones
+    checkpoint: bool,
+    idle_skip: bool,
+    namespaces: bool,
+    // Whether no change followed the last returned timestamp, the latest
+    // timestamp of an idle stretch is held in case a change or the end of the
+    // dump follows it
+    idle: bool,
+    held: Option<u64>,
+    // A change returned after the held timestamp it ended
+    held_entry: Option<VcdEntry>,
+    transforms: VcdTransforms,
+    demanglers: VcdDemanglers,
+    middlewares: VcdTokenMiddlewares,
+    header_byte_limit: Option<usize>,
+    header_time_limit: Option<Duration>,
+    warning_count: usize,
+    warnings: bool,
+    warning_messages: Option<Vec<String>>,
+    // Changes read but not returned, to zero-width variables or events that
+    // did not trigger
+    elided_count: usize,
 }
 
 impl VcdReader {
@@ -334,10 +1050,50 @@ impl VcdReader {
         Self {
             bs: ByteStorage::new(),
             header: VcdHeader::new(),
-            scope_depth: 0,
+            open_scopes: Vec::new(),
+            pending_attrs: Vec::new(),
+            max_scope_depth: None,
+            dumpall_validator: None,
+            width_reconciler: None,
+            idcode_issues: None,
+            repairs: None,
+            duplicate_policy: VcdDuplicatePolicy::default(),
+            simulator_profile: VcdSimulatorProfile::default(),
+            truncated: HashSet::new(),
+            timestamp: None,
+            changed: HashSet::new(),
+            pending: None,
+            rewound: false,
+            checkpoint: false,
+            idle_skip: false,
+            namespaces: false,
+            idle: false,
+            held: None,
+            held_entry: None,
+            transforms: VcdTransforms::new(),
+            demanglers: VcdDemanglers::new(),
+            middlewares: VcdTokenMiddlewares::new(),
+            header_byte_limit: None,
+            header_time_limit: None,
+            warning_count: 0,
+            warnings: true,
+            warning_messages: None,
+            elided_count: 0,
         }
     }
 
+    // Creates a reader for a region of the body that starts at a timestamp
+    // from the reader that parsed the header, which only needs the declared
+    // idcodes and the settings that change how the body is read
+    pub(crate) fn new_region(&self) -> Self {
+        let mut reader = Self::new();
+        reader.header.idcodes = self.header.idcodes.clone();
+        reader.simulator_profile = self.simulator_profile;
+        reader.duplicate_policy = self.duplicate_policy;
+        reader.warnings = self.warnings;
+        reader
+    }
+
     pub fn get_byte_storage(&self) -> &ByteStorage {
         &self.bs
     }
@@ -354,44 +1110,383 @@ impl VcdReader {
         self.header
     }
 
+    /// Returns an idcode as it appeared in the source
+    pub fn get_idcode_string(&self, idcode: usize) -> String {
+        TokenIdCode::new(idcode).to_printable(&self.bs)
+    }
+
+    /// Returns every declared idcode with its encoding and source string,
+    /// sorted by id
+    pub fn get_idcodes(&self) -> Vec<(usize, TokenIdCodeEncoding, String)> {
+        let mut idcodes: Vec<(usize, TokenIdCodeEncoding, String)> = self
+            .header
+            .idcodes
+            .keys()
+            .map(|idcode| {
+                let token_idcode = TokenIdCode::new(*idcode);
+                (
+                    *idcode,
+                    token_idcode.get_encoding(),
+                    token_idcode.to_printable(&self.bs),
+                )
+            })
+            .collect();
+        idcodes.sort_unstable_by_key(|(idcode, _, _)| *idcode);
+        idcodes
+    }
+
+    /// Checks every $dumpall block against the values seen so far, which
+    /// keeps a copy of the latest value of every signal while enabled
+    pub fn set_dumpall_validation(&mut self, enabled: bool) {
+        self.dumpall_validator = if enabled {
+            Some(VcdDumpAllValidator::new())
+        } else {
+            None
+        };
+    }
+
+    /// Returns the $dumpall discrepancies found so far, always empty if
+    /// validation is not enabled
+    pub fn get_dumpall_issues(&self) -> &[VcdDumpAllIssue] {
+        match &self.dumpall_validator {
+            Some(validator) => validator.get_issues(),
+            None => &[],
+        }
+    }
+
+    /// Compares the width of every dumped value with the width its variable
+    /// was declared with
+    pub fn set_width_reconciliation(&mut self, enabled: bool) {
+        self.width_reconciler = if enabled {
+            Some(VcdWidthReconciler::new())
+        } else {
+            None
+        };
+    }
+
+    /// Returns the idcodes that had values of another width dumped for them
+    /// so far, always empty if reconciliation is not enabled
+    pub fn get_width_mismatches(&self) -> Vec<VcdWidthMismatch> {
+        match &self.width_reconciler {
+            Some(reconciler) => reconciler.get_mismatches(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Warns about declared idcodes with characters outside of `!` to `~`
+    pub fn set_idcode_validation(&mut self, enabled: bool) {
+        self.idcode_issues = if enabled { Some(Vec::new()) } else { None };
+    }
+
+    /// Returns the nonconforming idcodes declared so far, always empty if
+    /// validation is not enabled
+    pub fn get_idcode_issues(&self) -> &[VcdIdcodeIssue] {
+        match &self.idcode_issues {
+            Some(issues) => issues,
+            None => &[],
+        }
+    }
+
+    /// Fixes unbalanced scopes and a missing $enddefinitions in the header
+    /// instead of failing, each fix is logged as a warning and recorded
+    pub fn set_header_repair(&mut self, enabled: bool) {
+        self.repairs = enabled.then(Vec::new);
+    }
+
+    /// Returns the repairs applied to the header, always empty if repair is
+    /// not enabled
+    pub fn get_repairs(&self) -> &[VcdRepair] {
+        match &self.repairs {
+            Some(repairs) => repairs,
+            None => &[],
+        }
+    }
+
+    // Records a repair, returning false if repair is not enabled
+    fn repair(&mut self, kind: VcdRepairKind, pos: LexerPosition) -> bool {
+        let Some(repairs) = &mut self.repairs else {
+            return false;
+        };
+        let repair = VcdRepair::new(kind, pos);
+        warn_input!(self, "Repairing header: {}", repair);
+        repairs.push(repair);
+        true
+    }
+
+    // Closes the innermost open scope, adding it to its parent
+    fn close_scope(&mut self) -> Option<()> {
+        let mut scope = self.open_scopes.pop()?;
+        attach_attrs(
+            &mut self.pending_attrs,
+            &mut scope.attrs,
+            &mut scope.attributes,
+        );
+        let siblings = match self.open_scopes.last_mut() {
+            Some(parent) => &mut parent.scopes,
+            // Roots after a namespace marker belong to that namespace
+            None => match self.header.namespaces.last_mut() {
+                Some(namespace) => &mut namespace.scopes,
+                None => &mut self.header.scopes,
+            },
+        };
+        if self.simulator_profile.merges_repeated_scopes() {
+            merge_scope(siblings, scope);
+        } else {
+            siblings.push(scope);
+        }
+        Some(())
+    }
+
+    /// Drops the timestamps of an idle stretch with no changes except the one
+    /// that starts it and the last timestamp of the dump, which shrinks the
+    /// waveform of sparse dumps without changing the value of any signal at
+    /// any time
+    pub fn set_idle_skip(&mut self, enabled: bool) {
+        self.idle_skip = enabled;
+    }
+
+    /// Reads the namespace markers the VCD exporter writes before the roots
+    /// of each namespace, off by default so every comment is kept as one
+    pub fn set_namespaces(&mut self, enabled: bool) {
+        self.namespaces = enabled;
+    }
+
+    pub fn set_duplicate_policy(&mut self, policy: VcdDuplicatePolicy) {
+        self.duplicate_policy = policy;
+    }
+
+    pub fn get_duplicate_policy(&self) -> VcdDuplicatePolicy {
+        self.duplicate_policy
+    }
+
+    /// Tolerates the quirks of a simulator, Auto picks the profile once the
+    /// $version is read
+    pub fn set_simulator_profile(&mut self, profile: VcdSimulatorProfile) {
+        self.simulator_profile = profile;
+    }
+
+    /// Returns the profile in use, still Auto if no $version was read
+    pub fn get_simulator_profile(&self) -> VcdSimulatorProfile {
+        self.simulator_profile
+    }
+
+    /// Fails parse_header on a $scope nested deeper than a limit, which keeps
+    /// malformed input from building a hierarchy too deep to walk
+    pub fn set_max_scope_depth(&mut self, depth: Option<usize>) {
+        self.max_scope_depth = depth;
+    }
+
+    /// Fails parse_header if $enddefinitions is not reached within a number
+    /// of bytes, so input that is not a VCD fails before it is fully scanned
+    pub fn set_header_byte_limit(&mut self, limit: Option<usize>) {
+        self.header_byte_limit = limit;
+    }
+
+    /// Fails parse_header if $enddefinitions is not reached within a time
+    pub fn set_header_time_limit(&mut self, limit: Option<Duration>) {
+        self.header_time_limit = limit;
+    }
+
+    /// Replaces the transforms applied to value changes before they are
+    /// returned by parse_waveform
+    pub fn set_transforms(&mut self, transforms: VcdTransforms) {
+        self.transforms = transforms;
+    }
+
+    pub fn get_transforms_mut(&mut self) -> &mut VcdTransforms {
+        &mut self.transforms
+    }
+
+    // Takes the transforms out of the reader with the ones added by path
+    // resolved against its header, to hand them to a region reader
+    pub(crate) fn take_transforms(&mut self) -> VcdTransforms {
+        self.resolve_transforms();
+        std::mem::take(&mut self.transforms)
+    }
+
+    // Transforms can be added by path before the header is parsed
+    fn resolve_transforms(&mut self) {
+        for path in self.transforms.resolve(&self.header) {
+            warn_input!(self, "Ignoring transform of undeclared variable {}", path);
+        }
+    }
+
+    /// Replaces the demanglers applied to variable and scope names by
+    /// parse_header, the declared names are kept as attributes
+    pub fn set_demanglers(&mut self, demanglers: VcdDemanglers) {
+        self.demanglers = demanglers;
+    }
+
+    pub fn get_demanglers_mut(&mut self) -> &mut VcdDemanglers {
+        &mut self.demanglers
+    }
+
+    /// Replaces the middleware every token passes through between the
+    /// tokenizer and the parser, empty by default
+    pub fn set_token_middlewares(&mut self, middlewares: VcdTokenMiddlewares) {
+        self.middlewares = middlewares;
+    }
+
+    pub fn get_token_middlewares_mut(&mut self) -> &mut VcdTokenMiddlewares {
+        &mut self.middlewares
+    }
+
+    /// Logs a warning for every problem tolerated in the input, true by
+    /// default, the problems are still counted when warnings are off
+    pub fn set_warnings(&mut self, enabled: bool) {
+        self.warnings = enabled;
+    }
+
+    /// Returns how many warnings were found so far
+    pub fn get_warning_count(&self) -> usize {
+        self.warning_count
+    }
+
+    /// Keeps the message of every warning until it is taken, off by default
+    pub fn set_warning_messages(&mut self, enabled: bool) {
+        self.warning_messages = enabled.then(Vec::new);
+    }
+
+    /// Returns the warnings kept since the last call, see set_warning_messages
+    pub fn take_warning_messages(&mut self) -> Vec<String> {
+        self.warning_messages
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Returns how many changes were read but dropped so far, those of
+    /// zero-width variables and event values that are not a trigger
+    pub fn get_elided_count(&self) -> usize {
+        self.elided_count
+    }
+
+    // Records a header directive, returning true if its value should replace
+    // any earlier one
+    fn record_directive(
+        &mut self,
+        directive: VcdDirective,
+        pos: LexerPosition,
+    ) -> ParserResult<bool> {
+        let duplicate = self
+            .header
+            .directives
+            .iter()
+            .any(|(other, _)| *other == directive);
+        self.header.directives.push((directive, pos));
+        if !duplicate {
+            return Ok(true);
+        }
+        match self.duplicate_policy {
+            VcdDuplicatePolicy::Error => Err(ParserError::DuplicateDirective(pos)),
+            VcdDuplicatePolicy::KeepFirst => {
+                warn_input!(
+                    self,
+                    "Ignoring duplicate {} at line {}",
+                    directive,
+                    pos.get_line()
+                );
+                Ok(false)
+            }
+            VcdDuplicatePolicy::KeepLast => {
+                warn_input!(
+                    self,
+                    "Overriding {} with line {}",
+                    directive,
+                    pos.get_line()
+                );
+                Ok(true)
+            }
+        }
+    }
+
     pub fn parse_header<F>(&mut self, token_generator: &mut F) -> ParserResult<()>
     where
         F: FnMut(&mut ByteStorage) -> TokenizerResult<Option<Token>>,
     {
+        let deadline = self.header_time_limit.map(|limit| Instant::now() + limit);
         loop {
-            let token = match token_generator(&mut self.bs) {
+            let token = match self.middlewares.next(&mut self.bs, token_generator) {
                 Ok(Some(token)) => token,
                 Ok(None) => return Err(ParserError::UnexpectedTermination),
+                // The lexer stops a block at the byte limit, see
+                // Lexer::set_block_limit
+                Err(TokenizerError::LexerError(pos)) if matches!(self.header_byte_limit, Some(limit) if pos.get_range().end > limit) =>
+                {
+                    return Err(ParserError::HeaderLimit(pos));
+                }
                 Err(err) => return Err(ParserError::Tokenizer(err)),
             };
+            let pos = token.get_position();
+            if matches!(self.header_byte_limit, Some(limit) if pos.get_range().end > limit)
+                || matches!(deadline, Some(deadline) if Instant::now() > deadline)
+            {
+                return Err(ParserError::HeaderLimit(pos));
+            }
             match token {
-                Token::Comment(_, _) => {}
-                Token::Date(id, _) => {
-                    self.header.date =
-                        Some(String::from_utf8_lossy(&self.bs.get_bytes(id)).to_string());
+                Token::Comment(id, _) => {
+                    let comment = String::from_utf8_lossy(&self.bs.get_bytes(id)).to_string();
+                    match parse_marker(&comment) {
+                        Some(name)
+                            if self.namespaces
+                                && self.open_scopes.is_empty()
+                                && self.header.get_namespace(name).is_none() =>
+                        {
+                            self.header.namespaces.push(VcdNamespace::new(name))
+                        }
+                        _ => self.header.comments.push(comment),
+                    }
                 }
-                Token::Version(id, _) => {
-                    self.header.version =
-                        Some(String::from_utf8_lossy(&self.bs.get_bytes(id)).to_string());
+                Token::Date(id, pos) => {
+                    if self.record_directive(VcdDirective::Date, pos)? {
+                        self.header.date =
+                            Some(String::from_utf8_lossy(&self.bs.get_bytes(id)).to_string());
+                    }
+                }
+                Token::Version(id, pos) => {
+                    if self.record_directive(VcdDirective::Version, pos)? {
+                        let version = String::from_utf8_lossy(&self.bs.get_bytes(id)).to_string();
+                        if self.simulator_profile == VcdSimulatorProfile::Auto {
+                            self.simulator_profile = VcdSimulatorProfile::detect(&version);
+                            if self.warnings {
+                                log::debug!("Using the {} profile", self.simulator_profile);
+                            }
+                        }
+                        self.header.version = Some(version);
+                    }
                 }
                 Token::Timescale {
                     timescale,
                     offset,
-                    pos: _,
+                    pos,
                 } => {
-                    self.header.timescale = Some(convert_timescale(timescale, offset));
+                    if self.record_directive(VcdDirective::Timescale, pos)? {
+                        self.header.timescale = Some(convert_timescale(timescale, offset));
+                    }
                 }
                 Token::Scope {
                     scope_type,
                     scope_id,
-                    pos: _,
+                    pos,
                 } => {
-                    let mut scopes = &mut self.header.scopes;
-                    for _ in 0..self.scope_depth {
-                        scopes = &mut scopes.last_mut().unwrap().scopes;
+                    if let Some(max_depth) = self.max_scope_depth {
+                        if self.open_scopes.len() >= max_depth {
+                            return Err(ParserError::ScopeDepth(max_depth, pos));
+                        }
+                    }
+                    let mut scope = VcdScope::new(scope_id, scope_type, &self.bs);
+                    attach_attrs(
+                        &mut self.pending_attrs,
+                        &mut scope.attrs,
+                        &mut scope.attributes,
+                    );
+                    if let Some(name) = self.demanglers.apply(&scope.name) {
+                        let original = std::mem::replace(&mut scope.name, name);
+                        scope
+                            .attributes
+                            .insert(VCD_ORIGINAL_NAME.to_string(), original);
                     }
-                    scopes.push(VcdScope::new(scope_id, scope_type, &self.bs));
-                    self.scope_depth += 1;
+                    self.open_scopes.push(scope);
                 }
                 Token::Var {
                     net_type,
@@ -400,10 +1495,10 @@ impl VcdReader {
                     variable_description,
                     pos,
                 } => {
-                    if self.scope_depth == 0 {
+                    if self.open_scopes.is_empty() {
                         return Err(ParserError::UnexpectedVariable(pos));
                     }
-                    let variable = VcdVariable::new(
+                    let mut variable = VcdVariable::new(
                         width,
                         variable_description,
                         net_type,
@@ -411,6 +1506,12 @@ impl VcdReader {
                         &pos,
                         &self.bs,
                     )?;
+                    if let Some(name) = self.demanglers.apply(&variable.name) {
+                        let original = std::mem::replace(&mut variable.name, name);
+                        variable
+                            .attributes
+                            .insert(VCD_ORIGINAL_NAME.to_string(), original);
+                    }
                     if let Some(old_width) = self
                         .header
                         .idcodes
@@ -420,55 +1521,358 @@ impl VcdReader {
                             return Err(ParserError::UnmatchedIdcode(pos));
                         }
                     }
-                    let mut scopes = &mut self.header.scopes;
-                    for _ in 0..self.scope_depth - 1 {
-                        scopes = &mut scopes.last_mut().unwrap().scopes;
+                    self.header
+                        .idcode_strings
+                        .entry(token_idcode.get_id())
+                        .or_insert_with(|| token_idcode.to_printable(&self.bs));
+                    if let Some(issues) = &mut self.idcode_issues {
+                        let bytes = token_idcode.get_bytes(&self.bs);
+                        if !is_printable_idcode(&bytes) {
+                            let idcode = String::from_utf8_lossy(&bytes).to_string();
+                            warn_input!(
+                                self,
+                                "Variable {} at line {} has idcode {:?} with characters outside of '!' to '~'",
+                                variable.get_name(),
+                                pos.get_line(),
+                                idcode
+                            );
+                            issues.push(VcdIdcodeIssue { idcode, pos });
+                        }
+                    }
+                    if variable.width.is_empty() {
+                        warn_input!(
+                            self,
+                            "Variable {} at line {} has no bits, its changes are ignored",
+                            variable.get_name(),
+                            pos.get_line()
+                        );
+                    }
+                    attach_attrs(
+                        &mut self.pending_attrs,
+                        &mut variable.attrs,
+                        &mut variable.attributes,
+                    );
+                    match self.open_scopes.last_mut() {
+                        Some(scope) => scope.variables.push(variable),
+                        None => return Err(ParserError::UnexpectedVariable(pos)),
                     }
-                    scopes.last_mut().unwrap().variables.push(variable);
                 }
+                Token::Unknown(id, pos) => {
+                    let block = String::from_utf8_lossy(&self.bs.get_bytes(id)).to_string();
+                    let directive = VcdUnknownDirective::new(&block, pos);
+                    warn_input!(
+                        self,
+                        "Keeping unknown directive ${} at line {}",
+                        directive.get_keyword(),
+                        pos.get_line()
+                    );
+                    self.header.unknown_directives.push(directive);
+                }
+                Token::AttrBegin(id, _) => {
+                    let text = String::from_utf8_lossy(&self.bs.get_bytes(id)).to_string();
+                    self.pending_attrs.push(VcdAttr::parse(&text));
+                }
+                // Attributes are attached as they are declared, so there is
+                // nothing left to close
+                Token::AttrEnd(_) => {}
                 Token::UpScope(pos) => {
-                    if self.scope_depth == 0 {
+                    if self.close_scope().is_none()
+                        && !self.repair(VcdRepairKind::ExtraUpscope, pos)
+                    {
                         return Err(ParserError::UnexpectedUpscope(pos));
                     }
-                    self.scope_depth -= 1;
                 }
                 Token::EndDefinitions(pos) => {
-                    if self.scope_depth != 0 {
-                        return Err(ParserError::UnexpectedEndDefinitions(pos));
+                    self.header.attrs.append(&mut self.pending_attrs);
+                    if !self.open_scopes.is_empty() {
+                        if !self.repair(VcdRepairKind::MissingUpscope, pos) {
+                            return Err(ParserError::UnexpectedEndDefinitions(pos));
+                        }
+                        while self.close_scope().is_some() {}
                     }
                     return Ok(());
                 }
+                // The body is parsed from the token that started it
+                t if starts_body(&t)
+                    && self.repair(VcdRepairKind::MissingEndDefinitions, t.get_position()) =>
+                {
+                    while self.close_scope().is_some() {}
+                    self.header.attrs.append(&mut self.pending_attrs);
+                    self.pending = Some(t);
+                    return Ok(());
+                }
                 t => return Err(ParserError::UnexpectedToken(t)),
             }
         }
     }
 
+    // Records a change of a signal at the current timestamp
+    fn check_change(&mut self, idcode: usize, pos: LexerPosition) -> ParserResult<()> {
+        if self.timestamp.is_none() {
+            return Err(ParserError::MissingTimestamp(pos));
+        }
+        if !self.changed.insert(idcode) {
+            return Err(ParserError::DuplicateChange(pos));
+        }
+        Ok(())
+    }
+
+    fn reconcile_width(&mut self, idcode: usize, used: VcdVariableWidth, pos: LexerPosition) {
+        if let (Some(reconciler), Some(declared)) =
+            (&mut self.width_reconciler, self.header.idcodes.get(&idcode))
+        {
+            reconciler.check(idcode, declared, used, pos);
+        }
+    }
+
     pub fn parse_waveform<F>(&mut self, token_generator: &mut F) -> ParserResult<Option<VcdEntry>>
     where
         F: FnMut(&mut ByteStorage) -> TokenizerResult<Option<Token>>,
     {
+        if !self.idle_skip {
+            return self.parse_entry(token_generator);
+        }
+        if let Some(entry) = self.held_entry.take() {
+            return Ok(Some(entry));
+        }
+        loop {
+            match self.parse_entry(token_generator)? {
+                Some(VcdEntry::Timestamp(timestamp)) if self.idle => {
+                    if self.held.replace(timestamp).is_some() {
+                        self.elided_count += 1;
+                    }
+                }
+                Some(VcdEntry::Timestamp(timestamp)) => {
+                    self.idle = true;
+                    return Ok(Some(VcdEntry::Timestamp(timestamp)));
+                }
+                Some(entry) => {
+                    self.idle = false;
+                    return Ok(match self.held.take() {
+                        Some(timestamp) => {
+                            self.held_entry = Some(entry);
+                            Some(VcdEntry::Timestamp(timestamp))
+                        }
+                        None => Some(entry),
+                    });
+                }
+                // The end of the dump is kept
+                None => return Ok(self.held.take().map(VcdEntry::Timestamp)),
+            }
+        }
+    }
+
+    fn parse_entry<F>(&mut self, token_generator: &mut F) -> ParserResult<Option<VcdEntry>>
+    where
+        F: FnMut(&mut ByteStorage) -> TokenizerResult<Option<Token>>,
+    {
+        self.resolve_transforms();
         let entry = loop {
-            let token = match token_generator(&mut self.bs) {
-                Ok(Some(token)) => token,
-                Ok(None) => return Ok(None),
-                Err(err) => return Err(ParserError::Tokenizer(err)),
+            let token = match self.pending.take() {
+                Some(token) => token,
+                None => match self.middlewares.next(&mut self.bs, token_generator) {
+                    Ok(Some(token)) => token,
+                    Ok(None) => return Ok(None),
+                    Err(err) => return Err(ParserError::Tokenizer(err)),
+                },
             };
+            let change = match &token {
+                Token::ScalarValue(_, idcode, _)
+                | Token::VectorValue(_, idcode, _)
+                | Token::RealValue(_, idcode, _)
+                | Token::StringValue(_, idcode, _)
+                | Token::PortValue(_, idcode, _) => Some(idcode.get_id()),
+                _ => None,
+            };
+            let is_change = change.is_some();
+            if is_change && self.rewound {
+                self.elided_count += 1;
+                continue;
+            }
+            // A signal that already changed at the timestamp of a $dumpall
+            // keeps that change instead of failing as a duplicate
+            if let Some(idcode) =
+                change.filter(|idcode| self.checkpoint && self.changed.contains(idcode))
+            {
+                if let Some(validator) = &mut self.dumpall_validator {
+                    validator.mark_seen(idcode);
+                }
+                self.elided_count += 1;
+                continue;
+            }
+            if is_change
+                && self.timestamp.is_none()
+                && self.simulator_profile.assumes_initial_timestamp()
+            {
+                warn_input!(
+                    self,
+                    "Placing changes before the first timestamp at line {} at zero",
+                    token.get_position().get_line()
+                );
+                self.timestamp = Some(0);
+                self.pending = Some(token);
+                break VcdEntry::Timestamp(0);
+            }
             match token {
-                Token::Timestamp(timestamp, _) => break VcdEntry::Timestamp(timestamp),
-                Token::VectorValue(bv, idcode, _) => break VcdEntry::Vector(bv, idcode.get_id()),
-                Token::RealValue(value, idcode, _) => break VcdEntry::Real(value, idcode.get_id()),
+                Token::Timestamp(timestamp, pos) => {
+                    if let Some(last) = self.timestamp {
+                        if (timestamp < last || (self.rewound && timestamp == last))
+                            && self.simulator_profile.drops_rewound_timestamps()
+                        {
+                            if !self.rewound {
+                                warn_input!(
+                                    self,
+                                    "Dropping changes from line {} until time passes {}",
+                                    pos.get_line(),
+                                    last
+                                );
+                            }
+                            self.rewound = true;
+                            continue;
+                        }
+                    }
+                    self.rewound = false;
+                    // Repeated timestamps are merged by the waveform
+                    if self.timestamp != Some(timestamp) {
+                        self.changed.clear();
+                    }
+                    self.timestamp = Some(timestamp);
+                    break VcdEntry::Timestamp(timestamp);
+                }
+                Token::ScalarValue(bit, idcode, pos) => {
+                    self.reconcile_width(
+                        idcode.get_id(),
+                        VcdVariableWidth::Vector { width: 1 },
+                        pos,
+                    );
+                    match self.header.idcodes.get(&idcode.get_id()) {
+                        Some(VcdVariableWidth::Vector { width: 0 }) => self.elided_count += 1,
+                        Some(VcdVariableWidth::Event) => {
+                            if bit == Logic::One {
+                                match self.check_change(idcode.get_id(), pos) {
+                                    Ok(()) => break VcdEntry::Event(idcode.get_id()),
+                                    Err(ParserError::DuplicateChange(_)) => {}
+                                    Err(err) => return Err(err),
+                                }
+                            }
+                            self.elided_count += 1;
+                        }
+                        _ => {
+                            self.check_change(idcode.get_id(), pos)?;
+                            if let Some(validator) = &mut self.dumpall_validator {
+                                validator.update_vector(idcode.get_id(), &bit.into(), pos);
+                            }
+                            break VcdEntry::Scalar(bit, idcode.get_id());
+                        }
+                    }
+                }
+                Token::VectorValue(bv, idcode, pos) => {
+                    let width = bv.get_bit_width();
+                    self.reconcile_width(idcode.get_id(), VcdVariableWidth::Vector { width }, pos);
+                    let bv = match self.header.idcodes.get(&idcode.get_id()) {
+                        Some(VcdVariableWidth::Vector { width })
+                            if *width < bv.get_bit_width()
+                                && self.simulator_profile.truncates_wide_values() =>
+                        {
+                            if self.truncated.insert(idcode.get_id()) {
+                                warn_input!(
+                                    self,
+                                    "Truncating values of {} bits at line {} to {} bits",
+                                    bv.get_bit_width(),
+                                    pos.get_line(),
+                                    width
+                                );
+                            }
+                            truncate_bits(&bv, *width)
+                        }
+                        _ => bv,
+                    };
+                    match self.header.idcodes.get(&idcode.get_id()) {
+                        // Only a value with a one marks an event trigger, other
+                        // values like the initial unknown in $dumpvars are
+                        // dropped, as are repeated triggers in the same timestamp
+                        Some(VcdVariableWidth::Vector { width: 0 }) => self.elided_count += 1,
+                        Some(VcdVariableWidth::Event) => {
+                            if bv.iter().any(|bit| bit == Logic::One) {
+                                match self.check_change(idcode.get_id(), pos) {
+                                    Ok(()) => break VcdEntry::Event(idcode.get_id()),
+                                    Err(ParserError::DuplicateChange(_)) => {}
+                                    Err(err) => return Err(err),
+                                }
+                            }
+                            self.elided_count += 1;
+                        }
+                        _ => {
+                            self.check_change(idcode.get_id(), pos)?;
+                            if let Some(validator) = &mut self.dumpall_validator {
+                                validator.update_vector(idcode.get_id(), &bv, pos);
+                            }
+                            break VcdEntry::Vector(bv, idcode.get_id());
+                        }
+                    }
+                }
+                Token::RealValue(value, idcode, pos) => {
+                    self.reconcile_width(idcode.get_id(), VcdVariableWidth::Real, pos);
+                    self.check_change(idcode.get_id(), pos)?;
+                    if let Some(validator) = &mut self.dumpall_validator {
+                        validator.update_real(idcode.get_id(), value, pos);
+                    }
+                    break VcdEntry::Real(value, idcode.get_id());
+                }
+                Token::StringValue(value, idcode, pos) => {
+                    self.reconcile_width(idcode.get_id(), VcdVariableWidth::String, pos);
+                    self.check_change(idcode.get_id(), pos)?;
+                    break VcdEntry::String(value, idcode.get_id());
+                }
+                Token::PortValue(value, idcode, pos) => {
+                    let width = value.get_bit_width();
+                    self.reconcile_width(idcode.get_id(), VcdVariableWidth::Vector { width }, pos);
+                    self.check_change(idcode.get_id(), pos)?;
+                    if let Some(validator) = &mut self.dumpall_validator {
+                        validator.update_vector(idcode.get_id(), &value.to_bitvector(), pos);
+                    }
+                    break VcdEntry::Port(value, idcode.get_id());
+                }
+                // The dump ends at the time it was closed, if that is later
+                // than the last change
+                Token::VcdClose(timestamp, _) => {
+                    if self.timestamp.map_or(true, |last| timestamp > last) && !self.rewound {
+                        self.changed.clear();
+                        self.timestamp = Some(timestamp);
+                        break VcdEntry::Timestamp(timestamp);
+                    }
+                }
+                Token::DumpAll(pos) => {
+                    self.checkpoint = true;
+                    if let Some(validator) = &mut self.dumpall_validator {
+                        validator.begin(pos);
+                    }
+                }
+                Token::End(_) => {
+                    self.checkpoint = false;
+                    if let Some(validator) = &mut self.dumpall_validator {
+                        validator.end(&self.header);
+                    }
+                }
                 // Ignore these tokens
                 Token::Comment(_, _) => {}
-                Token::DumpAll(_) => {}
+                Token::AttrBegin(_, _) => {}
+                Token::AttrEnd(_) => {}
+                Token::Unknown(_, pos) => {
+                    warn_input!(
+                        self,
+                        "Skipping unknown directive at line {}",
+                        pos.get_line()
+                    );
+                }
                 Token::DumpOff(_) => {}
                 Token::DumpOn(_) => {}
                 Token::DumpVars(_) => {}
-                Token::End(_) => {}
                 t => return Err(ParserError::UnexpectedToken(t)),
             }
         };
 
-        Ok(Some(entry))
+        Ok(Some(self.transforms.apply(entry)))
     }
 }
 
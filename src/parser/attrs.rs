@@ -0,0 +1,58 @@
+/// Metadata declared with a $attrbegin block, as written by FST converters
+/// and some simulators, like the source file a variable was declared in.
+/// Attributes belong to the scope or variable declared after them, or to the
+/// enclosing scope if none follows before its $upscope.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VcdAttr {
+    kind: String,
+    subtype: String,
+    name: String,
+    arg: String,
+}
+
+impl VcdAttr {
+    /// Splits the text of a block into its kind, subtype, name and argument,
+    /// any of them can be missing, the argument holds the rest of the text
+    pub fn parse(text: &str) -> Self {
+        let mut words = text.split_whitespace();
+        let kind = words.next().unwrap_or_default().to_string();
+        let subtype = words.next().unwrap_or_default().to_string();
+        let name = words.next().unwrap_or_default().to_string();
+        let arg = words.collect::<Vec<&str>>().join(" ");
+        Self {
+            kind,
+            subtype,
+            name,
+            arg,
+        }
+    }
+
+    /// Returns the kind of attribute, like misc, array, enum or class
+    pub fn get_kind(&self) -> &String {
+        &self.kind
+    }
+
+    pub fn get_subtype(&self) -> &String {
+        &self.subtype
+    }
+
+    pub fn get_name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn get_arg(&self) -> &String {
+        &self.arg
+    }
+}
+
+impl std::fmt::Display for VcdAttr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let words = [&self.kind, &self.subtype, &self.name, &self.arg];
+        let words: Vec<&str> = words
+            .iter()
+            .map(|word| word.as_str())
+            .filter(|word| !word.is_empty())
+            .collect();
+        write!(f, "{}", words.join(" "))
+    }
+}
@@ -0,0 +1,95 @@
+/// Attribute holding the name a variable or scope was declared with, set on
+/// every variable and scope whose name was demangled
+pub const VCD_ORIGINAL_NAME: &str = "original_name";
+
+/// Rewrites the name of a variable or scope as the header is built, returning
+/// None to leave it as it is
+pub type VcdDemangleFn = Box<dyn Fn(&str) -> Option<String> + Send>;
+
+/// Name demanglers applied by the reader, each one sees the name left by the
+/// ones before it
+#[derive(Default)]
+pub struct VcdDemanglers {
+    demanglers: Vec<VcdDemangleFn>,
+}
+
+impl VcdDemanglers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_demangler(&mut self, demangler: VcdDemangleFn) {
+        self.demanglers.push(demangler);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.demanglers.is_empty()
+    }
+
+    /// Returns the demangled name, None if no demangler changed it
+    pub fn apply(&self, name: &str) -> Option<String> {
+        let mut result: Option<String> = None;
+        for demangler in &self.demanglers {
+            if let Some(demangled) = demangler(result.as_deref().unwrap_or(name)) {
+                result = Some(demangled);
+            }
+        }
+        result.filter(|demangled| demangled != name)
+    }
+}
+
+/// Undoes the Verilator encoding of flattened hierarchy (`a__DOT__b` becomes
+/// `a.b`) and of characters not allowed in C identifiers (`__05F` becomes
+/// `_`). Names with dots are not found by path lookups, which split on them.
+pub fn demangle_verilator() -> VcdDemangleFn {
+    Box::new(|name| {
+        if !name.contains("__") {
+            return None;
+        }
+        let mut result = String::with_capacity(name.len());
+        let mut rest = name;
+        while let Some(start) = rest.find("__") {
+            result.push_str(&rest[..start]);
+            rest = &rest[start..];
+            if let Some(after) = rest.strip_prefix("__DOT__") {
+                result.push('.');
+                rest = after;
+                continue;
+            }
+            let decoded = rest
+                .get(2..5)
+                .filter(|hex| hex.starts_with('0'))
+                .and_then(|hex| u8::from_str_radix(&hex[1..], 16).ok())
+                .filter(|byte| byte.is_ascii_graphic());
+            match decoded {
+                Some(byte) => {
+                    result.push(byte as char);
+                    rest = &rest[5..];
+                }
+                None => {
+                    result.push_str("__");
+                    rest = &rest[2..];
+                }
+            }
+        }
+        result.push_str(rest);
+        Some(result)
+    })
+}
+
+/// Drops the leading backslash of a Verilog escaped identifier like `\a+b`
+pub fn demangle_escaped() -> VcdDemangleFn {
+    Box::new(|name| name.strip_prefix('\\').map(|name| name.to_string()))
+}
+
+/// Drops any of a set of suffixes added by synthesis, like `_reg` on flops
+pub fn demangle_suffixes(suffixes: &[&str]) -> VcdDemangleFn {
+    let suffixes: Vec<String> = suffixes.iter().map(|suffix| suffix.to_string()).collect();
+    Box::new(move |name| {
+        suffixes
+            .iter()
+            .filter(|suffix| name.len() > suffix.len())
+            .find_map(|suffix| name.strip_suffix(suffix.as_str()))
+            .map(|name| name.to_string())
+    })
+}
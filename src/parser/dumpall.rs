@@ -0,0 +1,112 @@
+use std::collections::{HashMap, HashSet};
+
+use makai_waveform_db::bitvector::BitVector;
+
+use crate::lexer::position::LexerPosition;
+use crate::parser::{VcdHeader, VcdVariableWidth};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VcdDumpAllIssue {
+    // A declared idcode did not receive a value in the $dumpall block
+    Missing { idcode: usize, pos: LexerPosition },
+    // A $dumpall value differed from the value the signal last changed to
+    Mismatch { idcode: usize, pos: LexerPosition },
+}
+
+impl VcdDumpAllIssue {
+    pub fn get_idcode(&self) -> usize {
+        match self {
+            Self::Missing { idcode, pos: _ } | Self::Mismatch { idcode, pos: _ } => *idcode,
+        }
+    }
+
+    pub fn get_position(&self) -> LexerPosition {
+        match self {
+            Self::Missing { idcode: _, pos } | Self::Mismatch { idcode: _, pos } => *pos,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum VcdDumpAllValue {
+    Vector(BitVector),
+    // Compared bit-for-bit so NaN values still match themselves
+    Real(u64),
+}
+
+// Tracks the latest value of every signal so that $dumpall blocks, which are
+// supposed to restate the current state, can be checked against it
+#[derive(Clone, Debug, Default)]
+pub struct VcdDumpAllValidator {
+    values: HashMap<usize, VcdDumpAllValue>,
+    // Position of the open $dumpall block and the idcodes seen in it
+    block: Option<(LexerPosition, HashSet<usize>)>,
+    issues: Vec<VcdDumpAllIssue>,
+}
+
+impl VcdDumpAllValidator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn begin(&mut self, pos: LexerPosition) {
+        self.block = Some((pos, HashSet::new()));
+    }
+
+    /// Closes an open $dumpall block, reporting every declared idcode that
+    /// did not get a value, events and empty vectors are skipped since they
+    /// have no state
+    pub fn end(&mut self, header: &VcdHeader) {
+        let (pos, seen) = match self.block.take() {
+            Some(block) => block,
+            None => return,
+        };
+        let mut missing: Vec<usize> = header
+            .get_idcodes_map()
+            .iter()
+            .filter(|(idcode, width)| {
+                !matches!(width, VcdVariableWidth::Event | VcdVariableWidth::String)
+                    && !width.is_empty()
+                    && !seen.contains(idcode)
+            })
+            .map(|(idcode, _)| *idcode)
+            .collect();
+        missing.sort_unstable();
+        self.issues.extend(
+            missing
+                .into_iter()
+                .map(|idcode| VcdDumpAllIssue::Missing { idcode, pos }),
+        );
+    }
+
+    fn update(&mut self, idcode: usize, value: VcdDumpAllValue, pos: LexerPosition) {
+        let old = self.values.insert(idcode, value);
+        if let Some((_, seen)) = &mut self.block {
+            seen.insert(idcode);
+            if old.is_some() && old.as_ref() != self.values.get(&idcode) {
+                self.issues.push(VcdDumpAllIssue::Mismatch { idcode, pos });
+            }
+        }
+    }
+
+    /// Counts an idcode as restated by the open $dumpall block without
+    /// comparing its value, for signals that already changed at the same
+    /// timestamp and keep that change
+    pub fn mark_seen(&mut self, idcode: usize) {
+        if let Some((_, seen)) = &mut self.block {
+            seen.insert(idcode);
+        }
+    }
+
+    pub fn update_vector(&mut self, idcode: usize, bv: &BitVector, pos: LexerPosition) {
+        self.update(idcode, VcdDumpAllValue::Vector(bv.clone()), pos);
+    }
+
+    pub fn update_real(&mut self, idcode: usize, value: f64, pos: LexerPosition) {
+        self.update(idcode, VcdDumpAllValue::Real(value.to_bits()), pos);
+    }
+
+    pub fn get_issues(&self) -> &Vec<VcdDumpAllIssue> {
+        &self.issues
+    }
+}
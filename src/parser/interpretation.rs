@@ -0,0 +1,34 @@
+/// How the bits of a vector variable should be read as a number, used by the
+/// formatting and export functions. Conversions are implemented in export.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum VcdInterpretation {
+    #[default]
+    Unsigned,
+    // Two's complement integer
+    Signed,
+    // Q-format fixed point, the integer value is scaled by 2^-fraction_bits
+    FixedPoint {
+        signed: bool,
+        fraction_bits: usize,
+    },
+    // IEEE 754 single or double precision bits, 32 or 64 bits wide
+    Float,
+}
+
+impl std::fmt::Display for VcdInterpretation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unsigned => write!(f, "unsigned"),
+            Self::Signed => write!(f, "signed"),
+            Self::FixedPoint {
+                signed: true,
+                fraction_bits,
+            } => write!(f, "sq{}", fraction_bits),
+            Self::FixedPoint {
+                signed: false,
+                fraction_bits,
+            } => write!(f, "uq{}", fraction_bits),
+            Self::Float => write!(f, "float"),
+        }
+    }
+}
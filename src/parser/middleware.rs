@@ -0,0 +1,74 @@
+use std::collections::VecDeque;
+
+use makai::utils::bytes::ByteStorage;
+
+use crate::errors::TokenizerResult;
+use crate::tokenizer::token::Token;
+
+/// Rewrites a token between the tokenizer and the parser, returning None to
+/// drop it. Tokens pushed to the second argument are handed on before the
+/// returned one, to synthesize tokens the input is missing.
+pub type VcdTokenMiddlewareFn = Box<dyn FnMut(Token, &mut Vec<Token>) -> Option<Token> + Send>;
+
+/// Token middleware applied by the reader in order, each one sees the tokens
+/// left and added by the ones before it
+#[derive(Default)]
+pub struct VcdTokenMiddlewares {
+    middlewares: Vec<VcdTokenMiddlewareFn>,
+    pending: VecDeque<Token>,
+}
+
+impl VcdTokenMiddlewares {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_middleware(&mut self, middleware: VcdTokenMiddlewareFn) {
+        self.middlewares.push(middleware);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.middlewares.is_empty()
+    }
+
+    // Returns the next token out of the last middleware, pulling as many
+    // tokens from the tokenizer as it takes
+    pub(crate) fn next<F>(
+        &mut self,
+        bs: &mut ByteStorage,
+        token_generator: &mut F,
+    ) -> TokenizerResult<Option<Token>>
+    where
+        F: FnMut(&mut ByteStorage) -> TokenizerResult<Option<Token>>,
+    {
+        if self.middlewares.is_empty() {
+            return token_generator(bs);
+        }
+        loop {
+            if let Some(token) = self.pending.pop_front() {
+                return Ok(Some(token));
+            }
+            let Some(token) = token_generator(bs)? else {
+                return Ok(None);
+            };
+            let mut tokens = vec![token];
+            for middleware in &mut self.middlewares {
+                let mut next = Vec::with_capacity(tokens.len());
+                for token in tokens {
+                    let kept = middleware(token, &mut next);
+                    next.extend(kept);
+                }
+                tokens = next;
+            }
+            self.pending.extend(tokens);
+        }
+    }
+}
+
+/// Wraps a middleware that only drops or rewrites tokens one for one
+pub fn map_tokens<F>(mut f: F) -> VcdTokenMiddlewareFn
+where
+    F: FnMut(Token) -> Option<Token> + Send + 'static,
+{
+    Box::new(move |token, _| f(token))
+}
@@ -0,0 +1,64 @@
+use crate::parser::VcdScope;
+
+/// Separates the namespace from the rest of a path, as in `runA:TOP.clk`
+pub const VCD_NAMESPACE_SEPARATOR: char = ':';
+
+// Written as a $comment before the root scopes of each namespace, tools that
+// do not know about namespaces see every root as a top-level scope. The
+// marker is versioned so a comment of another tool is not mistaken for one.
+const NAMESPACE_MARKER: &str = "makai_vcd_reader.namespace.v1";
+
+/// An independent root tree of a header, its variables share the idcodes of
+/// the header so they never collide with the ones of other namespaces
+#[derive(Clone, Debug, PartialEq)]
+pub struct VcdNamespace {
+    name: String,
+    pub(crate) scopes: Vec<VcdScope>,
+}
+
+impl VcdNamespace {
+    pub(crate) fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            scopes: Vec::new(),
+        }
+    }
+
+    pub fn get_name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn get_scopes(&self) -> &Vec<VcdScope> {
+        &self.scopes
+    }
+}
+
+/// Whether a name can be used for a namespace, it cannot be empty or contain
+/// the separators of a path
+pub fn is_valid_namespace(name: &str) -> bool {
+    !name.is_empty()
+        && !name.contains(VCD_NAMESPACE_SEPARATOR)
+        && !name.contains('.')
+        && !name.contains(char::is_whitespace)
+}
+
+// Shifts the idcode of every variable in a tree
+pub(crate) fn offset_idcodes(scope: &mut VcdScope, offset: usize) {
+    for variable in &mut scope.variables {
+        variable.idcode += offset;
+    }
+    for child in &mut scope.scopes {
+        offset_idcodes(child, offset);
+    }
+}
+
+// Returns the name of the namespace a header comment starts, if it is one
+pub(crate) fn parse_marker(comment: &str) -> Option<&str> {
+    let name = comment.trim().strip_prefix(NAMESPACE_MARKER)?;
+    let name = name.strip_prefix(char::is_whitespace)?.trim();
+    is_valid_namespace(name).then_some(name)
+}
+
+pub(crate) fn format_marker(name: &str) -> String {
+    format!(" {} {} ", NAMESPACE_MARKER, name)
+}
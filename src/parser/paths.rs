@@ -0,0 +1,71 @@
+use crate::parser::VcdHeader;
+
+/// The full paths of variables kept sorted, for type-ahead search over
+/// hierarchies too large to scan on every keystroke. The variables under a
+/// prefix are one contiguous run found by binary search.
+#[derive(Clone, Debug, Default)]
+pub struct VcdPathIndex {
+    // Sorted by path, a path declared twice keeps its idcodes in the order
+    // they were inserted
+    paths: Vec<(String, usize)>,
+}
+
+impl VcdPathIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Indexes every variable of a header, including the ones in namespaces
+    pub fn from_header(header: &VcdHeader) -> Self {
+        let mut paths: Vec<(String, usize)> = header
+            .get_variables_with_paths()
+            .into_iter()
+            .map(|(path, variable)| (path, variable.get_idcode()))
+            .collect();
+        paths.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Self { paths }
+    }
+
+    /// Adds a variable, so an index can be kept up to date as variables are
+    /// declared instead of being rebuilt
+    pub fn insert(&mut self, path: &str, idcode: usize) {
+        let position = self.paths.partition_point(|(p, _)| p.as_str() <= path);
+        self.paths.insert(position, (path.to_string(), idcode));
+    }
+
+    /// Returns how many variables are indexed
+    pub fn len(&self) -> usize {
+        self.paths.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+
+    // Returns the run of variables whose path starts with a prefix
+    fn with_prefix(&self, prefix: &str) -> &[(String, usize)] {
+        let start = self.paths.partition_point(|(p, _)| p.as_str() < prefix);
+        let paths = &self.paths[start..];
+        &paths[..paths.partition_point(|(p, _)| p.starts_with(prefix))]
+    }
+
+    /// Returns how many variables have a path starting with a prefix
+    pub fn count_with_prefix(&self, prefix: &str) -> usize {
+        self.with_prefix(prefix).len()
+    }
+
+    /// Returns a page of the variables whose path starts with a prefix, as
+    /// (path, idcode) pairs in lexical order of their paths, skipping the
+    /// first offset matches and returning at most limit of them
+    pub fn find_with_prefix(
+        &self,
+        prefix: &str,
+        offset: usize,
+        limit: usize,
+    ) -> Vec<(String, usize)> {
+        let matches = self.with_prefix(prefix);
+        let start = offset.min(matches.len());
+        let end = start.saturating_add(limit).min(matches.len());
+        matches[start..end].to_vec()
+    }
+}
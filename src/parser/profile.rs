@@ -0,0 +1,72 @@
+/// Simulators whose known quirks the reader tolerates, a profile turns on
+/// every tolerance its simulator needs in one switch
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum VcdSimulatorProfile {
+    // Strict parsing, no tolerances
+    #[default]
+    Generic,
+    // Chosen from the $version of the header, strict until it is read
+    Auto,
+    Verilator,
+    Icarus,
+    Ghdl,
+    Questa,
+}
+
+impl VcdSimulatorProfile {
+    /// Returns the profile of the simulator named in a $version, Generic if
+    /// it is not known
+    pub fn detect(version: &str) -> Self {
+        let version = version.to_ascii_lowercase();
+        // Verilator writes "Generated by VerilatedVcd"
+        if version.contains("verilat") {
+            Self::Verilator
+        } else if version.contains("icarus") {
+            Self::Icarus
+        } else if version.contains("ghdl") {
+            Self::Ghdl
+        } else if version.contains("questa") || version.contains("modelsim") {
+            Self::Questa
+        } else {
+            Self::Generic
+        }
+    }
+
+    /// Merges a scope opened again at the same level into the first one,
+    /// Verilator declares a module once per trace file it was split into
+    pub fn merges_repeated_scopes(&self) -> bool {
+        matches!(self, Self::Verilator)
+    }
+
+    /// Keeps only the low bits of a vector value wider than its variable
+    /// instead of failing, which Verilator writes for some wide signals
+    pub fn truncates_wide_values(&self) -> bool {
+        matches!(self, Self::Verilator)
+    }
+
+    /// Places changes written before the first timestamp at zero instead of
+    /// failing, Icarus and GHDL dump initial and parameter values that way
+    pub fn assumes_initial_timestamp(&self) -> bool {
+        matches!(self, Self::Icarus | Self::Ghdl)
+    }
+
+    /// Drops the changes under a timestamp earlier than the latest one until
+    /// time passes it again instead of failing, Questa rewinds the dump when
+    /// a checkpoint is restored
+    pub fn drops_rewound_timestamps(&self) -> bool {
+        matches!(self, Self::Questa)
+    }
+}
+
+impl std::fmt::Display for VcdSimulatorProfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Generic => write!(f, "generic"),
+            Self::Auto => write!(f, "auto"),
+            Self::Verilator => write!(f, "Verilator"),
+            Self::Icarus => write!(f, "Icarus"),
+            Self::Ghdl => write!(f, "GHDL"),
+            Self::Questa => write!(f, "Questa"),
+        }
+    }
+}
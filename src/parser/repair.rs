@@ -0,0 +1,124 @@
+use crate::errors::{ParserError, TokenizerError};
+use crate::lexer::position::LexerPosition;
+use crate::tokenizer::token::Token;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VcdRepairKind {
+    // $enddefinitions was reached with scopes still open
+    MissingUpscope,
+    ExtraUpscope,
+    VariableOutsideScope,
+    // The declared width of a $var and the width of its range
+    WidthMismatch { declared: usize, range: usize },
+    // The body started without $enddefinitions
+    MissingEndDefinitions,
+}
+
+/// A fix for a malformed header, suggested for the error it caused or
+/// recorded by a reader that applied it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VcdRepair {
+    kind: VcdRepairKind,
+    pos: LexerPosition,
+}
+
+impl VcdRepair {
+    pub(crate) fn new(kind: VcdRepairKind, pos: LexerPosition) -> Self {
+        Self { kind, pos }
+    }
+
+    /// Suggests a repair for a header error, None if the error has no simple
+    /// fix
+    pub fn from_error(err: &ParserError) -> Option<Self> {
+        let (kind, pos) = match err {
+            ParserError::UnexpectedEndDefinitions(pos) => (VcdRepairKind::MissingUpscope, *pos),
+            ParserError::UnexpectedUpscope(pos) => (VcdRepairKind::ExtraUpscope, *pos),
+            ParserError::UnexpectedVariable(pos) => (VcdRepairKind::VariableOutsideScope, *pos),
+            ParserError::Tokenizer(err) => return Self::from_tokenizer_error(err),
+            ParserError::UnexpectedToken(token) if starts_body(token) => {
+                (VcdRepairKind::MissingEndDefinitions, token.get_position())
+            }
+            _ => return None,
+        };
+        Some(Self { kind, pos })
+    }
+
+    pub fn from_tokenizer_error(err: &TokenizerError) -> Option<Self> {
+        match err {
+            TokenizerError::IncorrectVariableWidth(declared, range, pos) => Some(Self {
+                kind: VcdRepairKind::WidthMismatch {
+                    declared: *declared,
+                    range: *range,
+                },
+                pos: *pos,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn get_kind(&self) -> VcdRepairKind {
+        self.kind
+    }
+
+    pub fn get_position(&self) -> LexerPosition {
+        self.pos
+    }
+
+    /// Returns true for the repairs a reader can apply with
+    /// VcdReader::set_header_repair, the others need the source fixed
+    pub fn is_applicable(&self) -> bool {
+        matches!(
+            self.kind,
+            VcdRepairKind::MissingUpscope
+                | VcdRepairKind::ExtraUpscope
+                | VcdRepairKind::MissingEndDefinitions
+        )
+    }
+}
+
+// Tokens that can only appear once the header is over
+pub(crate) fn starts_body(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::Timestamp(..)
+            | Token::ScalarValue(..)
+            | Token::VectorValue(..)
+            | Token::RealValue(..)
+            | Token::StringValue(..)
+            | Token::PortValue(..)
+            | Token::VcdClose(..)
+            | Token::DumpAll(_)
+            | Token::DumpOff(_)
+            | Token::DumpOn(_)
+            | Token::DumpVars(_)
+    )
+}
+
+impl std::fmt::Display for VcdRepair {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let line = self.pos.get_line();
+        match self.kind {
+            VcdRepairKind::MissingUpscope => {
+                write!(
+                    f,
+                    "missing $upscope before $enddefinitions at line {}",
+                    line
+                )
+            }
+            VcdRepairKind::ExtraUpscope => {
+                write!(f, "remove the unmatched $upscope at line {}", line)
+            }
+            VcdRepairKind::VariableOutsideScope => {
+                write!(f, "add a $scope before the $var at line {}", line)
+            }
+            VcdRepairKind::WidthMismatch { declared, range } => write!(
+                f,
+                "change the width of the $var at line {} from {} to {} to match its range",
+                line, declared, range
+            ),
+            VcdRepairKind::MissingEndDefinitions => {
+                write!(f, "missing $enddefinitions before line {}", line)
+            }
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+
+use makai_waveform_db::bitvector::{BitVector, Logic};
+
+use crate::parser::{VcdEntry, VcdHeader};
+
+/// A correction applied to a value change after it is parsed and before it
+/// reaches the waveform, it should keep the idcode and width of the entry
+pub type VcdTransformFn = Box<dyn FnMut(VcdEntry) -> VcdEntry + Send>;
+
+/// Value transforms applied by the reader, either to every change or to the
+/// changes of a single variable, timestamps are never transformed
+#[derive(Default)]
+pub struct VcdTransforms {
+    all: Vec<VcdTransformFn>,
+    // Transforms by path are resolved to idcodes once the header is parsed
+    paths: Vec<(String, VcdTransformFn)>,
+    idcodes: HashMap<usize, Vec<VcdTransformFn>>,
+}
+
+impl VcdTransforms {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a transform applied to the changes of every variable
+    pub fn add_transform(&mut self, transform: VcdTransformFn) {
+        self.all.push(transform);
+    }
+
+    /// Adds a transform applied to the changes of the variable at a path like
+    /// "top.cpu.pc", paths that are not declared are ignored with a warning
+    pub fn add_path_transform(&mut self, path: &str, transform: VcdTransformFn) {
+        self.paths.push((path.to_string(), transform));
+    }
+
+    /// Adds a transform applied to the changes of an idcode
+    pub fn add_idcode_transform(&mut self, idcode: usize, transform: VcdTransformFn) {
+        self.idcodes.entry(idcode).or_default().push(transform);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.all.is_empty() && self.paths.is_empty() && self.idcodes.is_empty()
+    }
+
+    /// Moves the transforms added by path to the idcodes of the variables,
+    /// returns the paths that are not declared whose transforms are dropped
+    pub fn resolve(&mut self, header: &VcdHeader) -> Vec<String> {
+        let mut undeclared = Vec::new();
+        for (path, transform) in self.paths.drain(..) {
+            match header.get_variable(&path) {
+                Some(variable) => self
+                    .idcodes
+                    .entry(variable.get_idcode())
+                    .or_default()
+                    .push(transform),
+                None => undeclared.push(path),
+            }
+        }
+        undeclared
+    }
+
+    /// Applies the transforms of the entry's idcode and then the transforms
+    /// of every variable, in the order they were added
+    pub fn apply(&mut self, entry: VcdEntry) -> VcdEntry {
+        let idcode = match &entry {
+            VcdEntry::Timestamp(_) => return entry,
+            VcdEntry::Scalar(_, idcode)
+            | VcdEntry::Vector(_, idcode)
+            | VcdEntry::Real(_, idcode)
+            | VcdEntry::Event(idcode)
+            | VcdEntry::Port(_, idcode)
+            | VcdEntry::String(_, idcode) => *idcode,
+        };
+        let transforms = self.idcodes.get_mut(&idcode);
+        if transforms.is_none() && self.all.is_empty() {
+            return entry;
+        }
+        // Transforms only see vectors, scalars are widened for them
+        let mut entry = entry.into_vector();
+        if let Some(transforms) = transforms {
+            for transform in transforms {
+                entry = transform(entry);
+            }
+        }
+        for transform in &mut self.all {
+            entry = transform(entry);
+        }
+        entry
+    }
+}
+
+/// Returns a vector with its bits in the opposite order, for buses that were
+/// declared with their range flipped
+pub fn reverse_bits(bv: &BitVector) -> BitVector {
+    let width = bv.get_bit_width();
+    let mut reversed = BitVector::new(width, bv.is_four_state());
+    for i in 0..width {
+        reversed.set_bit(width - 1 - i, bv.get_bit(i));
+    }
+    reversed
+}
+
+/// Returns the low bits of a vector, all of it if it is not wider
+pub fn truncate_bits(bv: &BitVector, width: usize) -> BitVector {
+    if bv.get_bit_width() <= width {
+        return bv.clone();
+    }
+    let mut truncated = BitVector::new(width, bv.is_four_state());
+    for i in 0..width {
+        truncated.set_bit(i, bv.get_bit(i));
+    }
+    truncated
+}
+
+/// Returns a vector with the given bits forced to zero, indices outside the
+/// vector are ignored
+pub fn clear_bits(bv: &BitVector, bits: &[usize]) -> BitVector {
+    let mut cleared = bv.clone();
+    for bit in bits.iter().filter(|bit| **bit < bv.get_bit_width()) {
+        cleared.set_bit(*bit, Logic::Zero);
+    }
+    cleared
+}
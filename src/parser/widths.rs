@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+
+use crate::lexer::position::LexerPosition;
+use crate::parser::VcdVariableWidth;
+
+// Names a width for a report, the widths of 1-bit vectors and reals are not
+// written out by VcdVariableWidth
+fn describe(width: &VcdVariableWidth) -> String {
+    match width {
+        VcdVariableWidth::Vector { width: 1 } => "1 bit".to_string(),
+        VcdVariableWidth::Vector { width } => format!("{} bits", width),
+        VcdVariableWidth::Real => "real".to_string(),
+        VcdVariableWidth::Event => "event".to_string(),
+        VcdVariableWidth::String => "string".to_string(),
+    }
+}
+
+/// The values dumped for an idcode that did not match the width it was
+/// declared with
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VcdWidthMismatch {
+    idcode: usize,
+    declared: VcdVariableWidth,
+    // Every mismatched width, in the order they were first seen
+    used: Vec<VcdVariableWidth>,
+    count: usize,
+    first: LexerPosition,
+}
+
+impl VcdWidthMismatch {
+    pub fn get_idcode(&self) -> usize {
+        self.idcode
+    }
+
+    pub fn get_declared_width(&self) -> &VcdVariableWidth {
+        &self.declared
+    }
+
+    /// Returns the widths of the values that did not match, a real value
+    /// dumped for a vector is a mismatch regardless of its size
+    pub fn get_used_widths(&self) -> &[VcdVariableWidth] {
+        &self.used
+    }
+
+    /// Returns how many values did not match
+    pub fn get_count(&self) -> usize {
+        self.count
+    }
+
+    pub fn get_first_position(&self) -> LexerPosition {
+        self.first
+    }
+}
+
+impl std::fmt::Display for VcdWidthMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let used: Vec<String> = self.used.iter().map(describe).collect();
+        write!(
+            f,
+            "declared {}, dumped {} (count {}), first at line {}, column {}",
+            describe(&self.declared),
+            used.join(", "),
+            self.count,
+            self.first.get_line(),
+            self.first.get_column()
+        )
+    }
+}
+
+// Cross-checks the width of every dumped value against its declaration.
+// Values narrower than a vector are legal and extended to its width, but they
+// are reported too since they usually mean the declaration is wrong.
+#[derive(Clone, Debug, Default)]
+pub struct VcdWidthReconciler {
+    mismatches: HashMap<usize, VcdWidthMismatch>,
+}
+
+impl VcdWidthReconciler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a dumped value, events and empty vectors are skipped since
+    /// their values are not stored
+    pub fn check(
+        &mut self,
+        idcode: usize,
+        declared: &VcdVariableWidth,
+        used: VcdVariableWidth,
+        pos: LexerPosition,
+    ) {
+        if *declared == VcdVariableWidth::Event || declared.is_empty() || *declared == used {
+            return;
+        }
+        let mismatch = self
+            .mismatches
+            .entry(idcode)
+            .or_insert_with(|| VcdWidthMismatch {
+                idcode,
+                declared: declared.clone(),
+                used: Vec::new(),
+                count: 0,
+                first: pos,
+            });
+        if !mismatch.used.contains(&used) {
+            mismatch.used.push(used);
+        }
+        mismatch.count += 1;
+    }
+
+    /// Returns the mismatches of every idcode in the order they were first
+    /// seen
+    pub fn get_mismatches(&self) -> Vec<VcdWidthMismatch> {
+        let mut mismatches: Vec<VcdWidthMismatch> = self.mismatches.values().cloned().collect();
+        mismatches.sort_unstable_by_key(|mismatch| mismatch.first.get_index());
+        mismatches
+    }
+}
@@ -0,0 +1,232 @@
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use makai_waveform_db::bitvector::BitVector;
+
+use crate::export::vcd::idcode_string;
+
+// Small xorshift generator so dumps are reproducible from a seed without
+// pulling in a random number crate
+#[derive(Clone, Debug)]
+struct VcdGeneratorRng {
+    state: u64,
+}
+
+impl VcdGeneratorRng {
+    fn new(seed: u64, stream: u64) -> Self {
+        // Mix the stream in so every signal gets an independent sequence
+        let mut rng = Self {
+            state: (seed ^ stream.wrapping_mul(0x9e37_79b9_7f4a_7c15)) | 1,
+        };
+        for _ in 0..4 {
+            rng.next_u64();
+        }
+        rng
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // Returns a random binary string, most significant bit first
+    fn next_bits(&mut self, width: usize) -> Vec<u8> {
+        let mut bits = Vec::with_capacity(width);
+        let mut word = 0;
+        for i in 0..width {
+            if i % 64 == 0 {
+                word = self.next_u64();
+            }
+            bits.push(if (word >> (i % 64)) & 1 == 1 {
+                b'1'
+            } else {
+                b'0'
+            });
+        }
+        bits
+    }
+}
+
+// Tracks one generated signal while walking through the timesteps
+struct VcdGeneratorSignal {
+    rng: VcdGeneratorRng,
+    value: Vec<u8>,
+}
+
+impl VcdGeneratorSignal {
+    fn new(seed: u64, index: usize, width: usize) -> Self {
+        let mut rng = VcdGeneratorRng::new(seed, index as u64);
+        let value = rng.next_bits(width);
+        Self { rng, value }
+    }
+
+    // Returns true if the signal changed, a new value always differs from the
+    // old one so every change is visible in the waveform
+    fn step(&mut self, change_density: f64) -> bool {
+        if self.rng.next_f64() >= change_density {
+            return false;
+        }
+        let mut value = self.rng.next_bits(self.value.len());
+        if value == self.value {
+            let bit = value.last_mut().unwrap();
+            *bit = if *bit == b'0' { b'1' } else { b'0' };
+        }
+        self.value = value;
+        true
+    }
+}
+
+/// Generates synthetic dumps of two-state signals with a configurable number
+/// of signals, widths, change density and duration, the same seed always
+/// produces the same dump
+#[derive(Clone, Debug)]
+pub struct VcdGenerator {
+    signals: usize,
+    widths: Vec<usize>,
+    change_density: f64,
+    timesteps: u64,
+    period: u64,
+    seed: u64,
+}
+
+impl VcdGenerator {
+    pub fn new(signals: usize, timesteps: u64) -> Self {
+        Self {
+            signals,
+            widths: vec![1],
+            change_density: 0.1,
+            timesteps,
+            period: 1,
+            seed: 1,
+        }
+    }
+
+    /// Sets the widths of the signals, repeated in order over all signals
+    pub fn set_widths(&mut self, widths: &[usize]) {
+        self.widths = widths.iter().map(|width| (*width).max(1)).collect();
+        if self.widths.is_empty() {
+            self.widths.push(1);
+        }
+    }
+
+    /// Sets the chance (from zero to one) that a signal changes per timestep
+    pub fn set_change_density(&mut self, change_density: f64) {
+        self.change_density = change_density.clamp(0.0, 1.0);
+    }
+
+    /// Sets the time between timesteps
+    pub fn set_period(&mut self, period: u64) {
+        self.period = period.max(1);
+    }
+
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    pub fn get_signal_count(&self) -> usize {
+        self.signals
+    }
+
+    pub fn get_width(&self, signal: usize) -> usize {
+        self.widths[signal % self.widths.len()]
+    }
+
+    /// Returns the full path of a generated signal
+    pub fn get_path(&self, signal: usize) -> String {
+        format!("top.sig{}", signal)
+    }
+
+    fn new_signals(&self) -> Vec<VcdGeneratorSignal> {
+        (0..self.signals)
+            .map(|i| VcdGeneratorSignal::new(self.seed, i, self.get_width(i)))
+            .collect()
+    }
+
+    fn write_value(
+        writer: &mut dyn Write,
+        signal: &VcdGeneratorSignal,
+        idcode: &str,
+    ) -> io::Result<()> {
+        if signal.value.len() == 1 {
+            writer.write_all(&signal.value)?;
+        } else {
+            writer.write_all(b"b")?;
+            writer.write_all(&signal.value)?;
+            writer.write_all(b" ")?;
+        }
+        writer.write_all(idcode.as_bytes())?;
+        writer.write_all(b"\n")
+    }
+
+    pub fn write_to(&self, writer: &mut dyn Write) -> io::Result<()> {
+        let idcodes: Vec<String> = (0..self.signals).map(idcode_string).collect();
+        writeln!(writer, "$version makai_vcd_reader testgen $end")?;
+        writeln!(writer, "$timescale 1ns $end")?;
+        writeln!(writer, "$scope module top $end")?;
+        for (i, idcode) in idcodes.iter().enumerate() {
+            writeln!(
+                writer,
+                "$var wire {} {} sig{} $end",
+                self.get_width(i),
+                idcode,
+                i
+            )?;
+        }
+        writeln!(writer, "$upscope $end")?;
+        writeln!(writer, "$enddefinitions $end")?;
+        let mut signals = self.new_signals();
+        writeln!(writer, "#0")?;
+        writeln!(writer, "$dumpvars")?;
+        for (signal, idcode) in signals.iter().zip(&idcodes) {
+            Self::write_value(writer, signal, idcode)?;
+        }
+        writeln!(writer, "$end")?;
+        for step in 1..self.timesteps {
+            let mut timestamp_written = false;
+            for (signal, idcode) in signals.iter_mut().zip(&idcodes) {
+                if !signal.step(self.change_density) {
+                    continue;
+                }
+                // Timesteps without any changes are left out of the dump
+                if !timestamp_written {
+                    writeln!(writer, "#{}", step * self.period)?;
+                    timestamp_written = true;
+                }
+                Self::write_value(writer, signal, idcode)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn generate(&self) -> String {
+        let mut bytes = Vec::new();
+        self.write_to(&mut bytes).unwrap();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    pub fn write_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut writer = BufWriter::new(fs::File::create(path)?);
+        self.write_to(&mut writer)?;
+        writer.flush()
+    }
+
+    /// Returns every change of a generated signal as (timestamp, value) pairs,
+    /// including its initial value, without generating the whole dump
+    pub fn get_expected_changes(&self, signal: usize) -> Vec<(u64, BitVector)> {
+        let mut state = VcdGeneratorSignal::new(self.seed, signal, self.get_width(signal));
+        let mut changes = vec![(0, BitVector::from_ascii(&state.value))];
+        for step in 1..self.timesteps {
+            if state.step(self.change_density) {
+                changes.push((step * self.period, BitVector::from_ascii(&state.value)));
+            }
+        }
+        changes
+    }
+}
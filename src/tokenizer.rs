@@ -6,7 +6,7 @@ use std::str;
 
 use bytes::Bytes;
 use makai::utils::bytes::ByteStorage;
-use makai_waveform_db::bitvector::BitVector;
+use makai_waveform_db::bitvector::{BitVector, Logic};
 
 use crate::errors::*;
 use crate::lexer::position::*;
@@ -15,8 +15,10 @@ use crate::tokenizer::token::*;
 
 pub type ByteRange = Range<usize>;
 
+// Splits bytes at the first run of whitespace, either range is empty if there
+// is nothing before or after the whitespace
 fn split_bytes(bytes: &[u8]) -> (ByteRange, ByteRange) {
-    let mut first = 0;
+    let mut first = bytes.len();
     for (i, b) in bytes.iter().enumerate() {
         match b {
             b' ' | b'\t' | b'\n' => {
@@ -26,7 +28,7 @@ fn split_bytes(bytes: &[u8]) -> (ByteRange, ByteRange) {
             _ => {}
         }
     }
-    let mut second = 0;
+    let mut second = bytes.len();
     for (i, b) in bytes.iter().enumerate().skip(first) {
         match b {
             b' ' | b'\t' | b'\n' => {}
@@ -39,43 +41,69 @@ fn split_bytes(bytes: &[u8]) -> (ByteRange, ByteRange) {
     (0..first, second..bytes.len())
 }
 
-fn tokenize_timestamp(bytes: &[u8]) -> TokenizerResult<u64> {
-    let mut result = 0u64;
-    for b in bytes.iter().skip(1) {
-        result *= 10;
-        result += (b - b'0') as u64;
+pub(crate) fn tokenize_timestamp(bytes: &[u8], pos: LexerPosition) -> TokenizerResult<u64> {
+    // Spaces are allowed after the hash and timestamps may overflow
+    let digits = bytes.get(1..).ok_or(TokenizerError::LexerError(pos))?;
+    match String::from_utf8_lossy(digits).trim().parse::<u64>() {
+        Ok(result) => Ok(result),
+        Err(err) => Err(TokenizerError::IntegerParseError(err, pos)),
     }
-    Ok(result)
 }
 
-fn tokenize_idcode(bs: &mut ByteStorage, bytes: &[u8]) -> TokenIdCode {
+// Packs an idcode short enough to fit in a usize without the storage flag,
+// returns None if it has to be kept in the byte storage instead
+pub(crate) fn short_idcode(bytes: &[u8]) -> Option<usize> {
     let usize_bytes = (usize::BITS / 8) as usize;
     if bytes.len() > usize_bytes
         || (bytes.len() == usize_bytes && (bytes[usize_bytes - 1] >> 7) == 0)
     {
-        TokenIdCode::new(bs.insert(Bytes::copy_from_slice(bytes)) | (1 << (usize::BITS - 1)))
-    } else {
-        let mut id: usize = 0;
-        for i in (0..bytes.len()).rev() {
-            id <<= 8;
-            id |= bytes[i] as usize;
-        }
-        TokenIdCode::new(id)
+        return None;
+    }
+    let mut id: usize = 0;
+    for i in (0..bytes.len()).rev() {
+        id <<= 8;
+        id |= bytes[i] as usize;
     }
+    Some(id)
 }
 
-fn tokenize_vector(bs: &mut ByteStorage, bytes: &[u8]) -> (BitVector, TokenIdCode) {
-    let (vector_range, idcode_range) = split_bytes(bytes);
-    let vector = BitVector::from_ascii(&bytes[vector_range][1..]);
-    let idcode = tokenize_idcode(bs, &bytes[idcode_range]);
-    (vector, idcode)
+pub(crate) fn tokenize_idcode(bs: &mut ByteStorage, bytes: &[u8]) -> TokenIdCode {
+    match short_idcode(bytes) {
+        Some(id) => TokenIdCode::new(id),
+        None => TokenIdCode::new(bs.insert(Bytes::copy_from_slice(bytes)) | IDCODE_STORAGE_FLAG),
+    }
 }
 
-fn tokenize_vector_four_state(bs: &mut ByteStorage, bytes: &[u8]) -> (BitVector, TokenIdCode) {
-    let (vector_range, idcode_range) = split_bytes(bytes);
-    let vector = BitVector::from_ascii_four_state(&bytes[vector_range][1..]);
-    let idcode = tokenize_idcode(bs, &bytes[idcode_range]);
-    (vector, idcode)
+// Splits a value into the bytes after its type prefix and the idcode, both of
+// which must be present
+fn split_value(bytes: &[u8], pos: LexerPosition) -> TokenizerResult<(&[u8], &[u8])> {
+    let (value_range, idcode_range) = split_bytes(bytes);
+    if value_range.len() < 2 || idcode_range.is_empty() {
+        return Err(TokenizerError::LexerError(pos));
+    }
+    Ok((&bytes[value_range][1..], &bytes[idcode_range]))
+}
+
+fn tokenize_vector(
+    bs: &mut ByteStorage,
+    bytes: &[u8],
+    pos: LexerPosition,
+) -> TokenizerResult<(BitVector, TokenIdCode)> {
+    let (vector_bytes, idcode_bytes) = split_value(bytes, pos)?;
+    let vector = BitVector::from_ascii(vector_bytes);
+    let idcode = tokenize_idcode(bs, idcode_bytes);
+    Ok((vector, idcode))
+}
+
+fn tokenize_vector_four_state(
+    bs: &mut ByteStorage,
+    bytes: &[u8],
+    pos: LexerPosition,
+) -> TokenizerResult<(BitVector, TokenIdCode)> {
+    let (vector_bytes, idcode_bytes) = split_value(bytes, pos)?;
+    let vector = BitVector::from_ascii_four_state(vector_bytes);
+    let idcode = tokenize_idcode(bs, idcode_bytes);
+    Ok((vector, idcode))
 }
 
 fn tokenize_real(
@@ -83,93 +111,206 @@ fn tokenize_real(
     bytes: &[u8],
     pos: LexerPosition,
 ) -> TokenizerResult<(f64, TokenIdCode)> {
-    let (real_range, idcode_range) = split_bytes(bytes);
-    let real = match String::from_utf8_lossy(&bytes[real_range][1..])
-        .trim()
-        .parse::<f64>()
-    {
+    let (real_bytes, idcode_bytes) = split_value(bytes, pos)?;
+    let real = match String::from_utf8_lossy(real_bytes).trim().parse::<f64>() {
         Ok(result) => result,
         Err(err) => return Err(TokenizerError::RealParseError(err, pos)),
     };
-    let idcode = tokenize_idcode(bs, &bytes[idcode_range]);
+    let idcode = tokenize_idcode(bs, idcode_bytes);
     Ok((real, idcode))
 }
 
+fn tokenize_string(
+    bs: &mut ByteStorage,
+    bytes: &[u8],
+    pos: LexerPosition,
+) -> TokenizerResult<(Bytes, TokenIdCode)> {
+    // Unlike other values the string after the prefix may be empty
+    let (value_range, idcode_range) = split_bytes(bytes);
+    if value_range.is_empty() || idcode_range.is_empty() {
+        return Err(TokenizerError::LexerError(pos));
+    }
+    let value = Bytes::copy_from_slice(&bytes[value_range][1..]);
+    let idcode = tokenize_idcode(bs, &bytes[idcode_range]);
+    Ok((value, idcode))
+}
+
+fn tokenize_port(
+    bs: &mut ByteStorage,
+    bytes: &[u8],
+    pos: LexerPosition,
+) -> TokenizerResult<(TokenPortValue, TokenIdCode)> {
+    // The value is followed by two strengths and then the idcode
+    let (states, rest) = split_value(bytes, pos)?;
+    let (strength0_range, rest_range) = split_bytes(rest);
+    let (strength0, rest) = (&rest[strength0_range], &rest[rest_range]);
+    let (strength1_range, idcode_range) = split_bytes(rest);
+    if idcode_range.is_empty() {
+        return Err(TokenizerError::LexerError(pos));
+    }
+    let value = TokenPortValue::new(states, strength0, &rest[strength1_range])
+        .ok_or(TokenizerError::LexerError(pos))?;
+    let idcode = tokenize_idcode(bs, &rest[idcode_range]);
+    Ok((value, idcode))
+}
+
 fn tokenize_scope(
     bs: &mut ByteStorage,
     bytes: Bytes,
     pos: LexerPosition,
 ) -> TokenizerResult<(TokenScopeType, usize)> {
     let (scope_type_range, scope_name_range) = split_bytes(&bytes[..]);
+    if scope_name_range.is_empty() {
+        return Err(TokenizerError::LexerError(pos));
+    }
     let scope_type = TokenScopeType::from_byte_str(&bytes.slice(scope_type_range))
         .ok_or(TokenizerError::LexerError(pos))?;
     let scope_name = bs.insert(bytes.slice(scope_name_range));
     Ok((scope_type, scope_name))
 }
 
-fn tokenize_timescale(bytes: Bytes) -> TokenizerResult<(TokenTimescale, TokenTimescaleOffset)> {
-    let offset = match bytes[1] {
-        b'0' => match bytes[2] {
-            b'0' => TokenTimescaleOffset::Hundred,
-            _ => TokenTimescaleOffset::Ten,
-        },
-        _ => TokenTimescaleOffset::One,
+fn tokenize_timescale(
+    bytes: Bytes,
+    pos: LexerPosition,
+) -> TokenizerResult<(TokenTimescale, TokenTimescaleOffset)> {
+    // The magnitude and unit may or may not be separated by whitespace
+    let digits = bytes.iter().take_while(|b| b.is_ascii_digit()).count();
+    let offset = match &bytes[..digits] {
+        b"1" => TokenTimescaleOffset::One,
+        b"10" => TokenTimescaleOffset::Ten,
+        b"100" => TokenTimescaleOffset::Hundred,
+        _ => return Err(TokenizerError::LexerError(pos)),
     };
-    let timescale = match bytes[bytes.len() - 2] {
-        b'f' => TokenTimescale::Femtoseconds,
-        b'p' => TokenTimescale::Picoseconds,
-        b'n' => TokenTimescale::Nanoseconds,
-        b'u' => TokenTimescale::Microseconds,
-        b'm' => TokenTimescale::Milliseconds,
-        _ => TokenTimescale::Seconds,
+    let timescale = match String::from_utf8_lossy(&bytes[digits..]).trim() {
+        "fs" => TokenTimescale::Femtoseconds,
+        "ps" => TokenTimescale::Picoseconds,
+        "ns" => TokenTimescale::Nanoseconds,
+        "us" => TokenTimescale::Microseconds,
+        "ms" => TokenTimescale::Milliseconds,
+        "s" => TokenTimescale::Seconds,
+        _ => return Err(TokenizerError::LexerError(pos)),
     };
     Ok((timescale, offset))
 }
 
+// Parses a width or bit index, leading zeros are allowed and underscores are
+// separators like in Verilog literals
+fn parse_integer(bytes: &[u8], pos: LexerPosition) -> TokenizerResult<usize> {
+    let text: String = String::from_utf8_lossy(bytes)
+        .trim()
+        .chars()
+        .filter(|c| *c != '_')
+        .collect();
+    text.parse::<usize>()
+        .map_err(|err| TokenizerError::IntegerParseError(err, pos))
+}
+
+// Skips leading whitespace, like trim_ascii_start which needs Rust 1.80
+fn skip_whitespace(bytes: &[u8]) -> &[u8] {
+    &bytes[bytes
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(bytes.len())..]
+}
+
+// Parses bracketed dimensions like "[3][7:0]" or "[1:0] [15:0]", returning
+// None if the bytes hold anything else
+fn tokenize_dimensions(
+    bytes: &[u8],
+    pos: LexerPosition,
+) -> TokenizerResult<Option<Vec<TokenVariableDimension>>> {
+    let mut dimensions = Vec::new();
+    let mut rest = skip_whitespace(bytes);
+    while !rest.is_empty() {
+        let Some(inner) = rest.strip_prefix(b"[") else {
+            return Ok(None);
+        };
+        let Some(close) = inner.iter().position(|b| *b == b']') else {
+            return Ok(None);
+        };
+        let group = &inner[..close];
+        if !group
+            .iter()
+            .all(|b| b.is_ascii_digit() || matches!(b, b'_' | b':' | b' '))
+        {
+            return Ok(None);
+        }
+        dimensions.push(match group.iter().position(|b| *b == b':') {
+            Some(colon) => TokenVariableDimension::Range(
+                parse_integer(&group[..colon], pos)?,
+                parse_integer(&group[colon + 1..], pos)?,
+            ),
+            None => TokenVariableDimension::Index(parse_integer(group, pos)?),
+        });
+        rest = skip_whitespace(&inner[close + 1..]);
+    }
+    Ok(Some(dimensions))
+}
+
 fn tokenize_variable_description(
     bs: &mut ByteStorage,
     bytes: Bytes,
     pos: LexerPosition,
 ) -> TokenizerResult<TokenVariableDescription> {
-    // Check if a width is even specified, split by whitespace
-    let (id_range, width_range) = split_bytes(&bytes[..]);
-    if id_range.is_empty() {
-        let id = bs.insert(bytes);
-        return Ok(TokenVariableDescription::Unspecified { id });
-    }
-    // Check that the width is wrapped by square brackets
-    let id = bs.insert(bytes.slice(0..id_range.end));
-    let width_range = if width_range.len() <= 2
-        || bytes[width_range.start] != b'['
-        || bytes[width_range.end - 1] != b']'
-    {
+    // Split the name from any dimensions after it by whitespace
+    let (name_range, rest_range) = split_bytes(&bytes[..]);
+    if name_range.is_empty() {
         return Err(TokenizerError::LexerError(pos));
-    } else {
-        width_range.start + 1..width_range.end - 1
-    };
-    // Search for a colon splitting the msb and lsb
-    let colon = width_range.clone().find(|i| bytes[*i] == b':');
-    // Parse the width into integers
-    if let Some(colon) = colon {
-        let msb_bytes = bytes.slice(width_range.start..colon);
-        let lsb_bytes = bytes.slice(colon + 1..width_range.end);
-        let msb = match String::from_utf8_lossy(&msb_bytes).trim().parse::<usize>() {
-            Ok(result) => result,
-            Err(err) => return Err(TokenizerError::IntegerParseError(err, pos)),
-        };
-        let lsb = match String::from_utf8_lossy(&lsb_bytes).trim().parse::<usize>() {
-            Ok(result) => result,
-            Err(err) => return Err(TokenizerError::IntegerParseError(err, pos)),
-        };
-        Ok(TokenVariableDescription::VectorSelect { id, msb, lsb })
-    } else {
-        let bytes = bytes.slice(width_range);
-        let width = match String::from_utf8_lossy(&bytes).trim().parse::<usize>() {
-            Ok(result) => result,
-            Err(err) => return Err(TokenizerError::IntegerParseError(err, pos)),
-        };
-        Ok(TokenVariableDescription::Vector { id, width })
     }
+    let name = &bytes[name_range];
+    let separate =
+        tokenize_dimensions(&bytes[rest_range], pos)?.ok_or(TokenizerError::LexerError(pos))?;
+    // Dimensions can also be written onto the name like mem[3][7:0], names
+    // with a single bracket like data[0] are left alone. Leading indices pick
+    // an element of an unpacked array so they stay part of the name.
+    let attached = name
+        .iter()
+        .enumerate()
+        .filter(|(_, b)| **b == b'[')
+        .find_map(|(start, _)| {
+            let dimensions = tokenize_dimensions(&name[start..], pos).ok()??;
+            Some((start, dimensions))
+        });
+    let (name_end, mut dimensions) = match attached {
+        Some((start, attached))
+            if attached.len() >= 2
+                && attached
+                    .iter()
+                    .any(|dimension| matches!(dimension, TokenVariableDimension::Range(_, _))) =>
+        {
+            let first_range = attached
+                .iter()
+                .position(|dimension| matches!(dimension, TokenVariableDimension::Range(_, _)))
+                .unwrap_or(0);
+            let name_end = match first_range {
+                0 => start,
+                _ => name[start..]
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, b)| **b == b']')
+                    .map(|(i, _)| start + i + 1)
+                    .nth(first_range - 1)
+                    .unwrap_or(name.len()),
+            };
+            (name_end, attached[first_range..].to_vec())
+        }
+        _ => (name.len(), Vec::new()),
+    };
+    dimensions.extend(separate);
+    let id = bs.insert(bytes.slice(0..name_end));
+    Ok(match dimensions.as_slice() {
+        [] => TokenVariableDescription::Unspecified { id },
+        // A lone index is read as a width
+        [TokenVariableDimension::Index(width)] => {
+            TokenVariableDescription::Vector { id, width: *width }
+        }
+        [TokenVariableDimension::Range(msb, lsb)] => TokenVariableDescription::VectorSelect {
+            id,
+            msb: *msb,
+            lsb: *lsb,
+        },
+        _ => TokenVariableDescription::Dimensions { id, dimensions },
+    })
 }
 
 fn tokenize_variable(
@@ -187,12 +328,10 @@ fn tokenize_variable(
         .ok_or(TokenizerError::LexerError(pos))?;
     let bytes = bytes.slice(range);
     let (width_range, range) = split_bytes(&bytes[..]);
-    let width = match String::from_utf8_lossy(&bytes.slice(width_range))
-        .trim()
-        .parse::<usize>()
-    {
-        Ok(width) => width,
-        Err(err) => return Err(TokenizerError::IntegerParseError(err, pos)),
+    // Extended VCD ports may be declared with a range instead of a width
+    let width = match tokenize_dimensions(&bytes[width_range.clone()], pos)?.as_deref() {
+        Some([TokenVariableDimension::Range(msb, lsb)]) => msb.abs_diff(*lsb).saturating_add(1),
+        _ => parse_integer(&bytes[width_range], pos)?,
     };
     let bytes = bytes.slice(range);
     let (idcode_range, variable_description_range) = split_bytes(&bytes[..]);
@@ -212,34 +351,48 @@ fn tokenize_variable(
             }
         }
     }
-    match net_type {
-        TokenVariableNetType::Real | TokenVariableNetType::Realtime => {
-            if width != 64 {
-                return Err(TokenizerError::IncorrectRealWidth(pos));
-            }
+    match net_type.get_real_width() {
+        Some(real_width) if width != real_width => {
+            return Err(TokenizerError::IncorrectRealWidth(pos));
         }
         _ => {}
     }
     Ok((net_type, width, idcode, variable_description))
 }
 
+// Cloning is cheap since the source bytes are shared
+#[derive(Clone)]
 pub struct Tokenizer {
     bytes: Bytes,
+    // Index in the source of the first byte, for a window of a stream
+    offset: usize,
 }
 
 impl Tokenizer {
     pub fn new(s: &str) -> Self {
         Self {
             bytes: Bytes::copy_from_slice(s.as_bytes()),
+            offset: 0,
         }
     }
 
+    // Creates a tokenizer over part of a source that starts at an offset,
+    // tokens keep the ranges they have in the whole source
+    pub(crate) fn new_window(bytes: Bytes, offset: usize) -> Self {
+        Self { bytes, offset }
+    }
+
+    #[inline]
+    fn local(&self, range: ByteRange) -> ByteRange {
+        (range.start - self.offset)..(range.end - self.offset)
+    }
+
     pub fn get_bytes(&self, range: ByteRange) -> Bytes {
-        self.bytes.slice(range)
+        self.bytes.slice(self.local(range))
     }
 
     pub fn get_bytes_trimmed(&self, range: ByteRange) -> Bytes {
-        let mut range = range;
+        let mut range = self.local(range);
         for i in range.start..range.end {
             match self.bytes[i] {
                 b' ' | b'\t' | b'\n' => range.start += 1,
@@ -255,8 +408,41 @@ impl Tokenizer {
         self.bytes.slice(range)
     }
 
+    /// Returns the original source text of a range, such as the range of a
+    /// token position, or None if it is out of bounds or splits a character
+    pub fn source_slice(&self, range: ByteRange) -> Option<&str> {
+        let start = range.start.checked_sub(self.offset)?;
+        let end = range.end.checked_sub(self.offset)?;
+        std::str::from_utf8(self.bytes.get(start..end)?).ok()
+    }
+
     pub fn write_range(&self, range: ByteRange, writer: &mut dyn io::Write) -> io::Result<usize> {
-        writer.write(&self.bytes[range])
+        writer.write(&self.bytes[self.local(range)])
+    }
+
+    /// Returns the idcode of a value change without tokenizing the value, or
+    /// None if the token is not a value change
+    pub fn get_idcode_bytes(&self, lexer_token: &LexerToken) -> TokenizerResult<Option<&[u8]>> {
+        match lexer_token {
+            LexerToken::ScalarZero(span, _)
+            | LexerToken::ScalarOne(span, _)
+            | LexerToken::ScalarUnknown(span, _)
+            | LexerToken::ScalarHighImpedance(span, _) => {
+                Ok(Some(&self.bytes[self.local(span.clone())][1..]))
+            }
+            LexerToken::VectorValue(span, pos)
+            | LexerToken::VectorValueFourState(span, pos)
+            | LexerToken::RealValue(span, pos) => Ok(Some(
+                split_value(&self.bytes[self.local(span.clone())], *pos)?.1,
+            )),
+            // The idcode is the last word, after the strengths of a port or
+            // a string that may be empty
+            LexerToken::StringValue(span, _) | LexerToken::PortValue(span, _) => Ok(self.bytes
+                [self.local(span.clone())]
+            .rsplit(|b| b.is_ascii_whitespace())
+            .next()),
+            _ => Ok(None),
+        }
     }
 
     pub fn next(
@@ -277,6 +463,12 @@ impl Tokenizer {
             LexerToken::SectionVersion(span, pos) => {
                 Token::Version(bs.insert(self.get_bytes(span)), pos)
             }
+            LexerToken::SectionUnknown(span, pos) => {
+                Token::Unknown(bs.insert(self.get_bytes(span)), pos)
+            }
+            LexerToken::SectionAttrBegin(span, pos) => {
+                Token::AttrBegin(bs.insert(self.get_bytes(span)), pos)
+            }
             // Formatted blocks
             LexerToken::SectionScope(span, pos) => {
                 let (scope_type, scope_id) = tokenize_scope(bs, self.get_bytes_trimmed(span), pos)?;
@@ -287,7 +479,7 @@ impl Tokenizer {
                 }
             }
             LexerToken::SectionTimescale(span, pos) => {
-                let (timescale, offset) = tokenize_timescale(self.get_bytes_trimmed(span))?;
+                let (timescale, offset) = tokenize_timescale(self.get_bytes_trimmed(span), pos)?;
                 Token::Timescale {
                     timescale,
                     offset,
@@ -308,6 +500,10 @@ impl Tokenizer {
             // Empty blocks
             LexerToken::SectionUpScope(pos) => Token::UpScope(pos),
             LexerToken::SectionEndDefinitions(pos) => Token::EndDefinitions(pos),
+            LexerToken::SectionAttrEnd(pos) => Token::AttrEnd(pos),
+            LexerToken::SectionVcdClose(span, pos) => {
+                Token::VcdClose(tokenize_timestamp(&self.get_bytes_trimmed(span), pos)?, pos)
+            }
             // Single word blocks
             LexerToken::CommandDumpAll(pos) => Token::DumpAll(pos),
             LexerToken::CommandDumpOff(pos) => Token::DumpOff(pos),
@@ -316,37 +512,98 @@ impl Tokenizer {
             LexerToken::CommandEnd(pos) => Token::End(pos),
             // Waveform events
             LexerToken::Timestamp(span, pos) => {
-                Token::Timestamp(tokenize_timestamp(&self.bytes[span])?, pos)
+                Token::Timestamp(tokenize_timestamp(&self.bytes[self.local(span)], pos)?, pos)
             }
             LexerToken::ScalarZero(span, pos) => {
-                let idcode = tokenize_idcode(bs, &self.bytes[span][1..]);
-                Token::VectorValue(BitVector::new_zero_bit(), idcode, pos)
+                let idcode = tokenize_idcode(bs, &self.bytes[self.local(span)][1..]);
+                Token::ScalarValue(Logic::Zero, idcode, pos)
             }
             LexerToken::ScalarOne(span, pos) => {
-                let idcode = tokenize_idcode(bs, &self.bytes[span][1..]);
-                Token::VectorValue(BitVector::new_one_bit(), idcode, pos)
+                let idcode = tokenize_idcode(bs, &self.bytes[self.local(span)][1..]);
+                Token::ScalarValue(Logic::One, idcode, pos)
             }
             LexerToken::ScalarUnknown(span, pos) => {
-                let idcode = tokenize_idcode(bs, &self.bytes[span][1..]);
-                Token::VectorValue(BitVector::new_unknown_bit(), idcode, pos)
+                let idcode = tokenize_idcode(bs, &self.bytes[self.local(span)][1..]);
+                Token::ScalarValue(Logic::Unknown, idcode, pos)
             }
             LexerToken::ScalarHighImpedance(span, pos) => {
-                let idcode = tokenize_idcode(bs, &self.bytes[span][1..]);
-                Token::VectorValue(BitVector::new_high_impedance_bit(), idcode, pos)
+                let idcode = tokenize_idcode(bs, &self.bytes[self.local(span)][1..]);
+                Token::ScalarValue(Logic::HighImpedance, idcode, pos)
             }
             LexerToken::VectorValue(span, pos) => {
-                let (vector, idcode) = tokenize_vector(bs, &self.bytes[span]);
+                let (vector, idcode) = tokenize_vector(bs, &self.bytes[self.local(span)], pos)?;
                 Token::VectorValue(vector, idcode, pos)
             }
             LexerToken::VectorValueFourState(span, pos) => {
-                let (vector, idcode) = tokenize_vector_four_state(bs, &self.bytes[span]);
+                let (vector, idcode) =
+                    tokenize_vector_four_state(bs, &self.bytes[self.local(span)], pos)?;
                 Token::VectorValue(vector, idcode, pos)
             }
             LexerToken::RealValue(span, pos) => {
-                let (real, idcode) = tokenize_real(bs, &self.bytes[span], pos)?;
+                let (real, idcode) = tokenize_real(bs, &self.bytes[self.local(span)], pos)?;
                 Token::RealValue(real, idcode, pos)
             }
+            LexerToken::StringValue(span, pos) => {
+                let (value, idcode) = tokenize_string(bs, &self.bytes[self.local(span)], pos)?;
+                Token::StringValue(value, idcode, pos)
+            }
+            LexerToken::PortValue(span, pos) => {
+                let (value, idcode) = tokenize_port(bs, &self.bytes[self.local(span)], pos)?;
+                Token::PortValue(value, idcode, pos)
+            }
         };
         Ok(Some(token))
     }
 }
+
+/// Iterates the tokens of a header up to and including $enddefinitions,
+/// the body is never lexed. Blocks and idcodes are kept in a byte storage
+/// that can be read while iterating.
+pub struct TokenHeaderIter<'a> {
+    lexer: Lexer<'a>,
+    tokenizer: Tokenizer,
+    bs: ByteStorage,
+    done: bool,
+}
+
+impl TokenHeaderIter<'_> {
+    pub fn get_byte_storage(&self) -> &ByteStorage {
+        &self.bs
+    }
+
+    pub fn into_byte_storage(self) -> ByteStorage {
+        self.bs
+    }
+}
+
+impl Iterator for TokenHeaderIter<'_> {
+    type Item = TokenizerResult<Token>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let token = self
+            .lexer
+            .next_token()
+            .map_err(TokenizerError::from)
+            .and_then(|lexer_token| self.tokenizer.next(lexer_token, &mut self.bs));
+        // Stops after the first error, as the lexer cannot resume from it
+        match &token {
+            Ok(Some(Token::EndDefinitions(_))) | Ok(None) | Err(_) => self.done = true,
+            Ok(Some(_)) => {}
+        }
+        token.transpose()
+    }
+}
+
+/// Tokenizes only the header of a source, for tools that want its raw tokens
+/// without building a VcdHeader
+pub fn tokenize_header(source: &str) -> TokenHeaderIter<'_> {
+    TokenHeaderIter {
+        lexer: Lexer::new(source),
+        tokenizer: Tokenizer::new(source),
+        bs: ByteStorage::new(),
+        done: false,
+    }
+}
@@ -1,10 +1,12 @@
 use std::io;
 
+use bytes::Bytes;
 use indiscriminant::indiscriminant;
 use makai::utils::bytes::ByteStorage;
-use makai_waveform_db::bitvector::BitVector;
+use makai_waveform_db::bitvector::{BitVector, Logic};
 
 use crate::lexer::position::*;
+use crate::tokenizer::ByteRange;
 
 fn bitvector_write_to(bv: &BitVector, writer: &mut dyn io::Write) -> io::Result<usize> {
     if bv.get_bit_width() == 1 {
@@ -12,7 +14,7 @@ fn bitvector_write_to(bv: &BitVector, writer: &mut dyn io::Write) -> io::Result<
     } else {
         let mut size = 0;
         size += writer.write(b"b")?;
-        for i in 0..bv.get_bit_width() {
+        for i in (0..bv.get_bit_width()).rev() {
             size += writer.write(bv.get_bit(i).to_str().as_bytes())?;
         }
         size += writer.write(b" ")?;
@@ -20,6 +22,29 @@ fn bitvector_write_to(bv: &BitVector, writer: &mut dyn io::Write) -> io::Result<
     }
 }
 
+// Set on ids that index into the byte storage instead of holding the idcode
+pub(crate) const IDCODE_STORAGE_FLAG: usize = 1 << (usize::BITS - 1);
+
+/// How an idcode is packed into its id
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenIdCodeEncoding {
+    // The characters of the idcode packed into the id, first character in
+    // the lowest byte
+    Short,
+    // The id (without its top bit) indexes the byte storage holding the
+    // idcode, used for idcodes too long to pack
+    Storage,
+}
+
+impl std::fmt::Display for TokenIdCodeEncoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Short => write!(f, "short"),
+            Self::Storage => write!(f, "storage"),
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct TokenIdCode {
     id: usize,
@@ -31,30 +56,38 @@ impl TokenIdCode {
     }
 
     pub fn write_to(&self, bs: &ByteStorage, writer: &mut dyn io::Write) -> io::Result<usize> {
-        let mask = 1 << (usize::BITS - 1);
-        if self.id & mask == 0 {
-            let mut size = 0;
-            let mut id = self.id;
-            for _ in 0..8 {
-                let b = (id & 0xff) as u8;
-                if b == 0 {
-                    break;
-                }
-                size += writer.write(&[b])?;
-                id >>= 8;
-            }
-            Ok(size)
-        } else {
-            let mut size = 0;
-            let bytes = bs.get_bytes(self.id & !mask);
-            size += writer.write(&bytes)?;
-            Ok(size)
-        }
+        writer.write(&self.get_bytes(bs))
     }
 
     pub fn get_id(&self) -> usize {
         self.id
     }
+
+    pub fn get_encoding(&self) -> TokenIdCodeEncoding {
+        if self.id & IDCODE_STORAGE_FLAG == 0 {
+            TokenIdCodeEncoding::Short
+        } else {
+            TokenIdCodeEncoding::Storage
+        }
+    }
+
+    /// Returns the idcode as it appeared in the source, the byte storage is
+    /// only needed for idcodes too long to pack into the id
+    pub fn get_bytes(&self, bs: &ByteStorage) -> Vec<u8> {
+        match self.get_encoding() {
+            TokenIdCodeEncoding::Short => self
+                .id
+                .to_le_bytes()
+                .into_iter()
+                .take_while(|b| *b != 0)
+                .collect(),
+            TokenIdCodeEncoding::Storage => bs.get_bytes(self.id & !IDCODE_STORAGE_FLAG).to_vec(),
+        }
+    }
+
+    pub fn to_printable(&self, bs: &ByteStorage) -> String {
+        String::from_utf8_lossy(&self.get_bytes(bs)).to_string()
+    }
 }
 
 #[indiscriminant()]
@@ -97,7 +130,9 @@ pub enum TokenVariableNetType {
     Parameter = b"parameter",
     Real = b"real",
     Realtime = b"realtime",
+    RealParameter = b"real_parameter",
     Reg = b"reg",
+    Shortreal = b"shortreal",
     Supply0 = b"supply0",
     Supply1 = b"supply1",
     Time = b"time",
@@ -110,13 +145,78 @@ pub enum TokenVariableNetType {
     Wand = b"wand",
     Wire = b"wire",
     Wor = b"wor",
+    // SystemVerilog types, only accepted by the parser under a simulator
+    // profile that writes them
+    Bit = b"bit",
+    Byte = b"byte",
+    Int = b"int",
+    Logic = b"logic",
+    LongInt = b"longint",
+    ShortInt = b"shortint",
+    // Extended VCD ports, written with $dumpports
+    Port = b"port",
+    // SystemVerilog strings, their values are not stored in a waveform
+    String = b"string",
+}
+
+impl TokenVariableNetType {
+    // Returns the declared width real-valued net types must have, or None if
+    // the net type holds a vector
+    pub fn get_real_width(&self) -> Option<usize> {
+        match self {
+            Self::Real | Self::Realtime | Self::RealParameter => Some(64),
+            Self::Shortreal => Some(32),
+            _ => None,
+        }
+    }
+}
+
+/// One bracketed part of a reference with several, an index like [3] or a
+/// range like [7:0]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenVariableDimension {
+    Index(usize),
+    Range(usize, usize),
+}
+
+impl TokenVariableDimension {
+    /// Returns how many bits the dimension spans, one for an index
+    pub fn get_size(&self) -> usize {
+        match self {
+            Self::Index(_) => 1,
+            Self::Range(msb, lsb) => msb.abs_diff(*lsb).saturating_add(1),
+        }
+    }
+}
+
+impl std::fmt::Display for TokenVariableDimension {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Index(index) => write!(f, "[{}]", index),
+            Self::Range(msb, lsb) => write!(f, "[{}:{}]", msb, lsb),
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum TokenVariableDescription {
-    Unspecified { id: usize },
-    Vector { id: usize, width: usize },
-    VectorSelect { id: usize, msb: usize, lsb: usize },
+    Unspecified {
+        id: usize,
+    },
+    Vector {
+        id: usize,
+        width: usize,
+    },
+    VectorSelect {
+        id: usize,
+        msb: usize,
+        lsb: usize,
+    },
+    // Packed arrays like bus[1:0][15:0], outermost dimension first
+    Dimensions {
+        id: usize,
+        dimensions: Vec<TokenVariableDimension>,
+    },
 }
 
 impl TokenVariableDescription {
@@ -125,6 +225,7 @@ impl TokenVariableDescription {
             Self::Unspecified { id } => *id,
             Self::Vector { id, width: _ } => *id,
             Self::VectorSelect { id, msb: _, lsb: _ } => *id,
+            Self::Dimensions { id, dimensions: _ } => *id,
         }
     }
 
@@ -132,7 +233,13 @@ impl TokenVariableDescription {
         match self {
             Self::Unspecified { id: _ } => 0,
             Self::Vector { id: _, width } => *width,
-            Self::VectorSelect { id: _, msb, lsb } => *msb - *lsb + 1,
+            // Ranges may be declared in either direction, such as [0:7]
+            Self::VectorSelect { id: _, msb, lsb } => msb.abs_diff(*lsb).saturating_add(1),
+            Self::Dimensions { id: _, dimensions } => {
+                dimensions.iter().fold(1usize, |width, dimension| {
+                    width.saturating_mul(dimension.get_size())
+                })
+            }
         }
     }
 
@@ -142,25 +249,159 @@ impl TokenVariableDescription {
             Self::Vector { id, width } => {
                 let mut size = 0;
                 size += writer.write(&bs.get_bytes(*id))?;
-                size += writer.write(format!("[{}]", width).as_bytes())?;
+                size += writer.write(format!(" [{}]", width).as_bytes())?;
                 Ok(size)
             }
             Self::VectorSelect { id, msb, lsb } => {
                 let mut size = 0;
                 size += writer.write(&bs.get_bytes(*id))?;
-                size += writer.write(format!("[{}:{}]", msb, lsb).as_bytes())?;
+                size += writer.write(format!(" [{}:{}]", msb, lsb).as_bytes())?;
+                Ok(size)
+            }
+            Self::Dimensions { id, dimensions } => {
+                let mut size = 0;
+                size += writer.write(&bs.get_bytes(*id))?;
+                size += writer.write(b" ")?;
+                for dimension in dimensions {
+                    size += writer.write(dimension.to_string().as_bytes())?;
+                }
                 Ok(size)
             }
         }
     }
 }
 
+/// Which side drives a bit of a port in an extended VCD
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenPortDirection {
+    // Driven by the test fixture
+    Input,
+    // Driven by the design under test
+    Output,
+    // Driven from both sides or from neither
+    Unknown,
+}
+
+/// The value of a port change in an extended VCD, a state character per bit
+/// with the strengths of its zero and one components
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TokenPortValue {
+    // Most significant bit first, as written
+    states: Box<[u8]>,
+    strength0: Box<[u8]>,
+    strength1: Box<[u8]>,
+}
+
+impl TokenPortValue {
+    /// Returns None if a state is not an EVCD state character or a strength
+    /// is not a digit from 0 to 7
+    pub fn new(states: &[u8], strength0: &[u8], strength1: &[u8]) -> Option<Self> {
+        let is_strength = |b: &u8| (b'0'..=b'7').contains(b);
+        if states.is_empty()
+            || !states
+                .iter()
+                .all(|b| b"01?DUNZduLHXTlhFfAaBbCc".contains(b))
+            || strength0.is_empty()
+            || !strength0.iter().all(is_strength)
+            || strength1.is_empty()
+            || !strength1.iter().all(is_strength)
+        {
+            return None;
+        }
+        Some(Self {
+            states: states.into(),
+            strength0: strength0.iter().map(|b| b - b'0').collect(),
+            strength1: strength1.iter().map(|b| b - b'0').collect(),
+        })
+    }
+
+    pub fn get_bit_width(&self) -> usize {
+        self.states.len()
+    }
+
+    /// Returns the state character of a bit, bit zero is the last written
+    pub fn get_state(&self, index: usize) -> u8 {
+        self.states[self.states.len() - 1 - index]
+    }
+
+    pub fn get_direction(&self, index: usize) -> TokenPortDirection {
+        match self.get_state(index) {
+            b'D' | b'U' | b'N' | b'Z' | b'd' | b'u' => TokenPortDirection::Input,
+            b'L' | b'H' | b'X' | b'T' | b'l' | b'h' => TokenPortDirection::Output,
+            _ => TokenPortDirection::Unknown,
+        }
+    }
+
+    /// Returns the level of a bit regardless of which side drives it,
+    /// conflicting drivers are unknown
+    pub fn get_bit(&self, index: usize) -> Logic {
+        match self.get_state(index) {
+            b'0' | b'D' | b'd' | b'L' | b'l' => Logic::Zero,
+            b'1' | b'U' | b'u' | b'H' | b'h' => Logic::One,
+            b'Z' | b'T' | b'F' | b'f' => Logic::HighImpedance,
+            _ => Logic::Unknown,
+        }
+    }
+
+    // Strengths may be written once for the whole port or once per bit
+    fn get_strength(strengths: &[u8], index: usize) -> u8 {
+        match strengths.len() {
+            1 => strengths[0],
+            len => strengths[len - 1 - index.min(len - 1)],
+        }
+    }
+
+    /// Returns the strength from 0 to 7 of the zero component of a bit
+    pub fn get_strength0(&self, index: usize) -> u8 {
+        Self::get_strength(&self.strength0, index)
+    }
+
+    /// Returns the strength from 0 to 7 of the one component of a bit
+    pub fn get_strength1(&self, index: usize) -> u8 {
+        Self::get_strength(&self.strength1, index)
+    }
+
+    /// Returns the levels of the port as stored in a waveform, dropping the
+    /// directions and strengths
+    pub fn to_bitvector(&self) -> BitVector {
+        let bits: Vec<Logic> = (0..self.get_bit_width()).map(|i| self.get_bit(i)).collect();
+        let four_state = bits
+            .iter()
+            .any(|bit| matches!(bit, Logic::Unknown | Logic::HighImpedance));
+        let mut bv = BitVector::new(bits.len(), four_state);
+        for (i, bit) in bits.into_iter().enumerate() {
+            bv.set_bit(i, bit);
+        }
+        bv
+    }
+}
+
+impl std::fmt::Display for TokenPortValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let digits = |strengths: &[u8]| -> String {
+            strengths.iter().map(|s| char::from(b'0' + s)).collect()
+        };
+        write!(
+            f,
+            "p{} {} {}",
+            String::from_utf8_lossy(&self.states),
+            digits(&self.strength0),
+            digits(&self.strength1)
+        )
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     // Unformatted blocks
     Comment(usize, LexerPosition),
     Date(usize, LexerPosition),
     Version(usize, LexerPosition),
+    // A directive the lexer does not know, its keyword followed by the text
+    // of its block like "timezero 0"
+    Unknown(usize, LexerPosition),
+    // Attribute text like "misc 07 top.v 12", kept as it was written
+    AttrBegin(usize, LexerPosition),
     // Formatted blocks
     Scope {
         scope_type: TokenScopeType,
@@ -182,15 +423,23 @@ pub enum Token {
     // Empty blocks
     UpScope(LexerPosition),
     EndDefinitions(LexerPosition),
+    AttrEnd(LexerPosition),
     // Waveform signals
     DumpAll(LexerPosition),
     DumpOff(LexerPosition),
     DumpOn(LexerPosition),
     DumpVars(LexerPosition),
     End(LexerPosition),
+    // The time an extended VCD was closed at
+    VcdClose(u64, LexerPosition),
     Timestamp(u64, LexerPosition),
+    // A change of a single bit like "1!", kept apart from vectors since most
+    // changes in a dump are scalars
+    ScalarValue(Logic, TokenIdCode, LexerPosition),
     VectorValue(BitVector, TokenIdCode, LexerPosition),
     RealValue(f64, TokenIdCode, LexerPosition),
+    StringValue(Bytes, TokenIdCode, LexerPosition),
+    PortValue(TokenPortValue, TokenIdCode, LexerPosition),
 }
 
 impl Token {
@@ -214,6 +463,8 @@ impl Token {
             Self::Comment(id, _) => self.write_to_block(bs, writer, id, b"comment")?,
             Self::Date(id, _) => self.write_to_block(bs, writer, id, b"date")?,
             Self::Version(id, _) => self.write_to_block(bs, writer, id, b"version")?,
+            Self::AttrBegin(id, _) => self.write_to_block(bs, writer, id, b"attrbegin")?,
+            Self::Unknown(id, _) => self.write_to_block(bs, writer, id, b"")?,
             Self::Scope {
                 scope_type,
                 scope_id,
@@ -261,12 +512,21 @@ impl Token {
             }
             Self::UpScope(_) => writer.write(b"$upscope $end\n")?,
             Self::EndDefinitions(_) => writer.write(b"$enddefinitions $end\n")?,
+            Self::AttrEnd(_) => writer.write(b"$attrend $end\n")?,
             Self::DumpAll(_) => writer.write(b"$dumpall\n")?,
             Self::DumpOff(_) => writer.write(b"$dumpoff\n")?,
             Self::DumpOn(_) => writer.write(b"$dumpon\n")?,
             Self::DumpVars(_) => writer.write(b"$dumpvars\n")?,
             Self::End(_) => writer.write(b"$end\n")?,
+            Self::VcdClose(t, _) => writer.write(format!("$vcdclose #{} $end\n", t).as_bytes())?,
             Self::Timestamp(t, _) => writer.write(format!("#{}\n", t).as_bytes())?,
+            Self::ScalarValue(bit, idcode, _) => {
+                let mut size = 0;
+                size += writer.write(bit.to_str().as_bytes())?;
+                size += idcode.write_to(bs, writer)?;
+                size += writer.write(b"\n")?;
+                size
+            }
             Self::VectorValue(bv, idcode, _) => {
                 let mut size = 0;
                 size += bitvector_write_to(bv, writer)?;
@@ -276,7 +536,23 @@ impl Token {
             }
             Self::RealValue(r, idcode, _) => {
                 let mut size = 0;
-                size += writer.write(format!("r{:.16} ", r).as_bytes())?;
+                size += writer.write(format!("r{:?} ", r).as_bytes())?;
+                size += idcode.write_to(bs, writer)?;
+                size += writer.write(b"\n")?;
+                size
+            }
+            Self::StringValue(value, idcode, _) => {
+                let mut size = 0;
+                size += writer.write(b"s")?;
+                size += writer.write(value)?;
+                size += writer.write(b" ")?;
+                size += idcode.write_to(bs, writer)?;
+                size += writer.write(b"\n")?;
+                size
+            }
+            Self::PortValue(value, idcode, _) => {
+                let mut size = 0;
+                size += writer.write(format!("{} ", value).as_bytes())?;
                 size += idcode.write_to(bs, writer)?;
                 size += writer.write(b"\n")?;
                 size
@@ -290,6 +566,8 @@ impl Token {
             Self::Comment(_, pos)
             | Self::Date(_, pos)
             | Self::Version(_, pos)
+            | Self::AttrBegin(_, pos)
+            | Self::Unknown(_, pos)
             | Self::Scope {
                 scope_type: _,
                 scope_id: _,
@@ -309,14 +587,19 @@ impl Token {
             }
             | Self::UpScope(pos)
             | Self::EndDefinitions(pos)
+            | Self::AttrEnd(pos)
             | Self::DumpAll(pos)
             | Self::DumpOff(pos)
             | Self::DumpOn(pos)
             | Self::DumpVars(pos)
             | Self::End(pos)
+            | Self::VcdClose(_, pos)
             | Self::Timestamp(_, pos)
+            | Self::ScalarValue(_, _, pos)
             | Self::VectorValue(_, _, pos)
-            | Self::RealValue(_, _, pos) => *pos,
+            | Self::RealValue(_, _, pos)
+            | Self::StringValue(_, _, pos)
+            | Self::PortValue(_, _, pos) => *pos,
         }
     }
 
@@ -324,6 +607,12 @@ impl Token {
         self.get_position().get_index()
     }
 
+    /// Returns the byte range of the whole token in the source, including
+    /// any keywords and the closing $end, see LexerPosition::get_range
+    pub fn get_span(&self) -> ByteRange {
+        self.get_position().get_range()
+    }
+
     pub fn len(&self) -> usize {
         self.get_position().len()
     }
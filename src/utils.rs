@@ -1,14 +1,68 @@
+pub mod builder;
+pub mod dedupe;
+#[cfg(feature = "parallel")]
+pub mod determinism;
+pub mod differential;
+#[cfg(feature = "parallel")]
+pub mod dispatch;
+pub mod events;
+pub mod indexed;
+pub mod lazy;
+pub mod logging;
+pub mod monitor;
+pub mod pipeline;
+pub mod priority;
+pub mod push;
+pub mod segments;
+pub mod sink;
+pub mod strings;
+pub mod summary;
+pub mod tee;
+pub mod validate;
+
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::sync::mpsc::Sender;
 use std::sync::{Arc, Mutex};
+#[cfg(feature = "parallel")]
 use std::thread::{self, JoinHandle};
+use std::time::Duration;
 
+#[cfg(feature = "parallel")]
 use crossbeam::channel::bounded;
+#[cfg(feature = "parallel")]
 use makai::utils::crossbeam::{ReceiverQueued, SenderQueued};
+#[cfg(feature = "parallel")]
+use makai_waveform_db::bitvector::{BitVector, Logic};
 use makai_waveform_db::{errors::WaveformError, Waveform};
 
 use crate::errors::*;
-use crate::lexer::{position::LexerPosition, Lexer, LexerToken};
-use crate::parser::{VcdEntry, VcdHeader, VcdReader};
+use crate::format::VcdFileFormat;
+#[cfg(feature = "parallel")]
+use crate::lexer::LexerToken;
+use crate::lexer::{
+    line_index::LineIndex,
+    position::LexerPosition,
+    stream::{StreamTokenizer, CHUNK_SIZE},
+    Lexer,
+};
+#[cfg(feature = "parallel")]
+use crate::parser::VcdEntry;
+use crate::parser::{
+    demangle::VcdDemanglers, middleware::VcdTokenMiddlewares, profile::VcdSimulatorProfile,
+    repair::VcdRepair, transform::VcdTransforms, VcdHeader, VcdReader,
+};
 use crate::tokenizer::Tokenizer;
+#[cfg(feature = "parallel")]
+use crate::utils::dispatch::{VcdDispatchStrategy, VcdShardMap};
+use crate::utils::events::{VcdEventRecorder, VcdLoadEvent};
+use crate::utils::logging::{VcdLoadLog, VcdVerbosity};
+use crate::utils::monitor::{VcdMonitorRecorder, VcdMonitors};
+use crate::utils::priority::{finish_priority_signals, VcdPriorityRecorder, VcdPrioritySignals};
+use crate::utils::sink::{VcdEventPulses, VcdWaveformSink};
+use crate::utils::summary::{VcdSummary, VcdSummaryRecorder};
+use crate::utils::tee::{VcdSharedTap, VcdTapRecorder};
 
 #[derive(Debug)]
 pub enum VcdError {
@@ -17,6 +71,71 @@ pub enum VcdError {
     Tokenizer(TokenizerError),
     Parser(ParserError),
     Waveform(WaveformError),
+    UnsupportedFormat(VcdFileFormat),
+    // The header of a segment (by index) differs from the first one
+    SegmentMismatch(usize),
+}
+
+impl VcdError {
+    pub fn get_position(&self) -> Option<LexerPosition> {
+        match self {
+            Self::Io(_)
+            | Self::Waveform(_)
+            | Self::UnsupportedFormat(_)
+            | Self::SegmentMismatch(_) => None,
+            Self::Lexer(pos) => Some(*pos),
+            Self::Tokenizer(err) => Some(err.get_position()),
+            Self::Parser(err) => err.get_position(),
+        }
+    }
+
+    /// Returns a stable code identifying the kind of error, tokenizer and
+    /// parser errors keep their own codes
+    pub fn get_code(&self) -> &'static str {
+        match self {
+            Self::Io(_) => "E001",
+            Self::Lexer(_) => "L001",
+            Self::Tokenizer(err) => err.get_code(),
+            Self::Parser(err) => err.get_code(),
+            Self::Waveform(_) => "W001",
+            Self::UnsupportedFormat(_) => "E002",
+            Self::SegmentMismatch(_) => "E003",
+        }
+    }
+
+    /// Suggests how to fix the header that caused the error, see
+    /// VcdRepair::from_error
+    pub fn get_repair(&self) -> Option<VcdRepair> {
+        match self {
+            Self::Tokenizer(err) => VcdRepair::from_tokenizer_error(err),
+            Self::Parser(err) => VcdRepair::from_error(err),
+            _ => None,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        match self {
+            Self::Tokenizer(err) => err.to_json(),
+            Self::Parser(err) => err.to_json(),
+            _ => error_to_json(self.get_code(), &self.to_string(), self.get_position()),
+        }
+    }
+}
+
+impl std::fmt::Display for VcdError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{}", err),
+            Self::Lexer(_) => write!(f, "unrecognized token"),
+            Self::Tokenizer(err) => write!(f, "{}", err),
+            Self::Parser(err) => write!(f, "{}", err),
+            Self::Waveform(err) => write!(f, "waveform error ({:?})", err),
+            Self::UnsupportedFormat(format) => write!(f, "{} files are not supported", format),
+            Self::SegmentMismatch(index) => {
+                write!(f, "segment {} declares different variables", index)
+            }
+        }
+    }
 }
 
 impl From<std::io::Error> for VcdError {
@@ -51,181 +170,806 @@ impl From<WaveformError> for VcdError {
 
 pub type VcdResult<T> = Result<T, VcdError>;
 
+fn log_error(bytes: &str, err: &VcdError) {
+    match err.get_position() {
+        Some(pos) => {
+            let pos = LineIndex::new(bytes).resolve_display(bytes, &pos);
+            log::error!(
+                "VCD error at line {}, column {}: {err:?}",
+                pos.get_line(),
+                pos.get_column()
+            );
+        }
+        None => log::error!("VCD error: {err:?}"),
+    }
+}
+
+// Tracks the index at which progress is next reported, so loaders only make
+// a comparison per entry
+struct VcdProgress {
+    step: usize,
+    next: usize,
+}
+
+impl VcdProgress {
+    fn new(file_size: usize, steps: usize, start: usize) -> Self {
+        Self::with_step(file_size / steps.max(1), start)
+    }
+
+    fn with_step(step: usize, start: usize) -> Self {
+        let step = step.max(1);
+        Self {
+            step,
+            next: start + step,
+        }
+    }
+
+    #[inline]
+    fn is_due(&mut self, index: usize) -> bool {
+        if index < self.next {
+            return false;
+        }
+        self.next = index + self.step;
+        true
+    }
+}
+
+/// Optional behaviour of the loaders beyond plain parsing
+pub struct VcdLoadOptions {
+    transforms: VcdTransforms,
+    priority_signals: Option<Arc<Mutex<VcdPrioritySignals>>>,
+    monitors: Option<Arc<Mutex<VcdMonitors>>>,
+    summary: Option<Arc<Mutex<VcdSummary>>>,
+    taps: Vec<VcdSharedTap>,
+    events: Option<Sender<VcdLoadEvent>>,
+    #[cfg(feature = "parallel")]
+    dispatch: Option<Box<dyn VcdDispatchStrategy>>,
+    progress_steps: Option<usize>,
+    verbosity: VcdVerbosity,
+    progress_log_interval: Option<Duration>,
+    lenient: bool,
+    unknown_directives: bool,
+    header_byte_limit: Option<usize>,
+    header_time_limit: Option<Duration>,
+    max_scope_depth: Option<usize>,
+    header_repair: bool,
+    idcode_validation: bool,
+    idle_skip: bool,
+    namespaces: bool,
+    demanglers: VcdDemanglers,
+    token_middlewares: VcdTokenMiddlewares,
+    simulator_profile: VcdSimulatorProfile,
+    window_size: Option<usize>,
+}
+
+impl Default for VcdLoadOptions {
+    fn default() -> Self {
+        Self {
+            transforms: VcdTransforms::default(),
+            priority_signals: None,
+            monitors: None,
+            summary: None,
+            taps: Vec::new(),
+            events: None,
+            #[cfg(feature = "parallel")]
+            dispatch: None,
+            progress_steps: Some(200),
+            verbosity: VcdVerbosity::default(),
+            progress_log_interval: None,
+            lenient: false,
+            unknown_directives: false,
+            header_byte_limit: None,
+            header_time_limit: None,
+            max_scope_depth: None,
+            header_repair: false,
+            idcode_validation: false,
+            idle_skip: false,
+            namespaces: false,
+            demanglers: VcdDemanglers::new(),
+            token_middlewares: VcdTokenMiddlewares::new(),
+            simulator_profile: VcdSimulatorProfile::default(),
+            window_size: None,
+        }
+    }
+}
+
+impl VcdLoadOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reports progress about steps times over the file, 200 by default.
+    /// None never reports progress and removes the check from the load loop,
+    /// though the multi-threaded loader still reports when it is done
+    pub fn set_progress_steps(&mut self, steps: Option<usize>) {
+        self.progress_steps = steps;
+    }
+
+    /// Sets how much the loader logs, everything by default. Below Warnings
+    /// the lexer and parser stop logging tolerated problems, though they are
+    /// still counted, see VcdReader::get_warning_count
+    pub fn set_verbosity(&mut self, verbosity: VcdVerbosity) {
+        self.verbosity = verbosity;
+    }
+
+    /// Also logs progress at info level, at most once per interval, whenever
+    /// it is reported. None by default, which never logs progress
+    pub fn set_progress_log_interval(&mut self, interval: Option<Duration>) {
+        self.progress_log_interval = interval;
+    }
+
+    /// Applies value transforms to every change before it is inserted into
+    /// the waveform, transforms run on the parser thread
+    pub fn set_transforms(&mut self, transforms: VcdTransforms) {
+        self.transforms = transforms;
+    }
+
+    /// Records the histories of a few signals into a shared structure as
+    /// their changes are parsed, so they can be read before the load finishes
+    pub fn set_priority_signals(&mut self, signals: Arc<Mutex<VcdPrioritySignals>>) {
+        self.priority_signals = Some(signals);
+    }
+
+    /// Evaluates monitors against every change as it is parsed, their
+    /// violations are filled in once the load ends
+    pub fn set_monitors(&mut self, monitors: Arc<Mutex<VcdMonitors>>) {
+        self.monitors = Some(monitors);
+    }
+
+    /// Counts and hashes every change as it is parsed, the summary is filled
+    /// in once the load succeeds
+    pub fn set_summary(&mut self, summary: Arc<Mutex<VcdSummary>>) {
+        self.summary = Some(summary);
+    }
+
+    /// Feeds every entry to a secondary consumer as it is parsed, like an
+    /// exporter, so the load and the export take a single pass over the file
+    pub fn add_tap(&mut self, tap: VcdSharedTap) {
+        self.taps.push(tap);
+    }
+
+    /// Sends what the load does as it happens, from the header being parsed
+    /// through every warning to the totals once it finishes
+    pub fn set_events(&mut self, events: Sender<VcdLoadEvent>) {
+        self.events = Some(events);
+    }
+
+    /// Loads everything before an incomplete last line with a warning, which
+    /// a simulator that was stopped while writing leaves behind, instead of
+    /// failing
+    pub fn set_lenient(&mut self, lenient: bool) {
+        self.lenient = lenient;
+    }
+
+    /// Keeps unknown directives like $timezero in the header instead of
+    /// failing, and skips them in the body, see Lexer::set_unknown_directives
+    pub fn set_unknown_directives(&mut self, enabled: bool) {
+        self.unknown_directives = enabled;
+    }
+
+    /// Bounds how much of the input is read looking for $enddefinitions, see
+    /// VcdReader::set_header_byte_limit and set_header_time_limit
+    pub fn set_header_limits(&mut self, bytes: Option<usize>, time: Option<Duration>) {
+        self.header_byte_limit = bytes;
+        self.header_time_limit = time;
+    }
+
+    /// Bounds how deeply scopes can be nested, see
+    /// VcdReader::set_max_scope_depth
+    pub fn set_max_scope_depth(&mut self, depth: Option<usize>) {
+        self.max_scope_depth = depth;
+    }
+
+    /// Fixes unbalanced scopes and a missing $enddefinitions instead of
+    /// failing, see VcdReader::set_header_repair
+    pub fn set_header_repair(&mut self, enabled: bool) {
+        self.header_repair = enabled;
+    }
+
+    /// Warns about idcodes with characters the spec does not allow, see
+    /// VcdReader::set_idcode_validation
+    pub fn set_idcode_validation(&mut self, enabled: bool) {
+        self.idcode_validation = enabled;
+    }
+
+    /// Keeps only the first and last timestamp of stretches with no changes,
+    /// see VcdReader::set_idle_skip
+    pub fn set_idle_skip(&mut self, enabled: bool) {
+        self.idle_skip = enabled;
+    }
+
+    /// Rewrites variable and scope names as the header is built, see
+    /// VcdReader::set_demanglers
+    pub fn set_demanglers(&mut self, demanglers: VcdDemanglers) {
+        self.demanglers = demanglers;
+    }
+
+    /// Passes every token through middleware before it is parsed, see
+    /// VcdReader::set_token_middlewares
+    pub fn set_token_middlewares(&mut self, middlewares: VcdTokenMiddlewares) {
+        self.token_middlewares = middlewares;
+    }
+
+    /// Reads the namespace markers written by the VCD exporter, see
+    /// VcdReader::set_namespaces
+    pub fn set_namespaces(&mut self, enabled: bool) {
+        self.namespaces = enabled;
+    }
+
+    /// Tolerates the quirks of a simulator, see
+    /// VcdReader::set_simulator_profile
+    pub fn set_simulator_profile(&mut self, profile: VcdSimulatorProfile) {
+        self.simulator_profile = profile;
+    }
+
+    /// Sets how many bytes the streaming loaders read at a time, see
+    /// StreamTokenizer::set_chunk_size
+    pub fn set_window_size(&mut self, size: Option<usize>) {
+        self.window_size = size;
+    }
+
+    /// Chooses which shard builds each signal in the sharded assembly of the
+    /// multi-threaded loader, by default the idcode modulo the shard count
+    #[cfg(feature = "parallel")]
+    pub fn set_dispatch_strategy(&mut self, strategy: Box<dyn VcdDispatchStrategy>) {
+        self.dispatch = Some(strategy);
+    }
+
+    fn has_warnings(&self) -> bool {
+        self.verbosity >= VcdVerbosity::Warnings
+    }
+
+    /// Applies the options that change how the input is parsed to a parser,
+    /// moving the transforms, demanglers and token middleware into it, so
+    /// every loader reads the input the same way
+    pub fn configure(&mut self, parser: &mut VcdReader) {
+        parser.set_transforms(std::mem::take(&mut self.transforms));
+        parser.set_header_byte_limit(self.header_byte_limit);
+        parser.set_header_time_limit(self.header_time_limit);
+        parser.set_max_scope_depth(self.max_scope_depth);
+        parser.set_idcode_validation(self.idcode_validation);
+        parser.set_demanglers(std::mem::take(&mut self.demanglers));
+        parser.set_token_middlewares(std::mem::take(&mut self.token_middlewares));
+        parser.set_simulator_profile(self.simulator_profile);
+        parser.set_header_repair(self.header_repair);
+        parser.set_idle_skip(self.idle_skip);
+        parser.set_namespaces(self.namespaces);
+        parser.set_warnings(self.has_warnings());
+        parser.set_warning_messages(self.events.is_some());
+    }
+
+    /// Applies the options that change how the input is lexed to a lexer
+    pub fn configure_lexer(&self, lexer: &mut Lexer) {
+        lexer.set_lenient(self.lenient);
+        lexer.set_unknown_directives(self.unknown_directives);
+        lexer.set_warnings(self.has_warnings());
+        lexer.set_block_limit(self.header_byte_limit);
+    }
+
+    /// Applies the options that change how the input is lexed to a streaming
+    /// tokenizer, along with the window size
+    pub fn configure_stream<R: BufRead>(&self, tokenizer: &mut StreamTokenizer<R>) {
+        tokenizer.set_lenient(self.lenient);
+        tokenizer.set_unknown_directives(self.unknown_directives);
+        tokenizer.set_warnings(self.has_warnings());
+        tokenizer.set_block_limit(self.header_byte_limit);
+        if let Some(size) = self.window_size {
+            tokenizer.set_chunk_size(size);
+        }
+    }
+}
+
 pub fn load_single_threaded(
     bytes: String,
     status: &mut dyn FnMut((usize, usize)),
 ) -> VcdResult<(VcdHeader, Waveform)> {
-    log::debug!("Loading VCD (single-threaded)...");
-    let file_size = bytes.as_bytes().len();
-    let mut lexer = Lexer::new(&bytes);
-    let mut tokenizer = Tokenizer::new(&bytes);
-    let mut parser = VcdReader::new();
+    load_single_threaded_with_options(bytes, VcdLoadOptions::new(), status)
+}
+
+pub fn load_single_threaded_with_options(
+    bytes: String,
+    options: VcdLoadOptions,
+    status: &mut dyn FnMut((usize, usize)),
+) -> VcdResult<(VcdHeader, Waveform)> {
     let mut waveform = Waveform::new();
+    let header = load_into_sink(&bytes, &mut waveform, options, status)?;
+    Ok((header, waveform))
+}
+
+/// Loads a VCD on the calling thread into any storage instead of a Waveform,
+/// returning the header once every change has been handed to the sink
+pub fn load_into_sink<S: VcdWaveformSink + ?Sized>(
+    bytes: &str,
+    sink: &mut S,
+    options: VcdLoadOptions,
+    status: &mut dyn FnMut((usize, usize)),
+) -> VcdResult<VcdHeader> {
+    let mut log = VcdLoadLog::new(options.verbosity, options.progress_log_interval);
+    log.debug("Loading VCD (single-threaded)...");
+    let priority_signals = options.priority_signals.clone();
+    let result = match options.progress_steps {
+        Some(_) => load_single_threaded_internal::<true, S>(bytes, sink, options, &mut log, status),
+        None => load_single_threaded_internal::<false, S>(bytes, sink, options, &mut log, status),
+    };
+    finish_priority_signals(&priority_signals);
+    match result {
+        Ok(ok) => {
+            log.debug("VCD loaded!");
+            Ok(ok)
+        }
+        Err(err) => {
+            log.error(Some(bytes), &err);
+            Err(err)
+        }
+    }
+}
+
+fn load_single_threaded_internal<const PROGRESS: bool, S: VcdWaveformSink + ?Sized>(
+    bytes: &str,
+    sink: &mut S,
+    mut options: VcdLoadOptions,
+    log: &mut VcdLoadLog,
+    status: &mut dyn FnMut((usize, usize)),
+) -> VcdResult<VcdHeader> {
+    let file_size = bytes.len();
+    let mut lexer = Lexer::new(bytes);
+    options.configure_lexer(&mut lexer);
+    let mut tokenizer = Tokenizer::new(bytes);
+    let mut parser = VcdReader::new();
+    options.configure(&mut parser);
+    let mut events = VcdEventRecorder::new(options.events);
     parser.parse_header(&mut |bs| tokenizer.next(lexer.next_token()?, bs))?;
-    parser.get_header().initialize_waveform(&mut waveform);
-    log::debug!("Header parsed...");
-    let mut last_index = lexer.get_position().get_index();
-    status((last_index, file_size));
+    sink.initialize(parser.get_header())?;
+    let mut priority = VcdPriorityRecorder::new(options.priority_signals, parser.get_header(), log);
+    let mut monitors = VcdMonitorRecorder::new(options.monitors, parser.get_header(), log);
+    let mut summary = VcdSummaryRecorder::new(options.summary);
+    let mut taps = VcdTapRecorder::new(options.taps, parser.get_header())?;
+    let mut pulses = VcdEventPulses::new();
+    log.debug("Header parsed...");
+    events.header(&mut parser);
+    let start = lexer.get_position().get_index();
+    let mut progress = VcdProgress::new(file_size, options.progress_steps.unwrap_or(1), start);
+    if PROGRESS {
+        status((start, file_size));
+    }
     loop {
         let entry =
             match parser.parse_waveform(&mut |bs| tokenizer.next(lexer.next_token()?, bs))? {
                 Some(entry) => entry,
                 None => break,
             };
-        match entry {
-            VcdEntry::Timestamp(timestamp) => waveform.insert_timestamp(timestamp)?,
-            VcdEntry::Vector(bv, idcode) => waveform.update_vector(idcode, bv.clone())?,
-            VcdEntry::Real(value, idcode) => waveform.update_real(idcode, value)?,
+        priority.record(&entry);
+        monitors.record(&entry);
+        summary.record(&entry);
+        taps.record(&entry)?;
+        events.record(&entry, &mut parser);
+        pulses.apply(sink, entry)?;
+        if PROGRESS {
+            let index = lexer.get_position().get_index();
+            if progress.is_due(index) {
+                status((index, file_size));
+                log.progress(index, Some(file_size));
+            }
         }
-        let index = lexer.get_position().get_index();
-        if (index - last_index) * 200 / file_size > 0 {
-            last_index = index;
-            status((last_index, file_size));
+    }
+    pulses.apply_finish(sink)?;
+    sink.finish()?;
+    taps.finish()?;
+    summary.finish(&parser);
+    events.finish(&mut parser, file_size);
+    Ok(parser.into_header())
+}
+
+/// Loads a VCD on the calling thread as it is read, without holding the whole
+/// file in memory, progress is reported as the number of bytes read so far
+pub fn load_from_reader<R: BufRead>(
+    reader: R,
+    status: &mut dyn FnMut(usize),
+) -> VcdResult<(VcdHeader, Waveform)> {
+    load_from_reader_with_options(reader, VcdLoadOptions::new(), status)
+}
+
+pub fn load_from_reader_with_options<R: BufRead>(
+    reader: R,
+    options: VcdLoadOptions,
+    status: &mut dyn FnMut(usize),
+) -> VcdResult<(VcdHeader, Waveform)> {
+    let mut log = VcdLoadLog::new(options.verbosity, options.progress_log_interval);
+    log.debug("Loading VCD (streaming)...");
+    let mut waveform = Waveform::new();
+    let priority_signals = options.priority_signals.clone();
+    let result = load_from_reader_internal(reader, &mut waveform, options, &mut log, status);
+    finish_priority_signals(&priority_signals);
+    match result {
+        Ok(header) => {
+            log.debug("VCD loaded!");
+            Ok((header, waveform))
+        }
+        Err(err) => {
+            // The source is gone, so only the position can be reported
+            log.error(None, &err);
+            Err(err)
         }
     }
-    log::debug!("VCD loaded!");
-    Ok((parser.into_header(), waveform))
 }
 
+/// Loads a file a window at a time with the streaming loader, so memory use
+/// is bounded by the waveform being built rather than the size of the file.
+/// Progress is reported as (bytes read, file size) like the other loaders.
+pub fn load_file_windowed<P: AsRef<Path>>(
+    path: P,
+    options: VcdLoadOptions,
+    status: &mut dyn FnMut((usize, usize)),
+) -> VcdResult<(VcdHeader, Waveform)> {
+    let file = File::open(path)?;
+    let file_size = file.metadata()?.len() as usize;
+    let window_size = options.window_size.unwrap_or(CHUNK_SIZE);
+    let reader = BufReader::with_capacity(window_size, file);
+    load_from_reader_with_options(reader, options, &mut |index| status((index, file_size)))
+}
+
+fn load_from_reader_internal<R: BufRead>(
+    reader: R,
+    waveform: &mut Waveform,
+    mut options: VcdLoadOptions,
+    log: &mut VcdLoadLog,
+    status: &mut dyn FnMut(usize),
+) -> VcdResult<VcdHeader> {
+    let mut tokenizer = StreamTokenizer::new(reader);
+    options.configure_stream(&mut tokenizer);
+    let mut parser = VcdReader::new();
+    options.configure(&mut parser);
+    let mut events = VcdEventRecorder::new(options.events);
+    parser.parse_header(&mut |bs| tokenizer.next(bs))?;
+    waveform.initialize(parser.get_header())?;
+    let mut priority = VcdPriorityRecorder::new(options.priority_signals, parser.get_header(), log);
+    let mut monitors = VcdMonitorRecorder::new(options.monitors, parser.get_header(), log);
+    let mut summary = VcdSummaryRecorder::new(options.summary);
+    let mut taps = VcdTapRecorder::new(options.taps, parser.get_header())?;
+    let mut pulses = VcdEventPulses::new();
+    log.debug("Header parsed...");
+    events.header(&mut parser);
+    // The size of a stream is not known, so progress is reported about every
+    // megabyte instead of a number of times over the file
+    let mut progress = VcdProgress::with_step(1 << 20, tokenizer.get_index());
+    while let Some(entry) = parser.parse_waveform(&mut |bs| tokenizer.next(bs))? {
+        priority.record(&entry);
+        monitors.record(&entry);
+        summary.record(&entry);
+        taps.record(&entry)?;
+        events.record(&entry, &mut parser);
+        pulses.apply(waveform, entry)?;
+        if options.progress_steps.is_some() && progress.is_due(tokenizer.get_index()) {
+            status(tokenizer.get_index());
+            log.progress(tokenizer.get_index(), None);
+        }
+    }
+    pulses.apply_finish(waveform)?;
+    waveform.finish()?;
+    taps.finish()?;
+    summary.finish(&parser);
+    events.finish(&mut parser, tokenizer.get_index());
+    Ok(parser.into_header())
+}
+
+#[cfg(feature = "parallel")]
+const CHANNEL_LIMIT: usize = 1024;
+#[cfg(feature = "parallel")]
+const QUEUE_LIMIT: usize = 4096;
+
+/// Loads a VCD with the parser and the waveform assembly on their own threads.
+/// The waveform is the same, down to the order of every history, as the one
+/// the single-threaded loader builds for any thread count or scheduling, see
+/// verify_deterministic_load.
+#[cfg(feature = "parallel")]
 pub fn load_multi_threaded(
     bytes: String,
     waveform_threads: usize,
     status: Arc<Mutex<(usize, usize)>>,
 ) -> JoinHandle<VcdResult<(VcdHeader, Waveform)>> {
-    let channel_limit = 1024;
-    let queue_limit = 4096;
+    load_multi_threaded_with_options(bytes, waveform_threads, VcdLoadOptions::new(), status)
+}
+
+#[cfg(feature = "parallel")]
+pub fn load_multi_threaded_with_options(
+    bytes: String,
+    waveform_threads: usize,
+    mut options: VcdLoadOptions,
+    status: Arc<Mutex<(usize, usize)>>,
+) -> JoinHandle<VcdResult<(VcdHeader, Waveform)>> {
     let file_size = bytes.as_bytes().len();
 
     let status_clean = status.clone();
+    let priority_signals = options.priority_signals.clone();
+    let progress_steps = options.progress_steps;
 
-    let loader_fn = move || {
-        log::debug!("Loading VCD (multi-threaded)...");
+    let loader_fn = move |bytes: &str, log: &mut VcdLoadLog| {
+        log.debug("Loading VCD (multi-threaded)...");
         // Create a tokenizer and parser for the file
-        let mut lexer = Lexer::new(&bytes);
-        let mut tokenizer = Tokenizer::new(&bytes);
+        let mut lexer = Lexer::new(bytes);
+        options.configure_lexer(&mut lexer);
+        let mut tokenizer = Tokenizer::new(bytes);
         let mut parser = VcdReader::new();
-        let mut waveform = Waveform::new();
-        *status.lock().unwrap() = (lexer.get_position().get_index(), file_size);
+        options.configure(&mut parser);
+        let mut events = VcdEventRecorder::new(options.events);
+        if progress_steps.is_some() {
+            *status.lock().unwrap() = (lexer.get_position().get_index(), file_size);
+        }
         parser.parse_header(&mut |bs| tokenizer.next(lexer.next_token()?, bs))?;
-        parser.get_header().initialize_waveform(&mut waveform);
-        *status.lock().unwrap() = (lexer.get_position().get_index(), file_size);
-        log::debug!("Header parsed...");
+        let priority = VcdPriorityRecorder::new(options.priority_signals, parser.get_header(), log);
+        let mut monitors = VcdMonitorRecorder::new(options.monitors, parser.get_header(), log);
+        let mut summary = VcdSummaryRecorder::new(options.summary);
+        let mut taps = VcdTapRecorder::new(options.taps, parser.get_header())?;
+        // Signals are split into shards before the parser thread takes the header
+        let mut dispatch = options.dispatch;
+        let shard_map = VcdShardMap::new(
+            parser.get_header(),
+            waveform_threads,
+            dispatch.as_deref_mut(),
+        );
+        let shards = shard_map.split(parser.get_header());
+        if progress_steps.is_some() {
+            *status.lock().unwrap() = (lexer.get_position().get_index(), file_size);
+        }
+        log.debug("Header parsed...");
+        events.header(&mut parser);
+        let shard_events = events.get_sender();
 
         // Spawn threads for lexing, parsing/tokenizing, and assembling the waveform
-        let (tx_lexer, rx_lexer) = bounded::<Vec<LexerToken>>(channel_limit);
-        let (mut tx_lexer, mut rx_lexer) = (
-            SenderQueued::new(tx_lexer, queue_limit),
+        let (tx_lexer, rx_lexer) = bounded::<Vec<LexerToken>>(CHANNEL_LIMIT);
+        let (tx_lexer, mut rx_lexer) = (
+            SenderQueued::new(tx_lexer, QUEUE_LIMIT),
             ReceiverQueued::new(rx_lexer),
         );
-        let (tx_parser, rx_parser) = bounded::<Vec<VcdEntry>>(channel_limit);
-        let (mut tx_parser, mut rx_parser) = (
-            SenderQueued::new(tx_parser, queue_limit),
+        let (tx_parser, rx_parser) = bounded::<Vec<VcdEntry>>(CHANNEL_LIMIT);
+        let (mut tx_parser, rx_parser) = (
+            SenderQueued::new(tx_parser, QUEUE_LIMIT),
             ReceiverQueued::new(rx_parser),
         );
-        let mut waveform_handles: Vec<JoinHandle<Result<Waveform, WaveformError>>> = Vec::new();
-        let mut tx_dispatchers = Vec::new();
-        for mut waveform_shard in waveform.shard(waveform_threads) {
-            let (tx_dispatcher, rx_dispatcher) = bounded(channel_limit);
-            let (tx_dispatcher, mut rx_dispatcher) = (
-                SenderQueued::new(tx_dispatcher, queue_limit),
-                ReceiverQueued::new(rx_dispatcher),
-            );
-            tx_dispatchers.push(tx_dispatcher);
-            waveform_handles.push(thread::spawn(move || loop {
-                match rx_dispatcher.recv().unwrap() {
-                    Some(VcdEntry::Timestamp(timestamp)) => {
-                        waveform_shard.insert_timestamp(timestamp)?
-                    }
-                    Some(VcdEntry::Vector(value, id)) => waveform_shard.update_vector(id, value)?,
-                    Some(VcdEntry::Real(value, id)) => waveform_shard.update_real(id, value)?,
-                    None => return Ok(waveform_shard),
-                }
-            }));
-        }
-        let parser_handle = thread::spawn(move || loop {
-            match parser.parse_waveform(&mut |bs| tokenizer.next(rx_lexer.recv().unwrap(), bs)) {
-                Ok(Some(entry)) => tx_parser.send(entry).unwrap(),
-                Ok(None) => {
-                    tx_parser.finish().unwrap();
-                    return Ok(parser);
-                }
-                Err(err) => {
-                    tx_parser.finish().unwrap();
-                    return Err(err);
-                }
-            }
-        });
-        let dispatcher_handle = thread::spawn(move || loop {
-            match rx_parser.recv().unwrap() {
-                Some(entry) => match entry {
-                    VcdEntry::Timestamp(timestamp) => {
-                        for tx_dispatcher in &mut tx_dispatchers {
-                            tx_dispatcher.send(VcdEntry::Timestamp(timestamp)).unwrap();
+        // Monitors, the summary and the taps are evaluated on the parser
+        // thread, which sees every change in order and is not the one feeding
+        // the shards
+        let parser_handle = thread::spawn(move || -> VcdResult<_> {
+            loop {
+                let entry = parser
+                    .parse_waveform(&mut |bs| tokenizer.next(rx_lexer.recv().unwrap(), bs))
+                    .map_err(VcdError::from)
+                    .and_then(|entry| {
+                        if let Some(entry) = &entry {
+                            taps.record(entry)?;
                         }
+                        Ok(entry)
+                    });
+                match entry {
+                    Ok(Some(entry)) => {
+                        monitors.record(&entry);
+                        summary.record(&entry);
+                        events.record(&entry, &mut parser);
+                        tx_parser.send(entry).unwrap()
                     }
-                    VcdEntry::Vector(value, id) => {
-                        tx_dispatchers[id % waveform_threads]
-                            .send(VcdEntry::Vector(value, id))
-                            .unwrap();
-                    }
-                    VcdEntry::Real(value, id) => {
-                        tx_dispatchers[id % waveform_threads]
-                            .send(VcdEntry::Real(value, id))
-                            .unwrap();
+                    Ok(None) => {
+                        tx_parser.finish().unwrap();
+                        taps.finish()?;
+                        return Ok((parser, summary, events));
                     }
-                },
-                None => {
-                    for tx_dispatcher in tx_dispatchers {
-                        tx_dispatcher.finish().unwrap();
+                    Err(err) => {
+                        tx_parser.finish().unwrap();
+                        return Err(err);
                     }
-                    return;
                 }
             }
         });
+        let assembly_handle =
+            spawn_sharded_assembly(shards, shard_map, rx_parser, priority, shard_events);
 
-        let mut last_index = lexer.get_position().get_index();
-        loop {
-            match lexer.next_token() {
-                Ok(Some(lexer_token)) => {
-                    tx_lexer.send(lexer_token).unwrap();
+        let start = lexer.get_position().get_index();
+        let mut progress = VcdProgress::new(file_size, progress_steps.unwrap_or(1), start);
+        match progress_steps {
+            Some(_) => {
+                lex_body::<true>(&mut lexer, tx_lexer, &mut progress, log, &status, file_size)?
+            }
+            None => {
+                lex_body::<false>(&mut lexer, tx_lexer, &mut progress, log, &status, file_size)?
+            }
+        }
+        *status.lock().unwrap() = (file_size, file_size);
+        let (mut parser, summary, events) = parser_handle.join().unwrap()?;
+        let waveform = assembly_handle.join().unwrap()?;
+        summary.finish(&parser);
+        events.finish(&mut parser, file_size);
+        log.debug("Body parsed and shards combined...");
+        Ok((parser.into_header(), waveform))
+    };
+
+    let (verbosity, interval) = (options.verbosity, options.progress_log_interval);
+    thread::spawn(move || {
+        let mut log = VcdLoadLog::new(verbosity, interval);
+        let result = loader_fn(&bytes, &mut log);
+        finish_priority_signals(&priority_signals);
+        match result {
+            Ok(ok) => {
+                log.debug("VCD loaded!");
+                Ok(ok)
+            }
+            Err(err) => {
+                log.error(Some(&bytes), &err);
+                *status_clean.lock().unwrap() = (file_size, file_size);
+                Err(err)
+            }
+        }
+    })
+}
+
+// Sends every token of the body to the parser thread
+#[cfg(feature = "parallel")]
+fn lex_body<const PROGRESS: bool>(
+    lexer: &mut Lexer,
+    mut tx_lexer: SenderQueued<LexerToken>,
+    progress: &mut VcdProgress,
+    log: &mut VcdLoadLog,
+    status: &Mutex<(usize, usize)>,
+    file_size: usize,
+) -> VcdResult<()> {
+    loop {
+        match lexer.next_token() {
+            Ok(Some(lexer_token)) => {
+                tx_lexer.send(lexer_token).unwrap();
+                if PROGRESS {
                     let index = lexer.get_position().get_index();
-                    if (index - last_index) * 200 / file_size > 0 {
+                    if progress.is_due(index) {
                         *status.lock().unwrap() = (index, file_size);
-                        last_index = index;
+                        log.progress(index, Some(file_size));
                     }
                 }
-                Ok(None) => {
-                    tx_lexer.finish().unwrap();
-                    *status.lock().unwrap() = (file_size, file_size);
-                    break;
+            }
+            Ok(None) => {
+                tx_lexer.finish().unwrap();
+                return Ok(());
+            }
+            Err(err) => {
+                tx_lexer.finish().unwrap();
+                return Err(VcdError::from(err));
+            }
+        }
+    }
+}
+
+// What the dispatcher hands to a shard, timestamps are collected into runs
+// shared by every shard and a run is only sent to a shard once it has a change
+// after it, so timestamps are neither copied per shard nor sent one by one
+#[cfg(feature = "parallel")]
+enum VcdShardEntry {
+    Timestamps(Arc<[u64]>),
+    Change(VcdEntry),
+}
+
+// Builds the waveform with one thread per shard, a dispatcher thread hands
+// every change to the thread owning its signal
+#[cfg(feature = "parallel")]
+fn spawn_sharded_assembly(
+    shards: Vec<Waveform>,
+    shard_map: VcdShardMap,
+    mut rx_parser: ReceiverQueued<VcdEntry>,
+    mut priority: VcdPriorityRecorder,
+    events: Option<Sender<VcdLoadEvent>>,
+) -> JoinHandle<Result<Waveform, WaveformError>> {
+    let mut waveform_handles: Vec<JoinHandle<Result<Waveform, WaveformError>>> = Vec::new();
+    let mut tx_dispatchers = Vec::new();
+    for mut waveform_shard in shards {
+        let (tx_dispatcher, rx_dispatcher) = bounded(CHANNEL_LIMIT);
+        let (tx_dispatcher, mut rx_dispatcher) = (
+            SenderQueued::new(tx_dispatcher, QUEUE_LIMIT),
+            ReceiverQueued::new(rx_dispatcher),
+        );
+        tx_dispatchers.push(tx_dispatcher);
+        let mut pulses = VcdEventPulses::new();
+        waveform_handles.push(thread::spawn(move || loop {
+            let entry = match rx_dispatcher.recv().unwrap() {
+                Some(VcdShardEntry::Timestamps(timestamps)) => {
+                    for &timestamp in timestamps.iter() {
+                        for id in pulses.release() {
+                            waveform_shard.update_vector(id, BitVector::from(Logic::Zero))?;
+                        }
+                        waveform_shard.insert_timestamp(timestamp)?;
+                    }
+                    continue;
                 }
-                Err(err) => {
-                    tx_lexer.finish().unwrap();
-                    return Err(VcdError::from(err));
+                Some(VcdShardEntry::Change(entry)) => entry,
+                None => {
+                    for id in pulses.finish() {
+                        waveform_shard.update_vector(id, BitVector::from(Logic::Zero))?;
+                    }
+                    return Ok(waveform_shard);
+                }
+            };
+            match entry {
+                VcdEntry::Scalar(bit, id) => {
+                    waveform_shard.update_vector(id, BitVector::from(bit))?
                 }
+                VcdEntry::Vector(value, id) => waveform_shard.update_vector(id, value)?,
+                VcdEntry::Real(value, id) => waveform_shard.update_real(id, value)?,
+                VcdEntry::Event(id) => {
+                    waveform_shard.update_vector(id, BitVector::new_one_bit())?;
+                    pulses.trigger(id);
+                }
+                VcdEntry::Port(value, id) => {
+                    waveform_shard.update_vector(id, value.to_bitvector())?
+                }
+                VcdEntry::Timestamp(_) | VcdEntry::String(_, _) => {}
             }
+        }));
+    }
+    // Priority changes are recorded by the dispatcher, which sees every
+    // change in order before it is handed to the shards
+    let dispatcher_handle = thread::spawn(move || {
+        // Timestamps not in a run yet, the runs not yet sent to every shard,
+        // how many runs were dropped before them and how many runs each shard
+        // has been sent
+        let mut timestamps: Vec<u64> = Vec::new();
+        let mut last = None;
+        let mut runs: Vec<Arc<[u64]>> = Vec::new();
+        let mut dropped = 0;
+        let mut sent = vec![0; tx_dispatchers.len()];
+        let mut send_runs = |shard: usize,
+                             tx_dispatcher: &mut SenderQueued<VcdShardEntry>,
+                             timestamps: &mut Vec<u64>| {
+            if !timestamps.is_empty() {
+                runs.push(std::mem::take(timestamps).into());
+            }
+            for run in &runs[sent[shard] - dropped..] {
+                tx_dispatcher
+                    .send(VcdShardEntry::Timestamps(run.clone()))
+                    .unwrap();
+            }
+            sent[shard] = dropped + runs.len();
+            let done = sent.iter().min().unwrap() - dropped;
+            runs.drain(..done);
+            dropped += done;
+        };
+        loop {
+            let Some(entry) = rx_parser.recv().unwrap() else {
+                // Every shard needs every timestamp to be combined
+                for (shard, mut tx_dispatcher) in tx_dispatchers.into_iter().enumerate() {
+                    send_runs(shard, &mut tx_dispatcher, &mut timestamps);
+                    tx_dispatcher.finish().unwrap();
+                }
+                return;
+            };
+            priority.record(&entry);
+            let id = match &entry {
+                VcdEntry::Timestamp(timestamp) => {
+                    // Repeated timestamps are merged by the waveform
+                    if last != Some(*timestamp) {
+                        timestamps.push(*timestamp);
+                        last = Some(*timestamp);
+                    }
+                    continue;
+                }
+                VcdEntry::Scalar(_, id)
+                | VcdEntry::Vector(_, id)
+                | VcdEntry::Real(_, id)
+                | VcdEntry::Event(id)
+                | VcdEntry::Port(_, id)
+                | VcdEntry::String(_, id) => *id,
+            };
+            let shard = shard_map.get(id);
+            send_runs(shard, &mut tx_dispatchers[shard], &mut timestamps);
+            tx_dispatchers[shard]
+                .send(VcdShardEntry::Change(entry))
+                .unwrap();
         }
-        let parser = parser_handle.join().unwrap()?;
+    });
+
+    thread::spawn(move || {
         dispatcher_handle.join().unwrap();
         let mut waveform_shards = Vec::new();
-        for handle in waveform_handles {
+        let shards = waveform_handles.len();
+        for (shard, handle) in waveform_handles.into_iter().enumerate() {
             waveform_shards.push(handle.join().unwrap()?);
+            if let Some(events) = &events {
+                let _ = events.send(VcdLoadEvent::ShardMerged { shard, shards });
+            }
         }
-        log::debug!("Body parsed...");
-        let waveform = Waveform::unshard(waveform_shards)?;
-        log::debug!("Shards combined...");
-        Ok((parser.into_header(), waveform))
-    };
-
-    thread::spawn(move || match loader_fn() {
-        Ok(ok) => {
-            log::debug!("VCD loaded!");
-            Ok(ok)
-        }
-        Err(err) => {
-            log::error!("VCD error: {err:?}");
-            *status_clean.lock().unwrap() = (file_size, file_size);
-            Err(err)
-        }
+        Waveform::unshard(waveform_shards)
     })
 }
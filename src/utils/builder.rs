@@ -0,0 +1,206 @@
+use bytes::Bytes;
+use makai_waveform_db::bitvector::{BitVector, Logic};
+use makai_waveform_db::Waveform;
+
+use crate::errors::ParserError;
+use crate::export::vcd::idcode_string;
+use crate::lexer::position::LexerPosition;
+use crate::parser::{split_timescale, VcdHeader, VcdReader, VcdScopeType, VcdVariableNetType};
+use crate::tokenizer::token::{Token, TokenIdCode, TokenVariableDescription};
+use crate::tokenizer::tokenize_idcode;
+use crate::utils::sink::VcdEventPulses;
+use crate::utils::VcdResult;
+
+/// Builds a header and waveform from declarations and changes pushed by the
+/// caller, like a cosimulation or an interactive stimulus tool. Everything
+/// goes through the same reader and waveform insertion as a loaded VCD, so
+/// the result cannot be told apart from a file with the same contents.
+pub struct VcdWaveformBuilder {
+    parser: VcdReader,
+    waveform: Waveform,
+    pulses: VcdEventPulses,
+    // Declarations so far, parsed all at once when the header is finished
+    header_tokens: Option<Vec<Token>>,
+    variables: usize,
+}
+
+impl Default for VcdWaveformBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VcdWaveformBuilder {
+    pub fn new() -> Self {
+        Self {
+            parser: VcdReader::new(),
+            waveform: Waveform::new(),
+            pulses: VcdEventPulses::new(),
+            header_tokens: Some(Vec::new()),
+            variables: 0,
+        }
+    }
+
+    /// Returns the reader, whose settings like transforms should be changed
+    /// before the header is finished
+    pub fn get_parser_mut(&mut self) -> &mut VcdReader {
+        &mut self.parser
+    }
+
+    fn insert_bytes(&mut self, s: &str) -> usize {
+        self.parser
+            .get_byte_storage_mut()
+            .insert(Bytes::copy_from_slice(s.as_bytes()))
+    }
+
+    fn declare(&mut self, token: Token) -> VcdResult<()> {
+        match &mut self.header_tokens {
+            Some(tokens) => {
+                tokens.push(token);
+                Ok(())
+            }
+            None => Err(ParserError::UnexpectedToken(token).into()),
+        }
+    }
+
+    pub fn set_version(&mut self, version: &str) -> VcdResult<()> {
+        let id = self.insert_bytes(version);
+        self.declare(Token::Version(id, LexerPosition::default()))
+    }
+
+    pub fn set_date(&mut self, date: &str) -> VcdResult<()> {
+        let id = self.insert_bytes(date);
+        self.declare(Token::Date(id, LexerPosition::default()))
+    }
+
+    /// Sets the timescale resolution x, where x is 10^(-x), which has to be 1,
+    /// 10 or 100 of a unit
+    pub fn set_timescale(&mut self, timescale: i32) -> VcdResult<()> {
+        let (timescale, offset) = split_timescale(timescale).ok_or_else(|| {
+            ParserError::Custom(
+                format!("timescale 10^-{} cannot be declared", timescale),
+                None,
+            )
+        })?;
+        self.declare(Token::Timescale {
+            timescale,
+            offset,
+            pos: LexerPosition::default(),
+        })
+    }
+
+    pub fn open_scope(&mut self, scope_type: VcdScopeType, name: &str) -> VcdResult<()> {
+        let scope_id = self.insert_bytes(name);
+        self.declare(Token::Scope {
+            scope_type,
+            scope_id,
+            pos: LexerPosition::default(),
+        })
+    }
+
+    pub fn close_scope(&mut self) -> VcdResult<()> {
+        self.declare(Token::UpScope(LexerPosition::default()))
+    }
+
+    /// Declares a variable in the innermost open scope, returning the idcode
+    /// its changes are pushed with
+    pub fn add_variable(
+        &mut self,
+        net_type: VcdVariableNetType,
+        width: usize,
+        name: &str,
+    ) -> VcdResult<usize> {
+        let id = self.insert_bytes(name);
+        let token_idcode = tokenize_idcode(
+            self.parser.get_byte_storage_mut(),
+            idcode_string(self.variables).as_bytes(),
+        );
+        self.declare(Token::Var {
+            net_type,
+            width,
+            token_idcode: token_idcode.clone(),
+            variable_description: TokenVariableDescription::Unspecified { id },
+            pos: LexerPosition::default(),
+        })?;
+        self.variables += 1;
+        Ok(token_idcode.get_id())
+    }
+
+    /// Ends the declarations and creates a signal for every variable, called
+    /// by the first change if it was not called before
+    pub fn finish_header(&mut self) -> VcdResult<()> {
+        let mut tokens = match self.header_tokens.take() {
+            Some(tokens) => tokens.into_iter(),
+            None => return Ok(()),
+        };
+        let mut end = Some(Token::EndDefinitions(LexerPosition::default()));
+        self.parser
+            .parse_header(&mut |_| Ok(tokens.next().or_else(|| end.take())))?;
+        self.parser
+            .get_header()
+            .initialize_waveform(&mut self.waveform);
+        Ok(())
+    }
+
+    /// Returns the header, None until it is finished
+    pub fn get_header(&self) -> Option<&VcdHeader> {
+        match self.header_tokens {
+            Some(_) => None,
+            None => Some(self.parser.get_header()),
+        }
+    }
+
+    pub fn get_waveform(&self) -> &Waveform {
+        &self.waveform
+    }
+
+    fn change(&mut self, token: Token) -> VcdResult<()> {
+        self.finish_header()?;
+        let mut token = Some(token);
+        while let Some(entry) = self.parser.parse_waveform(&mut |_| Ok(token.take()))? {
+            self.pulses.apply(&mut self.waveform, entry)?;
+        }
+        Ok(())
+    }
+
+    /// Starts a new timestamp, which cannot be earlier than the last one
+    pub fn insert_timestamp(&mut self, timestamp: u64) -> VcdResult<()> {
+        self.change(Token::Timestamp(timestamp, LexerPosition::default()))
+    }
+
+    pub fn update_scalar(&mut self, idcode: usize, bit: Logic) -> VcdResult<()> {
+        self.change(Token::ScalarValue(
+            bit,
+            TokenIdCode::new(idcode),
+            LexerPosition::default(),
+        ))
+    }
+
+    pub fn update_vector(&mut self, idcode: usize, bv: BitVector) -> VcdResult<()> {
+        self.change(Token::VectorValue(
+            bv,
+            TokenIdCode::new(idcode),
+            LexerPosition::default(),
+        ))
+    }
+
+    pub fn update_real(&mut self, idcode: usize, value: f64) -> VcdResult<()> {
+        self.change(Token::RealValue(
+            value,
+            TokenIdCode::new(idcode),
+            LexerPosition::default(),
+        ))
+    }
+
+    pub fn trigger_event(&mut self, idcode: usize) -> VcdResult<()> {
+        self.update_scalar(idcode, Logic::One)
+    }
+
+    /// Returns the header and waveform, finishing the header if no change was
+    /// ever pushed
+    pub fn into_parts(mut self) -> VcdResult<(VcdHeader, Waveform)> {
+        self.finish_header()?;
+        self.pulses.apply_finish(&mut self.waveform)?;
+        Ok((self.parser.into_header(), self.waveform))
+    }
+}
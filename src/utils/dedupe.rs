@@ -0,0 +1,126 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+use makai_waveform_db::vector::WaveformSignalVector;
+use makai_waveform_db::Waveform;
+
+use crate::parser::{VcdHeader, VcdVariableWidth};
+
+/// Idcodes found to always change identically to another idcode after a load,
+/// only one history is kept and the others are read through it
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VcdAliases {
+    // Idcode whose history was dropped, idcode holding the history
+    aliases: HashMap<usize, usize>,
+    saved: usize,
+}
+
+impl VcdAliases {
+    pub fn get_aliases(&self) -> &HashMap<usize, usize> {
+        &self.aliases
+    }
+
+    /// Returns the idcode holding the history of an idcode, itself if its
+    /// history was kept
+    pub fn resolve(&self, idcode: usize) -> usize {
+        self.aliases.get(&idcode).copied().unwrap_or(idcode)
+    }
+
+    /// Returns how many bytes of history and values were freed
+    pub fn get_saved_size(&self) -> usize {
+        self.saved
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.aliases.is_empty()
+    }
+}
+
+// Hashes the timestamp indices and bits of every change, signals with
+// different fingerprints cannot be aliases
+fn fingerprint(signal: &WaveformSignalVector) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    if !signal.is_empty() {
+        for index in signal.get_history() {
+            index.get_timestamp_index().hash(&mut hasher);
+            let bv = signal.get_bitvector(index.get_value_index());
+            for i in 0..bv.get_bit_width() {
+                usize::from(bv.get_bit(i)).hash(&mut hasher);
+            }
+        }
+    }
+    hasher.finish()
+}
+
+fn is_identical(a: &WaveformSignalVector, b: &WaveformSignalVector) -> bool {
+    if a.is_empty() || b.is_empty() {
+        return a.is_empty() && b.is_empty();
+    }
+    let mut a_history = a.get_history().into_iter();
+    let mut b_history = b.get_history().into_iter();
+    loop {
+        match (a_history.next(), b_history.next()) {
+            (None, None) => return true,
+            (Some(a_index), Some(b_index)) => {
+                if a_index.get_timestamp_index() != b_index.get_timestamp_index()
+                    || a.get_bitvector(a_index.get_value_index())
+                        != b.get_bitvector(b_index.get_value_index())
+                {
+                    return false;
+                }
+            }
+            _ => return false,
+        }
+    }
+}
+
+/// Finds vector and event signals of the same width with identical histories
+/// and drops all but the history of the lowest idcode, reals are left alone
+/// since their values cannot be read back to compare
+pub fn dedupe_aliases(header: &VcdHeader, waveform: &mut Waveform) -> VcdAliases {
+    let size = |waveform: &Waveform| waveform.get_block_size() + waveform.get_vector_size();
+    let before = size(waveform);
+    let mut idcodes: Vec<(&usize, &VcdVariableWidth)> = header
+        .get_idcodes_map()
+        .iter()
+        .filter(|(_, width)| **width != VcdVariableWidth::Real)
+        .collect();
+    idcodes.sort_unstable_by_key(|(idcode, _)| **idcode);
+    // Idcodes that kept their history, by width and fingerprint
+    let mut kept: HashMap<(bool, usize, u64), Vec<usize>> = HashMap::new();
+    let mut aliases = HashMap::new();
+    for (idcode, width) in idcodes {
+        let Some(signal) = waveform.get_vector_signal(*idcode) else {
+            continue;
+        };
+        let candidates = kept
+            .entry((
+                *width == VcdVariableWidth::Event,
+                width.get_width(),
+                fingerprint(signal),
+            ))
+            .or_default();
+        let alias_of = candidates.iter().copied().find(|other| {
+            waveform
+                .get_vector_signal(*other)
+                .is_some_and(|other| is_identical(signal, other))
+        });
+        match alias_of {
+            Some(other) => {
+                aliases.insert(*idcode, other);
+            }
+            None => candidates.push(*idcode),
+        }
+    }
+    // Replacing a signal with an empty one frees its history
+    for idcode in aliases.keys() {
+        if let Some(width) = waveform.get_vector_signal(*idcode).map(|s| s.get_width()) {
+            waveform.initialize_vector(*idcode, width);
+        }
+    }
+    VcdAliases {
+        saved: before.saturating_sub(size(waveform)),
+        aliases,
+    }
+}
@@ -0,0 +1,134 @@
+use std::sync::{Arc, Mutex};
+
+use makai_waveform_db::{Waveform, WaveformSignalResult};
+
+use crate::parser::VcdHeader;
+use crate::utils::{load_multi_threaded, load_single_threaded, VcdResult};
+
+/// Where two waveforms loaded from the same dump first differ
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VcdWaveformDifference {
+    Timestamps,
+    // The history of the signal with the idcode differs
+    Signal(usize),
+}
+
+impl std::fmt::Display for VcdWaveformDifference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Timestamps => write!(f, "timestamps"),
+            Self::Signal(idcode) => write!(f, "history of idcode {}", idcode),
+        }
+    }
+}
+
+/// A multi-threaded load that built a waveform different from the one built
+/// on a single thread
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VcdDeterminismMismatch {
+    threads: usize,
+    difference: VcdWaveformDifference,
+}
+
+impl VcdDeterminismMismatch {
+    pub fn get_threads(&self) -> usize {
+        self.threads
+    }
+
+    pub fn get_difference(&self) -> VcdWaveformDifference {
+        self.difference
+    }
+}
+
+impl std::fmt::Display for VcdDeterminismMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} threads built a different {}",
+            self.threads, self.difference
+        )
+    }
+}
+
+// Compares the histories of one signal in two waveforms, the timestamps are
+// already known to be equal so their indices are compared
+fn is_same_signal(expected: &Waveform, waveform: &Waveform, idcode: usize) -> bool {
+    match (expected.get_signal(idcode), waveform.get_signal(idcode)) {
+        (
+            Some(WaveformSignalResult::Vector(expected)),
+            Some(WaveformSignalResult::Vector(signal)),
+        ) => {
+            if expected.get_width() != signal.get_width() || expected.len() != signal.len() {
+                return false;
+            }
+            expected.is_empty()
+                || expected
+                    .get_history()
+                    .into_iter()
+                    .zip(signal.get_history())
+                    .all(|(a, b)| {
+                        a.get_timestamp_index() == b.get_timestamp_index()
+                            && expected.get_bitvector(a.get_value_index())
+                                == signal.get_bitvector(b.get_value_index())
+                    })
+        }
+        (Some(WaveformSignalResult::Real(expected)), Some(WaveformSignalResult::Real(signal))) => {
+            if expected.len() != signal.len() {
+                return false;
+            }
+            expected.is_empty()
+                || expected
+                    .get_history()
+                    .into_iter()
+                    .zip(signal.get_history())
+                    .all(|(a, b)| a.get_timestamp_index() == b.get_timestamp_index())
+        }
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+/// Compares the timestamps and the history of every declared signal of two
+/// waveforms built from the same header, returning the first difference in
+/// idcode order. Every vector change is compared bit for bit, real changes
+/// only by their timestamps since their values cannot be read back.
+pub fn compare_waveforms(
+    header: &VcdHeader,
+    expected: &Waveform,
+    waveform: &Waveform,
+) -> Option<VcdWaveformDifference> {
+    if expected.get_timestamps() != waveform.get_timestamps() {
+        return Some(VcdWaveformDifference::Timestamps);
+    }
+    let mut idcodes: Vec<usize> = header.get_idcodes_map().keys().copied().collect();
+    idcodes.sort_unstable();
+    idcodes
+        .into_iter()
+        .find(|idcode| !is_same_signal(expected, waveform, *idcode))
+        .map(VcdWaveformDifference::Signal)
+}
+
+/// Loads a VCD on one thread and then with each thread count, comparing each
+/// waveform against the single-threaded one with compare_waveforms. The
+/// multi-threaded loader guarantees identical waveforms, so any mismatch
+/// returned is a bug.
+pub fn verify_deterministic_load(
+    bytes: &str,
+    thread_counts: &[usize],
+) -> VcdResult<Vec<VcdDeterminismMismatch>> {
+    let (header, expected) = load_single_threaded(bytes.to_string(), &mut |_| {})?;
+    let mut mismatches = Vec::new();
+    for &threads in thread_counts {
+        let status = Arc::new(Mutex::new((0, 0)));
+        let (_, waveform) = load_multi_threaded(bytes.to_string(), threads, status)
+            .join()
+            .unwrap()?;
+        if let Some(difference) = compare_waveforms(&header, &expected, &waveform) {
+            mismatches.push(VcdDeterminismMismatch {
+                threads,
+                difference,
+            });
+        }
+    }
+    Ok(mismatches)
+}
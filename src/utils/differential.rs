@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+use std::iter::Peekable;
+
+use makai_waveform_db::bitvector::BitVector;
+use makai_waveform_db::Waveform;
+
+use crate::analysis::{signal_changes, vector_changes, AnalysisResult};
+use crate::parser::{VcdEntry, VcdHeader, VcdVariableWidth};
+use crate::utils::sink::VcdWaveformSink;
+use crate::utils::{load_into_sink, VcdLoadOptions, VcdResult};
+
+/// Where the history of a signal of a differential load is kept
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VcdDifferentialSource {
+    // The signal matched the baseline and is read from it under its idcode
+    Baseline(usize),
+    // The signal matched the baseline before the timestamp, its changes from
+    // the timestamp on are kept in the waveform
+    Diverged { baseline: usize, timestamp: u64 },
+    Own(usize),
+}
+
+/// Signals of a waveform whose changes were not stored because the same
+/// variable changes identically in a baseline waveform kept in memory, for
+/// the whole history or up to the first change that differs
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VcdDifferential {
+    // Idcode that stored no changes, idcode in the baseline holding them
+    shared: HashMap<usize, usize>,
+    // Idcode that stored its changes from a timestamp on, idcode in the
+    // baseline holding the ones before it
+    diverged: HashMap<usize, (usize, u64)>,
+    shared_changes: usize,
+}
+
+impl VcdDifferential {
+    pub fn get_shared(&self) -> &HashMap<usize, usize> {
+        &self.shared
+    }
+
+    pub fn get_diverged(&self) -> &HashMap<usize, (usize, u64)> {
+        &self.diverged
+    }
+
+    pub fn resolve(&self, idcode: usize) -> VcdDifferentialSource {
+        if let Some(baseline) = self.shared.get(&idcode) {
+            return VcdDifferentialSource::Baseline(*baseline);
+        }
+        match self.diverged.get(&idcode) {
+            Some((baseline, timestamp)) => VcdDifferentialSource::Diverged {
+                baseline: *baseline,
+                timestamp: *timestamp,
+            },
+            None => VcdDifferentialSource::Own(idcode),
+        }
+    }
+
+    /// Returns every change of a signal like signal_changes, read from
+    /// whichever waveform holds each part of its history
+    pub fn signal_changes(
+        &self,
+        baseline: &Waveform,
+        waveform: &Waveform,
+        idcode: usize,
+    ) -> AnalysisResult<Vec<(u64, VcdEntry)>> {
+        match self.resolve(idcode) {
+            VcdDifferentialSource::Baseline(baseline_idcode) => {
+                baseline_changes(baseline, baseline_idcode, idcode, None)
+            }
+            VcdDifferentialSource::Diverged {
+                baseline: baseline_idcode,
+                timestamp,
+            } => {
+                let mut changes =
+                    baseline_changes(baseline, baseline_idcode, idcode, Some(timestamp))?;
+                changes.extend(signal_changes(waveform, idcode)?);
+                Ok(changes)
+            }
+            VcdDifferentialSource::Own(idcode) => signal_changes(waveform, idcode),
+        }
+    }
+
+    /// Returns how many changes were read from the baseline instead of being
+    /// stored
+    pub fn get_shared_changes(&self) -> usize {
+        self.shared_changes
+    }
+}
+
+// Returns the changes of a baseline signal before a timestamp under the
+// idcode of the loaded signal
+fn baseline_changes(
+    baseline: &Waveform,
+    baseline_idcode: usize,
+    idcode: usize,
+    until: Option<u64>,
+) -> AnalysisResult<Vec<(u64, VcdEntry)>> {
+    Ok(signal_changes(baseline, baseline_idcode)?
+        .into_iter()
+        .take_while(|(timestamp, _)| until.map_or(true, |until| *timestamp < until))
+        .map(|(timestamp, entry)| match entry {
+            VcdEntry::Vector(bv, _) => (timestamp, VcdEntry::Vector(bv, idcode)),
+            entry => (timestamp, entry),
+        })
+        .collect())
+}
+
+type VcdBaselineChanges<'a> = Peekable<Box<dyn Iterator<Item = (u64, BitVector)> + 'a>>;
+
+// Compares every change against the baseline as it is loaded, a signal only
+// stores changes once it stops matching
+struct VcdDifferentialSink<'a> {
+    baseline_header: &'a VcdHeader,
+    baseline: &'a Waveform,
+    waveform: Waveform,
+    timestamp: u64,
+    // Signals still matching, with the baseline changes not matched yet
+    matching: HashMap<usize, (usize, VcdBaselineChanges<'a>)>,
+    differential: VcdDifferential,
+}
+
+impl VcdDifferentialSink<'_> {
+    // Ends the shared part of a signal at a timestamp
+    fn diverge(&mut self, idcode: usize, timestamp: u64) {
+        if let Some((baseline_idcode, _)) = self.matching.remove(&idcode) {
+            self.differential
+                .diverged
+                .insert(idcode, (baseline_idcode, timestamp));
+        }
+    }
+}
+
+impl<'a> VcdWaveformSink for VcdDifferentialSink<'a> {
+    fn initialize(&mut self, header: &VcdHeader) -> VcdResult<()> {
+        header.initialize_waveform(&mut self.waveform);
+        let (baseline_header, baseline): (&'a VcdHeader, &'a Waveform) =
+            (self.baseline_header, self.baseline);
+        // Reals are always kept since their values cannot be read back
+        for (path, variable) in header.get_variables_with_paths() {
+            let idcode = variable.get_idcode();
+            if *variable.get_width() == VcdVariableWidth::Real
+                || self.matching.contains_key(&idcode)
+            {
+                continue;
+            }
+            let baseline_variable = match baseline_header.get_variable(&path) {
+                Some(baseline_variable)
+                    if baseline_variable.get_width() == variable.get_width() =>
+                {
+                    baseline_variable
+                }
+                _ => continue,
+            };
+            let baseline_idcode = baseline_variable.get_idcode();
+            if let Some(signal) = baseline.get_vector_signal(baseline_idcode) {
+                let changes: Box<dyn Iterator<Item = (u64, BitVector)> + 'a> =
+                    Box::new(vector_changes(baseline, signal));
+                self.matching
+                    .insert(idcode, (baseline_idcode, changes.peekable()));
+            }
+        }
+        Ok(())
+    }
+
+    fn insert_timestamp(&mut self, timestamp: u64) -> VcdResult<()> {
+        self.timestamp = timestamp;
+        Ok(self.waveform.insert_timestamp(timestamp)?)
+    }
+
+    fn update_vector(&mut self, idcode: usize, bv: BitVector) -> VcdResult<()> {
+        if let Some((_, changes)) = self.matching.get_mut(&idcode) {
+            // The shared part ends at the first change only one of the two
+            // waveforms has
+            let diverged = match changes.peek() {
+                Some((timestamp, value)) if *timestamp == self.timestamp && *value == bv => None,
+                Some((timestamp, _)) => Some(self.timestamp.min(*timestamp)),
+                None => Some(self.timestamp),
+            };
+            match diverged {
+                Some(timestamp) => self.diverge(idcode, timestamp),
+                None => {
+                    changes.next();
+                    self.differential.shared_changes += 1;
+                    return Ok(());
+                }
+            }
+        }
+        Ok(self.waveform.update_vector(idcode, bv)?)
+    }
+
+    fn update_real(&mut self, idcode: usize, value: f64) -> VcdResult<()> {
+        Ok(self.waveform.update_real(idcode, value)?)
+    }
+
+    fn finish(&mut self) -> VcdResult<()> {
+        // Baseline changes left over are changes this load does not have
+        let idcodes: Vec<usize> = self.matching.keys().copied().collect();
+        for idcode in idcodes {
+            let (baseline_idcode, changes) = self.matching.get_mut(&idcode).unwrap();
+            match changes.peek() {
+                Some((timestamp, _)) => {
+                    let timestamp = *timestamp;
+                    self.diverge(idcode, timestamp);
+                }
+                None => {
+                    let baseline_idcode = *baseline_idcode;
+                    self.matching.remove(&idcode);
+                    self.differential.shared.insert(idcode, baseline_idcode);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Loads a VCD on the calling thread, comparing every vector and event
+/// change against the variable at the same path in a baseline already in
+/// memory. Changes are only stored once a signal stops matching the
+/// baseline, so signals identical to the baseline or to the start of its
+/// history never take up memory.
+pub fn load_differential(
+    bytes: String,
+    options: VcdLoadOptions,
+    baseline_header: &VcdHeader,
+    baseline: &Waveform,
+    status: &mut dyn FnMut((usize, usize)),
+) -> VcdResult<(VcdHeader, Waveform, VcdDifferential)> {
+    let mut sink = VcdDifferentialSink {
+        baseline_header,
+        baseline,
+        waveform: Waveform::new(),
+        timestamp: 0,
+        matching: HashMap::new(),
+        differential: VcdDifferential::default(),
+    };
+    let header = load_into_sink(&bytes, &mut sink, options, status)?;
+    Ok((header, sink.waveform, sink.differential))
+}
@@ -0,0 +1,148 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+
+use makai_waveform_db::Waveform;
+
+use crate::parser::{VcdHeader, VcdVariableWidth};
+
+/// Decides which shard of the multi-threaded loader stores each signal,
+/// signals on the same shard are built by the same thread and stay together
+/// until the shards are combined
+pub trait VcdDispatchStrategy: Send {
+    /// Returns the shard of every idcode, idcodes left out go to the shard
+    /// given by the idcode modulo the number of shards
+    fn assign(&mut self, header: &VcdHeader, shards: usize) -> HashMap<usize, usize>;
+}
+
+/// Assigns idcodes by modulo, like the loader does by default
+#[derive(Clone, Copy, Debug, Default)]
+pub struct VcdHashDispatch;
+
+impl VcdDispatchStrategy for VcdHashDispatch {
+    fn assign(&mut self, header: &VcdHeader, shards: usize) -> HashMap<usize, usize> {
+        header
+            .get_idcodes_map()
+            .keys()
+            .map(|idcode| (*idcode, idcode % shards))
+            .collect()
+    }
+}
+
+/// Deals idcodes out to shards one at a time from the most to the least
+/// active, so the busiest signals are spread across shards. Activity can come
+/// from a VcdIndex or an earlier load of the same design.
+#[derive(Clone, Debug, Default)]
+pub struct VcdActivityDispatch {
+    change_counts: HashMap<usize, usize>,
+}
+
+impl VcdActivityDispatch {
+    pub fn new(change_counts: HashMap<usize, usize>) -> Self {
+        Self { change_counts }
+    }
+}
+
+impl VcdDispatchStrategy for VcdActivityDispatch {
+    fn assign(&mut self, header: &VcdHeader, shards: usize) -> HashMap<usize, usize> {
+        let mut idcodes: Vec<(usize, usize)> = header
+            .get_idcodes_map()
+            .keys()
+            .map(|idcode| {
+                let count = self.change_counts.get(idcode).copied().unwrap_or(0);
+                (*idcode, count)
+            })
+            .collect();
+        // Ties are broken by idcode so the assignment is deterministic
+        idcodes.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        idcodes
+            .into_iter()
+            .enumerate()
+            .map(|(i, (idcode, _))| (idcode, i % shards))
+            .collect()
+    }
+}
+
+/// Keeps the variables of a scope together, each scope goes to the shard
+/// with the fewest variables so far, largest scopes first
+#[derive(Clone, Copy, Debug, Default)]
+pub struct VcdScopeDispatch;
+
+impl VcdDispatchStrategy for VcdScopeDispatch {
+    fn assign(&mut self, header: &VcdHeader, shards: usize) -> HashMap<usize, usize> {
+        let mut scopes: HashMap<String, Vec<usize>> = HashMap::new();
+        for (path, variable) in header.get_variables_with_paths() {
+            let scope = match path.rsplit_once('.') {
+                Some((scope, _)) => scope.to_string(),
+                None => String::new(),
+            };
+            scopes.entry(scope).or_default().push(variable.get_idcode());
+        }
+        let mut scopes: Vec<(String, Vec<usize>)> = scopes.into_iter().collect();
+        scopes.sort_unstable_by(|a, b| b.1.len().cmp(&a.1.len()).then(a.0.cmp(&b.0)));
+        let mut sizes = vec![0; shards];
+        let mut assignment = HashMap::new();
+        for (_, idcodes) in scopes {
+            let shard = (0..shards).min_by_key(|shard| sizes[*shard]).unwrap_or(0);
+            for idcode in idcodes {
+                // Aliased variables keep the shard of their first scope
+                if let Entry::Vacant(entry) = assignment.entry(idcode) {
+                    entry.insert(shard);
+                    sizes[shard] += 1;
+                }
+            }
+        }
+        assignment
+    }
+}
+
+// The shard of every idcode, the modulo of the idcode unless a strategy says
+// otherwise
+pub(crate) struct VcdShardMap {
+    shards: usize,
+    table: Option<HashMap<usize, usize>>,
+}
+
+impl VcdShardMap {
+    pub(crate) fn new(
+        header: &VcdHeader,
+        shards: usize,
+        strategy: Option<&mut (dyn VcdDispatchStrategy + 'static)>,
+    ) -> Self {
+        let shards = shards.max(1);
+        let table = strategy.map(|strategy| {
+            let mut table = strategy.assign(header, shards);
+            table.values_mut().for_each(|shard| *shard %= shards);
+            table
+        });
+        Self { shards, table }
+    }
+
+    #[inline]
+    pub(crate) fn get(&self, idcode: usize) -> usize {
+        match &self.table {
+            Some(table) => table.get(&idcode).copied().unwrap_or(idcode % self.shards),
+            None => idcode % self.shards,
+        }
+    }
+
+    /// Creates one waveform per shard holding the signals assigned to it
+    pub(crate) fn split(&self, header: &VcdHeader) -> Vec<Waveform> {
+        if self.table.is_none() {
+            let mut waveform = Waveform::new();
+            header.initialize_waveform(&mut waveform);
+            return waveform.shard(self.shards);
+        }
+        let mut shards: Vec<Waveform> = (0..self.shards).map(|_| Waveform::new()).collect();
+        for (idcode, width) in header.get_idcodes_map() {
+            let shard = &mut shards[self.get(*idcode)];
+            match width {
+                VcdVariableWidth::Vector { width: 0 } => {}
+                VcdVariableWidth::Vector { width } => shard.initialize_vector(*idcode, *width),
+                VcdVariableWidth::Real => shard.initialize_real(*idcode),
+                VcdVariableWidth::Event => shard.initialize_vector(*idcode, 1),
+                VcdVariableWidth::String => {}
+            }
+        }
+        shards
+    }
+}
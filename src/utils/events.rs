@@ -0,0 +1,156 @@
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+
+use crate::parser::{VcdEntry, VcdReader, VcdScope};
+
+/// What a load has done or noticed, sent as it happens so one subscription
+/// can drive a status display and a log, see VcdLoadOptions::set_events
+#[derive(Clone, Debug, PartialEq)]
+pub enum VcdLoadEvent {
+    HeaderParsed { variables: usize, scopes: usize },
+    BodyStarted,
+    // A problem tolerated in the input, also counted by the parser
+    WarningRaised(String),
+    // A shard of a multi-threaded load is done and about to be combined
+    ShardMerged { shard: usize, shards: usize },
+    Finished { stats: VcdLoadStats },
+}
+
+/// Totals of a load or validation that finished
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VcdLoadStats {
+    bytes: usize,
+    timestamps: usize,
+    changes: usize,
+    warnings: usize,
+    elided: usize,
+    elapsed: Duration,
+}
+
+impl VcdLoadStats {
+    // Takes the counts only the parser knows from it
+    pub(crate) fn new(
+        bytes: usize,
+        timestamps: usize,
+        changes: usize,
+        parser: &VcdReader,
+        start: Instant,
+    ) -> Self {
+        Self {
+            bytes,
+            timestamps,
+            changes,
+            warnings: parser.get_warning_count(),
+            elided: parser.get_elided_count(),
+            elapsed: start.elapsed(),
+        }
+    }
+
+    /// Returns the size of the file, or how far a stream was read up to its
+    /// last token
+    pub fn get_bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn get_timestamps(&self) -> usize {
+        self.timestamps
+    }
+
+    /// Returns every value change handed to the waveform
+    pub fn get_changes(&self) -> usize {
+        self.changes
+    }
+
+    pub fn get_warnings(&self) -> usize {
+        self.warnings
+    }
+
+    pub fn get_elided(&self) -> usize {
+        self.elided
+    }
+
+    pub fn get_elapsed(&self) -> Duration {
+        self.elapsed
+    }
+}
+
+// Returns the variables and scopes declared in scopes and those under them
+fn count_scopes(scopes: &[VcdScope]) -> (usize, usize) {
+    scopes.iter().fold((0, 0), |(variables, count), scope| {
+        let (nested_variables, nested) = count_scopes(scope.get_scopes());
+        (
+            variables + scope.get_variables().len() + nested_variables,
+            count + 1 + nested,
+        )
+    })
+}
+
+// Sends the events of one load, counting the entries only if there is anyone
+// listening. A receiver that hung up does not fail the load.
+pub(crate) struct VcdEventRecorder {
+    events: Option<Sender<VcdLoadEvent>>,
+    start: Instant,
+    timestamps: usize,
+    changes: usize,
+}
+
+impl VcdEventRecorder {
+    pub(crate) fn new(events: Option<Sender<VcdLoadEvent>>) -> Self {
+        Self {
+            events,
+            start: Instant::now(),
+            timestamps: 0,
+            changes: 0,
+        }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.events.is_some()
+    }
+
+    #[cfg(feature = "parallel")]
+    pub(crate) fn get_sender(&self) -> Option<Sender<VcdLoadEvent>> {
+        self.events.clone()
+    }
+
+    pub(crate) fn send(&self, event: VcdLoadEvent) {
+        if let Some(events) = &self.events {
+            let _ = events.send(event);
+        }
+    }
+
+    pub(crate) fn header(&self, parser: &mut VcdReader) {
+        if self.is_enabled() {
+            self.warnings(parser);
+            let (variables, scopes) = count_scopes(parser.get_header().get_scopes());
+            self.send(VcdLoadEvent::HeaderParsed { variables, scopes });
+            self.send(VcdLoadEvent::BodyStarted);
+        }
+    }
+
+    #[inline]
+    pub(crate) fn record(&mut self, entry: &VcdEntry, parser: &mut VcdReader) {
+        if self.is_enabled() {
+            match entry {
+                VcdEntry::Timestamp(_) => self.timestamps += 1,
+                _ => self.changes += 1,
+            }
+            self.warnings(parser);
+        }
+    }
+
+    // Sends the warnings the parser raised since the last call
+    pub(crate) fn warnings(&self, parser: &mut VcdReader) {
+        for message in parser.take_warning_messages() {
+            self.send(VcdLoadEvent::WarningRaised(message));
+        }
+    }
+
+    pub(crate) fn finish(&self, parser: &mut VcdReader, bytes: usize) {
+        if self.is_enabled() {
+            self.warnings(parser);
+            let stats = VcdLoadStats::new(bytes, self.timestamps, self.changes, parser, self.start);
+            self.send(VcdLoadEvent::Finished { stats });
+        }
+    }
+}
@@ -0,0 +1,467 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use std::thread;
+
+use makai::utils::bytes::ByteStorage;
+use makai_waveform_db::bitvector::{BitVector, Logic};
+use makai_waveform_db::Waveform;
+
+use crate::errors::*;
+use crate::lexer::{Lexer, LexerToken};
+use crate::parser::{VcdEntry, VcdHeader, VcdReader};
+use crate::tokenizer::token::{Token, TokenIdCode, TokenIdCodeEncoding};
+use crate::tokenizer::{short_idcode, tokenize_timestamp, Tokenizer};
+use crate::utils::logging::VcdLoadLog;
+use crate::utils::sink::VcdEventPulses;
+use crate::utils::{load_single_threaded_with_options, VcdLoadOptions, VcdResult};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VcdIndexTimestamp {
+    timestamp: u64,
+    offset: usize,
+    changes_before: usize,
+}
+
+impl VcdIndexTimestamp {
+    pub fn get_timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    /// Returns the byte offset of the timestamp in the source
+    pub fn get_offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Returns the number of value changes in the body before the timestamp
+    pub fn get_changes_before(&self) -> usize {
+        self.changes_before
+    }
+}
+
+// A region of the body that can be parsed on its own, with the number of
+// timestamps and the number of changes of every shard it produces at most
+struct VcdIndexRegion {
+    range: Range<usize>,
+    timestamps: usize,
+    shard_changes: Vec<usize>,
+}
+
+// Splits the body into up to count regions of similar size while it is
+// scanned. Regions only start at a timestamp greater than the one before it
+// so no region continues a timestamp from the previous region.
+struct VcdRegionPlanner {
+    count: usize,
+    body_start: usize,
+    body_size: usize,
+    last_timestamp: Option<u64>,
+    regions: Vec<VcdIndexRegion>,
+}
+
+impl VcdRegionPlanner {
+    fn new(count: usize, shards: usize, body_start: usize, file_size: usize) -> Self {
+        Self {
+            count,
+            body_start,
+            body_size: file_size - body_start,
+            last_timestamp: None,
+            regions: vec![VcdIndexRegion {
+                range: body_start..file_size,
+                timestamps: 0,
+                shard_changes: vec![0; shards],
+            }],
+        }
+    }
+
+    fn timestamp(&mut self, timestamp: u64, offset: usize) {
+        let target = self.body_start + self.body_size * self.regions.len() / self.count;
+        let increasing = self.last_timestamp.is_some_and(|last| timestamp > last);
+        self.last_timestamp = Some(timestamp);
+        if self.regions.len() < self.count && increasing && offset >= target {
+            let region = self.regions.last_mut().unwrap();
+            let end = std::mem::replace(&mut region.range.end, offset);
+            let shards = region.shard_changes.len();
+            self.regions.push(VcdIndexRegion {
+                range: offset..end,
+                timestamps: 0,
+                shard_changes: vec![0; shards],
+            });
+        }
+        self.regions.last_mut().unwrap().timestamps += 1;
+    }
+
+    fn change(&mut self, idcode: usize) {
+        let shard_changes = &mut self.regions.last_mut().unwrap().shard_changes;
+        let shards = shard_changes.len();
+        shard_changes[idcode % shards] += 1;
+    }
+}
+
+// The entries parsed from a region, the changes are split by the shard that
+// builds their signal and kept with the number of the region's timestamps
+// before them
+struct VcdParsedRegion {
+    timestamps: Vec<u64>,
+    shards: Vec<Vec<(usize, VcdEntry)>>,
+}
+
+/// The location of every timestamp and the number of changes of every signal
+/// in a VCD body, found by lexing the body without storing any values
+#[derive(Clone, Debug, Default)]
+pub struct VcdIndex {
+    body_start: usize,
+    timestamps: Vec<VcdIndexTimestamp>,
+    change_counts: HashMap<usize, usize>,
+    total_changes: usize,
+    // Idcodes too long to pack into a usize, by their source bytes
+    storage_idcodes: HashMap<Vec<u8>, usize>,
+}
+
+impl VcdIndex {
+    /// Parses the header and scans the body of a VCD
+    pub fn scan(bytes: &str) -> VcdResult<(VcdHeader, Self)> {
+        let (reader, index) = Self::scan_with_options(bytes, &mut VcdLoadOptions::new())?;
+        Ok((reader.into_header(), index))
+    }
+
+    /// Parses the header with the parser and lexer options of a load and
+    /// scans the body, returning the reader the header was parsed with
+    pub(crate) fn scan_with_options(
+        bytes: &str,
+        options: &mut VcdLoadOptions,
+    ) -> VcdResult<(VcdReader, Self)> {
+        let (reader, index, _) = scan_internal(bytes, &Tokenizer::new(bytes), options, 1, 1)?;
+        Ok((reader, index))
+    }
+
+    /// Returns the byte offset just after $enddefinitions
+    pub fn get_body_start(&self) -> usize {
+        self.body_start
+    }
+
+    pub fn get_timestamps(&self) -> &Vec<VcdIndexTimestamp> {
+        &self.timestamps
+    }
+
+    /// Returns the number of value changes of an idcode, including values
+    /// like event resets that are not stored
+    pub fn get_change_count(&self, idcode: usize) -> usize {
+        self.change_counts.get(&idcode).copied().unwrap_or(0)
+    }
+
+    pub fn get_change_counts(&self) -> &HashMap<usize, usize> {
+        &self.change_counts
+    }
+
+    pub fn get_total_changes(&self) -> usize {
+        self.total_changes
+    }
+
+    /// Returns the id of an idcode written in the body, None if it is too
+    /// long to be packed into an id and was not declared
+    pub(crate) fn lookup_idcode(&self, idcode_bytes: &[u8]) -> Option<usize> {
+        match short_idcode(idcode_bytes) {
+            Some(idcode) => Some(idcode),
+            None => self.storage_idcodes.get(idcode_bytes).copied(),
+        }
+    }
+}
+
+// Parses the header and scans the body, splitting it into up to count
+// regions with the changes of each counted by shard
+fn scan_internal(
+    bytes: &str,
+    tokenizer: &Tokenizer,
+    options: &mut VcdLoadOptions,
+    count: usize,
+    shards: usize,
+) -> VcdResult<(VcdReader, VcdIndex, Vec<VcdIndexRegion>)> {
+    let mut lexer = Lexer::new(bytes);
+    options.configure_lexer(&mut lexer);
+    let mut parser = VcdReader::new();
+    options.configure(&mut parser);
+    let mut header_tokenizer = tokenizer.clone();
+    parser.parse_header(&mut |bs| header_tokenizer.next(lexer.next_token()?, bs))?;
+    let storage_idcodes = parser
+        .get_idcodes()
+        .into_iter()
+        .filter(|(_, encoding, _)| *encoding == TokenIdCodeEncoding::Storage)
+        .map(|(idcode, _, _)| {
+            let bytes = TokenIdCode::new(idcode).get_bytes(parser.get_byte_storage());
+            (bytes, idcode)
+        })
+        .collect();
+    let mut index = VcdIndex {
+        body_start: lexer.checkpoint().get_index(),
+        storage_idcodes,
+        ..Default::default()
+    };
+    let mut planner = VcdRegionPlanner::new(count, shards, index.body_start, bytes.len());
+    while let Some(lexer_token) = lexer.next_token()? {
+        if let LexerToken::Timestamp(span, pos) = &lexer_token {
+            let timestamp = tokenize_timestamp(&tokenizer.get_bytes(span.clone()), *pos)?;
+            planner.timestamp(timestamp, pos.get_index());
+            index.timestamps.push(VcdIndexTimestamp {
+                timestamp,
+                offset: pos.get_index(),
+                changes_before: index.total_changes,
+            });
+        } else if let Some(idcode_bytes) = tokenizer.get_idcode_bytes(&lexer_token)? {
+            if let Some(idcode) = index.lookup_idcode(idcode_bytes) {
+                *index.change_counts.entry(idcode).or_default() += 1;
+                planner.change(idcode);
+            }
+            index.total_changes += 1;
+        }
+    }
+    Ok((parser, index, planner.regions))
+}
+
+// Replaces an idcode kept in a region's own byte storage with the id it was
+// given when the header was parsed
+fn remap_idcode(idcode: TokenIdCode, bs: &ByteStorage, index: &VcdIndex) -> TokenIdCode {
+    if idcode.get_encoding() != TokenIdCodeEncoding::Storage {
+        return idcode;
+    }
+    match index.storage_idcodes.get(&idcode.get_bytes(bs)) {
+        Some(id) => TokenIdCode::new(*id),
+        None => idcode,
+    }
+}
+
+// Remaps the idcode of a value change token parsed after the header
+pub(crate) fn remap_token(
+    token: Option<Token>,
+    bs: &ByteStorage,
+    index: &VcdIndex,
+) -> Option<Token> {
+    match token {
+        Some(Token::ScalarValue(bit, idcode, pos)) => Some(Token::ScalarValue(
+            bit,
+            remap_idcode(idcode, bs, index),
+            pos,
+        )),
+        Some(Token::VectorValue(bv, idcode, pos)) => {
+            Some(Token::VectorValue(bv, remap_idcode(idcode, bs, index), pos))
+        }
+        Some(Token::RealValue(value, idcode, pos)) => Some(Token::RealValue(
+            value,
+            remap_idcode(idcode, bs, index),
+            pos,
+        )),
+        Some(Token::StringValue(value, idcode, pos)) => Some(Token::StringValue(
+            value,
+            remap_idcode(idcode, bs, index),
+            pos,
+        )),
+        Some(Token::PortValue(value, idcode, pos)) => Some(Token::PortValue(
+            value,
+            remap_idcode(idcode, bs, index),
+            pos,
+        )),
+        token => token,
+    }
+}
+
+fn parse_region(
+    lexer: &Lexer,
+    tokenizer: &Tokenizer,
+    header_parser: &VcdReader,
+    index: &VcdIndex,
+    region: &VcdIndexRegion,
+) -> VcdResult<VcdParsedRegion> {
+    let mut lexer = lexer.fork_at(region.range.start);
+    let mut tokenizer = tokenizer.clone();
+    let mut parser = header_parser.new_region();
+    let mut parsed = VcdParsedRegion {
+        timestamps: Vec::with_capacity(region.timestamps),
+        shards: region
+            .shard_changes
+            .iter()
+            .map(|changes| Vec::with_capacity(*changes))
+            .collect(),
+    };
+    let shards = parsed.shards.len();
+    let mut next_token = |bs: &mut ByteStorage| -> TokenizerResult<Option<Token>> {
+        let lexer_token = match lexer.next_token()? {
+            Some(lexer_token) if lexer_token.get_position().get_index() < region.range.end => {
+                lexer_token
+            }
+            _ => return Ok(None),
+        };
+        let token = tokenizer.next(Some(lexer_token), bs)?;
+        Ok(remap_token(token, bs, index))
+    };
+    while let Some(entry) = parser.parse_waveform(&mut next_token)? {
+        let id = match &entry {
+            VcdEntry::Timestamp(timestamp) => {
+                parsed.timestamps.push(*timestamp);
+                continue;
+            }
+            VcdEntry::String(_, _) => continue,
+            VcdEntry::Scalar(_, id)
+            | VcdEntry::Vector(_, id)
+            | VcdEntry::Real(_, id)
+            | VcdEntry::Event(id)
+            | VcdEntry::Port(_, id) => *id,
+        };
+        parsed.shards[id % shards].push((parsed.timestamps.len(), entry));
+    }
+    Ok(parsed)
+}
+
+// Inserts a change into the shard that builds its signal
+fn insert_change(
+    shard: &mut Waveform,
+    pulses: &mut VcdEventPulses,
+    entry: VcdEntry,
+) -> VcdResult<()> {
+    match entry {
+        VcdEntry::Scalar(bit, id) => shard.update_vector(id, bit.into())?,
+        VcdEntry::Vector(bv, id) => shard.update_vector(id, bv)?,
+        VcdEntry::Real(value, id) => shard.update_real(id, value)?,
+        VcdEntry::Event(id) => {
+            shard.update_vector(id, BitVector::new_one_bit())?;
+            pulses.trigger(id);
+        }
+        VcdEntry::Port(value, id) => shard.update_vector(id, value.to_bitvector())?,
+        VcdEntry::Timestamp(_) | VcdEntry::String(_, _) => {}
+    }
+    Ok(())
+}
+
+/// Loads a VCD in two passes, the first finds where every timestamp is and
+/// counts the changes of every shard, the second parses regions of the body
+/// in parallel into one buffer per shard sized from those counts. Every
+/// shard then inserts its own buffers in parallel.
+pub fn load_indexed(
+    bytes: String,
+    threads: usize,
+    status: &mut dyn FnMut((usize, usize)),
+) -> VcdResult<(VcdHeader, Waveform)> {
+    load_indexed_with_options(bytes, threads, VcdLoadOptions::new(), status)
+}
+
+/// Loads a VCD in two passes with the options that change how the input is
+/// read. Transforms, token middleware and idle skipping carry state across
+/// the whole body, so a load with any of them falls back to
+/// load_single_threaded_with_options.
+pub fn load_indexed_with_options(
+    bytes: String,
+    threads: usize,
+    mut options: VcdLoadOptions,
+    status: &mut dyn FnMut((usize, usize)),
+) -> VcdResult<(VcdHeader, Waveform)> {
+    if !options.transforms.is_empty() || !options.token_middlewares.is_empty() || options.idle_skip
+    {
+        return load_single_threaded_with_options(bytes, options, status);
+    }
+    let log = VcdLoadLog::new(options.verbosity, None);
+    log.debug("Loading VCD (indexed)...");
+    match load_indexed_internal(&bytes, threads.max(1), &mut options, &log, status) {
+        Ok(ok) => {
+            log.debug("VCD loaded!");
+            Ok(ok)
+        }
+        Err(err) => {
+            log.error(Some(&bytes), &err);
+            Err(err)
+        }
+    }
+}
+
+fn load_indexed_internal(
+    bytes: &str,
+    threads: usize,
+    options: &mut VcdLoadOptions,
+    log: &VcdLoadLog,
+    status: &mut dyn FnMut((usize, usize)),
+) -> VcdResult<(VcdHeader, Waveform)> {
+    let file_size = bytes.len();
+    let tokenizer = Tokenizer::new(bytes);
+    let (parser, index, regions) = scan_internal(bytes, &tokenizer, options, threads, threads)?;
+    log.debug(&format!(
+        "Body scanned, {} timestamps and {} changes...",
+        index.timestamps.len(),
+        index.total_changes
+    ));
+    status((index.body_start, file_size));
+
+    let mut lexer = Lexer::new(bytes);
+    options.configure_lexer(&mut lexer);
+    // Regions are forked from this lexer and only cover the body, which the
+    // header limit does not cover
+    lexer.set_block_limit(None);
+    let mut parsed = Vec::new();
+    thread::scope(|scope| {
+        let handles: Vec<_> = regions
+            .iter()
+            .map(|region| {
+                let (lexer, tokenizer, parser, index) = (&lexer, &tokenizer, &parser, &index);
+                scope.spawn(move || parse_region(lexer, tokenizer, parser, index, region))
+            })
+            .collect();
+        for (handle, region) in handles.into_iter().zip(&regions) {
+            parsed.push(handle.join().unwrap());
+            status((region.range.end, file_size));
+        }
+    });
+    let parsed = parsed
+        .into_iter()
+        .collect::<VcdResult<Vec<VcdParsedRegion>>>()?;
+    log.debug(&format!("Body parsed in {} regions...", parsed.len()));
+
+    // Every shard takes its part of every region, the timestamps are shared
+    let mut parts: Vec<Vec<Vec<(usize, VcdEntry)>>> = (0..threads)
+        .map(|_| Vec::with_capacity(parsed.len()))
+        .collect();
+    let mut timestamps = Vec::with_capacity(parsed.len());
+    for region in parsed {
+        timestamps.push(region.timestamps);
+        for (part, changes) in parts.iter_mut().zip(region.shards) {
+            part.push(changes);
+        }
+    }
+    let header = parser.into_header();
+    let mut waveform = Waveform::new();
+    header.initialize_waveform(&mut waveform);
+    let shards = waveform.shard(threads);
+    let shards = thread::scope(|scope| {
+        let handles: Vec<_> = shards
+            .into_iter()
+            .zip(parts)
+            .map(|(mut shard, part)| {
+                let timestamps = &timestamps;
+                scope.spawn(move || {
+                    let mut pulses = VcdEventPulses::new();
+                    for (timestamps, changes) in timestamps.iter().zip(part) {
+                        let mut changes = changes.into_iter().peekable();
+                        for before in 0..=timestamps.len() {
+                            while let Some((_, entry)) =
+                                changes.next_if(|(count, _)| *count == before)
+                            {
+                                insert_change(&mut shard, &mut pulses, entry)?;
+                            }
+                            if let Some(timestamp) = timestamps.get(before) {
+                                for id in pulses.release() {
+                                    shard.update_vector(id, BitVector::from(Logic::Zero))?;
+                                }
+                                shard.insert_timestamp(*timestamp)?;
+                            }
+                        }
+                    }
+                    for id in pulses.finish() {
+                        shard.update_vector(id, BitVector::from(Logic::Zero))?;
+                    }
+                    Ok(shard)
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect::<VcdResult<Vec<Waveform>>>()
+    })?;
+    let waveform = Waveform::unshard(shards)?;
+    log.debug("Shards combined...");
+    Ok((header, waveform))
+}
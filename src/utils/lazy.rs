@@ -0,0 +1,208 @@
+use std::collections::HashSet;
+
+use makai::utils::bytes::ByteStorage;
+use makai_waveform_db::bitvector::{BitVector, Logic};
+use makai_waveform_db::{Waveform, WaveformSignalResult};
+
+use crate::errors::*;
+use crate::lexer::Lexer;
+use crate::parser::transform::VcdTransforms;
+use crate::parser::{VcdEntry, VcdHeader, VcdReader, VcdScope, VcdVariableWidth};
+use crate::tokenizer::token::Token;
+use crate::tokenizer::Tokenizer;
+use crate::utils::indexed::{remap_token, VcdIndex};
+use crate::utils::sink::VcdEventPulses;
+use crate::utils::{VcdLoadOptions, VcdResult};
+
+fn scope_idcodes(scope: &VcdScope, idcodes: &mut Vec<usize>) {
+    idcodes.extend(scope.get_variables().iter().map(|v| v.get_idcode()));
+    for child in scope.get_scopes() {
+        scope_idcodes(child, idcodes);
+    }
+}
+
+// Inserts the changes a region reader parses from the body into a shard,
+// events are released a timestamp after they trigger
+fn load_changes<F>(
+    parser: &mut VcdReader,
+    next_token: &mut F,
+    shard: &mut Waveform,
+) -> VcdResult<()>
+where
+    F: FnMut(&mut ByteStorage) -> TokenizerResult<Option<Token>>,
+{
+    let mut pulses = VcdEventPulses::new();
+    while let Some(entry) = parser.parse_waveform(next_token)? {
+        match entry {
+            VcdEntry::Timestamp(timestamp) => {
+                for idcode in pulses.release() {
+                    shard.update_vector(idcode, BitVector::from(Logic::Zero))?;
+                }
+                shard.insert_timestamp(timestamp)?;
+            }
+            VcdEntry::Scalar(bit, idcode) => shard.update_vector(idcode, bit.into())?,
+            VcdEntry::Vector(bv, idcode) => shard.update_vector(idcode, bv)?,
+            VcdEntry::Real(value, idcode) => shard.update_real(idcode, value)?,
+            VcdEntry::Event(idcode) => {
+                shard.update_vector(idcode, BitVector::new_one_bit())?;
+                pulses.trigger(idcode);
+            }
+            VcdEntry::Port(value, idcode) => shard.update_vector(idcode, value.to_bitvector())?,
+            VcdEntry::String(_, _) => {}
+        }
+    }
+    for idcode in pulses.finish() {
+        shard.update_vector(idcode, BitVector::from(Logic::Zero))?;
+    }
+    Ok(())
+}
+
+/// A waveform that starts out with only the header and timestamps, the
+/// histories of signals are loaded on first access by scanning the body again
+/// for just their changes. The source can be anything holding the text, such
+/// as a memory-mapped file.
+pub struct VcdLazyWaveform<B: AsRef<str>> {
+    bytes: B,
+    // The reader the header was parsed with, which every scan of the body
+    // takes its settings from
+    reader: VcdReader,
+    transforms: VcdTransforms,
+    // What is left of the options once the reader took its settings, to set
+    // up the lexer of every scan
+    options: VcdLoadOptions,
+    index: VcdIndex,
+    waveform: Waveform,
+    loaded: HashSet<usize>,
+}
+
+impl<B: AsRef<str>> VcdLazyWaveform<B> {
+    /// Parses the header and indexes the body without loading any values
+    pub fn new(bytes: B) -> VcdResult<Self> {
+        Self::with_options(bytes, VcdLoadOptions::new())
+    }
+
+    /// Parses the header and indexes the body with the parser and lexer
+    /// options of a load, the transforms are applied as signals are loaded.
+    /// Token middleware and idle skipping only apply to the header since the
+    /// body is scanned one set of signals at a time and the timestamps come
+    /// from the index.
+    pub fn with_options(bytes: B, mut options: VcdLoadOptions) -> VcdResult<Self> {
+        let (mut reader, index) = VcdIndex::scan_with_options(bytes.as_ref(), &mut options)?;
+        let transforms = reader.take_transforms();
+        let mut waveform = Waveform::new();
+        for timestamp in index.get_timestamps() {
+            waveform.insert_timestamp(timestamp.get_timestamp())?;
+        }
+        Ok(Self {
+            bytes,
+            reader,
+            transforms,
+            options,
+            index,
+            waveform,
+            loaded: HashSet::new(),
+        })
+    }
+
+    pub fn get_header(&self) -> &VcdHeader {
+        self.reader.get_header()
+    }
+
+    pub fn get_index(&self) -> &VcdIndex {
+        &self.index
+    }
+
+    /// Returns the waveform with every timestamp but only the signals loaded
+    /// so far
+    pub fn get_waveform(&self) -> &Waveform {
+        &self.waveform
+    }
+
+    pub fn is_loaded(&self, idcode: usize) -> bool {
+        self.loaded.contains(&idcode)
+    }
+
+    /// Loads the histories of idcodes that are not loaded yet with a single
+    /// scan of the body, undeclared idcodes are ignored
+    pub fn load_idcodes(&mut self, idcodes: &[usize]) -> VcdResult<()> {
+        let widths = self.reader.get_header().get_idcodes_map();
+        let wanted: HashSet<usize> = idcodes
+            .iter()
+            .copied()
+            .filter(|idcode| !self.loaded.contains(idcode) && widths.contains_key(idcode))
+            .collect();
+        if wanted.is_empty() {
+            return Ok(());
+        }
+        let mut shard = Waveform::new();
+        for idcode in &wanted {
+            match widths[idcode] {
+                VcdVariableWidth::Vector { width: 0 } => {}
+                VcdVariableWidth::Vector { width } => shard.initialize_vector(*idcode, width),
+                VcdVariableWidth::Real => shard.initialize_real(*idcode),
+                VcdVariableWidth::Event => shard.initialize_vector(*idcode, 1),
+                VcdVariableWidth::String => {}
+            }
+        }
+
+        let bytes = self.bytes.as_ref();
+        let index = &self.index;
+        let mut lexer = Lexer::new_at(bytes, index.get_body_start());
+        self.options.configure_lexer(&mut lexer);
+        // Only the body is lexed, which the header limit does not cover
+        lexer.set_block_limit(None);
+        let mut tokenizer = Tokenizer::new(bytes);
+        let mut parser = self.reader.new_region();
+        parser.set_transforms(std::mem::take(&mut self.transforms));
+        // Changes of other signals are skipped before their values are read
+        let mut next_token = |bs: &mut ByteStorage| -> TokenizerResult<Option<Token>> {
+            while let Some(lexer_token) = lexer.next_token()? {
+                if let Some(idcode_bytes) = tokenizer.get_idcode_bytes(&lexer_token)? {
+                    match index.lookup_idcode(idcode_bytes) {
+                        Some(idcode) if wanted.contains(&idcode) => {}
+                        _ => continue,
+                    }
+                }
+                let token = tokenizer.next(Some(lexer_token), bs)?;
+                return Ok(remap_token(token, bs, index));
+            }
+            Ok(None)
+        };
+        let result = load_changes(&mut parser, &mut next_token, &mut shard);
+        self.transforms = parser.take_transforms();
+        result?;
+
+        let waveform = std::mem::take(&mut self.waveform);
+        self.waveform = Waveform::unshard(vec![waveform, shard])?;
+        self.loaded.extend(wanted);
+        Ok(())
+    }
+
+    /// Loads the variable at a path, returns false if it is not declared
+    pub fn load_variable(&mut self, path: &str) -> VcdResult<bool> {
+        let idcode = match self.get_header().get_variable(path) {
+            Some(variable) => variable.get_idcode(),
+            None => return Ok(false),
+        };
+        self.load_idcodes(&[idcode])?;
+        Ok(true)
+    }
+
+    /// Loads every variable in a scope and the scopes below it, returns false
+    /// if the scope is not declared
+    pub fn load_scope(&mut self, path: &str) -> VcdResult<bool> {
+        let mut idcodes = Vec::new();
+        match self.get_header().get_scope(path) {
+            Some(scope) => scope_idcodes(scope, &mut idcodes),
+            None => return Ok(false),
+        }
+        self.load_idcodes(&idcodes)?;
+        Ok(true)
+    }
+
+    /// Returns a signal, loading it first if needed
+    pub fn get_signal(&mut self, idcode: usize) -> VcdResult<Option<WaveformSignalResult<'_>>> {
+        self.load_idcodes(&[idcode])?;
+        Ok(self.waveform.get_signal(idcode))
+    }
+}
@@ -0,0 +1,76 @@
+use std::time::{Duration, Instant};
+
+use crate::utils::{log_error, VcdError};
+
+/// How much the loaders log, so applications with their own progress and
+/// error reporting can make loads silent without configuring a logger
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum VcdVerbosity {
+    Quiet,
+    // Only the error that fails a load
+    Errors,
+    // Errors and the problems tolerated in the input
+    Warnings,
+    // Everything, including the stages of a load at debug level and progress
+    #[default]
+    Normal,
+}
+
+// Logs the stages, progress and error of one load at its verbosity, progress
+// is logged at most once per interval
+pub(crate) struct VcdLoadLog {
+    verbosity: VcdVerbosity,
+    interval: Option<Duration>,
+    last: Instant,
+}
+
+impl VcdLoadLog {
+    pub(crate) fn new(verbosity: VcdVerbosity, interval: Option<Duration>) -> Self {
+        Self {
+            verbosity,
+            interval,
+            last: Instant::now(),
+        }
+    }
+
+    pub(crate) fn has_warnings(&self) -> bool {
+        self.verbosity >= VcdVerbosity::Warnings
+    }
+
+    pub(crate) fn debug(&self, message: &str) {
+        if self.verbosity >= VcdVerbosity::Normal {
+            log::debug!("{}", message);
+        }
+    }
+
+    // Logs the error of a load, with the line and column if the source is
+    // still around
+    pub(crate) fn error(&self, bytes: Option<&str>, err: &VcdError) {
+        if self.verbosity >= VcdVerbosity::Errors {
+            match bytes {
+                Some(bytes) => log_error(bytes, err),
+                None => log::error!("VCD error: {err:?}"),
+            }
+        }
+    }
+
+    // Logs how far a load is, the size of a stream is not known
+    pub(crate) fn progress(&mut self, index: usize, size: Option<usize>) {
+        let Some(interval) = self.interval else {
+            return;
+        };
+        if self.verbosity < VcdVerbosity::Normal || self.last.elapsed() < interval {
+            return;
+        }
+        self.last = Instant::now();
+        match size {
+            Some(size) => log::info!(
+                "Loaded {} of {} bytes ({}%)",
+                index,
+                size,
+                index * 100 / size.max(1)
+            ),
+            None => log::info!("Loaded {} bytes", index),
+        }
+    }
+}
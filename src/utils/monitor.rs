@@ -0,0 +1,305 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use makai_waveform_db::bitvector::{BitVector, Logic};
+
+use crate::parser::{VcdEntry, VcdHeader};
+use crate::utils::logging::VcdLoadLog;
+
+/// A temporal property checked against the changes of a load as they are
+/// parsed, times are in timestamps of the dump
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VcdMonitor {
+    // Every time the trigger becomes asserted the response must become
+    // asserted at most within timestamps later
+    Response {
+        trigger: String,
+        response: String,
+        within: u64,
+    },
+    // The signal must not hold unknown or high-impedance bits from after on
+    NeverUnknown {
+        path: String,
+        after: u64,
+    },
+}
+
+impl std::fmt::Display for VcdMonitor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Response {
+                trigger,
+                response,
+                within,
+            } => write!(
+                f,
+                "{} must be followed by {} within {}",
+                trigger, response, within
+            ),
+            Self::NeverUnknown { path, after } => {
+                write!(f, "{} must not be x or z after {}", path, after)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VcdViolation {
+    monitor: usize,
+    timestamp: u64,
+}
+
+impl VcdViolation {
+    /// Returns the index of the violated monitor in the order it was added
+    pub fn get_monitor(&self) -> usize {
+        self.monitor
+    }
+
+    /// Returns when the violation started, the trigger of a response monitor
+    /// or the first unknown value of a never unknown monitor
+    pub fn get_timestamp(&self) -> u64 {
+        self.timestamp
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct VcdMonitorState {
+    asserted: bool,
+    // Trigger timestamps still waiting on a response
+    pending: VecDeque<u64>,
+    armed: bool,
+    unknown: bool,
+    // Set when the monitor arms so the value it holds is checked once every
+    // change at that timestamp has been seen
+    check_held: Option<u64>,
+}
+
+/// Monitors evaluated while a load is running, without a second pass over
+/// the waveform. Violations are filled in when the load ends.
+#[derive(Clone, Debug, Default)]
+pub struct VcdMonitors {
+    monitors: Vec<VcdMonitor>,
+    states: Vec<VcdMonitorState>,
+    // Monitor indices by the idcodes they watch, with the role of the idcode
+    idcodes: HashMap<usize, Vec<(usize, bool)>>,
+    violations: Vec<VcdViolation>,
+    timestamp: u64,
+}
+
+fn is_asserted(bv: &BitVector) -> bool {
+    let mut asserted = false;
+    for bit in bv.iter() {
+        match bit {
+            Logic::Zero => {}
+            Logic::One => asserted = true,
+            Logic::Unknown | Logic::HighImpedance => return false,
+        }
+    }
+    asserted
+}
+
+fn is_unknown(bv: &BitVector) -> bool {
+    bv.iter()
+        .any(|bit| matches!(bit, Logic::Unknown | Logic::HighImpedance))
+}
+
+impl VcdMonitors {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a monitor and returns its index
+    pub fn add_monitor(&mut self, monitor: VcdMonitor) -> usize {
+        self.monitors.push(monitor);
+        self.monitors.len() - 1
+    }
+
+    pub fn get_monitors(&self) -> &Vec<VcdMonitor> {
+        &self.monitors
+    }
+
+    pub fn get_violations(&self) -> &Vec<VcdViolation> {
+        &self.violations
+    }
+
+    /// Resolves the paths of the monitors to idcodes, monitors watching a
+    /// path that is not declared are ignored, with a warning if enabled
+    pub fn resolve(&mut self, header: &VcdHeader, warnings: bool) {
+        let idcode = |path: &str| {
+            let idcode = header.get_variable(path).map(|v| v.get_idcode());
+            if idcode.is_none() && warnings {
+                log::warn!("Ignoring monitor of undeclared variable {}", path);
+            }
+            idcode
+        };
+        self.states = vec![VcdMonitorState::default(); self.monitors.len()];
+        self.idcodes.clear();
+        for (index, monitor) in self.monitors.iter().enumerate() {
+            let watched = match monitor {
+                VcdMonitor::Response {
+                    trigger, response, ..
+                } => match (idcode(trigger), idcode(response)) {
+                    (Some(trigger), Some(response)) => vec![(trigger, true), (response, false)],
+                    _ => continue,
+                },
+                VcdMonitor::NeverUnknown { path, .. } => match idcode(path) {
+                    Some(idcode) => vec![(idcode, true)],
+                    None => continue,
+                },
+            };
+            for (idcode, role) in watched {
+                self.idcodes.entry(idcode).or_default().push((index, role));
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.idcodes.is_empty()
+    }
+
+    fn violate(&mut self, monitor: usize, timestamp: u64) {
+        self.violations.push(VcdViolation { monitor, timestamp });
+    }
+
+    // Checks deadlines and held values before time moves on to timestamp
+    fn advance(&mut self, timestamp: u64) {
+        for index in 0..self.monitors.len() {
+            let state = &mut self.states[index];
+            let expired = match &self.monitors[index] {
+                VcdMonitor::Response { within, .. } => {
+                    let mut expired = Vec::new();
+                    while let Some(trigger) = state.pending.front() {
+                        if trigger.saturating_add(*within) >= timestamp {
+                            break;
+                        }
+                        expired.push(*trigger);
+                        state.pending.pop_front();
+                    }
+                    expired
+                }
+                VcdMonitor::NeverUnknown { after, .. } => {
+                    let mut expired = Vec::new();
+                    if let (Some(armed_at), true) = (state.check_held.take(), state.unknown) {
+                        expired.push(armed_at);
+                    }
+                    if !state.armed && timestamp >= *after {
+                        state.armed = true;
+                        state.check_held = Some(timestamp);
+                    }
+                    expired
+                }
+            };
+            for trigger in expired {
+                self.violate(index, trigger);
+            }
+        }
+        self.timestamp = timestamp;
+    }
+
+    fn change(&mut self, index: usize, role: bool, asserted: bool, unknown: bool) {
+        let timestamp = self.timestamp;
+        let state = &mut self.states[index];
+        match &self.monitors[index] {
+            VcdMonitor::Response { .. } if role => {
+                if asserted && !state.asserted {
+                    state.pending.push_back(timestamp);
+                }
+                state.asserted = asserted;
+            }
+            VcdMonitor::Response { .. } => {
+                if asserted {
+                    state.pending.clear();
+                }
+            }
+            VcdMonitor::NeverUnknown { .. } => {
+                let was_unknown = std::mem::replace(&mut state.unknown, unknown);
+                // The held value is checked later, a change at the arming
+                // timestamp replaces it
+                if state.armed && state.check_held.is_none() && unknown && !was_unknown {
+                    self.violate(index, timestamp);
+                }
+            }
+        }
+    }
+
+    /// Evaluates the monitors against a parsed entry
+    pub fn record(&mut self, entry: &VcdEntry) {
+        let (idcode, asserted, unknown) = match entry {
+            VcdEntry::Timestamp(timestamp) => {
+                self.advance(*timestamp);
+                return;
+            }
+            VcdEntry::Scalar(bit, idcode) => (
+                *idcode,
+                *bit == Logic::One,
+                matches!(bit, Logic::Unknown | Logic::HighImpedance),
+            ),
+            VcdEntry::Vector(bv, idcode) => (*idcode, is_asserted(bv), is_unknown(bv)),
+            // Events are only asserted at the instant they are triggered
+            VcdEntry::Event(idcode) => (*idcode, true, false),
+            VcdEntry::Port(value, idcode) => {
+                let bv = value.to_bitvector();
+                (*idcode, is_asserted(&bv), is_unknown(&bv))
+            }
+            VcdEntry::Real(_, _) | VcdEntry::String(_, _) => return,
+        };
+        let Some(watchers) = self.idcodes.get(&idcode) else {
+            return;
+        };
+        for (index, role) in watchers.clone() {
+            self.change(index, role, asserted, unknown);
+        }
+    }
+
+    /// Finishes checking values held at the last timestamp, triggers still
+    /// waiting on a response when the dump ends are not violations
+    pub fn finish(&mut self) {
+        for index in 0..self.monitors.len() {
+            let state = &mut self.states[index];
+            if let (Some(armed_at), true) = (state.check_held.take(), state.unknown) {
+                self.violate(index, armed_at);
+            }
+        }
+        self.violations
+            .sort_by_key(|violation| (violation.timestamp, violation.monitor));
+    }
+}
+
+// Evaluates monitors on a loader thread without taking the lock per entry,
+// the monitors are taken out of the shared structure once the header is
+// parsed and put back with their violations when the recorder is dropped
+pub(crate) struct VcdMonitorRecorder {
+    shared: Option<Arc<Mutex<VcdMonitors>>>,
+    monitors: VcdMonitors,
+}
+
+impl VcdMonitorRecorder {
+    pub(crate) fn new(
+        shared: Option<Arc<Mutex<VcdMonitors>>>,
+        header: &VcdHeader,
+        log: &VcdLoadLog,
+    ) -> Self {
+        let mut monitors = match &shared {
+            Some(shared) => std::mem::take(&mut *shared.lock().unwrap()),
+            None => VcdMonitors::new(),
+        };
+        monitors.resolve(header, log.has_warnings());
+        Self { shared, monitors }
+    }
+
+    #[inline]
+    pub(crate) fn record(&mut self, entry: &VcdEntry) {
+        if !self.monitors.is_empty() {
+            self.monitors.record(entry);
+        }
+    }
+}
+
+impl Drop for VcdMonitorRecorder {
+    fn drop(&mut self) {
+        if let Some(shared) = &self.shared {
+            self.monitors.finish();
+            *shared.lock().unwrap() = std::mem::take(&mut self.monitors);
+        }
+    }
+}
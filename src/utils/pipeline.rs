@@ -0,0 +1,469 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{self, BufRead, Write};
+
+use makai::utils::bytes::ByteStorage;
+
+use crate::errors::TokenizerResult;
+use crate::export::csv::VcdCsvExporter;
+use crate::export::ndjson::VcdJsonExporter;
+use crate::export::vcd::{write_entry, write_header};
+use crate::lexer::stream::StreamTokenizer;
+use crate::lexer::Lexer;
+use crate::parser::transform::VcdTransformFn;
+use crate::parser::{VcdEntry, VcdHeader, VcdReader, VcdVariableWidth};
+use crate::tokenizer::token::Token;
+use crate::tokenizer::Tokenizer;
+use crate::utils::{log_error, VcdResult};
+
+/// A step of a pipeline, it receives entries in the order they are parsed
+/// and hands the entries it keeps to the next step
+pub trait VcdPipelineStage {
+    /// Called once the header is parsed, before any entry
+    fn start(&mut self, _header: &VcdHeader) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called right after start, to change the header seen by the steps
+    /// after this one, like the header an export step writes
+    fn rewrite_header(&mut self, _header: &mut VcdHeader) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn process(&mut self, entry: VcdEntry, out: &mut Vec<VcdEntry>) -> io::Result<()>;
+
+    /// Called after the last entry, to hand on anything still buffered
+    fn finish(&mut self, _out: &mut Vec<VcdEntry>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn get_idcode(entry: &VcdEntry) -> Option<usize> {
+    match entry {
+        VcdEntry::Timestamp(_) => None,
+        VcdEntry::Scalar(_, idcode)
+        | VcdEntry::Vector(_, idcode)
+        | VcdEntry::Real(_, idcode)
+        | VcdEntry::Event(idcode)
+        | VcdEntry::Port(_, idcode)
+        | VcdEntry::String(_, idcode) => Some(*idcode),
+    }
+}
+
+struct VcdFilterStage<F: Fn(&str) -> bool> {
+    filter: F,
+    idcodes: HashSet<usize>,
+    // Timestamps are held back until a change after them is kept
+    timestamp: Option<u64>,
+    // Whether the variables filtered out are removed from the header too
+    prune: bool,
+}
+
+impl<F: Fn(&str) -> bool> VcdPipelineStage for VcdFilterStage<F> {
+    fn start(&mut self, header: &VcdHeader) -> io::Result<()> {
+        self.idcodes = header
+            .get_variables_with_paths()
+            .into_iter()
+            .filter(|(path, _)| (self.filter)(path))
+            .map(|(_, variable)| variable.get_idcode())
+            .collect();
+        Ok(())
+    }
+
+    fn rewrite_header(&mut self, header: &mut VcdHeader) -> io::Result<()> {
+        if self.prune {
+            header.retain_variables(&self.filter);
+        }
+        Ok(())
+    }
+
+    fn process(&mut self, entry: VcdEntry, out: &mut Vec<VcdEntry>) -> io::Result<()> {
+        match get_idcode(&entry) {
+            None => {
+                if let VcdEntry::Timestamp(timestamp) = entry {
+                    self.timestamp = Some(timestamp);
+                }
+            }
+            Some(idcode) if self.idcodes.contains(&idcode) => {
+                if let Some(timestamp) = self.timestamp.take() {
+                    out.push(VcdEntry::Timestamp(timestamp));
+                }
+                out.push(entry);
+            }
+            Some(_) => {}
+        }
+        Ok(())
+    }
+}
+
+// Samples changes at multiples of a tick like export::downsample, buffering
+// only the changes since the last tick
+struct VcdDecimateStage {
+    tick: u64,
+    sample: Option<u64>,
+    changes: BTreeMap<usize, VcdEntry>,
+    last: HashMap<usize, VcdEntry>,
+    widths: HashMap<usize, VcdVariableWidth>,
+}
+
+impl VcdDecimateStage {
+    fn flush(&mut self, out: &mut Vec<VcdEntry>) {
+        let Some(sample) = self.sample else {
+            return;
+        };
+        let mut timestamp = Some(VcdEntry::Timestamp(sample));
+        for (idcode, entry) in std::mem::take(&mut self.changes) {
+            let is_event = self.widths.get(&idcode) == Some(&VcdVariableWidth::Event);
+            if !is_event && self.last.get(&idcode) == Some(&entry) {
+                continue;
+            }
+            out.extend(timestamp.take());
+            self.last.insert(idcode, entry.clone());
+            out.push(entry);
+        }
+    }
+}
+
+impl VcdPipelineStage for VcdDecimateStage {
+    fn start(&mut self, header: &VcdHeader) -> io::Result<()> {
+        self.widths = header.get_idcodes_map().clone();
+        Ok(())
+    }
+
+    fn process(&mut self, entry: VcdEntry, out: &mut Vec<VcdEntry>) -> io::Result<()> {
+        match (get_idcode(&entry), &entry) {
+            (None, VcdEntry::Timestamp(timestamp)) => {
+                let sample = timestamp.div_ceil(self.tick).saturating_mul(self.tick);
+                if self.sample != Some(sample) {
+                    self.flush(out);
+                    self.sample = Some(sample);
+                }
+            }
+            (Some(idcode), _) => {
+                // Changes before the first timestamp belong to time zero
+                self.sample.get_or_insert(0);
+                self.changes.insert(idcode, entry);
+            }
+            (None, _) => {}
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, out: &mut Vec<VcdEntry>) -> io::Result<()> {
+        self.flush(out);
+        Ok(())
+    }
+}
+
+struct VcdRenameScopeStage {
+    path: String,
+    name: String,
+}
+
+impl VcdPipelineStage for VcdRenameScopeStage {
+    fn rewrite_header(&mut self, header: &mut VcdHeader) -> io::Result<()> {
+        match header.get_scope_mut(&self.path) {
+            Some(scope) => scope.set_name(&self.name),
+            None => log::warn!("Cannot rename missing scope {}", self.path),
+        }
+        Ok(())
+    }
+
+    fn process(&mut self, entry: VcdEntry, out: &mut Vec<VcdEntry>) -> io::Result<()> {
+        out.push(entry);
+        Ok(())
+    }
+}
+
+struct VcdRemapStage<F: Fn(usize) -> usize> {
+    remap: F,
+    // Idcodes changed since the last timestamp, merged variables can only
+    // change once per timestamp
+    changed: HashSet<usize>,
+}
+
+impl<F: Fn(usize) -> usize> VcdPipelineStage for VcdRemapStage<F> {
+    fn rewrite_header(&mut self, header: &mut VcdHeader) -> io::Result<()> {
+        header.remap_idcodes(&self.remap);
+        Ok(())
+    }
+
+    fn process(&mut self, entry: VcdEntry, out: &mut Vec<VcdEntry>) -> io::Result<()> {
+        let remap = &self.remap;
+        let entry = match entry {
+            VcdEntry::Timestamp(_) => {
+                self.changed.clear();
+                entry
+            }
+            VcdEntry::Scalar(bit, idcode) => VcdEntry::Scalar(bit, remap(idcode)),
+            VcdEntry::Vector(bv, idcode) => VcdEntry::Vector(bv, remap(idcode)),
+            VcdEntry::Real(value, idcode) => VcdEntry::Real(value, remap(idcode)),
+            VcdEntry::Event(idcode) => VcdEntry::Event(remap(idcode)),
+            VcdEntry::Port(value, idcode) => VcdEntry::Port(value, remap(idcode)),
+            VcdEntry::String(value, idcode) => VcdEntry::String(value, remap(idcode)),
+        };
+        match get_idcode(&entry) {
+            Some(idcode) if !self.changed.insert(idcode) => {}
+            _ => out.push(entry),
+        }
+        Ok(())
+    }
+}
+
+struct VcdMapStage {
+    transform: VcdTransformFn,
+}
+
+impl VcdPipelineStage for VcdMapStage {
+    fn process(&mut self, entry: VcdEntry, out: &mut Vec<VcdEntry>) -> io::Result<()> {
+        out.push(match entry {
+            VcdEntry::Timestamp(_) => entry,
+            entry => (self.transform)(entry),
+        });
+        Ok(())
+    }
+}
+
+// The exporters need the header, so they are created once the pipeline starts
+pub(crate) enum VcdExportStage<W: Write> {
+    Csv(Option<W>, Option<VcdCsvExporter<W>>),
+    Json(Option<W>, Option<VcdJsonExporter<W>>),
+    Vcd(W, HashMap<usize, String>, HashMap<usize, VcdVariableWidth>),
+}
+
+impl<W: Write> VcdExportStage<W> {
+    pub(crate) fn get_writer(&self) -> Option<&W> {
+        match self {
+            Self::Csv(Some(writer), _) | Self::Json(Some(writer), _) | Self::Vcd(writer, _, _) => {
+                Some(writer)
+            }
+            Self::Csv(_, Some(exporter)) => Some(exporter.get_writer()),
+            Self::Json(_, Some(exporter)) => Some(exporter.get_writer()),
+            _ => None,
+        }
+    }
+}
+
+impl<W: Write> VcdPipelineStage for VcdExportStage<W> {
+    fn start(&mut self, header: &VcdHeader) -> io::Result<()> {
+        match self {
+            Self::Csv(writer, exporter) => {
+                if let Some(writer) = writer.take() {
+                    *exporter = Some(VcdCsvExporter::new(header, writer)?);
+                }
+            }
+            Self::Json(writer, exporter) => {
+                if let Some(writer) = writer.take() {
+                    *exporter = Some(VcdJsonExporter::new(header, writer));
+                }
+            }
+            Self::Vcd(writer, idcodes, widths) => {
+                *idcodes = write_header(header, writer)?;
+                *widths = header.get_idcodes_map().clone();
+            }
+        }
+        Ok(())
+    }
+
+    fn process(&mut self, entry: VcdEntry, out: &mut Vec<VcdEntry>) -> io::Result<()> {
+        match self {
+            Self::Csv(_, Some(exporter)) => exporter.write_entry(&entry)?,
+            Self::Json(_, Some(exporter)) => exporter.write_entry(&entry)?,
+            Self::Vcd(writer, idcodes, widths) => match get_idcode(&entry) {
+                Some(idcode) => {
+                    if let (Some(width), Some(id)) = (widths.get(&idcode), idcodes.get(&idcode)) {
+                        write_entry(&entry, width, id, writer)?;
+                    }
+                }
+                // Timestamps are written without a width or idcode
+                None => write_entry(&entry, &VcdVariableWidth::Event, "", writer)?,
+            },
+            _ => {}
+        }
+        out.push(entry);
+        Ok(())
+    }
+
+    fn finish(&mut self, _out: &mut Vec<VcdEntry>) -> io::Result<()> {
+        match self {
+            Self::Csv(_, Some(exporter)) => exporter.flush(),
+            Self::Json(_, Some(exporter)) => exporter.flush(),
+            Self::Vcd(writer, _, _) => writer.flush(),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A chain of steps run over the entries of a VCD as it is parsed, without
+/// building a waveform, for example filtering signals, sampling them at a
+/// coarser tick and writing them out as CSV. Use run_reader to stream a file
+/// through it without reading it into memory first.
+#[derive(Default)]
+pub struct VcdPipeline<'a> {
+    stages: Vec<Box<dyn VcdPipelineStage + 'a>>,
+    // Entries are handed from step to step between these two, which are
+    // reused for every entry
+    entries: Vec<VcdEntry>,
+    out: Vec<VcdEntry>,
+}
+
+impl<'a> VcdPipeline<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a custom step
+    pub fn stage(mut self, stage: Box<dyn VcdPipelineStage + 'a>) -> Self {
+        self.stages.push(stage);
+        self
+    }
+
+    /// Keeps only the changes of variables whose full path passes the filter,
+    /// timestamps without any kept changes are dropped
+    pub fn filter<F: Fn(&str) -> bool + 'a>(self, filter: F) -> Self {
+        self.stage(Box::new(VcdFilterStage {
+            filter,
+            idcodes: HashSet::new(),
+            timestamp: None,
+            prune: false,
+        }))
+    }
+
+    /// Drops the variables whose full path passes the filter, both their
+    /// changes and their declarations in the header of later export steps,
+    /// for trimming a dump down without loading it
+    pub fn drop_signals<F: Fn(&str) -> bool + 'a>(self, filter: F) -> Self {
+        self.stage(Box::new(VcdFilterStage {
+            filter: move |path: &str| !filter(path),
+            idcodes: HashSet::new(),
+            timestamp: None,
+            prune: true,
+        }))
+    }
+
+    /// Renames the scope at a path in the header of later export steps
+    pub fn rename_scope(self, path: &str, name: &str) -> Self {
+        self.stage(Box::new(VcdRenameScopeStage {
+            path: path.to_string(),
+            name: name.to_string(),
+        }))
+    }
+
+    /// Changes the idcode of every variable and value change, variables
+    /// mapped to the same idcode are merged into one signal that keeps the
+    /// first of their changes in each timestamp
+    pub fn remap_idcodes<F: Fn(usize) -> usize + 'a>(self, remap: F) -> Self {
+        self.stage(Box::new(VcdRemapStage {
+            remap,
+            changed: HashSet::new(),
+        }))
+    }
+
+    /// Samples every signal at multiples of tick, see export::downsample
+    pub fn decimate(self, tick: u64) -> Self {
+        self.stage(Box::new(VcdDecimateStage {
+            tick: tick.max(1),
+            sample: None,
+            changes: BTreeMap::new(),
+            last: HashMap::new(),
+            widths: HashMap::new(),
+        }))
+    }
+
+    /// Replaces every value change with the result of a transform
+    pub fn map(self, transform: VcdTransformFn) -> Self {
+        self.stage(Box::new(VcdMapStage { transform }))
+    }
+
+    /// Writes the changes reaching this step as CSV, they are still handed on
+    /// to the next step, the writer can be a mutable reference
+    pub fn export_csv<W: Write + 'a>(self, writer: W) -> Self {
+        self.stage(Box::new(VcdExportStage::Csv(Some(writer), None)))
+    }
+
+    /// Writes the changes reaching this step as newline-delimited JSON
+    pub fn export_ndjson<W: Write + 'a>(self, writer: W) -> Self {
+        self.stage(Box::new(VcdExportStage::Json(Some(writer), None)))
+    }
+
+    /// Writes the changes reaching this step as a VCD with the same header
+    pub fn export_vcd<W: Write + 'a>(self, writer: W) -> Self {
+        self.stage(Box::new(VcdExportStage::Vcd(
+            writer,
+            HashMap::new(),
+            HashMap::new(),
+        )))
+    }
+
+    // Runs the pending entries through the steps from the given one on
+    fn push(&mut self, from: usize) -> io::Result<()> {
+        let (entries, out) = (&mut self.entries, &mut self.out);
+        for stage in &mut self.stages[from..] {
+            for entry in entries.drain(..) {
+                stage.process(entry, out)?;
+            }
+            std::mem::swap(entries, out);
+        }
+        entries.clear();
+        Ok(())
+    }
+
+    /// Parses a VCD as it is read and runs every entry through the steps,
+    /// returning the header as parsed, before any step rewrote it, once the
+    /// whole body has been processed. Only a window of the input is held in
+    /// memory, so this is how a pipeline runs over a file on disk.
+    pub fn run_reader<R: BufRead>(self, reader: R) -> VcdResult<VcdHeader> {
+        log::debug!("Running VCD pipeline (streaming)...");
+        let mut tokenizer = StreamTokenizer::new(reader);
+        match self.run_internal(&mut |bs| tokenizer.next(bs)) {
+            Ok(header) => {
+                log::debug!("VCD pipeline done!");
+                Ok(header)
+            }
+            Err(err) => {
+                // The source is gone, so only the position can be reported
+                log::error!("VCD error: {err:?}");
+                Err(err)
+            }
+        }
+    }
+
+    /// Same as run_reader over a VCD already in memory
+    pub fn run(self, bytes: &str) -> VcdResult<VcdHeader> {
+        log::debug!("Running VCD pipeline...");
+        let mut lexer = Lexer::new(bytes);
+        let mut tokenizer = Tokenizer::new(bytes);
+        match self.run_internal(&mut |bs| tokenizer.next(lexer.next_token()?, bs)) {
+            Ok(header) => {
+                log::debug!("VCD pipeline done!");
+                Ok(header)
+            }
+            Err(err) => {
+                log_error(bytes, &err);
+                Err(err)
+            }
+        }
+    }
+
+    fn run_internal<F>(mut self, next: &mut F) -> VcdResult<VcdHeader>
+    where
+        F: FnMut(&mut ByteStorage) -> TokenizerResult<Option<Token>>,
+    {
+        let mut parser = VcdReader::new();
+        parser.parse_header(next)?;
+        // Each step sees the header as rewritten by the steps before it
+        let mut header = parser.get_header().clone();
+        for stage in &mut self.stages {
+            stage.start(&header)?;
+            stage.rewrite_header(&mut header)?;
+        }
+        while let Some(entry) = parser.parse_waveform(next)? {
+            self.entries.push(entry);
+            self.push(0)?;
+        }
+        for index in 0..self.stages.len() {
+            self.stages[index].finish(&mut self.entries)?;
+            self.push(index + 1)?;
+        }
+        Ok(parser.into_header())
+    }
+}
@@ -0,0 +1,137 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use crate::parser::{VcdEntry, VcdHeader};
+use crate::utils::logging::VcdLoadLog;
+
+/// Change histories of a few signals that are filled in while a load is
+/// still running, so they can be shown before the whole waveform is ready
+#[derive(Clone, Debug, Default)]
+pub struct VcdPrioritySignals {
+    // Requested paths and the idcode they resolved to once the header is read
+    paths: HashMap<String, Option<usize>>,
+    histories: HashMap<usize, Vec<(u64, VcdEntry)>>,
+    header_parsed: bool,
+    complete: bool,
+}
+
+impl VcdPrioritySignals {
+    pub fn new(paths: &[&str]) -> Self {
+        Self {
+            paths: paths.iter().map(|path| (path.to_string(), None)).collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Resolves the requested paths to idcodes and returns the idcodes to
+    /// record, paths that are not declared are ignored, with a warning if
+    /// enabled
+    pub fn resolve(&mut self, header: &VcdHeader, warnings: bool) -> HashSet<usize> {
+        for (path, idcode) in &mut self.paths {
+            *idcode = header
+                .get_variable(path)
+                .map(|variable| variable.get_idcode());
+            match idcode {
+                Some(idcode) => {
+                    self.histories.entry(*idcode).or_default();
+                }
+                None if warnings => log::warn!("Ignoring undeclared priority signal {}", path),
+                None => {}
+            }
+        }
+        self.header_parsed = true;
+        self.histories.keys().copied().collect()
+    }
+
+    /// Records a change of a resolved signal at the given timestamp
+    pub fn record(&mut self, timestamp: u64, entry: VcdEntry) {
+        let idcode = match &entry {
+            VcdEntry::Timestamp(_) => return,
+            VcdEntry::Scalar(_, idcode)
+            | VcdEntry::Vector(_, idcode)
+            | VcdEntry::Real(_, idcode)
+            | VcdEntry::Event(idcode)
+            | VcdEntry::Port(_, idcode)
+            | VcdEntry::String(_, idcode) => *idcode,
+        };
+        // Histories hold the same vectors as the waveform
+        if let Some(history) = self.histories.get_mut(&idcode) {
+            history.push((timestamp, entry.into_vector()));
+        }
+    }
+
+    pub fn set_complete(&mut self) {
+        self.complete = true;
+    }
+
+    /// Returns true once the header is parsed and histories start filling
+    pub fn is_header_parsed(&self) -> bool {
+        self.header_parsed
+    }
+
+    /// Returns true once the load has finished, successfully or not
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    pub fn get_idcode(&self, path: &str) -> Option<usize> {
+        self.paths.get(path).copied().flatten()
+    }
+
+    /// Returns the changes of a priority signal received so far
+    pub fn get_history(&self, path: &str) -> Option<&Vec<(u64, VcdEntry)>> {
+        self.histories.get(&self.get_idcode(path)?)
+    }
+}
+
+// Forwards the changes of priority signals from a loader thread, only taking
+// the lock for the few changes that are actually recorded
+pub(crate) struct VcdPriorityRecorder {
+    signals: Option<Arc<Mutex<VcdPrioritySignals>>>,
+    idcodes: HashSet<usize>,
+    timestamp: u64,
+}
+
+impl VcdPriorityRecorder {
+    pub(crate) fn new(
+        signals: Option<Arc<Mutex<VcdPrioritySignals>>>,
+        header: &VcdHeader,
+        log: &VcdLoadLog,
+    ) -> Self {
+        let idcodes = match &signals {
+            Some(signals) => signals.lock().unwrap().resolve(header, log.has_warnings()),
+            None => HashSet::new(),
+        };
+        Self {
+            signals,
+            idcodes,
+            timestamp: 0,
+        }
+    }
+
+    pub(crate) fn record(&mut self, entry: &VcdEntry) {
+        match entry {
+            VcdEntry::Timestamp(timestamp) => self.timestamp = *timestamp,
+            VcdEntry::Scalar(_, idcode)
+            | VcdEntry::Vector(_, idcode)
+            | VcdEntry::Real(_, idcode)
+            | VcdEntry::Event(idcode)
+            | VcdEntry::Port(_, idcode)
+            | VcdEntry::String(_, idcode) => {
+                if let (true, Some(signals)) = (self.idcodes.contains(idcode), &self.signals) {
+                    signals
+                        .lock()
+                        .unwrap()
+                        .record(self.timestamp, entry.clone());
+                }
+            }
+        }
+    }
+}
+
+// Marks priority signals complete when a load ends, whether it failed or not
+pub(crate) fn finish_priority_signals(signals: &Option<Arc<Mutex<VcdPrioritySignals>>>) {
+    if let Some(signals) = signals {
+        signals.lock().unwrap().set_complete();
+    }
+}
@@ -0,0 +1,117 @@
+use std::io;
+
+use crate::errors::ParserError;
+use crate::lexer::stream::StreamTokenizer;
+use crate::parser::{VcdEntry, VcdHeader, VcdReader};
+use crate::tokenizer::token::Token;
+use crate::utils::VcdResult;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum VcdPushEvent {
+    // The header is complete and can be read from the parser
+    Header,
+    Entry(VcdEntry),
+}
+
+/// Parses a VCD from chunks of bytes handed over by the caller, so the input
+/// can come from anywhere (sockets, async runtimes, custom readers) without
+/// the parser doing any I/O. Chunks can split tokens and lines anywhere.
+pub struct VcdPushParser {
+    tokenizer: StreamTokenizer<io::Empty>,
+    parser: VcdReader,
+    // Tokens of the header so far, the header is only parsed once all of it
+    // has arrived since parse_header cannot stop part way through
+    header_tokens: Option<Vec<Token>>,
+}
+
+impl Default for VcdPushParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VcdPushParser {
+    pub fn new() -> Self {
+        Self {
+            tokenizer: StreamTokenizer::new(io::empty()),
+            parser: VcdReader::new(),
+            header_tokens: Some(Vec::new()),
+        }
+    }
+
+    /// Ends the input at an incomplete last line instead of failing, see
+    /// Lexer::set_lenient
+    pub fn set_lenient(&mut self, lenient: bool) {
+        self.tokenizer.set_lenient(lenient);
+    }
+
+    /// Returns the parser, whose settings should be changed before the first
+    /// chunk is fed
+    pub fn get_parser(&self) -> &VcdReader {
+        &self.parser
+    }
+
+    pub fn get_parser_mut(&mut self) -> &mut VcdReader {
+        &mut self.parser
+    }
+
+    /// Returns the header, None until it has been completely fed
+    pub fn get_header(&self) -> Option<&VcdHeader> {
+        match self.header_tokens {
+            Some(_) => None,
+            None => Some(self.parser.get_header()),
+        }
+    }
+
+    /// Returns how many bytes of the input have been lexed
+    pub fn get_index(&self) -> usize {
+        self.tokenizer.get_index()
+    }
+
+    /// Adds a chunk of input, returning the events it completed. Anything
+    /// that could still continue into the next chunk is held back.
+    pub fn feed(&mut self, bytes: &[u8]) -> VcdResult<Vec<VcdPushEvent>> {
+        self.tokenizer.push(bytes);
+        self.poll()
+    }
+
+    /// Ends the input, returning the events held back by the last chunk. Fails
+    /// if the header was never completed.
+    pub fn finish(&mut self) -> VcdResult<Vec<VcdPushEvent>> {
+        self.tokenizer.close();
+        let events = self.poll()?;
+        if self.header_tokens.is_some() {
+            return Err(ParserError::UnexpectedTermination.into());
+        }
+        Ok(events)
+    }
+
+    fn poll(&mut self) -> VcdResult<Vec<VcdPushEvent>> {
+        let mut events = Vec::new();
+        if let Some(tokens) = &mut self.header_tokens {
+            loop {
+                let bs = self.parser.get_byte_storage_mut();
+                let token = match self.tokenizer.next_buffered(bs)? {
+                    Some(token) => token,
+                    None => return Ok(events),
+                };
+                let end = matches!(token, Token::EndDefinitions(_));
+                tokens.push(token);
+                if end {
+                    break;
+                }
+            }
+            let mut tokens = self.header_tokens.take().unwrap_or_default().into_iter();
+            self.parser.parse_header(&mut |_| Ok(tokens.next()))?;
+            events.push(VcdPushEvent::Header);
+        }
+        let tokenizer = &mut self.tokenizer;
+        while let Some(entry) = self
+            .parser
+            .parse_waveform(&mut |bs| tokenizer.next_buffered(bs))?
+        {
+            events.push(VcdPushEvent::Entry(entry));
+        }
+        Ok(events)
+    }
+}
@@ -0,0 +1,115 @@
+use std::fs;
+use std::path::Path;
+
+use makai_waveform_db::bitvector::BitVector;
+use makai_waveform_db::Waveform;
+
+use crate::parser::{VcdHeader, VcdVariableWidth};
+use crate::utils::sink::VcdWaveformSink;
+use crate::utils::{load_into_sink, VcdError, VcdLoadOptions, VcdResult};
+
+type VcdDeclarations = (Option<i32>, Vec<(String, usize, VcdVariableWidth)>);
+
+// Variables that have to match between segments, the rest of the header like
+// $date and comments is allowed to differ
+fn get_declarations(header: &VcdHeader) -> VcdDeclarations {
+    let variables = header
+        .get_variables_with_paths()
+        .into_iter()
+        .map(|(path, variable)| (path, variable.get_idcode(), variable.get_width().clone()))
+        .collect();
+    (*header.get_timescale(), variables)
+}
+
+// Appends the changes of one segment to the waveform, shifting its timestamps
+// past the end of the previous segments if they do not already follow them
+struct VcdSegmentSink<'a> {
+    waveform: &'a mut Waveform,
+    index: usize,
+    // Declarations of the first segment, None while loading it
+    expected: Option<&'a VcdDeclarations>,
+    offset: Option<u64>,
+}
+
+impl VcdWaveformSink for VcdSegmentSink<'_> {
+    fn initialize(&mut self, header: &VcdHeader) -> VcdResult<()> {
+        match self.expected {
+            None => header.initialize_waveform(self.waveform),
+            Some(expected) if *expected != get_declarations(header) => {
+                return Err(VcdError::SegmentMismatch(self.index))
+            }
+            Some(_) => {}
+        }
+        Ok(())
+    }
+
+    fn insert_timestamp(&mut self, timestamp: u64) -> VcdResult<()> {
+        let last = self.waveform.get_timestamps().last().copied();
+        let offset = *self.offset.get_or_insert(match last {
+            Some(last) if timestamp <= last => last + 1 - timestamp,
+            _ => 0,
+        });
+        Ok(self.waveform.insert_timestamp(timestamp + offset)?)
+    }
+
+    fn update_vector(&mut self, idcode: usize, bv: BitVector) -> VcdResult<()> {
+        Ok(self.waveform.update_vector(idcode, bv)?)
+    }
+
+    fn update_real(&mut self, idcode: usize, value: f64) -> VcdResult<()> {
+        Ok(self.waveform.update_real(idcode, value)?)
+    }
+}
+
+/// Loads the segments of a run split across several dumps into one waveform,
+/// in order. Every segment has to declare the same variables with the same
+/// idcodes and timescale. A segment that starts at or before the end of the
+/// previous ones (usually at zero) is shifted to start right after them, the
+/// offset of every segment is returned along with the first header.
+pub fn load_segment_strings(
+    segments: Vec<String>,
+    status: &mut dyn FnMut((usize, usize)),
+) -> VcdResult<(VcdHeader, Waveform, Vec<u64>)> {
+    load_segment_strings_with_options(segments, &mut |_| VcdLoadOptions::new(), status)
+}
+
+/// Loads the segments of a run with the options each segment is loaded
+/// with, by the index of the segment since options are used up by a load
+pub fn load_segment_strings_with_options(
+    segments: Vec<String>,
+    options: &mut dyn FnMut(usize) -> VcdLoadOptions,
+    status: &mut dyn FnMut((usize, usize)),
+) -> VcdResult<(VcdHeader, Waveform, Vec<u64>)> {
+    let mut waveform = Waveform::new();
+    let mut first: Option<(VcdHeader, VcdDeclarations)> = None;
+    let mut offsets = Vec::new();
+    for (index, bytes) in segments.into_iter().enumerate() {
+        let mut sink = VcdSegmentSink {
+            waveform: &mut waveform,
+            index,
+            expected: first.as_ref().map(|(_, declarations)| declarations),
+            offset: None,
+        };
+        let header = load_into_sink(&bytes, &mut sink, options(index), status)?;
+        offsets.push(sink.offset.unwrap_or(0));
+        if first.is_none() {
+            let declarations = get_declarations(&header);
+            first = Some((header, declarations));
+        }
+    }
+    let header = first.map(|(header, _)| header).unwrap_or_default();
+    Ok((header, waveform, offsets))
+}
+
+/// Reads and loads the segments of a run from files, see
+/// load_segment_strings
+pub fn load_segments<P: AsRef<Path>>(
+    paths: &[P],
+    status: &mut dyn FnMut((usize, usize)),
+) -> VcdResult<(VcdHeader, Waveform, Vec<u64>)> {
+    let segments = paths
+        .iter()
+        .map(fs::read_to_string)
+        .collect::<Result<Vec<String>, _>>()?;
+    load_segment_strings(segments, status)
+}
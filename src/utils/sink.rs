@@ -0,0 +1,156 @@
+use bytes::Bytes;
+use makai_waveform_db::bitvector::{BitVector, Logic};
+use makai_waveform_db::Waveform;
+
+use crate::parser::{VcdEntry, VcdHeader};
+use crate::tokenizer::token::TokenPortValue;
+use crate::utils::VcdResult;
+
+/// Storage fed with value changes by a loader, in the order they appear in
+/// the body. Errors from other storage can be returned as VcdError::Io.
+pub trait VcdWaveformSink {
+    /// Called once the header is parsed, before any change
+    fn initialize(&mut self, _header: &VcdHeader) -> VcdResult<()> {
+        Ok(())
+    }
+
+    fn insert_timestamp(&mut self, timestamp: u64) -> VcdResult<()>;
+
+    /// Called for changes of 1-bit signals, stored as a one-bit vector by
+    /// default
+    fn update_scalar(&mut self, idcode: usize, bit: Logic) -> VcdResult<()> {
+        self.update_vector(idcode, BitVector::from(bit))
+    }
+
+    fn update_vector(&mut self, idcode: usize, bv: BitVector) -> VcdResult<()>;
+
+    fn update_real(&mut self, idcode: usize, value: f64) -> VcdResult<()>;
+
+    /// Called when an event is triggered, stored as a one-bit vector set to
+    /// one by default
+    fn update_event(&mut self, idcode: usize) -> VcdResult<()> {
+        self.update_vector(idcode, BitVector::new_one_bit())
+    }
+
+    /// Called at the end of the timestamp after an event was triggered, if it
+    /// was not triggered again, which clears the one-bit vector by default
+    fn release_event(&mut self, idcode: usize) -> VcdResult<()> {
+        self.update_vector(idcode, BitVector::from(Logic::Zero))
+    }
+
+    /// Called for changes of extended VCD ports, stored as a vector of the
+    /// levels of the port by default
+    fn update_port(&mut self, idcode: usize, value: TokenPortValue) -> VcdResult<()> {
+        self.update_vector(idcode, value.to_bitvector())
+    }
+
+    /// Called for changes of strings, which a waveform cannot store, they are
+    /// dropped by default
+    fn update_string(&mut self, _idcode: usize, _value: Bytes) -> VcdResult<()> {
+        Ok(())
+    }
+
+    /// Called after the last change of a load that did not fail
+    fn finish(&mut self) -> VcdResult<()> {
+        Ok(())
+    }
+}
+
+impl VcdWaveformSink for Waveform {
+    fn initialize(&mut self, header: &VcdHeader) -> VcdResult<()> {
+        header.initialize_waveform(self);
+        Ok(())
+    }
+
+    fn insert_timestamp(&mut self, timestamp: u64) -> VcdResult<()> {
+        Ok(Waveform::insert_timestamp(self, timestamp)?)
+    }
+
+    fn update_vector(&mut self, idcode: usize, bv: BitVector) -> VcdResult<()> {
+        Ok(Waveform::update_vector(self, idcode, bv)?)
+    }
+
+    fn update_real(&mut self, idcode: usize, value: f64) -> VcdResult<()> {
+        Ok(Waveform::update_real(self, idcode, value)?)
+    }
+}
+
+// Events are stored as pulses, set when they are triggered and cleared at the
+// end of the next timestamp unless triggered again, so a waveform holds their
+// triggers rather than a level
+#[derive(Debug, Default)]
+pub(crate) struct VcdEventPulses {
+    triggered: Vec<usize>,
+    releasing: Vec<usize>,
+}
+
+impl VcdEventPulses {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn trigger(&mut self, idcode: usize) {
+        self.releasing.retain(|id| *id != idcode);
+        if !self.triggered.contains(&idcode) {
+            self.triggered.push(idcode);
+        }
+    }
+
+    // The events to clear at the last timestamp, called before inserting the
+    // next one
+    pub(crate) fn release(&mut self) -> Vec<usize> {
+        std::mem::replace(&mut self.releasing, std::mem::take(&mut self.triggered))
+    }
+
+    // The events to clear at the last timestamp once there are no more
+    pub(crate) fn finish(&mut self) -> Vec<usize> {
+        self.triggered.clear();
+        std::mem::take(&mut self.releasing)
+    }
+
+    // Like apply_entry, but ends the pulse of every event triggered at the
+    // timestamp before the last
+    pub(crate) fn apply<S: VcdWaveformSink + ?Sized>(
+        &mut self,
+        sink: &mut S,
+        entry: VcdEntry,
+    ) -> VcdResult<()> {
+        match entry {
+            VcdEntry::Timestamp(timestamp) => {
+                for idcode in self.release() {
+                    sink.release_event(idcode)?;
+                }
+                sink.insert_timestamp(timestamp)
+            }
+            VcdEntry::Event(idcode) => {
+                self.trigger(idcode);
+                sink.update_event(idcode)
+            }
+            entry => apply_entry(sink, entry),
+        }
+    }
+
+    pub(crate) fn apply_finish<S: VcdWaveformSink + ?Sized>(
+        &mut self,
+        sink: &mut S,
+    ) -> VcdResult<()> {
+        for idcode in self.finish() {
+            sink.release_event(idcode)?;
+        }
+        Ok(())
+    }
+}
+
+/// Hands a parsed entry to the matching call of a sink, events are only set
+/// and left for the caller to release
+pub fn apply_entry<S: VcdWaveformSink + ?Sized>(sink: &mut S, entry: VcdEntry) -> VcdResult<()> {
+    match entry {
+        VcdEntry::Timestamp(timestamp) => sink.insert_timestamp(timestamp),
+        VcdEntry::Scalar(bit, idcode) => sink.update_scalar(idcode, bit),
+        VcdEntry::Vector(bv, idcode) => sink.update_vector(idcode, bv),
+        VcdEntry::Real(value, idcode) => sink.update_real(idcode, value),
+        VcdEntry::Event(idcode) => sink.update_event(idcode),
+        VcdEntry::Port(value, idcode) => sink.update_port(idcode, value),
+        VcdEntry::String(value, idcode) => sink.update_string(idcode, value),
+    }
+}
@@ -0,0 +1,66 @@
+use std::collections::HashMap;
+
+use bytes::Bytes;
+
+use crate::parser::{VcdEntry, VcdHeader, VcdVariableWidth};
+use crate::utils::tee::VcdTap;
+use crate::utils::VcdResult;
+
+/// The values of string variables, which a waveform cannot hold, kept when
+/// added as a tap of a load with VcdLoadOptions::add_tap
+#[derive(Clone, Debug, Default)]
+pub struct VcdStringSignals {
+    paths: HashMap<String, usize>,
+    histories: HashMap<usize, Vec<(u64, Bytes)>>,
+    timestamp: u64,
+}
+
+impl VcdStringSignals {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get_idcode(&self, path: &str) -> Option<usize> {
+        self.paths.get(path).copied()
+    }
+
+    /// Returns every change of a string variable in timestamp order
+    pub fn get_history(&self, idcode: usize) -> Option<&[(u64, Bytes)]> {
+        self.histories
+            .get(&idcode)
+            .map(|history| history.as_slice())
+    }
+
+    /// Returns the value a string variable held at a timestamp, None if it
+    /// had not changed yet
+    pub fn get_value_at(&self, idcode: usize, timestamp: u64) -> Option<&Bytes> {
+        let history = self.histories.get(&idcode)?;
+        let index = history.partition_point(|(t, _)| *t <= timestamp);
+        history.get(index.checked_sub(1)?).map(|(_, value)| value)
+    }
+}
+
+impl VcdTap for VcdStringSignals {
+    fn initialize(&mut self, header: &VcdHeader) -> VcdResult<()> {
+        for (path, variable) in header.get_variables_with_paths() {
+            if *variable.get_width() == VcdVariableWidth::String {
+                self.paths.insert(path, variable.get_idcode());
+                self.histories.entry(variable.get_idcode()).or_default();
+            }
+        }
+        Ok(())
+    }
+
+    fn record(&mut self, entry: &VcdEntry) -> VcdResult<()> {
+        match entry {
+            VcdEntry::Timestamp(timestamp) => self.timestamp = *timestamp,
+            VcdEntry::String(value, idcode) => {
+                if let Some(history) = self.histories.get_mut(idcode) {
+                    history.push((self.timestamp, value.clone()));
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
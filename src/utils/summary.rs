@@ -0,0 +1,210 @@
+use std::sync::{Arc, Mutex};
+
+use crate::analysis::fingerprint::VcdHasher;
+use crate::export::json_string;
+use crate::parser::{VcdEntry, VcdReader};
+
+/// Counts and a content hash of everything a load produced, two loads of the
+/// same dump always give the same summary
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VcdSummary {
+    timestamps: usize,
+    scalars: usize,
+    vectors: usize,
+    reals: usize,
+    events: usize,
+    ports: usize,
+    strings: usize,
+    warnings: usize,
+    elided: usize,
+    first_timestamp: Option<u64>,
+    last_timestamp: Option<u64>,
+    hasher: VcdHasher,
+}
+
+impl VcdSummary {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn hash_change(&mut self, kind: u8, idcode: usize) {
+        self.hasher.write(&[kind]);
+        self.hasher.write_u64(idcode as u64);
+    }
+
+    pub fn record(&mut self, entry: &VcdEntry) {
+        match entry {
+            VcdEntry::Timestamp(timestamp) => {
+                self.timestamps += 1;
+                self.first_timestamp.get_or_insert(*timestamp);
+                self.last_timestamp = Some(*timestamp);
+                self.hasher.write(b"#");
+                self.hasher.write_u64(*timestamp);
+            }
+            // Scalars hash the same as 1-bit vectors, so the hash does not
+            // depend on how the simulator wrote the change
+            VcdEntry::Scalar(bit, idcode) => {
+                self.scalars += 1;
+                self.hash_change(b'b', *idcode);
+                self.hasher.write_bit(*bit);
+            }
+            VcdEntry::Vector(bv, idcode) => {
+                self.vectors += 1;
+                self.hash_change(b'b', *idcode);
+                for bit in bv.iter() {
+                    self.hasher.write_bit(bit);
+                }
+            }
+            VcdEntry::Real(value, idcode) => {
+                self.reals += 1;
+                self.hash_change(b'r', *idcode);
+                self.hasher.write_u64(value.to_bits());
+            }
+            VcdEntry::Event(idcode) => {
+                self.events += 1;
+                self.hash_change(b'e', *idcode);
+            }
+            // Ports hash their states so a change of direction or strength
+            // changes the hash
+            VcdEntry::Port(value, idcode) => {
+                self.ports += 1;
+                self.hash_change(b'p', *idcode);
+                self.hasher.write(value.to_string().as_bytes());
+            }
+            VcdEntry::String(value, idcode) => {
+                self.strings += 1;
+                self.hash_change(b's', *idcode);
+                self.hasher.write(value);
+            }
+        }
+    }
+
+    /// Takes the counts only the parser knows, once it has finished
+    pub fn finish(&mut self, parser: &VcdReader) {
+        self.warnings = parser.get_warning_count();
+        self.elided = parser.get_elided_count();
+    }
+
+    /// Returns every value change, not counting timestamps
+    pub fn get_change_count(&self) -> usize {
+        self.scalars + self.vectors + self.reals + self.events + self.ports + self.strings
+    }
+
+    pub fn get_timestamp_count(&self) -> usize {
+        self.timestamps
+    }
+
+    pub fn get_scalar_count(&self) -> usize {
+        self.scalars
+    }
+
+    pub fn get_vector_count(&self) -> usize {
+        self.vectors
+    }
+
+    pub fn get_real_count(&self) -> usize {
+        self.reals
+    }
+
+    pub fn get_event_count(&self) -> usize {
+        self.events
+    }
+
+    /// Returns the port changes of an extended VCD
+    pub fn get_port_count(&self) -> usize {
+        self.ports
+    }
+
+    pub fn get_string_count(&self) -> usize {
+        self.strings
+    }
+
+    pub fn get_warning_count(&self) -> usize {
+        self.warnings
+    }
+
+    pub fn get_elided_count(&self) -> usize {
+        self.elided
+    }
+
+    /// Returns the first and last timestamps, None if there were none
+    pub fn get_timestamp_range(&self) -> Option<(u64, u64)> {
+        Some((self.first_timestamp?, self.last_timestamp?))
+    }
+
+    pub fn get_hash(&self) -> u64 {
+        self.hasher.finish()
+    }
+
+    pub fn to_json(&self) -> String {
+        let range = match self.get_timestamp_range() {
+            Some((first, last)) => format!("[{},{}]", first, last),
+            None => "null".to_string(),
+        };
+        format!(
+            "{{\"changes\":{},\"timestamps\":{},\"scalars\":{},\"vectors\":{},\"reals\":{},\"events\":{},\"ports\":{},\"strings\":{},\"warnings\":{},\"elided\":{},\"range\":{},\"hash\":{}}}",
+            self.get_change_count(),
+            self.timestamps,
+            self.scalars,
+            self.vectors,
+            self.reals,
+            self.events,
+            self.ports,
+            self.strings,
+            self.warnings,
+            self.elided,
+            range,
+            json_string(&format!("{:016x}", self.get_hash()))
+        )
+    }
+}
+
+impl std::fmt::Display for VcdSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "changes: {}", self.get_change_count())?;
+        writeln!(
+            f,
+            "  scalars: {}, vectors: {}, reals: {}, events: {}, ports: {}, strings: {}",
+            self.scalars, self.vectors, self.reals, self.events, self.ports, self.strings
+        )?;
+        match self.get_timestamp_range() {
+            Some((first, last)) => {
+                writeln!(f, "timestamps: {} ({} to {})", self.timestamps, first, last)?
+            }
+            None => writeln!(f, "timestamps: 0")?,
+        }
+        writeln!(f, "warnings: {}", self.warnings)?;
+        writeln!(f, "elided: {}", self.elided)?;
+        write!(f, "hash: {:016x}", self.get_hash())
+    }
+}
+
+// Builds the summary on the thread that parses, then hands it over once the
+// load succeeds
+pub(crate) struct VcdSummaryRecorder {
+    shared: Option<Arc<Mutex<VcdSummary>>>,
+    summary: VcdSummary,
+}
+
+impl VcdSummaryRecorder {
+    pub(crate) fn new(shared: Option<Arc<Mutex<VcdSummary>>>) -> Self {
+        Self {
+            shared,
+            summary: VcdSummary::new(),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn record(&mut self, entry: &VcdEntry) {
+        if self.shared.is_some() {
+            self.summary.record(entry);
+        }
+    }
+
+    pub(crate) fn finish(mut self, parser: &VcdReader) {
+        if let Some(shared) = &self.shared {
+            self.summary.finish(parser);
+            *shared.lock().unwrap() = self.summary;
+        }
+    }
+}
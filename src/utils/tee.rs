@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+use crate::parser::{VcdEntry, VcdHeader};
+use crate::utils::monitor::VcdMonitors;
+use crate::utils::pipeline::{VcdExportStage, VcdPipelineStage};
+use crate::utils::summary::VcdSummary;
+use crate::utils::VcdResult;
+
+/// A secondary consumer of the entries of a load, fed every entry in order
+/// alongside the waveform so one pass over the file can also export or
+/// collect statistics. An error from a tap fails the load.
+pub trait VcdTap: Send {
+    /// Called once the header is parsed, before any entry
+    fn initialize(&mut self, _header: &VcdHeader) -> VcdResult<()> {
+        Ok(())
+    }
+
+    fn record(&mut self, entry: &VcdEntry) -> VcdResult<()>;
+
+    /// Called after the last entry of a load that did not fail
+    fn finish(&mut self) -> VcdResult<()> {
+        Ok(())
+    }
+}
+
+pub type VcdSharedTap = Arc<Mutex<dyn VcdTap>>;
+
+impl VcdTap for VcdSummary {
+    fn record(&mut self, entry: &VcdEntry) -> VcdResult<()> {
+        VcdSummary::record(self, entry);
+        Ok(())
+    }
+}
+
+impl VcdTap for VcdMonitors {
+    fn initialize(&mut self, header: &VcdHeader) -> VcdResult<()> {
+        self.resolve(header);
+        Ok(())
+    }
+
+    fn record(&mut self, entry: &VcdEntry) -> VcdResult<()> {
+        VcdMonitors::record(self, entry);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> VcdResult<()> {
+        VcdMonitors::finish(self);
+        Ok(())
+    }
+}
+
+/// Exports every value change of a load while it is parsed, with the same
+/// writers as the export steps of a VcdPipeline
+pub struct VcdExportTap<W: Write + Send> {
+    stage: VcdExportStage<W>,
+    // Entries handed on by the stage, there is no next step to take them
+    out: Vec<VcdEntry>,
+}
+
+impl<W: Write + Send> VcdExportTap<W> {
+    fn new(stage: VcdExportStage<W>) -> Self {
+        Self {
+            stage,
+            out: Vec::new(),
+        }
+    }
+
+    pub fn csv(writer: W) -> Self {
+        Self::new(VcdExportStage::Csv(Some(writer), None))
+    }
+
+    pub fn ndjson(writer: W) -> Self {
+        Self::new(VcdExportStage::Json(Some(writer), None))
+    }
+
+    /// Writes a VCD with the same header as the one loaded
+    pub fn vcd(writer: W) -> Self {
+        Self::new(VcdExportStage::Vcd(writer, HashMap::new(), HashMap::new()))
+    }
+
+    pub fn get_writer(&self) -> Option<&W> {
+        self.stage.get_writer()
+    }
+}
+
+impl<W: Write + Send> VcdTap for VcdExportTap<W> {
+    fn initialize(&mut self, header: &VcdHeader) -> VcdResult<()> {
+        Ok(self.stage.start(header)?)
+    }
+
+    fn record(&mut self, entry: &VcdEntry) -> VcdResult<()> {
+        self.stage.process(entry.clone(), &mut self.out)?;
+        self.out.clear();
+        Ok(())
+    }
+}
+
+// Feeds the taps of a load, each tap is locked once per entry so they can be
+// read from other threads while the load runs
+pub(crate) struct VcdTapRecorder {
+    taps: Vec<VcdSharedTap>,
+}
+
+impl VcdTapRecorder {
+    pub(crate) fn new(taps: Vec<VcdSharedTap>, header: &VcdHeader) -> VcdResult<Self> {
+        for tap in &taps {
+            tap.lock().unwrap().initialize(header)?;
+        }
+        Ok(Self { taps })
+    }
+
+    #[inline]
+    pub(crate) fn record(&mut self, entry: &VcdEntry) -> VcdResult<()> {
+        for tap in &self.taps {
+            tap.lock().unwrap().record(entry)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn finish(&mut self) -> VcdResult<()> {
+        for tap in &self.taps {
+            tap.lock().unwrap().finish()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+use std::io::BufRead;
+use std::time::Instant;
+
+use makai::utils::bytes::ByteStorage;
+
+use crate::lexer::stream::StreamTokenizer;
+use crate::parser::{VcdEntry, VcdHeader, VcdReader};
+use crate::utils::events::VcdLoadStats;
+use crate::utils::logging::VcdLoadLog;
+use crate::utils::{VcdLoadOptions, VcdResult};
+
+/// Checks that a dump is well-formed without building a waveform, for gating
+/// on a simulator having produced a sane dump. The source is read a chunk at
+/// a time and nothing parsed from the body is kept, so memory use does not
+/// grow with the dump. Returns the header and the totals of the body.
+pub fn validate_stream<R: BufRead>(reader: R) -> VcdResult<(VcdHeader, VcdLoadStats)> {
+    validate_stream_with_options(reader, VcdLoadOptions::new())
+}
+
+/// Validates with the parser and lexer options a load would use, the ones
+/// that only change what is built from the changes are ignored
+pub fn validate_stream_with_options<R: BufRead>(
+    reader: R,
+    options: VcdLoadOptions,
+) -> VcdResult<(VcdHeader, VcdLoadStats)> {
+    let log = VcdLoadLog::new(options.verbosity, None);
+    log.debug("Validating VCD...");
+    match validate_internal(reader, options, &log) {
+        Ok(ok) => {
+            log.debug("VCD is valid!");
+            Ok(ok)
+        }
+        Err(err) => {
+            log.error(None, &err);
+            Err(err)
+        }
+    }
+}
+
+fn validate_internal<R: BufRead>(
+    reader: R,
+    mut options: VcdLoadOptions,
+    log: &VcdLoadLog,
+) -> VcdResult<(VcdHeader, VcdLoadStats)> {
+    let start = Instant::now();
+    let mut tokenizer = StreamTokenizer::new(reader);
+    options.configure_stream(&mut tokenizer);
+    let mut parser = VcdReader::new();
+    options.configure(&mut parser);
+    parser.parse_header(&mut |bs| tokenizer.next(bs))?;
+    log.debug("Header parsed...");
+    // The parser never reads back what the body stores, so it goes into
+    // scratch storage that is dropped at every timestamp
+    let mut scratch = ByteStorage::new();
+    let (mut timestamps, mut changes) = (0, 0);
+    loop {
+        let entry = parser.parse_waveform(&mut |_| tokenizer.next(&mut scratch))?;
+        match entry {
+            Some(VcdEntry::Timestamp(_)) => {
+                timestamps += 1;
+                scratch = ByteStorage::new();
+            }
+            Some(_) => changes += 1,
+            None => break,
+        }
+    }
+    let stats = VcdLoadStats::new(tokenizer.get_index(), timestamps, changes, &parser, start);
+    Ok((parser.into_header(), stats))
+}
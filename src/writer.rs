@@ -0,0 +1,87 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::io::{self, Write};
+use std::iter::Peekable;
+
+use makai_waveform_db::Waveform;
+
+use crate::analysis::iter_variable_changes;
+use crate::export::vcd::{write_entry, write_header};
+use crate::parser::{VcdEntry, VcdHeader, VcdVariableWidth};
+
+/// Writes a loaded header and waveform back out as a complete VCD, the values
+/// at the first timestamp go in a $dumpvars section and every later change is
+/// written in timestamp order. Every timestamp of the waveform is written,
+/// including ones without changes. Idcodes are reassigned, so the output is
+/// the same for equal waveforms no matter how they were loaded.
+///
+/// Real values cannot be read back out of a waveform, so writing one with a
+/// real signal that changed fails with AnalysisError::UnreadableReal before
+/// anything is written.
+pub struct VcdWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> VcdWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    pub fn write(&mut self, header: &VcdHeader, waveform: &Waveform) -> io::Result<()> {
+        let widths = header.get_idcodes_map();
+        let mut ids: Vec<usize> = widths.keys().copied().collect();
+        ids.sort_unstable();
+        // Each signal is read lazily and the next change of every signal is
+        // kept in a heap ordered by timestamp and then by id
+        let mut signals: Vec<Peekable<_>> = Vec::with_capacity(ids.len());
+        let mut next = BinaryHeap::new();
+        for &id in &ids {
+            let mut changes = iter_variable_changes(waveform, id, &widths[&id])?.peekable();
+            if let Some((timestamp, _)) = changes.peek() {
+                next.push(Reverse((*timestamp, id, signals.len())));
+                signals.push(changes);
+            }
+        }
+        let idcodes = write_header(header, &mut self.writer)?;
+        for (index, &timestamp) in waveform.get_timestamps().iter().enumerate() {
+            writeln!(self.writer, "#{}", timestamp)?;
+            let first = index == 0;
+            if first {
+                writeln!(self.writer, "$dumpvars")?;
+            }
+            // Events trigger rather than hold a value, so they are left out of
+            // the initial values
+            let mut events = Vec::new();
+            while let Some(&Reverse((change_timestamp, id, signal))) = next.peek() {
+                if change_timestamp != timestamp {
+                    break;
+                }
+                next.pop();
+                let (_, entry) = signals[signal].next().unwrap();
+                if first && widths[&id] == VcdVariableWidth::Event {
+                    events.push((id, entry));
+                } else {
+                    write_entry(&entry, &widths[&id], &idcodes[&id], &mut self.writer)?;
+                }
+                if let Some((next_timestamp, _)) = signals[signal].peek() {
+                    next.push(Reverse((*next_timestamp, id, signal)));
+                }
+            }
+            if first {
+                writeln!(self.writer, "$end")?;
+            }
+            for (id, entry) in events {
+                write_entry(&entry, &widths[&id], &idcodes[&id], &mut self.writer)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get_writer(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn into_writer(self) -> W {
+        self.writer
+    }
+}